@@ -2,14 +2,9 @@ use std::collections::hash_map::Entry;
 use std::collections::{HashMap, HashSet};
 use std::fmt::Write;
 
+use crate::pretty::Style;
 use crate::term::*;
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
-enum Predef {
-    /// java.util.Arrays.copyOf
-    ArrayCopy,
-}
-
 pub struct IRMod {
     name: RawSym,
     code: Vec<Item>,
@@ -18,29 +13,123 @@ pub struct IRMod {
     out_class: String,
 }
 
+/// What generated Java code is indented with
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IndentStyle {
+    Tabs,
+    Spaces(usize),
+}
+
+/// Whether `sep` can be put between a name and a number in a Java identifier, so it can be used to mangle names.
+pub fn valid_separator(sep: &str) -> bool {
+    !sep.is_empty()
+        && sep
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$')
+}
+
+/// An error in a program that type checks but can't be compiled to Java
+#[derive(Debug)]
+pub enum BackendError {
+    /// An extern function returning a tuple, other than a Java array that becomes a dynamic array
+    ExternTupleReturn(String, Span),
+    /// A `break` or `continue` outside of a loop
+    OutsideLoop(&'static str),
+    /// A `break` or `continue` to a label that isn't on an enclosing loop
+    UnknownLabel(&'static str, String),
+    /// A tuple used with an operator, which only works on single values
+    TupleOperand(&'static str),
+    /// A `for i in unroll a..b` loop whose ends aren't known at compile time
+    CantUnroll,
+    /// An index into a static array that isn't known at compile time
+    StaticIndex,
+}
+impl BackendError {
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            BackendError::ExternTupleReturn(_, span) => Some(*span),
+            _ => None,
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            BackendError::ExternTupleReturn(name, _) => {
+                format!("Extern function '{}' can't return a tuple", name)
+            }
+            BackendError::OutsideLoop(kw) => format!("'{}' outside of a loop", kw),
+            BackendError::UnknownLabel(kw, l) => format!("'{}' to unknown label '{}", kw, l),
+            BackendError::TupleOperand(op) => {
+                format!("Operator '{}' can't be used on tuples", op)
+            }
+            BackendError::CantUnroll => {
+                "Couldn't unroll loop, its ends must be known at compile time".to_string()
+            }
+            BackendError::StaticIndex => {
+                "Index into a static array must be known at compile time".to_string()
+            }
+        }
+    }
+
+    /// Prints the error, pointing at the source in `file` if it has a span
+    pub fn emit(self, file: FileId) {
+        match self.span() {
+            Some(span) => {
+                Spanned::new(Doc::start(self.message()), span).emit(Severity::Error, file)
+            }
+            None => Severity::Error
+                .start()
+                .add(": ")
+                .add(self.message())
+                .style(Style::Bold)
+                .emit(),
+        }
+    }
+}
+
 // Entry point
 
 pub fn declare_p1(code: &[Item], cxt: &mut Cxt) {
     for i in code {
         match i {
-            Item::ExternClass(c, _) => {
-                let class = cxt.fresh_class();
-                cxt.types.push((*c, class));
-
-                continue;
-            }
-            Item::Class(c, _, _) => {
+            Item::ExternClass(c, _) | Item::Class(c, _, _, _, _) | Item::Interface(c, _) => {
                 let class = cxt.fresh_class();
                 cxt.types.push((*c, class));
+                let params = cxt.bindings.type_params(*c);
+                if !params.is_empty() {
+                    cxt.generic_classes.insert(class, params.to_vec());
+                }
 
                 continue;
             }
-            Item::Enum(c, v, _, _, _) => {
+            Item::Enum(c, v, ext, _, methods) => {
                 let class = cxt.fresh_class();
                 cxt.types.push((*c, class));
                 if v.iter().any(|(_, v)| !v.is_empty()) {
-                    let wrapper = cxt.fresh_class();
-                    cxt.enum_wrappers.insert(class, wrapper);
+                    if cxt.pooled_enums && !ext && methods.is_empty() {
+                        // The field arrays are added by declare_p2(), once all the types they could contain exist
+                        let tag = cxt.fresh_var(false);
+                        let len = cxt.fresh_var(false);
+                        cxt.tys.insert(tag, JTy::Array(Box::new(JTy::I32)));
+                        cxt.tys.insert(len, JTy::I32);
+                        cxt.enum_pools.insert(
+                            class,
+                            EnumPool {
+                                variants: v.iter().map(|(s, _)| *s).collect(),
+                                tag,
+                                len,
+                                fields: Vec::new(),
+                            },
+                        );
+                    } else {
+                        let wrapper = cxt.fresh_class();
+                        cxt.enum_wrappers.insert(class, wrapper);
+                    }
+                } else if cxt.enums_as_ints && !ext && methods.is_empty() {
+                    cxt.int_enums
+                        .insert(class, v.iter().map(|(s, _)| *s).collect());
+                } else {
+                    cxt.enums.insert(class);
                 }
 
                 continue;
@@ -50,18 +139,17 @@ pub fn declare_p1(code: &[Item], cxt: &mut Cxt) {
     }
 }
 
-pub fn declare_p2(code: Vec<Item>, cxt: &mut Cxt, out_class: &str) -> IRMod {
+pub fn declare_p2(code: Vec<Item>, cxt: &mut Cxt, out_class: &str) -> Result<IRMod, BackendError> {
     // Declare items
     let mut mappings = Vec::new();
     let mut java = Vec::new();
 
-    let predefined = vec![(Predef::ArrayCopy, "System.arraycopy", JTys::empty())];
-    for (d, s, t) in predefined {
+    for (name, path, t) in cxt.predef_defs.clone() {
         let fn_id = cxt.fresh_fn();
+        let t = t.lower(cxt);
         cxt.fn_ret_tys.insert(fn_id, t);
-        let raw = cxt.bindings.raw(s);
-        mappings.push((fn_id.0, lpath(Spanned::new(raw, Span(0, 0))), false));
-        cxt.predefs.push((d, fn_id));
+        mappings.push((fn_id.0, lpath(Spanned::new(path, Span(0, 0))), false));
+        cxt.predefs.push((name, fn_id));
     }
 
     for i in &code {
@@ -90,7 +178,7 @@ pub fn declare_p2(code: Vec<Item>, cxt: &mut Cxt, out_class: &str) -> IRMod {
                 let class = cxt.class(*c).unwrap();
                 mappings.push((class.0, lpath(cxt.bindings.type_name(*c).stem()), false));
                 for (s, t) in members {
-                    let t = t.lower(&cxt);
+                    let t = t.lower(cxt);
                     let mut vars = Vec::new();
                     for t in t {
                         let var = cxt.fresh_var(cxt.bindings.public(*s));
@@ -103,14 +191,24 @@ pub fn declare_p2(code: Vec<Item>, cxt: &mut Cxt, out_class: &str) -> IRMod {
 
                 continue;
             }
-            Item::Class(c, members, methods) => {
+            Item::Interface(c, _) => {
+                let class = cxt.class(*c).unwrap();
+                mappings.push((class.0, cxt.bindings.type_name(*c), true));
+
+                continue;
+            }
+            Item::Class(c, members, methods, _, _) => {
                 let class = cxt.class(*c).unwrap();
                 mappings.push((class.0, cxt.bindings.type_name(*c), true));
                 for f in methods {
                     let item = cxt.fresh_fn();
                     cxt.fn_ids.push((f.id, item));
+                    if f.is_static {
+                        cxt.static_methods.insert(item, class);
+                    }
+                    f.declare_defaults(cxt);
 
-                    let ret = f.ret_ty.lower(&cxt);
+                    let ret = f.ret_ty.lower(cxt);
                     cxt.fn_ret_tys.insert(item, ret);
                     mappings.push((item.0, cxt.bindings.fn_name(f.id), !f.public));
 
@@ -119,10 +217,15 @@ pub fn declare_p2(code: Vec<Item>, cxt: &mut Cxt, out_class: &str) -> IRMod {
                     }
                 }
                 for (s, t, _) in members {
-                    let t = t.lower(&cxt);
+                    let t = t.lower(cxt);
                     let mut vars = Vec::new();
                     for t in t {
                         let var = cxt.fresh_var(cxt.bindings.public(*s));
+                        if cxt.bindings.volatile(*s) {
+                            cxt.volatile.insert(var);
+                        }
+                        cxt.visibility
+                            .insert(var, Visibility::from_pub(cxt.bindings.public(*s)));
                         cxt.tys.insert(var, t);
                         mappings.push((var.0, cxt.bindings.sym_path(*s), !var.1));
                         vars.push(var);
@@ -132,12 +235,12 @@ pub fn declare_p2(code: Vec<Item>, cxt: &mut Cxt, out_class: &str) -> IRMod {
 
                 continue;
             }
-            Item::Enum(c, _, ext, members, methods) => {
+            Item::Enum(c, variants, ext, members, methods) => {
                 let class = cxt.class(*c).unwrap();
                 if *ext {
                     mappings.push((class.0, lpath(cxt.bindings.type_name(*c).stem()), false));
                     for (s, t) in members {
-                        let t = t.lower(&cxt);
+                        let t = t.lower(cxt);
                         let mut vars = Vec::new();
                         for t in t {
                             let var = cxt.fresh_var(cxt.bindings.public(*s));
@@ -152,11 +255,45 @@ pub fn declare_p2(code: Vec<Item>, cxt: &mut Cxt, out_class: &str) -> IRMod {
                     if let Some(&wrapper) = cxt.enum_wrappers.get(&class) {
                         mappings.push((wrapper.0, cxt.bindings.type_name(*c), true));
                     }
+                    if let Some(pool) = cxt.enum_pools.get(&class) {
+                        // The pool arrays are globals in this module named after the enum, like `Enum$tag`
+                        let (tag, len) = (pool.tag, pool.len);
+                        let path = cxt.bindings.type_name(*c);
+                        let enum_name = cxt.bindings.resolve_raw(*path.stem()).to_string();
+                        let name = |cxt: &mut Cxt, suffix: String| {
+                            let raw = cxt
+                                .bindings
+                                .raw(format!("{}{}{}", enum_name, cxt.separator, suffix));
+                            RawPath(path.0.clone(), Spanned::new(raw, path.1.span))
+                        };
+                        mappings.push((tag.0, name(cxt, "tag".into()), true));
+                        mappings.push((len.0, name(cxt, "len".into()), true));
+                        let mut fields = Vec::new();
+                        for (s, tys) in variants {
+                            let tys: Vec<_> = tys.iter().flat_map(|t| t.lower(cxt)).collect();
+                            let mut arrs = Vec::new();
+                            for (n, t) in tys.into_iter().enumerate() {
+                                let var = cxt.fresh_var(false);
+                                let t = JTy::Array(Box::new(t));
+                                cxt.tys.insert(var, t.clone());
+                                let suffix = format!(
+                                    "{}{}{}",
+                                    cxt.bindings.resolve_raw(*s),
+                                    cxt.separator,
+                                    n
+                                );
+                                mappings.push((var.0, name(cxt, suffix), true));
+                                arrs.push((var, t));
+                            }
+                            fields.push(arrs);
+                        }
+                        cxt.enum_pools.get_mut(&class).unwrap().fields = fields;
+                    }
                     for f in methods {
                         let item = cxt.fresh_fn();
                         cxt.fn_ids.push((f.id, item));
 
-                        let ret = f.ret_ty.lower(&cxt);
+                        let ret = f.ret_ty.lower(cxt);
                         cxt.fn_ret_tys.insert(item, ret);
                         mappings.push((item.0, cxt.bindings.fn_name(f.id), !f.public));
 
@@ -173,10 +310,18 @@ pub fn declare_p2(code: Vec<Item>, cxt: &mut Cxt, out_class: &str) -> IRMod {
                 continue;
             }
             Item::Let(s, t, _) => {
-                let t = t.lower(&cxt);
+                let t = t.lower(cxt);
                 let mut vars = Vec::new();
                 for t in t {
                     let var = cxt.fresh_var(cxt.bindings.public(*s));
+                    if cxt.bindings.volatile(*s) {
+                        cxt.volatile.insert(var);
+                    }
+                    cxt.visibility
+                        .insert(var, Visibility::from_pub(cxt.bindings.public(*s)));
+                    if cxt.bindings.final_(*s) {
+                        cxt.finals.insert(var);
+                    }
                     cxt.tys.insert(var, t);
                     mappings.push((var.0, cxt.bindings.sym_path(*s), !var.1));
                     vars.push(var);
@@ -190,15 +335,32 @@ pub fn declare_p2(code: Vec<Item>, cxt: &mut Cxt, out_class: &str) -> IRMod {
         if let Some(i) = inline {
             cxt.inline_fns.insert(item, i);
         }
+        if let Some(c) = cxt.bindings.static_class(name) {
+            let class = cxt.class(c).unwrap();
+            cxt.static_methods.insert(item, class);
+        }
+        if let Item::Fn(f) = i {
+            f.declare_defaults(cxt);
+        }
 
-        let mut ret = ret.lower(&cxt);
+        let mut ret = if ext {
+            cxt.extern_fns.insert(item);
+            ret.lower_unboxed(cxt)
+        } else {
+            ret.lower(cxt)
+        };
         // Try to convert certain types - for example, convert Java arrays to Bytec dynamic arrays
         if ext {
             if ret.len() > 1 {
                 let mut fixed = false;
                 if ret.len() == 2 {
                     let mut v = ret.to_vec();
-                    if let JTy::Array(_) = &v[0] {
+                    // Arrays of tuple objects aren't Java arrays that an extern function could return
+                    let java_array = match &v[0] {
+                        JTy::Array(t) => cxt.tuple_fields(t).is_none(),
+                        _ => false,
+                    };
+                    if java_array {
                         ret = JTys::One(v.swap_remove(0));
                         fixed = true;
                     } else {
@@ -206,7 +368,11 @@ pub fn declare_p2(code: Vec<Item>, cxt: &mut Cxt, out_class: &str) -> IRMod {
                     }
                 }
                 if !fixed {
-                    panic!("Extern function can't return tuple");
+                    let name = cxt.bindings.fn_name(name).stem();
+                    return Err(BackendError::ExternTupleReturn(
+                        cxt.bindings.resolve_raw(*name).to_string(),
+                        name.span,
+                    ));
                 }
             }
         }
@@ -214,22 +380,82 @@ pub fn declare_p2(code: Vec<Item>, cxt: &mut Cxt, out_class: &str) -> IRMod {
         mappings.push((item.0, m, !public));
     }
 
-    IRMod {
+    Ok(IRMod {
         name: cxt.bindings.raw(out_class),
         code,
         mappings,
         java,
         out_class: out_class.to_string(),
-    }
+    })
 }
 
 impl IRMod {
-    pub fn codegen<T>(&self, cxt: &mut Cxt, mods: &[(IRMod, T)]) -> String {
+    /// Lowers and optimizes this module's items into `cxt.items`.
+    /// Returns the names of everything visible from this module, and the string literals to hoist into constants.
+    fn lower<T>(
+        &self,
+        cxt: &mut Cxt,
+        mods: &[(IRMod, T)],
+    ) -> Result<(Names, Vec<(JVar, RawSym)>), BackendError> {
         for i in &self.code {
             i.lower(cxt);
         }
+        if let Some(e) = cxt.error.take() {
+            return Err(e);
+        }
 
         cxt.opt();
+        // Anything that optimization was supposed to resolve but couldn't can't be generated
+        let mut unresolved = Unresolved(None);
+        for item in &mut cxt.items {
+            item.map(&mut unresolved);
+        }
+        if let Some(e) = unresolved.0 {
+            return Err(e);
+        }
+        let strings = if cxt.intern_strings {
+            cxt.repeated_strings()
+        } else {
+            Vec::new()
+        };
+
+        // Tuple classes go in the first module that's generated after they're created
+        for (class, fields) in &cxt.tuple_classes {
+            if !cxt.tuple_modules.contains_key(class) {
+                cxt.tuple_modules.insert(*class, self.name);
+                cxt.items.push(JItem::Tuple(*class, fields.clone()));
+            }
+        }
+
+        // Likewise for the shared return fields, which are created for every function returning a tuple the first time
+        if cxt.share_returns {
+            let mut fns: Vec<_> = cxt
+                .fn_ret_tys
+                .iter()
+                .filter(|(f, t)| t.len() > 1 && !cxt.inline_fns.contains_key(f))
+                .map(|(f, t)| (*f, t.clone().to_vec()))
+                .collect();
+            fns.sort_by_key(|(f, _)| f.0);
+            for (_, tys) in fns {
+                if cxt.ret_pools.iter().all(|(t, _, _)| *t != tys) {
+                    // Packed booleans only need the one field
+                    let fields = if cxt.pack_bools && all_bools(&tys) {
+                        vec![JTy::I32]
+                    } else {
+                        tys.clone()
+                    };
+                    let vars: Vec<_> = fields.iter().map(|_| cxt.fresh_var(false)).collect();
+                    cxt.items.push(JItem::Let(
+                        vars.iter()
+                            .zip(&fields)
+                            .map(|(v, t)| (*v, t.clone(), None))
+                            .collect(),
+                        Vec::new(),
+                    ));
+                    cxt.ret_pools.push((tys, vars, self.name));
+                }
+            }
+        }
 
         let mut names = HashMap::new();
         // Declare items
@@ -242,8 +468,53 @@ impl IRMod {
                 names.insert(*i, (m, *b));
             }
         }
+        let tuple = cxt.bindings.raw("Tuple");
+        for (class, module) in &cxt.tuple_modules {
+            let mut path = RawPath(Vec::new(), Spanned::hack(tuple));
+            if *module != self.name {
+                path.0.push(Spanned::hack(*module));
+            }
+            names.insert(class.0, (path, true));
+        }
+        for (_, vars, module) in &cxt.ret_pools {
+            for (i, var) in vars.iter().enumerate() {
+                let raw = cxt.bindings.raw(format!("$_ret{}", i));
+                let mut path = RawPath(Vec::new(), Spanned::hack(raw));
+                if *module != self.name {
+                    path.0.push(Spanned::hack(*module));
+                }
+                names.insert(var.0, (path, true));
+            }
+        }
+        for (path, f) in &cxt.lazy_predefs {
+            names.insert(f.0, (lpath(Spanned::hack(*path)), false));
+        }
+        let str_raw = cxt.bindings.raw("$_str");
+        for (var, _) in &strings {
+            names.insert(var.0, (RawPath(Vec::new(), Spanned::hack(str_raw)), true));
+        }
+        Ok((names, strings))
+    }
+
+    pub fn codegen<T>(&self, cxt: &mut Cxt, mods: &[(IRMod, T)]) -> Result<String, BackendError> {
+        let (names, strings) = self.lower(cxt, mods)?;
         let mut gen = Gen::new(cxt.bindings);
         gen.names = names;
+        gen.volatile = cxt.volatile.clone();
+        gen.finals = cxt.finals.clone();
+        gen.visibility = cxt.visibility.clone();
+        gen.static_methods = cxt.static_methods.clone();
+        gen.enums = cxt.enums.clone();
+        gen.generic_classes = cxt.generic_classes.clone();
+        gen.array_init_cap = cxt.array_init_cap;
+        gen.ret_vars = cxt.ret_vars();
+        gen.pack_bools = cxt.pack_bools;
+        gen.indent_style = cxt.indent_style;
+        gen.max_indent = cxt.max_indent;
+        gen.minify = cxt.minify;
+        gen.separator = cxt.separator.clone();
+        gen.debug_comments = cxt.debug_comments;
+        gen.strings = strings.iter().map(|(v, s)| (*s, *v)).collect();
         // Generate items
         let mut s = String::new();
         write!(s, "package {};\n\n", cxt.package).unwrap();
@@ -253,19 +524,30 @@ impl IRMod {
             s.push('\n');
         }
         write!(s, "\npublic class {} {{\n\n", self.out_class).unwrap();
+        for (var, lit) in strings {
+            writeln!(
+                s,
+                "{}static final String {} = {};",
+                Visibility::Private.modifier(),
+                gen.name_str(var),
+                java_str(cxt.bindings.resolve_raw(lit))
+            )
+            .unwrap();
+        }
         for i in cxt.items.drain(..) {
             s.push_str(&i.gen(&mut gen));
         }
         s.push_str("\n}");
 
-        s
+        Ok(s)
     }
 }
 
 // Java AST
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
-/// bool: whether it's public, so mangling should be skipped
+/// bool: whether mangling should be skipped, which it is for `pub` bindings.
+/// The Java visibility of fields is tracked separately, in `Cxt::visibility`.
 struct JVar(u64, bool);
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 struct JFnId(u64);
@@ -276,10 +558,15 @@ struct JBlock(u64);
 
 #[derive(Copy, Clone, Debug, PartialEq)]
 enum JLit {
+    Byte(i8),
+    Short(i16),
     Int(i32),
     Long(i64),
+    Float(f32),
+    Double(f64),
     Str(RawSym),
     Bool(bool),
+    Char(u16),
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
@@ -291,6 +578,9 @@ enum Prop {
 #[derive(Clone, Debug, PartialEq)]
 enum JTerm {
     Var(JVar, JTy),
+    /// A numeric conversion, including for arithmetic on bytes and shorts which Java does with ints, or a class cast
+    Cast(Box<JTerm>, JTy),
+    InstanceOf(Box<JTerm>, JClass),
     Lit(JLit),
     Call(Option<Box<JTerm>>, JFnId, Vec<JTerm>, JTy),
     Prop(Box<JTerm>, Prop, JTy),
@@ -299,12 +589,25 @@ enum JTerm {
     Array(Vec<JTerm>, JTy),
     ArrayNew(Box<JTerm>, JTy),
     ClassNew(JClass, Vec<JTerm>),
+    /// `super(a, b)` at the start of a constructor, with the type of the superclass
+    Super(Vec<JTerm>, JTy),
     Index(Box<JTerm>, Box<JTerm>, JTy),
     SIndex(Vec<JTerm>, Box<JTerm>),
     Not(Box<JTerm>),
+    BitNot(Box<JTerm>),
+    Neg(Box<JTerm>),
+    // cond ? a : b
+    Ternary(Box<JTerm>, Box<JTerm>, Box<JTerm>),
     Null(JTy),
     This(JClass),
     InlineJava(RawSym, JTy),
+    /// (args, body, result, functional interface type), a Java lambda `(a, b) -> { body; return result; }`
+    Lambda(
+        Vec<(RawSym, JVar, JTy)>,
+        Vec<JStmt>,
+        Option<Box<JTerm>>,
+        JTy,
+    ),
 }
 impl JTerm {
     fn to_lval(self) -> Option<JLVal> {
@@ -331,9 +634,23 @@ enum JStmt {
     Set(JLVal, Option<BinOp>, JTerm),
     Term(JTerm),
     If(JTerm, Vec<JStmt>, Vec<JStmt>),
-    Switch(JBlock, JTerm, Vec<(RawSym, Vec<JStmt>)>, Vec<JStmt>),
+    Switch(JBlock, JTerm, Vec<(JCase, Vec<JStmt>)>, Vec<JStmt>),
     While(JBlock, JTerm, Vec<JStmt>),
-    RangeFor(JBlock, RawSym, JVar, JTerm, JTerm, Vec<JStmt>, bool),
+    /// `do { ... } while (cond);`, the condition is evaluated after the body
+    DoWhile(JBlock, Vec<JStmt>, JTerm),
+    /// RangeFor(label, name, var, start, end, step, comparison, body, unroll)
+    /// The `BinOp` is the comparison between the variable and the end, `<` or `<=`, or `>` or `>=` for negative steps
+    RangeFor(
+        JBlock,
+        RawSym,
+        JVar,
+        JTerm,
+        JTerm,
+        JTerm,
+        BinOp,
+        Vec<JStmt>,
+        bool,
+    ),
     Continue(JBlock),
     Break(JBlock),
     Ret(JFnId, Vec<JTerm>),
@@ -344,27 +661,113 @@ enum JStmt {
         Vec<(RawSym, JVar, JTy)>,
     ),
     InlineJava(RawSym),
+    /// throw new Exception(message)
+    ThrowNew(RawSym, JTerm),
+    /// throw e;
+    Throw(JTerm),
+    /// try { body } catch (Exception e) { handler }
+    Try(Vec<JStmt>, Vec<(JClass, RawSym, JVar, Vec<JStmt>)>),
     Multi(Vec<JStmt>),
+    /// `// file.bt:line`, pointing to the source of the next statement; only created with debug comments on
+    Src(FileId, Span),
+}
+
+/// A `case` label in a `switch`
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum JCase {
+    Variant(RawSym),
+    Int(i32),
 }
 
 #[derive(Clone, Debug, PartialEq)]
 enum JTy {
+    I8,
+    I16,
     I32,
     I64,
+    F32,
+    F64,
     Bool,
+    Char,
     String,
+    StrBuilder,
     Class(JClass),
     Array(Box<JTy>),
+    /// A type parameter of the generic method or class it's used in
+    TypeVar(RawSym),
+    /// A generic class with its type arguments
+    Generic(JClass, Vec<JTy>),
+    /// A `java.util.HashMap` with the key and value types
+    Map(Box<JTy>, Box<JTy>),
 }
 impl JTy {
     fn primitive(&self) -> bool {
         match self {
+            JTy::I8 => true,
+            JTy::I16 => true,
             JTy::I32 => true,
             JTy::I64 => true,
+            JTy::F32 => true,
+            JTy::F64 => true,
             JTy::Bool => true,
+            JTy::Char => true,
             JTy::String => false,
+            JTy::StrBuilder => false,
             JTy::Class(_) => false,
             JTy::Array(_) => false,
+            JTy::TypeVar(_) => false,
+            JTy::Generic(_, _) => false,
+            JTy::Map(_, _) => false,
+        }
+    }
+    fn has_var(&self) -> bool {
+        match self {
+            JTy::TypeVar(_) => true,
+            JTy::Array(t) => t.has_var(),
+            JTy::Generic(_, v) => v.iter().any(JTy::has_var),
+            JTy::Map(k, v) => k.has_var() || v.has_var(),
+            _ => false,
+        }
+    }
+    /// Replaces type variables with the types in `map`
+    fn subst(&self, map: &[(RawSym, JTy)]) -> JTy {
+        match self {
+            JTy::TypeVar(r) => match map.iter().find(|(r2, _)| r2 == r) {
+                Some((_, t)) => t.clone(),
+                None => self.clone(),
+            },
+            JTy::Array(t) => JTy::Array(Box::new(t.subst(map))),
+            JTy::Generic(c, v) => JTy::Generic(*c, v.iter().map(|t| t.subst(map)).collect()),
+            JTy::Map(k, v) => JTy::Map(Box::new(k.subst(map)), Box::new(v.subst(map))),
+            t => t.clone(),
+        }
+    }
+}
+
+/// The access modifier of a Java field or method
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+enum Visibility {
+    Public,
+    /// Java's default when there's no modifier, visible to the other classes in the package
+    Package,
+    Private,
+}
+impl Visibility {
+    /// `pub` items are public, and everything else is package-private so other modules can still use it
+    fn from_pub(public: bool) -> Visibility {
+        if public {
+            Visibility::Public
+        } else {
+            Visibility::Package
+        }
+    }
+
+    /// The modifier to put before a declaration, with a trailing space if there is one
+    fn modifier(self) -> &'static str {
+        match self {
+            Visibility::Public => "public ",
+            Visibility::Package => "",
+            Visibility::Private => "private ",
         }
     }
 }
@@ -373,11 +776,16 @@ impl JTy {
 struct JFn {
     name: RawSym,
     fn_id: JFnId,
+    type_params: Vec<RawSym>,
     ret_tys: Vec<JTy>,
     args: Vec<(RawSym, JVar, JTy)>,
     body: Vec<JStmt>,
-    public: bool,
+    visibility: Visibility,
+    is_static: bool,
     throws: Vec<RawSym>,
+    /// Whether the body is wrapped in a `try` that rethrows checked exceptions as `RuntimeException`
+    unchecked: bool,
+    annotations: Vec<RawSym>,
 }
 
 /// This only includes the items that actually need to appear in the Java code
@@ -386,11 +794,17 @@ struct JFn {
 enum JItem {
     Fn(JFn),
     Enum(JClass, Vec<(RawSym, Vec<JTy>)>, Option<JClass>, Vec<JFn>),
+    /// The last fields are the interfaces it implements and the class it extends
     Class(
         JClass,
         Vec<(Vec<(JVar, JTy, Option<JTerm>)>, Vec<JStmt>)>,
         Vec<JFn>,
+        Vec<JTy>,
+        Option<JTy>,
     ),
+    Interface(JClass, Vec<JFnSig>),
+    /// A class for the elements of an array of tuples, used when struct-of-arrays is disabled
+    Tuple(JClass, Vec<(RawSym, JTy)>),
     // Unlike in statement position, a let may end up running statements that are in its value term
     // So it needs a block, which is realized as a `static { ... }` in Java
     Let(Vec<(JVar, JTy, Option<JTerm>)>, Vec<JStmt>),
@@ -402,6 +816,9 @@ enum MaybeList<T> {
     Tuple(Vec<T>),
 }
 impl<T> MaybeList<T> {
+    /// The single object, for a value that's never a tuple.
+    /// The type checker rejects tuples, arrays and options anywhere this is used, like conditions,
+    /// map keys, type arguments and interface methods, and operators on tuples are reported with `as_one()` first.
     fn one(self) -> T {
         match self {
             MaybeList::One(t) => t,
@@ -409,12 +826,21 @@ impl<T> MaybeList<T> {
                 if v.len() == 1 {
                     v.pop().unwrap()
                 } else {
-                    panic!("backend: one object required, but got {}", v.len())
+                    unreachable!("backend: one object required, but got {}", v.len())
                 }
             }
         }
     }
 
+    /// Like `one()`, but by reference and returning `None` if there isn't exactly one object
+    fn as_one(&self) -> Option<&T> {
+        match self {
+            MaybeList::One(t) => Some(t),
+            MaybeList::Tuple(v) if v.len() == 1 => Some(&v[0]),
+            MaybeList::Tuple(_) => None,
+        }
+    }
+
     fn to_vec(self) -> Vec<T> {
         match self {
             MaybeList::One(t) => vec![t],
@@ -469,19 +895,99 @@ impl JTerms {
 
 // CODEGEN
 
+/// The bool is whether to mangle names for deduplication
+type Names = HashMap<u64, (RawPath, bool)>;
+/// An interface method's name, arguments and return type, which is `None` for unit
+type JFnSig = (RawSym, Vec<(RawSym, JTy)>, Option<JTy>);
+
 #[derive(Clone, Debug)]
 struct Gen<'a> {
     bindings: &'a Bindings,
-    /// The bool is whether to mangle names for deduplication
-    names: HashMap<u64, (RawPath, bool)>,
+    names: Names,
+    /// Fields declared `volatile`
+    volatile: HashSet<JVar>,
+    /// Variables declared `final`
+    finals: HashSet<JVar>,
+    /// The Java visibility of fields, which are package-private if they're not in here
+    visibility: HashMap<JVar, Visibility>,
+    /// The class each static method is called on
+    static_methods: HashMap<JFnId, JClass>,
+    /// String literals that have been hoisted into constants
+    strings: HashMap<RawSym, JVar>,
+    /// Enums without any data, which are compared with `==` instead of `equals()`
+    enums: HashSet<JClass>,
+    /// The type parameters of generic classes
+    generic_classes: HashMap<JClass, Vec<RawSym>>,
+    /// The capacity of empty dynamic arrays
+    array_init_cap: usize,
+    /// The shared static fields that functions returning tuples return into, if return fields are shared
+    ret_vars: HashMap<JFnId, Vec<JVar>>,
+    /// Whether tuples of booleans are returned as the bits of one `int`
+    pack_bools: bool,
     indent: usize,
+    indent_style: IndentStyle,
+    /// The deepest level that's indented, if there's a limit
+    max_indent: Option<usize>,
+    /// Whether to leave out indentation and newlines between statements
+    minify: bool,
+    /// What goes between a name and its number in mangled names
+    separator: String,
+    /// Whether to put comments pointing to the source before statements
+    debug_comments: bool,
 }
 impl<'a> Gen<'a> {
     fn new(bindings: &'a Bindings) -> Self {
         Gen {
             bindings,
             names: HashMap::new(),
+            volatile: HashSet::new(),
+            finals: HashSet::new(),
+            visibility: HashMap::new(),
+            static_methods: HashMap::new(),
+            strings: HashMap::new(),
+            enums: HashSet::new(),
+            generic_classes: HashMap::new(),
+            array_init_cap: 8,
+            ret_vars: HashMap::new(),
+            pack_bools: false,
             indent: 0,
+            indent_style: IndentStyle::Tabs,
+            max_indent: Some(5),
+            minify: false,
+            separator: "$".into(),
+            debug_comments: false,
+        }
+    }
+
+    fn volatile_str(&self, v: JVar) -> &'static str {
+        if self.volatile.contains(&v) {
+            "volatile "
+        } else {
+            ""
+        }
+    }
+
+    /// What goes before a call to `f` without an object, which is the class for static methods, like `Foo.`
+    fn static_prefix(&self, f: JFnId) -> String {
+        match self.static_methods.get(&f) {
+            Some(class) => format!("{}.", self.class_str(*class)),
+            None => String::new(),
+        }
+    }
+
+    fn visibility_str(&self, v: JVar) -> &'static str {
+        self.visibility
+            .get(&v)
+            .copied()
+            .unwrap_or(Visibility::Package)
+            .modifier()
+    }
+
+    fn final_str(&self, v: JVar) -> &'static str {
+        if self.finals.contains(&v) {
+            "final "
+        } else {
+            ""
         }
     }
 
@@ -491,10 +997,26 @@ impl<'a> Gen<'a> {
     fn pop(&mut self) {
         self.indent -= 1;
     }
-    fn indent(&self) -> &'static str {
-        // More than five indentation levels will make it harder to read rather than easier
-        let s = "\t\t\t\t\t";
-        &s[0..self.indent.min(s.len())]
+    fn indent(&self) -> String {
+        if self.minify {
+            return String::new();
+        }
+        let level = match self.max_indent {
+            Some(max) => self.indent.min(max),
+            None => self.indent,
+        };
+        match self.indent_style {
+            IndentStyle::Tabs => "\t".repeat(level),
+            IndentStyle::Spaces(n) => " ".repeat(n * level),
+        }
+    }
+    /// Starts a new line at the current indentation level, or nothing if minifying.
+    fn line(&self) -> String {
+        if self.minify {
+            String::new()
+        } else {
+            format!("\n{}", self.indent())
+        }
     }
 
     fn name_str(&self, v: JVar) -> String {
@@ -505,7 +1027,7 @@ impl<'a> Gen<'a> {
         // let (i, b) = &self.names[&v.0];
         let s = self.bindings.resolve_path(i);
         if *b {
-            format!("{}${}", s, v.0)
+            format!("{}{}{}", s, self.separator, v.0)
         } else {
             s.to_string()
         }
@@ -514,22 +1036,109 @@ impl<'a> Gen<'a> {
         let (i, b) = &self.names[&v.0];
         let s = self.bindings.resolve_path(i);
         if *b {
-            format!("{}${}", s, v.0)
+            format!("{}{}{}", s, self.separator, v.0)
         } else {
             s.to_string()
         }
     }
+    /// Whether a function returning these types returns them packed into the bits of one `int` field
+    fn packed(&self, tys: &[JTy]) -> bool {
+        self.pack_bools && all_bools(tys)
+    }
+
+    /// The static field that element `i` of the tuple returned by `f` is stored in
+    fn ret_str(&self, f: JFnId, i: usize) -> String {
+        match self.ret_vars.get(&f) {
+            Some(vars) => self.name_str(vars[i]),
+            None => format!(
+                "{}{sep}_ret{}{sep}S",
+                self.fn_str(f),
+                i,
+                sep = self.separator
+            ),
+        }
+    }
+    /// The `<T, U>` after the name of a generic class, or nothing if it isn't generic
+    fn type_params_str(&self, v: JClass) -> String {
+        match self.generic_classes.get(&v) {
+            Some(params) => format!(
+                "<{}>",
+                params
+                    .iter()
+                    .map(|r| self.bindings.resolve_raw(*r))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            None => String::new(),
+        }
+    }
     fn class_str(&self, v: JClass) -> String {
         let (i, b) = &self.names[&v.0];
         let s = self.bindings.resolve_path(i);
         if *b {
-            format!("{}${}", s, v.0)
+            format!("{}{}{}", s, self.separator, v.0)
         } else {
             s.to_string()
         }
     }
 }
 
+/// A Java string literal with the contents `s`.
+/// Control characters use octal escapes, since Java replaces `\\u` escapes before parsing, so `\\u000a` would end the line.
+fn java_str(s: &str) -> String {
+    let mut buf = String::with_capacity(s.len() + 2);
+    buf.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => buf.push_str("\\\""),
+            '\\' => buf.push_str("\\\\"),
+            '\n' => buf.push_str("\\n"),
+            '\t' => buf.push_str("\\t"),
+            '\r' => buf.push_str("\\r"),
+            // Always three digits, so a digit after it isn't part of the escape
+            c if c.is_control() => write!(buf, "\\{:03o}", c as u32).unwrap(),
+            // Keep the output ASCII, since javac doesn't always read source files as UTF-8
+            c if !c.is_ascii() => {
+                for u in c.encode_utf16(&mut [0; 2]) {
+                    write!(buf, "\\u{:04x}", u).unwrap();
+                }
+            }
+            c => buf.push(c),
+        }
+    }
+    buf.push('"');
+    buf
+}
+
+/// A Java char literal with the UTF-16 code unit `c`, escaped the same way as `java_str()`
+fn java_char(c: u16) -> String {
+    match char::from_u32(c as u32) {
+        Some('\'') => "'\\''".into(),
+        Some('\\') => "'\\\\'".into(),
+        Some('\n') => "'\\n'".into(),
+        Some('\t') => "'\\t'".into(),
+        Some('\r') => "'\\r'".into(),
+        Some(c) if c.is_ascii_control() => format!("'\\{:03o}'", c as u32),
+        Some(c) if c.is_ascii() => format!("'{}'", c),
+        // Including unpaired surrogates, which aren't Rust chars
+        _ => format!("'\\u{:04x}'", c),
+    }
+}
+
+/// Whether these return types are 2 to 32 booleans, which fit in the bits of an `int`
+fn all_bools(tys: &[JTy]) -> bool {
+    (2..=32).contains(&tys.len()) && tys.iter().all(|t| *t == JTy::Bool)
+}
+
+/// `new T[len]`, but with the length before any brackets in `T`, so an array of `int[]` is `new int[len][]`
+fn new_array(t: &JTy, len: &str, cxt: &Gen) -> String {
+    let t = t.gen(cxt);
+    match t.find('[') {
+        Some(i) => format!("new {}[{}]{}", &t[..i], len, &t[i..]),
+        None => format!("new {}[{}]", t, len),
+    }
+}
+
 impl Prop {
     fn gen(&self, cxt: &Gen) -> String {
         match self {
@@ -538,21 +1147,127 @@ impl Prop {
         }
     }
 }
+// Java operator precedence, for only parenthesizing terms where it's needed
+// Binary operators are in between, see `BinOp::prec()`
+const PREC_TERNARY: u8 = 3;
+const PREC_UNARY: u8 = 14;
+/// Variables, literals, calls, field accesses, and other terms that can be used before `.` or `[]`
+const PREC_POSTFIX: u8 = 15;
+
+impl BinOp {
+    /// How tightly this operator binds in Java, between `PREC_TERNARY` and `PREC_UNARY`
+    fn prec(self) -> u8 {
+        match self {
+            BinOp::Or => 4,
+            BinOp::And => 5,
+            BinOp::BitOr => 6,
+            BinOp::BitXor => 7,
+            BinOp::BitAnd => 8,
+            BinOp::Eq | BinOp::Neq => 9,
+            BinOp::Gt | BinOp::Lt | BinOp::Geq | BinOp::Leq => 10,
+            BinOp::BitShr | BinOp::BitShl | BinOp::BitUshr => 11,
+            BinOp::Add | BinOp::Sub => 12,
+            BinOp::Mul | BinOp::Div | BinOp::Mod => 13,
+        }
+    }
+}
+
 impl JTerm {
+    /// Whether comparing `a` and `b` uses `.equals()` instead of `==`, because they're objects other than enums
+    fn java_equals(a: &JTerm, b: &JTerm, cxt: &Gen) -> bool {
+        !a.ty().primitive()
+            && !matches!(a.ty(), JTy::Class(c) if cxt.enums.contains(&c))
+            && !matches!(a, JTerm::Null(_))
+            && !matches!(b, JTerm::Null(_))
+    }
+
+    /// How tightly this term binds when it's generated as Java
+    fn prec(&self, cxt: &Gen) -> u8 {
+        match self {
+            JTerm::Not(_) | JTerm::BitNot(_) | JTerm::Neg(_) | JTerm::Cast(_, _) => PREC_UNARY,
+            JTerm::Ternary(_, _, _) => PREC_TERNARY,
+            JTerm::InstanceOf(_, _) => BinOp::Lt.prec(),
+            // Negative literals start with `-`, and bytes and shorts are casts
+            JTerm::Lit(JLit::Byte(_) | JLit::Short(_)) => PREC_UNARY,
+            JTerm::Lit(JLit::Int(i)) if *i < 0 => PREC_UNARY,
+            JTerm::Lit(JLit::Long(i)) if *i < 0 => PREC_UNARY,
+            JTerm::Lit(JLit::Float(f)) if f.is_finite() && f.is_sign_negative() => PREC_UNARY,
+            JTerm::Lit(JLit::Double(d)) if d.is_finite() && d.is_sign_negative() => PREC_UNARY,
+            JTerm::BinOp(BinOp::Sub, a, _) if **a == JTerm::Lit(JLit::Int(0)) => PREC_UNARY,
+            // `!a.equals(b)`
+            JTerm::BinOp(BinOp::Neq, a, b) if JTerm::java_equals(a, b, cxt) => PREC_UNARY,
+            JTerm::BinOp(BinOp::Eq, a, b) if JTerm::java_equals(a, b, cxt) => PREC_POSTFIX,
+            JTerm::BinOp(op, _, _) => op.prec(),
+            // Array creation expressions can't be indexed without parentheses
+            JTerm::Array(_, _) | JTerm::ArrayNew(_, _) => PREC_UNARY,
+            JTerm::SIndex(arr, i) => match &**i {
+                JTerm::Lit(JLit::Int(i)) => arr[*i as usize].prec(cxt),
+                _ => PREC_POSTFIX,
+            },
+            // Inline Java could be anything
+            JTerm::InlineJava(_, _) => 0,
+            JTerm::Lambda(_, _, _, _) => 0,
+            _ => PREC_POSTFIX,
+        }
+    }
+
+    /// Generates this term, in parentheses if it binds looser than `prec`
+    fn gen_prec(&self, cxt: &Gen, prec: u8) -> String {
+        if self.prec(cxt) < prec {
+            format!("({})", self.gen(cxt))
+        } else {
+            self.gen(cxt)
+        }
+    }
+
     fn gen(&self, cxt: &Gen) -> String {
         match self {
-            JTerm::Not(x) => format!("!({})", x.gen(cxt)),
+            JTerm::Not(x) => format!("!{}", x.gen_prec(cxt, PREC_UNARY)),
+            JTerm::BitNot(x) => format!("~{}", x.gen_prec(cxt, PREC_UNARY)),
+            // `-(-x)` can't be written `--x`
+            JTerm::Neg(x) => format!("-{}", x.gen_prec(cxt, PREC_POSTFIX)),
+            JTerm::Ternary(c, a, b) => {
+                format!(
+                    "{} ? {} : {}",
+                    c.gen_prec(cxt, PREC_TERNARY + 1),
+                    a.gen_prec(cxt, PREC_TERNARY + 1),
+                    b.gen_prec(cxt, PREC_TERNARY + 1)
+                )
+            }
+            // `(T)-x` would be parsed as a subtraction if `T` is a class
+            JTerm::Cast(x, t) => format!("({}){}", t.gen(cxt), x.gen_prec(cxt, PREC_POSTFIX)),
+            JTerm::InstanceOf(x, c) => format!(
+                "{} instanceof {}",
+                x.gen_prec(cxt, BinOp::Lt.prec()),
+                cxt.class_str(*c)
+            ),
             JTerm::Var(v, _) => cxt.name_str(*v),
             JTerm::Null(_) => "null".to_string(),
             JTerm::This(_) => "this".to_string(),
             JTerm::Lit(l) => match l {
+                // Java int literals aren't implicitly narrowed when passed to methods
+                JLit::Byte(i) => format!("(byte){}", i),
+                JLit::Short(i) => format!("(short){}", i),
                 JLit::Int(i) => i.to_string(),
                 JLit::Long(i) => format!("{}L", i),
-                JLit::Str(s) => format!("\"{}\"", cxt.bindings.resolve_raw(*s)),
+                JLit::Float(f) if f.is_nan() => "Float.NaN".into(),
+                JLit::Float(f) if f.is_infinite() && *f > 0.0 => "Float.POSITIVE_INFINITY".into(),
+                JLit::Float(f) if f.is_infinite() => "Float.NEGATIVE_INFINITY".into(),
+                JLit::Float(f) => format!("{:?}f", f),
+                JLit::Double(d) if d.is_nan() => "Double.NaN".into(),
+                JLit::Double(d) if d.is_infinite() && *d > 0.0 => "Double.POSITIVE_INFINITY".into(),
+                JLit::Double(d) if d.is_infinite() => "Double.NEGATIVE_INFINITY".into(),
+                // Debug formatting always includes a decimal point or exponent, like Java needs
+                JLit::Double(d) => format!("{:?}", d),
+                JLit::Str(s) => match cxt.strings.get(s) {
+                    Some(v) => cxt.name_str(*v),
+                    None => java_str(cxt.bindings.resolve_raw(*s)),
+                },
                 JLit::Bool(b) => b.to_string(),
+                JLit::Char(c) => java_char(*c),
             },
             JTerm::Call(None, f, a, _) => {
-                let mut buf = String::new();
+                let mut buf = cxt.static_prefix(*f);
                 buf.push_str(&cxt.fn_str(*f));
                 buf.push('(');
 
@@ -570,7 +1285,7 @@ impl JTerm {
                 buf
             }
             JTerm::Call(Some(obj), f, a, _) => {
-                let mut buf = format!("({}).", obj.gen(cxt));
+                let mut buf = format!("{}.", obj.gen_prec(cxt, PREC_POSTFIX));
                 buf.push_str(&cxt.fn_str(*f));
                 buf.push('(');
 
@@ -588,28 +1303,26 @@ impl JTerm {
                 buf
             }
             JTerm::Prop(obj, prop, _) => {
-                format!("{}.{}", obj.gen(cxt), prop.gen(cxt))
+                format!("{}.{}", obj.gen_prec(cxt, PREC_POSTFIX), prop.gen(cxt))
             }
             JTerm::BinOp(BinOp::Sub, a, b) if **a == JTerm::Lit(JLit::Int(0)) => {
-                format!("(-{})", b.gen(cxt))
+                format!("-{}", b.gen_prec(cxt, PREC_POSTFIX))
             }
-            JTerm::BinOp(op @ (BinOp::Eq | BinOp::Neq), a, b)
-                if !a.ty().primitive()
-                    && !matches!(&**a, JTerm::Null(_))
-                    && !matches!(&**b, JTerm::Null(_)) =>
-            {
+            JTerm::BinOp(op @ (BinOp::Eq | BinOp::Neq), a, b) if JTerm::java_equals(a, b, cxt) => {
                 let mut buf = String::new();
                 if *op == BinOp::Neq {
                     buf.push('!');
                 }
-                write!(buf, "({}).equals({})", a.gen(cxt), b.gen(cxt)).unwrap();
+                let a = a.gen_prec(cxt, PREC_POSTFIX);
+                write!(buf, "{}.equals({})", a, b.gen(cxt)).unwrap();
                 buf
             }
             JTerm::BinOp(op, a, b) => {
+                // Operators are left-associative, so an operand on the right with the same precedence needs parentheses
                 let mut buf = String::new();
-                write!(buf, "({}) ", a.gen(cxt)).unwrap();
+                write!(buf, "{} ", a.gen_prec(cxt, op.prec())).unwrap();
                 buf.push_str(op.repr());
-                write!(buf, " ({})", b.gen(cxt)).unwrap();
+                write!(buf, " {}", b.gen_prec(cxt, op.prec() + 1)).unwrap();
                 buf
             }
             JTerm::Variant(class, variant) => {
@@ -619,14 +1332,14 @@ impl JTerm {
                     cxt.bindings.resolve_raw(*variant)
                 )
             }
-            // We expand capacity by doubling, so don't allow creating an array with 0 capacity
-            // Instead, an empty array starts with 8 capacity
+            // We expand capacity by multiplying it, so don't allow creating an array with 0 capacity
+            // Instead, an empty array starts with the default capacity
             JTerm::Array(v, t) if v.is_empty() => {
                 let t = match t {
                     JTy::Array(t) => &**t,
                     _ => unreachable!(),
                 };
-                format!("new {}[8]", t.gen(cxt))
+                new_array(t, &cxt.array_init_cap.to_string(), cxt)
             }
             JTerm::Array(v, t) => {
                 let mut buf = format!("new {}{{ ", t.gen(cxt));
@@ -642,11 +1355,15 @@ impl JTerm {
                     JTy::Array(t) => &**t,
                     _ => unreachable!(),
                 };
-                format!("new {}[{}]", t.gen(cxt), len.gen(cxt))
+                new_array(t, &len.gen(cxt), cxt)
             }
             JTerm::ClassNew(class, a) => {
                 let mut buf = "new ".to_string();
                 buf.push_str(&cxt.class_str(*class));
+                // Java infers the type arguments
+                if cxt.generic_classes.contains_key(class) {
+                    buf.push_str("<>");
+                }
                 buf.push('(');
 
                 let mut first = true;
@@ -662,14 +1379,43 @@ impl JTerm {
 
                 buf
             }
+            JTerm::Super(a, _) => {
+                let a: Vec<_> = a.iter().map(|x| x.gen(cxt)).collect();
+                format!("super({})", a.join(", "))
+            }
             JTerm::Index(arr, i, _) => {
-                format!("{}[{}]", arr.gen(cxt), i.gen(cxt))
+                format!("{}[{}]", arr.gen_prec(cxt, PREC_POSTFIX), i.gen(cxt))
             }
             JTerm::SIndex(arr, i) => match &**i {
                 JTerm::Lit(JLit::Int(i)) => arr[*i as usize].gen(cxt),
-                _ => panic!("expected literal in static index, got {}", i.gen(cxt)),
+                // `Unresolved` reports any other index as `BackendError::StaticIndex` before codegen
+                _ => unreachable!("expected literal in static index, got {}", i.gen(cxt)),
             },
             JTerm::InlineJava(raw, _) => cxt.bindings.resolve_raw(*raw).to_string(),
+            JTerm::Lambda(args, body, x, _) => {
+                // The names in the body are only needed while generating it
+                let mut cxt = cxt.clone();
+                for (n, v, _) in args {
+                    cxt.names.insert(v.0, (lpath(Spanned::hack(*n)), !v.1));
+                }
+                let args: Vec<_> = args.iter().map(|(_, v, _)| cxt.name_str(*v)).collect();
+                let args = args.join(", ");
+                if let (true, Some(x)) = (body.is_empty(), x) {
+                    return format!("({}) -> {}", args, x.gen(&cxt));
+                }
+                let mut s = format!("({}) -> {{", args);
+                cxt.push();
+                for i in body {
+                    s.push_str(&i.gen(&mut cxt));
+                }
+                if let Some(x) = x {
+                    write!(s, "{}return {};", cxt.line(), x.gen(&cxt)).unwrap();
+                }
+                cxt.pop();
+                s.push_str(&cxt.line());
+                s.push('}');
+                s
+            }
         }
     }
 }
@@ -680,9 +1426,10 @@ impl JLVal {
             JLVal::Idx(l, i) => format!("{}[{}]", l.gen(cxt), i.gen(cxt)),
             JLVal::SIdx(arr, i) => match i {
                 JTerm::Lit(JLit::Int(i)) => arr[*i as usize].gen(cxt),
-                _ => panic!("expected literal in static index set, got {:?}", i),
+                // `Unresolved` reports any other index as `BackendError::StaticIndex` before codegen
+                _ => unreachable!("expected literal in static index set, got {:?}", i),
             },
-            JLVal::Prop(a, b) => format!("{}.{}", a.gen(cxt), b.gen(cxt)),
+            JLVal::Prop(a, b) => format!("{}.{}", a.gen_prec(cxt, PREC_POSTFIX), b.gen(cxt)),
         }
     }
 }
@@ -699,8 +1446,8 @@ impl JStmt {
             JStmt::Let(n, t, v, None) => {
                 cxt.names.insert(v.0, (lpath(Spanned::hack(*n)), !v.1));
                 format!(
-                    "\n{}{} {} = {};",
-                    cxt.indent(),
+                    "{}{} {} = {};",
+                    cxt.line(),
                     t.gen(cxt),
                     cxt.name_str(*v),
                     t.null()
@@ -709,8 +1456,9 @@ impl JStmt {
             JStmt::Let(n, t, v, Some(x)) => {
                 cxt.names.insert(v.0, (lpath(Spanned::hack(*n)), !v.1));
                 format!(
-                    "\n{}{} {} = {};",
-                    cxt.indent(),
+                    "{}{}{} {} = {};",
+                    cxt.line(),
+                    cxt.final_str(*v),
                     t.gen(cxt),
                     cxt.name_str(*v),
                     x.gen(cxt)
@@ -718,35 +1466,51 @@ impl JStmt {
             }
             JStmt::Set(v, op, x) => {
                 format!(
-                    "\n{}{} {}= {};",
-                    cxt.indent(),
+                    "{}{} {}= {};",
+                    cxt.line(),
                     v.gen(cxt),
                     op.map_or("", |op| op.repr()),
                     x.gen(cxt)
                 )
             }
             JStmt::Term(x) => {
-                let mut s = x.gen(cxt);
-                s.push(';');
-                s
+                // A cast isn't a valid statement in Java, and doesn't do anything on its own
+                let mut x = x;
+                while let JTerm::Cast(y, _) = x {
+                    x = y;
+                }
+                format!("{}{};", cxt.line(), x.gen(cxt))
             }
             JStmt::While(k, cond, block) => {
-                let mut s = format!("\n{}b${}: while ({}) {{", cxt.indent(), k.0, cond.gen(cxt));
+                let mut s = format!("{}b${}: while ({}) {{", cxt.line(), k.0, cond.gen(cxt));
                 cxt.push();
                 for i in block {
                     s.push_str(&i.gen(cxt));
                 }
                 cxt.pop();
 
-                s.push('\n');
-                s.push_str(cxt.indent());
+                s.push_str(&cxt.line());
                 s.push('}');
 
                 s
             }
-            JStmt::RangeFor(k, n, var, a, b, block, unroll) => {
+            JStmt::DoWhile(k, block, cond) => {
+                let mut s = format!("{}b${}: do {{", cxt.line(), k.0);
+                cxt.push();
+                for i in block {
+                    s.push_str(&i.gen(cxt));
+                }
+                cxt.pop();
+
+                s.push_str(&cxt.line());
+                write!(s, "}} while ({});", cond.gen(cxt)).unwrap();
+
+                s
+            }
+            JStmt::RangeFor(k, n, var, a, b, step, op, block, unroll) => {
+                // Optimization unrolls the loop if it can, and `Unresolved` reports it as `BackendError::CantUnroll` if not
                 if *unroll {
-                    panic!(
+                    unreachable!(
                         "Couldn't unroll range-for loop! Ends are {:?} .. {:?}",
                         a, b
                     );
@@ -754,18 +1518,88 @@ impl JStmt {
 
                 cxt.names.insert(var.0, (lpath(Spanned::hack(*n)), !var.1));
                 let i = cxt.name_str(*var);
-                let mut s = format!(
-                    "\n{}b${}: for (int {} = {}, $end_{} = {}; {} < $end_{}; {}++) {{",
-                    cxt.indent(),
-                    k.0,
-                    i,
-                    a.gen(cxt),
-                    k.0,
-                    b.gen(cxt),
-                    i,
-                    k.0,
-                    i
-                );
+                let invariant = b.invariant(block)
+                    && !matches!(b, JTerm::Var(v, _) if cxt.volatile.contains(v));
+                let update = match step {
+                    JTerm::Lit(JLit::Int(1)) => format!("{}++", i),
+                    JTerm::Lit(JLit::Int(-1)) => format!("{}--", i),
+                    JTerm::Lit(JLit::Int(x)) if *x < 0 && *x != i32::MIN => {
+                        format!("{} -= {}", i, -x)
+                    }
+                    _ => format!("{} += {}", i, step.gen(cxt)),
+                };
+                // An inclusive range can't stop by going past an end of the largest or smallest int, and an exclusive
+                // range with a bigger step could wrap around past it, so unless the end is a literal that leaves room for
+                // the step, whether there's another iteration is tested before incrementing
+                let overflows = match (op, b, step) {
+                    // The variable stops one before the end, so adding one more can't overflow
+                    (BinOp::Lt | BinOp::Gt, _, JTerm::Lit(JLit::Int(1 | -1))) => false,
+                    (BinOp::Lt | BinOp::Gt, JTerm::Lit(JLit::Int(b)), JTerm::Lit(JLit::Int(s))) => {
+                        b.checked_sub(s.signum())
+                            .and_then(|b| b.checked_add(*s))
+                            .is_none()
+                    }
+                    (_, JTerm::Lit(JLit::Int(b)), JTerm::Lit(JLit::Int(s))) => {
+                        b.checked_add(*s).is_none()
+                    }
+                    _ => true,
+                };
+                let mut s = if overflows {
+                    let next = match step {
+                        JTerm::Lit(JLit::Int(1 | -1)) => format!("{} != $end_{}", i, k.0),
+                        _ => format!(
+                            "(long){} + {} {} $end_{}",
+                            i,
+                            step.gen_prec(cxt, BinOp::Add.prec() + 1),
+                            op.repr(),
+                            k.0
+                        ),
+                    };
+                    format!(
+                        "{}b${}: for (int {} = {}, $end_{} = {}, $more_{} = {} {} $end_{} ? 1 : 0; $more_{} != 0; $more_{} = {} ? 1 : 0, {}) {{",
+                        cxt.line(),
+                        k.0,
+                        i,
+                        a.gen(cxt),
+                        k.0,
+                        b.gen(cxt),
+                        k.0,
+                        i,
+                        op.repr(),
+                        k.0,
+                        k.0,
+                        k.0,
+                        next,
+                        update
+                    )
+                } else if invariant {
+                    // The end can be read again each iteration instead of storing it in `$end_k`
+                    format!(
+                        "{}b${}: for (int {} = {}; {} {} {}; {}) {{",
+                        cxt.line(),
+                        k.0,
+                        i,
+                        a.gen(cxt),
+                        i,
+                        op.repr(),
+                        b.gen_prec(cxt, op.prec() + 1),
+                        update
+                    )
+                } else {
+                    format!(
+                        "{}b${}: for (int {} = {}, $end_{} = {}; {} {} $end_{}; {}) {{",
+                        cxt.line(),
+                        k.0,
+                        i,
+                        a.gen(cxt),
+                        k.0,
+                        b.gen(cxt),
+                        i,
+                        op.repr(),
+                        k.0,
+                        update
+                    )
+                };
 
                 cxt.push();
                 for i in block {
@@ -773,45 +1607,69 @@ impl JStmt {
                 }
                 cxt.pop();
 
-                s.push('\n');
-                s.push_str(cxt.indent());
+                s.push_str(&cxt.line());
                 s.push('}');
 
                 s
             }
-            JStmt::Continue(k) => format!("\n{}continue b${};", cxt.indent(), k.0),
-            JStmt::Break(k) => format!("\n{}break b${};", cxt.indent(), k.0),
-            JStmt::Ret(_, v) if v.is_empty() => format!("\n{}return;", cxt.indent()),
+            JStmt::Continue(k) => format!("{}continue b${};", cxt.line(), k.0),
+            JStmt::Break(k) => format!("{}break b${};", cxt.line(), k.0),
+            JStmt::Ret(_, v) if v.is_empty() => format!("{}return;", cxt.line()),
             JStmt::Ret(_, v) if v.len() == 1 => {
-                format!("\n{}return {};", cxt.indent(), v[0].gen(cxt))
+                format!("{}return {};", cxt.line(), v[0].gen(cxt))
+            }
+            JStmt::Ret(f, v) if cxt.packed(&v.iter().map(JTerm::ty).collect::<Vec<_>>()) => {
+                // f$_ret0$S = (a ? 1 : 0) | (b ? 2 : 0) | ...;
+                // Constant bits can be left out or written directly
+                let bits = v.iter().enumerate().filter_map(|(i, x)| match x {
+                    JTerm::Lit(JLit::Bool(false)) => None,
+                    JTerm::Lit(JLit::Bool(true)) => Some(JTerm::Lit(JLit::Int(1 << i))),
+                    x => Some(JTerm::Ternary(
+                        Box::new(x.clone()),
+                        Box::new(JTerm::Lit(JLit::Int(1 << i))),
+                        Box::new(JTerm::Lit(JLit::Int(0))),
+                    )),
+                });
+                let packed = bits
+                    .reduce(|a, b| JTerm::BinOp(BinOp::BitOr, Box::new(a), Box::new(b)))
+                    .unwrap_or(JTerm::Lit(JLit::Int(0)));
+                format!(
+                    "{}{} = {};{}return;",
+                    cxt.line(),
+                    cxt.ret_str(*f, 0),
+                    packed.gen(cxt),
+                    cxt.line()
+                )
             }
             JStmt::Ret(f, v) => {
                 let mut s = String::new();
 
                 for (i, t) in v.iter().enumerate() {
-                    s.push('\n');
-                    s.push_str(cxt.indent());
-                    write!(s, "{}$_ret{}$S = {};", cxt.fn_str(*f), i, t.gen(cxt)).unwrap();
+                    s.push_str(&cxt.line());
+                    write!(s, "{} = {};", cxt.ret_str(*f, i), t.gen(cxt)).unwrap();
                 }
 
-                s.push('\n');
-                s.push_str(cxt.indent());
+                s.push_str(&cxt.line());
                 s.push_str("return;");
                 s
             }
             JStmt::If(cond, a, b) => {
-                let mut s = format!("\n{}if ({}) {{", cxt.indent(), cond.gen(cxt));
+                let mut s = format!("{}if ({}) {{", cxt.line(), cond.gen(cxt));
                 cxt.push();
                 for i in a {
                     s.push_str(&i.gen(cxt));
                 }
                 cxt.pop();
 
-                s.push('\n');
-                s.push_str(cxt.indent());
+                s.push_str(&cxt.line());
                 s.push('}');
 
-                if !b.is_empty() {
+                let mut rest = b.iter().filter(|x| !x.is_empty());
+                if let (Some(x @ JStmt::If(_, _, _)), None) = (rest.next(), rest.next()) {
+                    // An `else` with just an `if` in it becomes `else if`, at the same indentation level
+                    s.push_str(" else ");
+                    s.push_str(x.gen(cxt).trim_start());
+                } else if !b.is_empty() {
                     cxt.push();
 
                     s.push_str(" else {");
@@ -820,35 +1678,37 @@ impl JStmt {
                     }
                     cxt.pop();
 
-                    s.push('\n');
-                    s.push_str(cxt.indent());
+                    s.push_str(&cxt.line());
                     s.push('}');
                 }
 
                 s
             }
             JStmt::Switch(k, x, branches, default) => {
-                let mut s = format!("\n{}b${}: switch ({}) {{", cxt.indent(), k.0, x.gen(cxt));
-                for (sym, block) in branches {
+                let mut s = format!("{}b${}: switch ({}) {{", cxt.line(), k.0, x.gen(cxt));
+                for (case, block) in branches {
                     // case Variant:
-                    s.push('\n');
-                    s.push_str(cxt.indent());
+                    s.push_str(&cxt.line());
                     s.push_str("case ");
-                    s.push_str(cxt.bindings.resolve_raw(*sym));
+                    match case {
+                        JCase::Variant(sym) => s.push_str(cxt.bindings.resolve_raw(*sym)),
+                        JCase::Int(i) => write!(s, "{}", i).unwrap(),
+                    }
                     s.push(':');
 
                     cxt.push();
                     for i in block {
                         s.push_str(&i.gen(cxt));
                     }
-                    s.push('\n');
-                    s.push_str(cxt.indent());
-                    write!(s, "break b${};", k.0).unwrap();
+                    // Java rejects a `break` after a `return` etc. as unreachable
+                    if !matches!(block.last(), Some(s) if s.terminates()) {
+                        s.push_str(&cxt.line());
+                        write!(s, "break b${};", k.0).unwrap();
+                    }
                     cxt.pop();
                 }
 
-                s.push('\n');
-                s.push_str(cxt.indent());
+                s.push_str(&cxt.line());
                 s.push_str("default:");
                 cxt.push();
                 for i in default {
@@ -856,8 +1716,7 @@ impl JStmt {
                 }
                 cxt.pop();
 
-                s.push('\n');
-                s.push_str(cxt.indent());
+                s.push_str(&cxt.line());
                 s.push('}');
 
                 s
@@ -866,12 +1725,12 @@ impl JStmt {
                 let buf = o
                     .as_ref()
                     .map(|x| {
-                        let mut s = x.gen(cxt);
+                        let mut s = x.gen_prec(cxt, PREC_POSTFIX);
                         s.push('.');
                         s
                     })
-                    .unwrap_or_default();
-                let mut buf = format!("\n{}{}", cxt.indent(), buf);
+                    .unwrap_or_else(|| cxt.static_prefix(*f));
+                let mut buf = format!("{}{}", cxt.line(), buf);
                 buf.push_str(&cxt.fn_str(*f));
                 buf.push('(');
 
@@ -886,91 +1745,244 @@ impl JStmt {
                 }
                 buf.push_str(");");
 
+                let tys: Vec<_> = rets.iter().map(|(_, _, t)| t.clone()).collect();
+                let packed = cxt.packed(&tys);
                 for (i, (raw, v, t)) in rets.iter().enumerate() {
                     cxt.names.insert(v.0, (lpath(Spanned::hack(*raw)), !v.1));
+                    // Shared return fields are module-level, but a method's own fields are on its class
+                    let class = o
+                        .as_ref()
+                        .filter(|_| !cxt.ret_vars.contains_key(f))
+                        .map(|o| {
+                            let ty = o.ty();
+                            let mut s = match ty {
+                                JTy::Class(class) | JTy::Generic(class, _) => cxt.class_str(class),
+                                _ => unreachable!(),
+                            };
+                            s.push('.');
+                            s
+                        })
+                        .unwrap_or_else(|| {
+                            if cxt.ret_vars.contains_key(f) {
+                                String::new()
+                            } else {
+                                cxt.static_prefix(*f)
+                            }
+                        });
+                    let field = if packed {
+                        format!("({}{} & {}) != 0", class, cxt.ret_str(*f, 0), 1 << i)
+                    } else {
+                        format!("{}{}", class, cxt.ret_str(*f, i))
+                    };
                     write!(
                         buf,
-                        "\n{}{} {} = {}{}$_ret{}$S;",
-                        cxt.indent(),
+                        "{}{} {} = {};",
+                        cxt.line(),
                         t.gen(cxt),
                         cxt.name_str(*v),
-                        o.as_ref()
-                            .map(|o| {
-                                let ty = o.ty();
-                                let mut s = match ty {
-                                    JTy::Class(class) => cxt.class_str(class),
-                                    _ => unreachable!(),
-                                };
-                                s.push('.');
-                                s
-                            })
-                            .unwrap_or(String::new()),
-                        cxt.fn_str(*f),
-                        i
+                        field
                     )
                     .unwrap();
                 }
 
                 buf
             }
-            JStmt::InlineJava(s) => format!(
-                "\n{}{}",
-                cxt.indent(),
-                cxt.bindings.resolve_raw(*s).to_string()
-            ),
-        }
-    }
-}
-impl JTy {
-    fn gen(&self, cxt: &Gen) -> String {
-        match self {
-            JTy::I32 => "int".into(),
-            JTy::I64 => "long".into(),
-            JTy::Bool => "boolean".into(),
-            JTy::String => "String".into(),
-            JTy::Class(c) => cxt.class_str(*c),
-            JTy::Array(t) => {
-                let mut s = t.gen(cxt);
-                s.push_str("[]");
+            JStmt::InlineJava(s) => {
+                let mut s = format!("{}{}", cxt.line(), cxt.bindings.resolve_raw(*s));
+                // Inline Java could end in a `//` comment, which would swallow the rest of the line
+                if cxt.minify {
+                    s.push('\n');
+                }
                 s
             }
-        }
-    }
-    fn null(&self) -> &'static str {
+            JStmt::ThrowNew(e, msg) => format!(
+                "{}throw new {}({});",
+                cxt.line(),
+                cxt.bindings.resolve_raw(*e),
+                msg.gen(cxt)
+            ),
+            JStmt::Throw(x) => format!("{}throw {};", cxt.line(), x.gen(cxt)),
+            JStmt::Src(file, span) => {
+                if !cxt.debug_comments {
+                    return String::new();
+                }
+                let source = INPUT_SOURCE.read().unwrap();
+                let line = source[file][..span.0].matches('\n').count() + 1;
+                let path = INPUT_PATH.read().unwrap();
+                let name = path[file].file_name().unwrap().to_string_lossy();
+                let mut s = format!("{}// {}:{}", cxt.line(), name, line);
+                if cxt.minify {
+                    s.push('\n');
+                }
+                s
+            }
+            JStmt::Try(body, handlers) => {
+                let mut s = format!("{}try {{", cxt.line());
+                cxt.push();
+                for i in body {
+                    s.push_str(&i.gen(cxt));
+                }
+                cxt.pop();
+                for (class, n, var, block) in handlers {
+                    cxt.names.insert(var.0, (lpath(Spanned::hack(*n)), !var.1));
+                    s.push_str(&cxt.line());
+                    write!(
+                        s,
+                        "}} catch ({} {}) {{",
+                        cxt.class_str(*class),
+                        cxt.name_str(*var)
+                    )
+                    .unwrap();
+                    cxt.push();
+                    for i in block {
+                        s.push_str(&i.gen(cxt));
+                    }
+                    cxt.pop();
+                }
+
+                s.push_str(&cxt.line());
+                s.push('}');
+
+                s
+            }
+        }
+    }
+}
+impl JTy {
+    fn gen(&self, cxt: &Gen) -> String {
+        match self {
+            JTy::I8 => "byte".into(),
+            JTy::I16 => "short".into(),
+            JTy::I32 => "int".into(),
+            JTy::I64 => "long".into(),
+            JTy::F32 => "float".into(),
+            JTy::F64 => "double".into(),
+            JTy::Bool => "boolean".into(),
+            JTy::Char => "char".into(),
+            JTy::String => "String".into(),
+            JTy::StrBuilder => "StringBuilder".into(),
+            JTy::Class(c) => cxt.class_str(*c),
+            JTy::Array(t) => {
+                let mut s = t.gen(cxt);
+                s.push_str("[]");
+                s
+            }
+            JTy::TypeVar(r) => cxt.bindings.resolve_raw(*r).into(),
+            JTy::Generic(c, v) => format!(
+                "{}<{}>",
+                cxt.class_str(*c),
+                v.iter()
+                    .map(|t| t.gen_boxed(cxt))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            JTy::Map(k, v) => format!(
+                "java.util.HashMap<{}, {}>",
+                k.gen_boxed(cxt),
+                v.gen_boxed(cxt)
+            ),
+        }
+    }
+    /// Like `gen()`, but primitives are their boxed classes, for use as type arguments
+    fn gen_boxed(&self, cxt: &Gen) -> String {
+        match self {
+            JTy::I8 => "Byte".into(),
+            JTy::I16 => "Short".into(),
+            JTy::I32 => "Integer".into(),
+            JTy::I64 => "Long".into(),
+            JTy::F32 => "Float".into(),
+            JTy::F64 => "Double".into(),
+            JTy::Bool => "Boolean".into(),
+            JTy::Char => "Character".into(),
+            t => t.gen(cxt),
+        }
+    }
+    /// The zero value of this type as a term, the same as `null()`
+    fn zero(&self) -> JTerm {
+        match self {
+            JTy::I8 => JTerm::Lit(JLit::Byte(0)),
+            JTy::I16 => JTerm::Lit(JLit::Short(0)),
+            JTy::I32 => JTerm::Lit(JLit::Int(0)),
+            JTy::I64 => JTerm::Lit(JLit::Long(0)),
+            JTy::F32 => JTerm::Lit(JLit::Float(0.0)),
+            JTy::F64 => JTerm::Lit(JLit::Double(0.0)),
+            JTy::Bool => JTerm::Lit(JLit::Bool(false)),
+            JTy::Char => JTerm::Lit(JLit::Char(0)),
+            t => JTerm::Null(t.clone()),
+        }
+    }
+    fn null(&self) -> &'static str {
         match self {
+            JTy::I8 => "0",
+            JTy::I16 => "0",
             JTy::I32 => "0",
             JTy::I64 => "0L",
+            JTy::F32 => "0.0f",
+            JTy::F64 => "0.0",
             JTy::Bool => "false",
+            JTy::Char => "'\\0'",
             JTy::String => "null",
+            JTy::StrBuilder => "null",
             JTy::Class(_) => "null",
             JTy::Array(_) => "null",
+            JTy::TypeVar(_) => "null",
+            JTy::Generic(_, _) => "null",
+            JTy::Map(_, _) => "null",
         }
     }
 }
 impl JFn {
-    fn gen(&self, cxt: &mut Gen, is_static: bool) -> String {
+    fn gen(&self, cxt: &mut Gen) -> String {
         let mut buf = String::new();
 
-        if self.ret_tys.len() != 1 {
+        if cxt.ret_vars.contains_key(&self.fn_id) {
+            // The return fields are shared and declared at module level
+        } else if cxt.packed(&self.ret_tys) {
+            write!(
+                buf,
+                "{}static int {};{}",
+                self.visibility.modifier(),
+                cxt.ret_str(self.fn_id, 0),
+                cxt.line(),
+            )
+            .unwrap();
+        } else if self.ret_tys.len() != 1 {
             // Generate static variables to return tuples into
             // This uses a little bit less bytecode than using e.g. custom classes
             for (i, ty) in self.ret_tys.iter().enumerate() {
                 write!(
                     buf,
-                    "public static {} {}$_ret{}$S;\n{}",
+                    "{}static {} {};{}",
+                    self.visibility.modifier(),
                     ty.gen(cxt),
-                    cxt.fn_str(self.fn_id),
-                    i,
-                    cxt.indent(),
+                    cxt.ret_str(self.fn_id, i),
+                    cxt.line(),
                 )
                 .unwrap();
             }
         }
 
+        for a in &self.annotations {
+            buf.push('@');
+            buf.push_str(cxt.bindings.resolve_raw(*a));
+            buf.push_str(if cxt.minify { " " } else { "" });
+            buf.push_str(&cxt.line());
+        }
         write!(
             buf,
-            "public {}{} {}(",
-            if is_static { "static " } else { "" },
+            "{}{}{}{} {}(",
+            self.visibility.modifier(),
+            if self.is_static { "static " } else { "" },
+            if self.type_params.is_empty() {
+                String::new()
+            } else {
+                let params: Vec<_> = self
+                    .type_params
+                    .iter()
+                    .map(|r| cxt.bindings.resolve_raw(*r))
+                    .collect();
+                format!("<{}> ", params.join(", "))
+            },
             if self.ret_tys.len() == 1 {
                 self.ret_tys[0].gen(cxt)
             } else {
@@ -1005,80 +2017,111 @@ impl JFn {
         buf.push_str(" {");
 
         cxt.push();
+        if self.unchecked {
+            buf.push_str(&cxt.line());
+            buf.push_str("try {");
+            cxt.push();
+        }
 
         for i in &self.body {
-            buf.push('\n');
-            buf.push_str(cxt.indent());
+            buf.push_str(&cxt.line());
             buf.push_str(&i.gen(cxt));
         }
 
+        if self.unchecked {
+            cxt.pop();
+            buf.push_str(&cxt.line());
+            // Runtime exceptions are passed through as-is, so they don't get wrapped more than once
+            buf.push_str("} catch (RuntimeException e) {");
+            cxt.push();
+            buf.push_str(&cxt.line());
+            buf.push_str("throw e;");
+            cxt.pop();
+            buf.push_str(&cxt.line());
+            buf.push_str("} catch (Exception e) {");
+            cxt.push();
+            buf.push_str(&cxt.line());
+            buf.push_str("throw new RuntimeException(e);");
+            cxt.pop();
+            buf.push_str(&cxt.line());
+            buf.push('}');
+        }
+
         cxt.names = names;
         cxt.pop();
 
-        buf.push('\n');
-        buf.push_str(cxt.indent());
-        buf.push_str("}\n");
-        buf.push_str(cxt.indent());
+        buf.push_str(&cxt.line());
+        buf.push('}');
+        buf.push_str(&cxt.line());
         buf
     }
 }
 impl JItem {
     fn gen(&self, cxt: &mut Gen) -> String {
         match self {
-            JItem::Fn(f) => f.gen(cxt, true),
-            JItem::Class(tid, members, methods) => {
+            JItem::Fn(f) => f.gen(cxt),
+            JItem::Class(tid, members, methods, implements, extends) => {
                 let mut buf = String::new();
 
-                write!(buf, "public static class {} {{", cxt.class_str(*tid)).unwrap();
+                let implements: Vec<_> = implements.iter().map(|t| t.gen(cxt)).collect();
+                write!(
+                    buf,
+                    "public static class {}{}{}{}{} {{",
+                    cxt.class_str(*tid),
+                    cxt.type_params_str(*tid),
+                    extends
+                        .as_ref()
+                        .map_or(String::new(), |t| format!(" extends {}", t.gen(cxt))),
+                    if implements.is_empty() {
+                        ""
+                    } else {
+                        " implements "
+                    },
+                    implements.join(", ")
+                )
+                .unwrap();
                 cxt.push();
 
                 for (vars, _block) in members {
                     for (r, ty, _x) in vars {
                         write!(
                             buf,
-                            "\n{}public {} {};",
-                            cxt.indent(),
+                            "{}{}{}{} {};",
+                            cxt.line(),
+                            cxt.visibility_str(*r),
+                            cxt.volatile_str(*r),
                             ty.gen(cxt),
                             cxt.name_str(*r)
                         )
                         .unwrap();
                     }
                 }
-                write!(buf, "\n{}public {}() {{", cxt.indent(), cxt.class_str(*tid)).unwrap();
+                write!(buf, "{}public {}() {{", cxt.line(), cxt.class_str(*tid)).unwrap();
                 cxt.push();
                 for (vars, block) in members {
                     for stmt in block {
                         buf.push_str(&stmt.gen(cxt));
-                        buf.push_str("\n");
-                        buf.push_str(cxt.indent());
+                        buf.push_str(&cxt.line());
                     }
 
                     for (r, _ty, x) in vars {
                         if let Some(x) = x {
-                            write!(
-                                buf,
-                                "\n{}{} = {};",
-                                cxt.indent(),
-                                cxt.name_str(*r),
-                                x.gen(cxt)
-                            )
-                            .unwrap();
+                            write!(buf, "{}{} = {};", cxt.line(), cxt.name_str(*r), x.gen(cxt))
+                                .unwrap();
                         }
                     }
                 }
                 cxt.pop();
-                write!(buf, "\n{}}}", cxt.indent()).unwrap();
-                buf.push('\n');
-                buf.push_str(cxt.indent());
+                write!(buf, "{}}}", cxt.line()).unwrap();
+                buf.push_str(&cxt.line());
                 for f in methods {
-                    buf.push_str(&f.gen(cxt, false));
+                    buf.push_str(&f.gen(cxt));
                 }
 
                 cxt.pop();
-                buf.push('\n');
-                buf.push_str(cxt.indent());
-                buf.push_str("}\n");
-                buf.push_str(cxt.indent());
+                buf.push_str(&cxt.line());
+                buf.push('}');
+                buf.push_str(&cxt.line());
 
                 buf
             }
@@ -1088,105 +2131,204 @@ impl JItem {
                 cxt.push();
 
                 for (i, _tys) in variants {
-                    buf.push('\n');
-                    buf.push_str(cxt.indent());
+                    buf.push_str(&cxt.line());
                     buf.push_str(cxt.bindings.resolve_raw(*i));
                     buf.push(',');
                 }
 
-                if wrapper.is_none() && !methods.is_empty() {
-                    buf.pop();
+                // The number of variants, for inline Java; `Enum::count` in ByteC is just a literal
+                let count = variants
+                    .iter()
+                    .all(|(i, _)| cxt.bindings.resolve_raw(*i) != "count");
+                if count || (wrapper.is_none() && !methods.is_empty()) {
+                    if buf.ends_with(',') {
+                        buf.pop();
+                    }
                     buf.push(';');
+                }
+                if count {
+                    write!(
+                        buf,
+                        "{}public static final int count = {};",
+                        cxt.line(),
+                        variants.len()
+                    )
+                    .unwrap();
+                }
+                if wrapper.is_none() {
                     for f in methods {
-                        buf.push_str(&f.gen(cxt, false));
+                        buf.push_str(&f.gen(cxt));
                     }
                 }
 
                 cxt.pop();
-                buf.push('\n');
-                buf.push_str(cxt.indent());
-                buf.push_str("}\n");
-                buf.push_str(cxt.indent());
+                buf.push_str(&cxt.line());
+                buf.push('}');
+                buf.push_str(&cxt.line());
 
                 if let Some(wrapper) = wrapper {
                     write!(buf, "public static class {} {{", cxt.class_str(*wrapper)).unwrap();
                     cxt.push();
 
                     // We need a field for the variant tag
-                    write!(
-                        buf,
-                        "\n{}public {} $type;",
-                        cxt.indent(),
-                        cxt.class_str(*tid)
-                    )
-                    .unwrap();
+                    write!(buf, "{}public {} $type;", cxt.line(), cxt.class_str(*tid)).unwrap();
 
                     // Variant members are made into global fields on the wrapper class that start out uninitialized
                     for (i, tys) in variants {
                         for (n, ty) in tys.iter().enumerate() {
                             write!(
                                 buf,
-                                "\n{}public {} _enum${}${};",
-                                cxt.indent(),
+                                "{}public {} _enum{sep}{}{sep}{};",
+                                cxt.line(),
                                 ty.gen(cxt),
                                 cxt.bindings.resolve_raw(*i),
-                                n
+                                n,
+                                sep = cxt.separator
                             )
                             .unwrap();
                         }
                     }
-                    buf.push('\n');
-                    buf.push_str(cxt.indent());
+                    buf.push_str(&cxt.line());
                     for f in methods {
-                        buf.push_str(&f.gen(cxt, false));
+                        buf.push_str(&f.gen(cxt));
                     }
 
                     cxt.pop();
-                    buf.push('\n');
-                    buf.push_str(cxt.indent());
-                    buf.push_str("}\n");
-                    buf.push_str(cxt.indent());
+                    buf.push_str(&cxt.line());
+                    buf.push('}');
+                    buf.push_str(&cxt.line());
+                }
+                buf
+            }
+            JItem::Interface(tid, methods) => {
+                let mut buf = String::new();
+                write!(
+                    buf,
+                    "public static interface {}{} {{",
+                    cxt.class_str(*tid),
+                    cxt.type_params_str(*tid)
+                )
+                .unwrap();
+                cxt.push();
+
+                for (name, args, ret) in methods {
+                    let args: Vec<_> = args
+                        .iter()
+                        .map(|(r, ty)| format!("{} {}", ty.gen(cxt), cxt.bindings.resolve_raw(*r)))
+                        .collect();
+                    write!(
+                        buf,
+                        "{}{} {}({});",
+                        cxt.line(),
+                        ret.as_ref().map_or("void".to_string(), |t| t.gen(cxt)),
+                        cxt.bindings.resolve_raw(*name),
+                        args.join(", ")
+                    )
+                    .unwrap();
+                }
+
+                cxt.pop();
+                buf.push_str(&cxt.line());
+                buf.push('}');
+                buf.push_str(&cxt.line());
+
+                buf
+            }
+            JItem::Tuple(tid, fields) => {
+                let mut buf = String::new();
+                write!(buf, "public static class {} {{", cxt.class_str(*tid)).unwrap();
+                cxt.push();
+
+                for (r, ty) in fields {
+                    write!(
+                        buf,
+                        "{}public {} {};",
+                        cxt.line(),
+                        ty.gen(cxt),
+                        cxt.bindings.resolve_raw(*r)
+                    )
+                    .unwrap();
+                }
+                // New arrays are filled with empty tuples, which have the same default values Java would use for separate arrays
+                write!(buf, "{}public {}() {{}}", cxt.line(), cxt.class_str(*tid)).unwrap();
+                let args: Vec<_> = fields
+                    .iter()
+                    .map(|(r, ty)| format!("{} {}", ty.gen(cxt), cxt.bindings.resolve_raw(*r)))
+                    .collect();
+                write!(
+                    buf,
+                    "{}public {}({}) {{",
+                    cxt.line(),
+                    cxt.class_str(*tid),
+                    args.join(", ")
+                )
+                .unwrap();
+                cxt.push();
+                for (r, _) in fields {
+                    let r = cxt.bindings.resolve_raw(*r);
+                    write!(buf, "{}this.{} = {};", cxt.line(), r, r).unwrap();
                 }
+                cxt.pop();
+                write!(buf, "{}}}", cxt.line()).unwrap();
+
+                cxt.pop();
+                buf.push_str(&cxt.line());
+                buf.push('}');
+                buf.push_str(&cxt.line());
                 buf
             }
             JItem::Let(vars, block) => {
                 let mut buf = String::new();
-                for (var, ty, _term) in vars {
+                // `final` fields are initialized where they're declared if they can be, so javac can inline constants
+                let inline: Vec<_> = vars
+                    .iter()
+                    .map(|(v, _, x)| block.is_empty() && x.is_some() && cxt.finals.contains(v))
+                    .collect();
+                for ((var, ty, value), &inline) in vars.iter().zip(&inline) {
                     write!(
                         buf,
-                        "public static {} {};\n{}",
+                        "{}static {}{}{} {}",
+                        cxt.visibility_str(*var),
+                        cxt.volatile_str(*var),
+                        cxt.final_str(*var),
                         ty.gen(cxt),
                         cxt.name_str(*var),
-                        cxt.indent()
                     )
                     .unwrap();
+                    if inline {
+                        write!(buf, " = {}", value.as_ref().unwrap().gen(cxt)).unwrap();
+                    }
+                    write!(buf, ";{}", cxt.line()).unwrap();
                 }
-                if !block.is_empty() || vars.iter().any(|(_, _, s)| s.is_some()) {
-                    buf.push_str("static {\n");
+                if !block.is_empty()
+                    || vars
+                        .iter()
+                        .zip(&inline)
+                        .any(|((_, _, x), i)| x.is_some() && !i)
+                {
+                    buf.push_str("static {");
                     cxt.push();
-                    buf.push_str(cxt.indent());
+                    buf.push_str(&cxt.line());
                     for stmt in block {
                         buf.push_str(&stmt.gen(cxt));
-                        buf.push_str("\n");
-                        buf.push_str(cxt.indent());
+                        buf.push_str(&cxt.line());
                     }
-                    for (var, _, value) in vars {
-                        if let Some(value) = value {
+                    for ((var, _, value), &inline) in vars.iter().zip(&inline) {
+                        if let (Some(value), false) = (value, inline) {
                             write!(
                                 buf,
-                                "{} = {};\n{}",
+                                "{} = {};{}",
                                 cxt.name_str(*var),
                                 value.gen(cxt),
-                                cxt.indent(),
+                                cxt.line(),
                             )
                             .unwrap();
-                            buf.push_str("\n");
-                            buf.push_str(cxt.indent());
+                            buf.push_str(&cxt.line());
                         }
                     }
-                    buf.push_str("}\n");
+                    buf.push('}');
                     cxt.pop();
-                    buf.push_str(cxt.indent());
+                    buf.push_str(&cxt.line());
                 }
                 buf
             }
@@ -1194,124 +2336,1450 @@ impl JItem {
     }
 }
 
-// LOWERING
+// COST ESTIMATION
+// A rough count of the Java bytecode instructions functions run, since Battlecode limits how many a robot can use each turn
 
-pub struct Cxt<'a> {
-    bindings: &'a mut Bindings,
-    scopes: Vec<(usize, usize, usize)>,
-    vars: Vec<(Sym, JVars)>,
-    tys: HashMap<JVar, JTy>,
-    fn_ids: Vec<(FnId, JFnId)>,
-    fn_ret_tys: HashMap<JFnId, JTys>,
-    inline_fns: HashMap<JFnId, (Vec<(Sym, Type)>, Term)>,
-    types: Vec<(TypeId, JClass)>,
-    block: Vec<JStmt>,
-    blocks: Vec<(Option<JBlock>, usize)>,
-    current_fn: JFnId,
-    items: Vec<JItem>,
-    predefs: Vec<(Predef, JFnId)>,
-    enum_wrappers: HashMap<JClass, JClass>,
-    next: u64,
-    package: String,
+/// The estimated bytecode costs of the functions in a module, from `IRMod::estimate_cost()`
+pub struct CostEstimate {
+    /// The Java name of each function, with the class first for methods, and its cost, in the order they're generated
+    pub fns: Vec<(String, u64)>,
+    /// The sum of the costs of all the functions
+    pub total: u64,
 }
-impl<'a> Cxt<'a> {
-    pub fn new(bindings: &'a mut Bindings, package: impl Into<String>) -> Self {
-        Cxt {
-            bindings,
-            scopes: Vec::new(),
-            vars: Vec::new(),
-            tys: HashMap::new(),
-            fn_ids: Vec::new(),
-            fn_ret_tys: HashMap::new(),
-            inline_fns: HashMap::new(),
-            types: Vec::new(),
-            block: Vec::new(),
-            blocks: Vec::new(),
-            current_fn: JFnId(0),
-            items: Vec::new(),
-            predefs: Vec::new(),
-            enum_wrappers: HashMap::new(),
-            next: 0,
-            package: package.into(),
+
+impl IRMod {
+    /// Lowers this module like `codegen()`, but instead of generating Java, estimates how many bytecodes each function costs to run once.
+    /// Each JVM instruction counts as one, with the more expensive branch of each `if` or `match`,
+    /// loops over constant ranges running the right number of times, and other loops running once.
+    /// Calls only count the call itself, not what the function called does.
+    pub fn estimate_cost<T>(
+        &self,
+        cxt: &mut Cxt,
+        mods: &[(IRMod, T)],
+    ) -> Result<CostEstimate, BackendError> {
+        let (names, _) = self.lower(cxt, mods)?;
+        let mut gen = Gen::new(cxt.bindings);
+        gen.names = names;
+        gen.enums = cxt.enums.clone();
+        gen.separator = cxt.separator.clone();
+
+        let mut fns = Vec::new();
+        for i in cxt.items.drain(..) {
+            match i {
+                JItem::Fn(f) => fns.push((gen.fn_str(f.fn_id), f.cost(&gen))),
+                JItem::Class(class, _, methods, _, _) | JItem::Enum(_, _, Some(class), methods) => {
+                    for f in methods {
+                        let name = format!("{}.{}", gen.class_str(class), gen.fn_str(f.fn_id));
+                        fns.push((name, f.cost(&gen)));
+                    }
+                }
+                JItem::Enum(class, _, None, methods) => {
+                    for f in methods {
+                        let name = format!("{}.{}", gen.class_str(class), gen.fn_str(f.fn_id));
+                        fns.push((name, f.cost(&gen)));
+                    }
+                }
+                JItem::Tuple(_, _) | JItem::Interface(_, _) | JItem::Let(_, _) => (),
+            }
         }
+        let total = fns.iter().map(|(_, c)| c).sum();
+        Ok(CostEstimate { fns, total })
     }
+}
 
-    fn var(&self, s: Sym) -> Option<JVars> {
-        self.vars
-            .iter()
-            .rfind(|(k, _v)| *k == s)
-            .map(|(_k, v)| v.clone())
-    }
-    fn fun(&self, s: FnId) -> Option<JFnId> {
-        self.fn_ids
-            .iter()
-            .rfind(|(k, _v)| *k == s)
-            .map(|(_k, v)| *v)
-    }
-    fn class(&self, s: TypeId) -> Option<JClass> {
-        self.types.iter().rfind(|(k, _v)| *k == s).map(|(_k, v)| *v)
-    }
-    fn predef(&self, p: Predef) -> JFnId {
-        self.predefs.iter().find(|(x, _)| *x == p).unwrap().1
-    }
+/// The cost of running a block once
+fn block_cost(block: &[JStmt], cxt: &Gen) -> u64 {
+    block.iter().map(|x| x.cost(cxt)).sum()
+}
 
-    fn block_label(&self) -> Option<JBlock> {
-        self.blocks.iter().rev().find_map(|(x, _)| x.clone())
-    }
-    fn push_loop(&mut self, k: JBlock) {
-        self.push();
-        self.blocks.push((Some(k), self.block.len()));
-    }
-    /// Implies push()
-    fn push_block(&mut self) {
-        self.push();
-        self.blocks.push((None, self.block.len()));
-    }
-    fn pop_block(&mut self) -> Vec<JStmt> {
-        self.pop();
-        self.block.split_off(self.blocks.pop().unwrap().1)
+impl JFn {
+    fn cost(&self, cxt: &Gen) -> u64 {
+        block_cost(&self.body, cxt)
     }
+}
 
-    fn push(&mut self) {
-        self.scopes
-            .push((self.vars.len(), self.fn_ids.len(), self.types.len()));
-    }
-    fn pop(&mut self) {
-        let (v, i, t) = self.scopes.pop().unwrap();
-        self.vars.truncate(v);
-        self.fn_ids.truncate(i);
-        self.types.truncate(t);
+impl JStmt {
+    fn cost(&self, cxt: &Gen) -> u64 {
+        match self {
+            JStmt::Let(_, _, _, Some(x)) => x.cost(cxt) + 1,
+            JStmt::Let(_, _, _, None) => 0,
+            // A compound assignment loads the old value and does the operation too
+            JStmt::Set(l, op, x) => l.cost(cxt) + x.cost(cxt) + if op.is_some() { 3 } else { 1 },
+            // The value is popped if it isn't used
+            JStmt::Term(x) => x.cost(cxt) + 1,
+            JStmt::If(cond, a, b) => {
+                cond.cost(cxt) + 1 + block_cost(a, cxt).max(block_cost(b, cxt) + 1)
+            }
+            JStmt::Switch(_, x, branches, default) => {
+                let branches = branches.iter().map(|(_, b)| block_cost(b, cxt));
+                x.cost(cxt) + 1 + branches.fold(block_cost(default, cxt), u64::max) + 1
+            }
+            JStmt::While(_, cond, block) => cond.cost(cxt) + 1 + block_cost(block, cxt) + 1,
+            JStmt::DoWhile(_, block, cond) => block_cost(block, cxt) + cond.cost(cxt) + 1,
+            JStmt::RangeFor(_, _, _, a, b, step, op, block, _) => {
+                let iters = match (a, b, step) {
+                    (
+                        JTerm::Lit(JLit::Int(a)),
+                        JTerm::Lit(JLit::Int(b)),
+                        JTerm::Lit(JLit::Int(step)),
+                    ) if *step != 0 => {
+                        // Counting down is the same as counting up from the negated start
+                        let (a, b, step) = if *step < 0 {
+                            (-(*a as i64), -(*b as i64), -(*step as i64))
+                        } else {
+                            (*a as i64, *b as i64, *step as i64)
+                        };
+                        let len = match op {
+                            BinOp::Leq | BinOp::Geq => b - a + 1,
+                            _ => b - a,
+                        };
+                        ((len + step - 1) / step).max(0) as u64
+                    }
+                    _ => 1,
+                };
+                // Each iteration checks the condition, runs the body, and adds the step
+                let iter = b.cost(cxt) + 2 + block_cost(block, cxt) + step.cost(cxt) + 1;
+                a.cost(cxt) + 1 + iter * iters + b.cost(cxt) + 2
+            }
+            JStmt::Continue(_) | JStmt::Break(_) => 1,
+            JStmt::Ret(_, v) => v.iter().map(|x| x.cost(cxt) + 1).sum::<u64>().max(1),
+            // Each returned value is read back from a static field and stored
+            JStmt::MultiCall(o, _, args, rets) => {
+                o.as_ref().map_or(0, |x| x.cost(cxt))
+                    + args.iter().map(|x| x.cost(cxt)).sum::<u64>()
+                    + 1
+                    + 2 * rets.len() as u64
+            }
+            // We don't know what it does
+            JStmt::InlineJava(_) => 1,
+            // new, dup, the message, invokespecial, athrow
+            JStmt::ThrowNew(_, msg) => msg.cost(cxt) + 4,
+            JStmt::Throw(x) => x.cost(cxt) + 1,
+            // Handlers only run if something's thrown, and entering a `try` is free
+            JStmt::Try(body, _) => block_cost(body, cxt),
+            JStmt::Multi(v) => block_cost(v, cxt),
+            JStmt::Src(_, _) => 0,
+        }
     }
+}
 
-    fn fresh_var(&mut self, public: bool) -> JVar {
-        self.next += 1;
-        JVar(self.next, public)
-    }
-    fn fresh_fn(&mut self) -> JFnId {
-        self.next += 1;
-        JFnId(self.next)
-    }
-    fn fresh_class(&mut self) -> JClass {
-        self.next += 1;
-        JClass(self.next)
-    }
-    fn fresh_block(&mut self) -> JBlock {
-        self.next += 1;
-        JBlock(self.next)
+impl JLVal {
+    /// The cost of everything before the value is stored
+    fn cost(&self, cxt: &Gen) -> u64 {
+        match self {
+            JLVal::Var(_) => 0,
+            JLVal::Idx(a, i) => a.cost(cxt) + 1 + i.cost(cxt),
+            JLVal::SIdx(v, i) => v.iter().map(|x| x.cost(cxt)).max().unwrap_or(0) + i.cost(cxt),
+            JLVal::Prop(x, _) => x.cost(cxt),
+        }
     }
 }
 
 impl JTerm {
-    /// Whether this term is simple enough to be 1 bytecode instruction.
-    /// Simple instructions can be duplicated freely.
-    fn simple(&self) -> bool {
+    fn cost(&self, cxt: &Gen) -> u64 {
         match self {
             JTerm::Var(_, _)
-            | JTerm::Variant(_, _)
             | JTerm::Lit(_)
+            | JTerm::Variant(_, _)
             | JTerm::Null(_)
-            | JTerm::SIndex(_, _)
-            | JTerm::This(_) => true,
+            | JTerm::This(_)
+            | JTerm::InlineJava(_, _) => 1,
+            // One `invokedynamic`, the body only runs when the lambda is called
+            JTerm::Lambda(_, _, _, _) => 1,
+            JTerm::Cast(x, _) | JTerm::Neg(x) | JTerm::Prop(x, _, _) => x.cost(cxt) + 1,
+            JTerm::InstanceOf(x, _) => x.cost(cxt) + 1,
+            // `x ^ -1`, and `!x` is `x ^ 1`
+            JTerm::BitNot(x) | JTerm::Not(x) => x.cost(cxt) + 2,
+            JTerm::Call(o, _, args, _) => {
+                o.as_ref().map_or(0, |x| x.cost(cxt))
+                    + args.iter().map(|x| x.cost(cxt)).sum::<u64>()
+                    + 1
+            }
+            // `a.equals(b)` is a call
+            JTerm::BinOp(BinOp::Neq, a, b) if JTerm::java_equals(a, b, cxt) => {
+                a.cost(cxt) + b.cost(cxt) + 3
+            }
+            JTerm::BinOp(_, a, b) => a.cost(cxt) + b.cost(cxt) + 1,
+            // The length and newarray, then dup, index, value and store for each element
+            JTerm::Array(v, _) => 2 + v.iter().map(|x| x.cost(cxt) + 3).sum::<u64>(),
+            JTerm::ArrayNew(len, _) => len.cost(cxt) + 1,
+            // new, dup, the arguments, invokespecial
+            JTerm::ClassNew(_, args) => 3 + args.iter().map(|x| x.cost(cxt)).sum::<u64>(),
+            // aload_0, the arguments, invokespecial
+            JTerm::Super(args, _) => 2 + args.iter().map(|x| x.cost(cxt)).sum::<u64>(),
+            JTerm::Index(a, i, _) => a.cost(cxt) + i.cost(cxt) + 1,
+            JTerm::SIndex(v, i) => match &**i {
+                JTerm::Lit(JLit::Int(i)) => v[*i as usize].cost(cxt),
+                _ => v.iter().map(|x| x.cost(cxt)).max().unwrap_or(0) + i.cost(cxt),
+            },
+            JTerm::Ternary(cond, a, b) => cond.cost(cxt) + 1 + a.cost(cxt).max(b.cost(cxt)) + 1,
+        }
+    }
+}
+
+// JSON OUTPUT
+// The lowered Java AST as JSON, for external tools that want the compiler's output without parsing Java
+
+#[cfg(feature = "serde")]
+mod json {
+    use super::*;
+    use serde_json::{json, Value};
+
+    impl IRMod {
+        /// Like `codegen()`, but produces the lowered items as JSON instead of Java source.
+        /// Names are resolved the same way as in the Java code, and types are given as Java types.
+        pub fn codegen_json<T>(
+            &self,
+            cxt: &mut Cxt,
+            mods: &[(IRMod, T)],
+        ) -> Result<String, BackendError> {
+            let (names, _) = self.lower(cxt, mods)?;
+            let mut gen = Gen::new(cxt.bindings);
+            gen.names = names;
+            gen.generic_classes = cxt.generic_classes.clone();
+            let items: Vec<_> = cxt.items.drain(..).map(|i| i.json(&mut gen)).collect();
+            Ok(json!({
+                "package": cxt.package,
+                "class": self.out_class,
+                "items": items,
+            })
+            .to_string())
+        }
+    }
+
+    fn raw(cxt: &Gen, s: RawSym) -> Value {
+        cxt.bindings.resolve_raw(s).into()
+    }
+
+    fn vars(cxt: &mut Gen, vars: &[(JVar, JTy, Option<JTerm>)]) -> Value {
+        vars.iter()
+            .map(|(v, t, x)| {
+                json!({
+                    "name": cxt.name_str(*v),
+                    "type": t.gen(cxt),
+                    "value": x.as_ref().map(|x| x.json(cxt)),
+                })
+            })
+            .collect()
+    }
+
+    fn block(cxt: &mut Gen, block: &[JStmt]) -> Value {
+        block.iter().map(|x| x.json(cxt)).collect()
+    }
+
+    impl JItem {
+        fn json(&self, cxt: &mut Gen) -> Value {
+            match self {
+                JItem::Fn(f) => f.json(cxt),
+                JItem::Enum(tid, variants, wrapper, methods) => json!({
+                    "kind": "enum",
+                    "name": cxt.class_str(*tid),
+                    "variants": variants
+                        .iter()
+                        .map(|(s, tys)| json!({
+                            "name": raw(cxt, *s),
+                            "fields": tys.iter().map(|t| t.gen(cxt)).collect::<Vec<_>>(),
+                        }))
+                        .collect::<Vec<_>>(),
+                    "wrapper": wrapper.map(|c| cxt.class_str(c)),
+                    "methods": methods.iter().map(|f| f.json(cxt)).collect::<Vec<_>>(),
+                }),
+                JItem::Class(tid, members, methods, implements, extends) => json!({
+                    "kind": "class",
+                    "name": cxt.class_str(*tid),
+                    "implements": implements.iter().map(|t| t.gen(cxt)).collect::<Vec<_>>(),
+                    "extends": extends.as_ref().map(|t| t.gen(cxt)),
+                    "type_params": cxt
+                        .generic_classes
+                        .get(tid)
+                        .map_or(Vec::new(), |v| v.iter().map(|r| cxt.bindings.resolve_raw(*r)).collect()),
+                    "members": members
+                        .iter()
+                        .map(|(v, b)| {
+                            // The block comes first, since the values can use locals it declares
+                            let b = block(cxt, b);
+                            json!({ "block": b, "vars": vars(cxt, v) })
+                        })
+                        .collect::<Vec<_>>(),
+                    "methods": methods.iter().map(|f| f.json(cxt)).collect::<Vec<_>>(),
+                }),
+                JItem::Interface(tid, methods) => json!({
+                    "kind": "interface",
+                    "name": cxt.class_str(*tid),
+                    "type_params": cxt
+                        .generic_classes
+                        .get(tid)
+                        .map_or(Vec::new(), |v| v.iter().map(|r| cxt.bindings.resolve_raw(*r)).collect()),
+                    "methods": methods
+                        .iter()
+                        .map(|(s, args, ret)| json!({
+                            "name": raw(cxt, *s),
+                            "args": args
+                                .iter()
+                                .map(|(s, t)| json!({ "name": raw(cxt, *s), "type": t.gen(cxt) }))
+                                .collect::<Vec<_>>(),
+                            "ret_ty": ret.as_ref().map(|t| t.gen(cxt)),
+                        }))
+                        .collect::<Vec<_>>(),
+                }),
+                JItem::Tuple(tid, fields) => json!({
+                    "kind": "tuple",
+                    "name": cxt.class_str(*tid),
+                    "fields": fields
+                        .iter()
+                        .map(|(s, t)| json!({ "name": raw(cxt, *s), "type": t.gen(cxt) }))
+                        .collect::<Vec<_>>(),
+                }),
+                JItem::Let(v, b) => {
+                    let b = block(cxt, b);
+                    json!({
+                        "kind": "let",
+                        "block": b,
+                        "vars": vars(cxt, v),
+                    })
+                }
+            }
+        }
+    }
+
+    impl JFn {
+        fn json(&self, cxt: &mut Gen) -> Value {
+            let names = cxt.names.clone();
+            let args: Vec<_> = self
+                .args
+                .iter()
+                .map(|(n, v, t)| {
+                    cxt.names.insert(v.0, (lpath(Spanned::hack(*n)), !v.1));
+                    json!({ "name": cxt.name_str(*v), "type": t.gen(cxt) })
+                })
+                .collect();
+            let r = json!({
+                "kind": "fn",
+                "name": cxt.fn_str(self.fn_id),
+                "public": self.visibility == Visibility::Public,
+                "static": self.is_static,
+                "annotations": self.annotations.iter().map(|s| raw(cxt, *s)).collect::<Vec<_>>(),
+                "type_params": self.type_params.iter().map(|s| raw(cxt, *s)).collect::<Vec<_>>(),
+                "args": args,
+                "ret": self.ret_tys.iter().map(|t| t.gen(cxt)).collect::<Vec<_>>(),
+                "throws": self.throws.iter().map(|s| raw(cxt, *s)).collect::<Vec<_>>(),
+                "body": block(cxt, &self.body),
+            });
+            cxt.names = names;
+            r
+        }
+    }
+
+    impl JStmt {
+        fn json(&self, cxt: &mut Gen) -> Value {
+            match self {
+                JStmt::Let(n, t, v, x) => {
+                    cxt.names.insert(v.0, (lpath(Spanned::hack(*n)), !v.1));
+                    json!({
+                        "kind": "let",
+                        "name": cxt.name_str(*v),
+                        "type": t.gen(cxt),
+                        "value": x.as_ref().map(|x| x.json(cxt)),
+                    })
+                }
+                JStmt::Set(l, op, x) => json!({
+                    "kind": "set",
+                    "lval": l.json(cxt),
+                    "op": op.map(|op| op.repr()),
+                    "value": x.json(cxt),
+                }),
+                JStmt::Term(x) => json!({ "kind": "term", "term": x.json(cxt) }),
+                JStmt::If(cond, a, b) => json!({
+                    "kind": "if",
+                    "cond": cond.json(cxt),
+                    "then": block(cxt, a),
+                    "else": block(cxt, b),
+                }),
+                JStmt::Switch(k, x, branches, default) => json!({
+                    "kind": "switch",
+                    "label": k.0,
+                    "scrutinee": x.json(cxt),
+                    "cases": branches
+                        .iter()
+                        .map(|(c, b)| match c {
+                            JCase::Variant(s) => json!({ "variant": raw(cxt, *s), "body": block(cxt, b) }),
+                            JCase::Int(i) => json!({ "value": i, "body": block(cxt, b) }),
+                        })
+                        .collect::<Vec<_>>(),
+                    "default": block(cxt, default),
+                }),
+                JStmt::While(k, cond, b) => json!({
+                    "kind": "while",
+                    "label": k.0,
+                    "cond": cond.json(cxt),
+                    "body": block(cxt, b),
+                }),
+                JStmt::DoWhile(k, b, cond) => json!({
+                    "kind": "do_while",
+                    "label": k.0,
+                    "body": block(cxt, b),
+                    "cond": cond.json(cxt),
+                }),
+                JStmt::RangeFor(k, n, var, a, b, step, op, body, _) => {
+                    cxt.names.insert(var.0, (lpath(Spanned::hack(*n)), !var.1));
+                    json!({
+                        "kind": "range_for",
+                        "label": k.0,
+                        "var": cxt.name_str(*var),
+                        "start": a.json(cxt),
+                        "end": b.json(cxt),
+                        "step": step.json(cxt),
+                        "op": op.repr(),
+                        "body": block(cxt, body),
+                    })
+                }
+                JStmt::Continue(k) => json!({ "kind": "continue", "label": k.0 }),
+                JStmt::Break(k) => json!({ "kind": "break", "label": k.0 }),
+                JStmt::Ret(f, v) => json!({
+                    "kind": "return",
+                    "fn": cxt.fn_str(*f),
+                    "values": v.iter().map(|x| x.json(cxt)).collect::<Vec<_>>(),
+                }),
+                JStmt::MultiCall(o, f, args, rets) => {
+                    let object = o.as_ref().map(|o| o.json(cxt));
+                    let args: Vec<_> = args.iter().map(|x| x.json(cxt)).collect();
+                    let rets: Vec<_> = rets
+                        .iter()
+                        .map(|(n, v, t)| {
+                            cxt.names.insert(v.0, (lpath(Spanned::hack(*n)), !v.1));
+                            json!({ "name": cxt.name_str(*v), "type": t.gen(cxt) })
+                        })
+                        .collect();
+                    json!({
+                        "kind": "multi_call",
+                        "object": object,
+                        "fn": cxt.fn_str(*f),
+                        "args": args,
+                        "rets": rets,
+                    })
+                }
+                JStmt::InlineJava(s) => json!({ "kind": "java", "code": raw(cxt, *s) }),
+                JStmt::ThrowNew(e, msg) => json!({
+                    "kind": "throw",
+                    "exception": raw(cxt, *e),
+                    "message": msg.json(cxt),
+                }),
+                JStmt::Throw(x) => json!({ "kind": "throw_value", "value": x.json(cxt) }),
+                JStmt::Src(file, span) => json!({
+                    "kind": "src",
+                    "file": file.0,
+                    "start": span.0,
+                    "end": span.1,
+                }),
+                JStmt::Try(body, handlers) => {
+                    let handlers: Vec<_> = handlers
+                        .iter()
+                        .map(|(class, n, var, b)| {
+                            cxt.names.insert(var.0, (lpath(Spanned::hack(*n)), !var.1));
+                            json!({
+                                "class": cxt.class_str(*class),
+                                "var": cxt.name_str(*var),
+                                "body": block(cxt, b),
+                            })
+                        })
+                        .collect();
+                    json!({ "kind": "try", "body": block(cxt, body), "handlers": handlers })
+                }
+                JStmt::Multi(v) => json!({ "kind": "multi", "body": block(cxt, v) }),
+            }
+        }
+    }
+
+    impl JLVal {
+        fn json(&self, cxt: &Gen) -> Value {
+            match self {
+                JLVal::Var(v) => json!({ "kind": "var", "name": cxt.name_str(*v) }),
+                JLVal::Idx(l, i) => json!({
+                    "kind": "index",
+                    "array": l.json(cxt),
+                    "index": i.json(cxt),
+                }),
+                JLVal::SIdx(v, i) => json!({
+                    "kind": "sindex",
+                    "arrays": v.iter().map(|l| l.json(cxt)).collect::<Vec<_>>(),
+                    "index": i.json(cxt),
+                }),
+                JLVal::Prop(x, p) => json!({
+                    "kind": "prop",
+                    "object": x.json(cxt),
+                    "name": p.gen(cxt),
+                }),
+            }
+        }
+    }
+
+    impl JTerm {
+        fn json(&self, cxt: &Gen) -> Value {
+            match self {
+                JTerm::Var(v, t) => json!({
+                    "kind": "var",
+                    "name": cxt.name_str(*v),
+                    "type": t.gen(cxt),
+                }),
+                JTerm::Lit(l) => json!({
+                    "kind": "lit",
+                    "type": self.ty().gen(cxt),
+                    "value": match l {
+                        JLit::Byte(i) => json!(i),
+                        JLit::Short(i) => json!(i),
+                        JLit::Int(i) => json!(i),
+                        JLit::Long(i) => json!(i),
+                        JLit::Float(f) => json!(f),
+                        JLit::Double(d) => json!(d),
+                        JLit::Str(s) => raw(cxt, *s),
+                        JLit::Bool(b) => json!(b),
+                        JLit::Char(c) => json!(c),
+                    },
+                }),
+                JTerm::Call(o, f, a, t) => json!({
+                    "kind": "call",
+                    "object": o.as_ref().map(|o| o.json(cxt)),
+                    "fn": cxt.fn_str(*f),
+                    "args": a.iter().map(|x| x.json(cxt)).collect::<Vec<_>>(),
+                    "type": t.gen(cxt),
+                }),
+                JTerm::Prop(x, p, t) => json!({
+                    "kind": "prop",
+                    "object": x.json(cxt),
+                    "name": p.gen(cxt),
+                    "type": t.gen(cxt),
+                }),
+                JTerm::BinOp(op, a, b) => json!({
+                    "kind": "binop",
+                    "op": op.repr(),
+                    "lhs": a.json(cxt),
+                    "rhs": b.json(cxt),
+                }),
+                JTerm::Variant(c, s) => json!({
+                    "kind": "variant",
+                    "enum": cxt.class_str(*c),
+                    "name": raw(cxt, *s),
+                }),
+                JTerm::Array(v, t) => json!({
+                    "kind": "array",
+                    "items": v.iter().map(|x| x.json(cxt)).collect::<Vec<_>>(),
+                    "type": t.gen(cxt),
+                }),
+                JTerm::ArrayNew(len, t) => json!({
+                    "kind": "array_new",
+                    "len": len.json(cxt),
+                    "type": t.gen(cxt),
+                }),
+                JTerm::ClassNew(c, a) => json!({
+                    "kind": "new",
+                    "class": cxt.class_str(*c),
+                    "args": a.iter().map(|x| x.json(cxt)).collect::<Vec<_>>(),
+                }),
+                JTerm::Super(a, t) => json!({
+                    "kind": "super",
+                    "args": a.iter().map(|x| x.json(cxt)).collect::<Vec<_>>(),
+                    "type": t.gen(cxt),
+                }),
+                JTerm::Index(a, i, t) => json!({
+                    "kind": "index",
+                    "array": a.json(cxt),
+                    "index": i.json(cxt),
+                    "type": t.gen(cxt),
+                }),
+                JTerm::SIndex(v, i) => json!({
+                    "kind": "sindex",
+                    "arrays": v.iter().map(|x| x.json(cxt)).collect::<Vec<_>>(),
+                    "index": i.json(cxt),
+                }),
+                JTerm::Not(x) => json!({ "kind": "not", "term": x.json(cxt) }),
+                JTerm::BitNot(x) => json!({ "kind": "bitnot", "term": x.json(cxt) }),
+                JTerm::Neg(x) => json!({ "kind": "neg", "term": x.json(cxt) }),
+                JTerm::Ternary(c, a, b) => json!({
+                    "kind": "ternary",
+                    "cond": c.json(cxt),
+                    "then": a.json(cxt),
+                    "else": b.json(cxt),
+                }),
+                JTerm::Cast(x, t) => json!({
+                    "kind": "cast",
+                    "term": x.json(cxt),
+                    "type": t.gen(cxt),
+                }),
+                JTerm::InstanceOf(x, c) => json!({
+                    "kind": "instanceof",
+                    "term": x.json(cxt),
+                    "class": cxt.class_str(*c),
+                }),
+                JTerm::Null(t) => json!({ "kind": "null", "type": t.gen(cxt) }),
+                JTerm::This(c) => json!({ "kind": "this", "class": cxt.class_str(*c) }),
+                JTerm::InlineJava(s, t) => json!({
+                    "kind": "java",
+                    "code": raw(cxt, *s),
+                    "type": t.gen(cxt),
+                }),
+                JTerm::Lambda(args, body, x, t) => {
+                    let mut cxt = cxt.clone();
+                    for (n, v, _) in args {
+                        cxt.names.insert(v.0, (lpath(Spanned::hack(*n)), !v.1));
+                    }
+                    json!({
+                        "kind": "lambda",
+                        "args": args.iter().map(|(_, v, t)| json!({
+                            "name": cxt.name_str(*v),
+                            "type": t.gen(&cxt),
+                        })).collect::<Vec<_>>(),
+                        "body": block(&mut cxt, body),
+                        "result": x.as_ref().map(|x| x.json(&cxt)),
+                        "type": t.gen(&cxt),
+                    })
+                }
+            }
+        }
+    }
+}
+
+// LOWERING
+
+/// The global arrays that hold the values of an enum with data when enum pools are on.
+/// Each value of the enum is an `int` index into these arrays, and constructing a variant appends to them.
+struct EnumPool {
+    variants: Vec<RawSym>,
+    /// The variant of each value, as its index in `variants`
+    tag: JVar,
+    /// The number of values that have been constructed
+    len: JVar,
+    /// The arrays for the fields of each variant, in the same order as `variants`
+    fields: Vec<Vec<(JVar, JTy)>>,
+}
+
+pub struct Cxt<'a> {
+    bindings: &'a mut Bindings,
+    scopes: Vec<(usize, usize, usize)>,
+    vars: Vec<(Sym, JVars)>,
+    tys: HashMap<JVar, JTy>,
+    fn_ids: Vec<(FnId, JFnId)>,
+    fn_ret_tys: HashMap<JFnId, JTys>,
+    inline_fns: HashMap<JFnId, (Vec<(Sym, Type)>, Term)>,
+    /// Extern functions, which take and return Java arrays rather than boxed dynamic arrays
+    extern_fns: HashSet<JFnId>,
+    /// The default values of functions' last parameters, with the index of the first one that has one
+    defaults: HashMap<FnId, (usize, Vec<Term>)>,
+    types: Vec<(TypeId, JClass)>,
+    block: Vec<JStmt>,
+    /// (loop, loop label, start of the block in `block`)
+    blocks: Vec<(Option<JBlock>, Option<RawSym>, usize)>,
+    /// The label of the loop statement being lowered, taken by the loop when it calls `push_loop()`
+    loop_label: Option<RawSym>,
+    /// Exception classes thrown with `throw` in the current function, which are added to its `throws` clause
+    thrown: Vec<RawSym>,
+    current_fn: JFnId,
+    /// Where the current function's locals start in `vars`, which lambdas need to copy to capture them
+    fn_vars: usize,
+    items: Vec<JItem>,
+    /// Java static methods the backend can call, as (name, Java path, return type)
+    predef_defs: Vec<(RawSym, RawSym, Type)>,
+    predefs: Vec<(RawSym, JFnId)>,
+    /// Java methods that are only declared once lowering uses them, like the `HashMap` methods,
+    /// so they don't change the names in programs that don't use them
+    lazy_predefs: Vec<(RawSym, JFnId)>,
+    enum_wrappers: HashMap<JClass, JClass>,
+    /// Enums without any data, which are plain Java enums and can be compared by reference
+    enums: HashSet<JClass>,
+    /// The type parameters of generic classes, including extern ones
+    generic_classes: HashMap<JClass, Vec<RawSym>>,
+    /// Whether enums without data or methods are lowered to `int`s instead of Java enums
+    enums_as_ints: bool,
+    /// Enums that are lowered to `int`s, with their variants in order, so each variant is its index
+    int_enums: HashMap<JClass, Vec<RawSym>>,
+    /// Whether enums with data are stored in global arrays instead of as objects of a wrapper class
+    pooled_enums: bool,
+    enum_pools: HashMap<JClass, EnumPool>,
+    /// Whether dynamic arrays of tuples are split into one array per element (struct-of-arrays)
+    soa: bool,
+    /// The classes used for array elements when `soa` is off
+    tuple_classes: Vec<(JClass, Vec<(RawSym, JTy)>)>,
+    /// The module each tuple class has been emitted in
+    tuple_modules: HashMap<JClass, RawSym>,
+    /// Fields declared `volatile`
+    volatile: HashSet<JVar>,
+    /// Variables declared `final`
+    finals: HashSet<JVar>,
+    /// The Java visibility of fields, which are package-private if they're not in here
+    visibility: HashMap<JVar, Visibility>,
+    /// The class each static method is called on
+    static_methods: HashMap<JFnId, JClass>,
+    /// Whether functions returning tuples of the same types share the static fields they return into
+    share_returns: bool,
+    /// The shared return fields for each tuple of return types, and the module they're declared in
+    ret_pools: Vec<(Vec<JTy>, Vec<JVar>, RawSym)>,
+    /// Whether functions returning two or more booleans pack them into the bits of one `int` field
+    pack_bools: bool,
+    /// The capacity to give the next empty dynamic array, if it's known how many elements will be pushed
+    array_capacity: Option<usize>,
+    /// The capacity of empty dynamic arrays when `array_capacity` isn't known
+    array_init_cap: usize,
+    /// How much the capacity of a dynamic array is multiplied by when it runs out of space
+    array_growth: f64,
+    /// What the generated Java is indented with
+    indent_style: IndentStyle,
+    /// How many levels of indentation the generated Java can have, if it's limited
+    max_indent: Option<usize>,
+    /// Whether the generated Java leaves out indentation and newlines
+    minify: bool,
+    /// What goes between a name and its number in mangled names
+    separator: String,
+    /// Whether statements remember where they came from so the generated Java can have comments pointing to the source
+    debug_comments: bool,
+    /// Whether string literals that appear more than once in a module are hoisted into constants
+    intern_strings: bool,
+    /// Whether each dynamic array is one object holding its Java arrays and length, instead of separate values
+    boxed_arrays: bool,
+    /// The tuple classes used for boxed dynamic arrays
+    array_classes: HashSet<JClass>,
+    /// Whether dynamic array accesses check the index against the array's length
+    bounds_checks: bool,
+    /// Whether `assert` statements are checked, or lowered to nothing
+    assertions_enabled: bool,
+    /// Whether functions with a `throws` clause rethrow checked exceptions as `RuntimeException` instead
+    unchecked: bool,
+    /// Whether the value of the next term to be lowered is unused, so `if` and `match` don't need result variables
+    discard: bool,
+    /// The first error found while lowering, which stops the module from being generated
+    error: Option<BackendError>,
+    next: u64,
+    package: String,
+}
+impl<'a> Cxt<'a> {
+    pub fn new(bindings: &'a mut Bindings, package: impl Into<String>) -> Self {
+        let mut cxt = Cxt {
+            bindings,
+            scopes: Vec::new(),
+            vars: Vec::new(),
+            tys: HashMap::new(),
+            fn_ids: Vec::new(),
+            fn_ret_tys: HashMap::new(),
+            inline_fns: HashMap::new(),
+            extern_fns: HashSet::new(),
+            defaults: HashMap::new(),
+            types: Vec::new(),
+            block: Vec::new(),
+            blocks: Vec::new(),
+            loop_label: None,
+            thrown: Vec::new(),
+            current_fn: JFnId(0),
+            fn_vars: usize::MAX,
+            items: Vec::new(),
+            predef_defs: Vec::new(),
+            predefs: Vec::new(),
+            lazy_predefs: Vec::new(),
+            enum_wrappers: HashMap::new(),
+            enums: HashSet::new(),
+            generic_classes: HashMap::new(),
+            enums_as_ints: false,
+            int_enums: HashMap::new(),
+            pooled_enums: false,
+            enum_pools: HashMap::new(),
+            soa: true,
+            tuple_classes: Vec::new(),
+            tuple_modules: HashMap::new(),
+            volatile: HashSet::new(),
+            finals: HashSet::new(),
+            visibility: HashMap::new(),
+            static_methods: HashMap::new(),
+            share_returns: false,
+            ret_pools: Vec::new(),
+            pack_bools: false,
+            array_capacity: None,
+            array_init_cap: 8,
+            array_growth: 2.0,
+            indent_style: IndentStyle::Tabs,
+            max_indent: Some(5),
+            minify: false,
+            separator: "$".into(),
+            debug_comments: false,
+            intern_strings: false,
+            unchecked: false,
+            boxed_arrays: false,
+            bounds_checks: false,
+            assertions_enabled: false,
+            array_classes: HashSet::new(),
+            discard: false,
+            error: None,
+            next: 0,
+            package: package.into(),
+        };
+        cxt.add_predef("arraycopy", "System.arraycopy", Type::Unit);
+        cxt.add_predef("codePointAt", "codePointAt", Type::I32);
+        cxt.add_predef("charAt", "charAt", Type::Char);
+        cxt.add_predef("length", "length", Type::I32);
+        cxt.add_predef("substring", "substring", Type::Str);
+        cxt.add_predef("startsWith", "startsWith", Type::Bool);
+        cxt.add_predef("endsWith", "endsWith", Type::Bool);
+        cxt.add_predef("contains", "contains", Type::Bool);
+        cxt.add_predef("parseInt", "Integer.parseInt", Type::I32);
+        cxt.add_predef("parseLong", "Long.parseLong", Type::I64);
+        cxt.add_predef("valueOf", "String.valueOf", Type::Str);
+        cxt.add_predef("append", "append", Type::StrBuilder);
+        cxt.add_predef("toString", "toString", Type::Str);
+        cxt
+    }
+
+    /// Registers a Java method that lowering code can call by name with `predef()`, like `System.arraycopy`.
+    /// Calling it on an object with `JTerm::Call(Some(obj), ..)` uses the path as the method name, like `codePointAt`.
+    /// The arguments aren't checked, so it's up to the caller to pass the right ones.
+    /// User code can't call these, so `--predef` declares an `extern fn` that every module imports instead.
+    fn add_predef(&mut self, name: &str, path: &str, ret_ty: Type) {
+        let name = self.bindings.raw(name);
+        let path = self.bindings.raw(path);
+        self.predef_defs.push((name, path, ret_ty));
+    }
+
+    /// Sets whether dynamic arrays of tuples are stored as one array per element (struct-of-arrays, the default),
+    /// or as a single array of objects with a field for each element (array-of-structs).
+    pub fn set_soa(&mut self, soa: bool) {
+        self.soa = soa;
+    }
+
+    /// Sets whether string literals that appear more than once in a module are replaced with a `static final String`,
+    /// which makes the generated code smaller when the same long string is used in many places.
+    pub fn set_intern_strings(&mut self, intern_strings: bool) {
+        self.intern_strings = intern_strings;
+    }
+
+    /// Sets whether each dynamic array is stored as a single object holding its Java arrays and length,
+    /// instead of as separate variables for each (the default).
+    /// This costs a field access for each array operation, but arrays passed to functions and stored in other variables
+    /// then always share the same length.
+    pub fn set_boxed_arrays(&mut self, boxed_arrays: bool) {
+        self.boxed_arrays = boxed_arrays;
+    }
+
+    /// Sets whether each access to a dynamic array checks that the index is less than the array's length,
+    /// and throws an `ArrayIndexOutOfBoundsException` if it isn't.
+    /// Without this, an index past the length but within the capacity of the Java array silently succeeds.
+    pub fn set_bounds_checks(&mut self, bounds_checks: bool) {
+        self.bounds_checks = bounds_checks;
+    }
+
+    /// Sets whether `assert` statements throw a `RuntimeException` when their condition is false.
+    /// When this is off, asserts aren't evaluated at all, so they cost nothing.
+    pub fn set_assertions_enabled(&mut self, assertions_enabled: bool) {
+        self.assertions_enabled = assertions_enabled;
+    }
+
+    /// Sets the capacity that empty dynamic arrays start with, 8 by default.
+    /// This must be at least 1, since capacity grows by multiplying it.
+    pub fn set_array_init_cap(&mut self, cap: usize) {
+        assert!(cap > 0, "array capacity must be at least 1");
+        self.array_init_cap = cap;
+    }
+
+    /// Sets the factor that a dynamic array's capacity is multiplied by when a push doesn't fit, 2 by default.
+    /// A factor that isn't a whole number is rounded to hundredths, and the new capacity is rounded up.
+    pub fn set_array_growth(&mut self, growth: f64) {
+        assert!(growth > 1.0, "array growth factor must be more than 1");
+        self.array_growth = growth;
+    }
+
+    /// Sets whether the generated Java is indented with tabs, the default, or a number of spaces per level.
+    pub fn set_indent_style(&mut self, style: IndentStyle) {
+        self.indent_style = style;
+    }
+
+    /// Sets the deepest indentation level in the generated Java, 5 by default; lines nested deeper are indented the same as that level.
+    /// `None` indents every level, which can make deeply nested code very wide.
+    pub fn set_max_indent(&mut self, max: Option<usize>) {
+        self.max_indent = max;
+    }
+
+    /// Sets whether the generated Java is minified, leaving out indentation and most newlines to make the output smaller.
+    /// Inline Java still ends with a newline, so `//` comments in it don't swallow the following code.
+    pub fn set_minify(&mut self, minify: bool) {
+        self.minify = minify;
+    }
+
+    /// Sets what goes between a name and its number in mangled names, `$` by default, like `x$12`.
+    /// It's also used in the names of the fields that tuples are returned into and that enum variants store their members in.
+    /// It must be made of ASCII letters, digits, `_` and `$` so that mangled names are still legal Java identifiers.
+    pub fn set_separator(&mut self, sep: &str) {
+        assert!(valid_separator(sep), "invalid name separator '{}'", sep);
+        self.separator = sep.to_string();
+    }
+
+    /// Sets whether statements in the generated Java are preceded by comments like `// main.bt:12` giving the line they came from.
+    /// This is off by default, since it makes the output bigger.
+    pub fn set_debug_comments(&mut self, debug_comments: bool) {
+        self.debug_comments = debug_comments;
+    }
+
+    /// Sets whether enums that don't have any data or methods are lowered to `int`s, one for each variant in order,
+    /// instead of Java enums. Comparing and matching on them is then cheaper, but they print as numbers.
+    /// This must be set before `declare_p1()`.
+    pub fn set_enums_as_ints(&mut self, enums_as_ints: bool) {
+        self.enums_as_ints = enums_as_ints;
+    }
+
+    /// Sets whether enums with data and no methods are stored as parallel global arrays, one for the variant of each value
+    /// and one for each field of each variant, instead of as objects of a wrapper class.
+    /// A value is then just an `int` index into the arrays, but the arrays only ever grow, so values are never freed.
+    /// This must be set before `declare_p1()`.
+    pub fn set_pooled_enums(&mut self, pooled_enums: bool) {
+        self.pooled_enums = pooled_enums;
+    }
+
+    /// Sets whether functions that return tuples of the same types share one set of static fields to return them in,
+    /// instead of each function having its own. The caller copies the fields into locals right after the call,
+    /// but a function that calls another function sharing its fields while evaluating its return values
+    /// (other than the first) would overwrite the ones it's already stored, so those values are stored in locals first.
+    pub fn set_share_returns(&mut self, share_returns: bool) {
+        self.share_returns = share_returns;
+    }
+
+    /// Sets whether functions that return a tuple of 2 to 32 booleans store them as the bits of one `int` field,
+    /// which the caller unpacks with a mask, instead of storing each in its own field.
+    pub fn set_pack_bool_returns(&mut self, pack_bools: bool) {
+        self.pack_bools = pack_bools;
+    }
+
+    /// Sets whether functions declared with `throws` catch checked exceptions and rethrow them as `RuntimeException`,
+    /// so that no Java method has a `throws` clause and callers don't need to declare anything.
+    /// ByteC doesn't know which extern methods throw, so this applies to the whole body of each function with `throws`.
+    pub fn set_unchecked_exceptions(&mut self, unchecked: bool) {
+        self.unchecked = unchecked;
+    }
+
+    fn var(&self, s: Sym) -> Option<JVars> {
+        self.vars
+            .iter()
+            .rfind(|(k, _v)| *k == s)
+            .map(|(_k, v)| v.clone())
+    }
+    fn fun(&self, s: FnId) -> Option<JFnId> {
+        self.fn_ids
+            .iter()
+            .rfind(|(k, _v)| *k == s)
+            .map(|(_k, v)| *v)
+    }
+    fn class(&self, s: TypeId) -> Option<JClass> {
+        self.types.iter().rfind(|(k, _v)| *k == s).map(|(_k, v)| *v)
+    }
+    fn predef(&mut self, name: &str) -> JFnId {
+        let name = self.bindings.raw(name);
+        self.predefs.iter().find(|(x, _)| *x == name).unwrap().1
+    }
+
+    /// Like `predef()`, but for a method that's declared the first time it's used
+    fn lazy_predef(&mut self, path: &str) -> JFnId {
+        let path = self.bindings.raw(path);
+        if let Some((_, f)) = self.lazy_predefs.iter().find(|(x, _)| *x == path) {
+            return *f;
+        }
+        let f = self.fresh_fn();
+        self.lazy_predefs.push((path, f));
+        f
+    }
+
+    /// Boxes a primitive with `Integer.valueOf()` and friends before it's passed to a `HashMap`.
+    /// Methods like `get()` take an `Object`, so Java's own boxing would make an `Integer` even if the keys are `Long`s.
+    fn boxed(&mut self, x: JTerm) -> JTerm {
+        let class = match x.ty() {
+            JTy::I8 => "Byte",
+            JTy::I16 => "Short",
+            JTy::I32 => "Integer",
+            JTy::I64 => "Long",
+            JTy::F32 => "Float",
+            JTy::F64 => "Double",
+            JTy::Bool => "Boolean",
+            JTy::Char => "Character",
+            _ => return x,
+        };
+        let f = self.lazy_predef(&format!("{}.valueOf", class));
+        let t = x.ty();
+        JTerm::Call(None, f, vec![x], t)
+    }
+
+    /// Records an error, keeping the first one if there's already been one
+    fn error(&mut self, e: BackendError) {
+        if self.error.is_none() {
+            self.error = Some(e);
+        }
+    }
+
+    /// The loop a `break` or `continue` with an optional label jumps to, or records an error if there isn't one
+    fn loop_block(&mut self, kw: &'static str, label: Option<RawSym>) -> Option<JBlock> {
+        let k = match label {
+            Some(l) => self.named_block(l),
+            None => self.block_label(),
+        };
+        if k.is_none() {
+            let e = match label {
+                Some(l) => BackendError::UnknownLabel(kw, self.bindings.resolve_raw(l).to_string()),
+                None => BackendError::OutsideLoop(kw),
+            };
+            self.error(e);
+        }
+        k
+    }
+
+    fn block_label(&self) -> Option<JBlock> {
+        self.blocks.iter().rev().find_map(|(x, _, _)| x.clone())
+    }
+    /// Finds the innermost loop with the label `label`
+    fn named_block(&self, label: RawSym) -> Option<JBlock> {
+        self.blocks
+            .iter()
+            .rev()
+            .find(|(_, l, _)| *l == Some(label))
+            .and_then(|(x, _, _)| *x)
+    }
+    fn push_loop(&mut self, k: JBlock, label: Option<RawSym>) {
+        self.push();
+        self.blocks.push((Some(k), label, self.block.len()));
+    }
+    /// Keeps the side effects of terms whose values are unused
+    fn discard_terms(&mut self, terms: JTerms) {
+        for mut i in terms {
+            let mut effects = SideEffects(false);
+            i.map(&mut effects);
+            if effects.0 {
+                self.block.push(JStmt::Term(i));
+            }
+        }
+    }
+
+    /// Stores the value of a branch of an `if` or `match` in the result variables, which are created by the first branch.
+    /// If the value is discarded there aren't any result variables, and only side effects are kept.
+    fn branch_result(
+        &mut self,
+        body: JTerms,
+        vars: &mut Option<Vec<(JVar, RawSym, JTy)>>,
+        discard: bool,
+    ) {
+        if discard {
+            self.discard_terms(body);
+            return;
+        }
+        // A branch that always jumps somewhere else doesn't have a value, so the result variables come from another branch
+        if self.block[self.blocks.last().unwrap().2..]
+            .iter()
+            .any(JStmt::terminates)
+        {
+            return;
+        }
+        let vars = vars.get_or_insert_with(|| {
+            body.ty()
+                .into_iter()
+                .enumerate()
+                .map(|(i, t)| {
+                    let var = self.fresh_var(false);
+                    self.tys.insert(var, t.clone());
+                    (var, self.bindings.raw(format!("_then${}", i)), t)
+                })
+                .collect()
+        });
+        for ((var, _, _), t) in vars.iter().zip(body) {
+            self.block.push(JStmt::Set(JLVal::Var(*var), None, t));
+        }
+    }
+
+    /// Declares the result variables of an `if` or `match`, and returns them as its value
+    fn declare_results(&mut self, vars: Option<Vec<(JVar, RawSym, JTy)>>) -> JTerms {
+        let mut ret = Vec::new();
+        for (var, raw, ty) in vars.unwrap_or_default() {
+            self.block.push(JStmt::Let(raw, ty.clone(), var, None));
+            ret.push(JTerm::Var(var, ty));
+        }
+        JTerms::Tuple(ret)
+    }
+
+    /// Whether the innermost block hasn't had any statements added yet
+    fn block_empty(&self) -> bool {
+        self.block.len() == self.blocks.last().unwrap().2
+    }
+
+    /// Implies push()
+    fn push_block(&mut self) {
+        self.push();
+        self.blocks.push((None, None, self.block.len()));
+    }
+    fn pop_block(&mut self) -> Vec<JStmt> {
+        self.pop();
+        self.block.split_off(self.blocks.pop().unwrap().2)
+    }
+
+    fn push(&mut self) {
+        self.scopes
+            .push((self.vars.len(), self.fn_ids.len(), self.types.len()));
+    }
+    fn pop(&mut self) {
+        let (v, i, t) = self.scopes.pop().unwrap();
+        self.vars.truncate(v);
+        self.fn_ids.truncate(i);
+        self.types.truncate(t);
+    }
+
+    fn fresh_var(&mut self, public: bool) -> JVar {
+        self.next += 1;
+        JVar(self.next, public)
+    }
+    /// The field of an enum wrapper class that member `n` of `variant` is stored in
+    fn enum_prop(&mut self, variant: RawSym, n: usize) -> RawSym {
+        let prop = format!(
+            "_enum{sep}{}{sep}{}",
+            self.bindings.resolve_raw(variant),
+            n,
+            sep = self.separator
+        );
+        self.bindings.raw(prop)
+    }
+    fn fresh_fn(&mut self) -> JFnId {
+        self.next += 1;
+        JFnId(self.next)
+    }
+    fn fresh_class(&mut self) -> JClass {
+        self.next += 1;
+        JClass(self.next)
+    }
+    fn fresh_block(&mut self) -> JBlock {
+        self.next += 1;
+        JBlock(self.next)
+    }
+
+    /// The class used for array elements of these types when struct-of-arrays is disabled
+    /// The shared return fields for each function that returns a tuple, once they've been created for its return types
+    fn ret_vars(&self) -> HashMap<JFnId, Vec<JVar>> {
+        self.fn_ret_tys
+            .iter()
+            .filter_map(|(f, t)| {
+                let t = t.clone().to_vec();
+                let (_, vars, _) = self.ret_pools.iter().find(|(t2, _, _)| *t2 == t)?;
+                Some((*f, vars.clone()))
+            })
+            .collect()
+    }
+
+    fn tuple_class(&mut self, tys: Vec<JTy>) -> JClass {
+        if let Some((class, _)) = self
+            .tuple_classes
+            .iter()
+            .find(|(_, fields)| fields.iter().map(|(_, t)| t).eq(&tys))
+        {
+            return *class;
+        }
+        let class = self.fresh_class();
+        let fields = tys
+            .into_iter()
+            .enumerate()
+            .map(|(i, t)| (self.bindings.raw(format!("_{}", i)), t))
+            .collect();
+        self.tuple_classes.push((class, fields));
+        class
+    }
+    fn tuple_fields(&self, ty: &JTy) -> Option<Vec<(RawSym, JTy)>> {
+        match ty {
+            // Boxed arrays use tuple classes, but they aren't split up like tuples
+            JTy::Class(c) if !self.array_classes.contains(c) => self.class_fields(*c),
+            _ => None,
+        }
+    }
+    fn class_fields(&self, c: JClass) -> Option<Vec<(RawSym, JTy)>> {
+        self.tuple_classes
+            .iter()
+            .find(|(x, _)| *x == c)
+            .map(|(_, fields)| fields.clone())
+    }
+    /// Packs the terms for an array element into a tuple object if struct-of-arrays is disabled
+    fn pack(&mut self, x: JTerms) -> JTerms {
+        if !self.soa && x.len() > 1 {
+            let class = self.tuple_class(x.ty().to_vec());
+            JTerms::One(JTerm::ClassNew(class, x.to_vec()))
+        } else {
+            x
+        }
+    }
+    /// The class for a boxed dynamic array with these Java arrays and length
+    fn array_class(&mut self, tys: Vec<JTy>) -> JClass {
+        let class = self.tuple_class(tys);
+        self.array_classes.insert(class);
+        class
+    }
+    /// Packs the Java arrays and length of a dynamic array into one object if arrays are boxed
+    fn box_array(&mut self, x: JTerms) -> JTerms {
+        if self.boxed_arrays {
+            let class = self.array_class(x.ty().to_vec());
+            JTerms::One(JTerm::ClassNew(class, x.to_vec()))
+        } else {
+            x
+        }
+    }
+    /// If bounds checks are on, throws when `idx` isn't in `0..len`; `idx` is used several times, so it should be simple
+    fn check_bounds(&mut self, idx: &JTerm, len: JTerm) {
+        if !self.bounds_checks {
+            return;
+        }
+        let binop = |op, a, b| JTerm::BinOp(op, Box::new(a), Box::new(b));
+        let mut lit = |s| JTerm::Lit(JLit::Str(self.bindings.raw(s)));
+        let cond = binop(
+            BinOp::Or,
+            binop(BinOp::Lt, idx.clone(), JTerm::Lit(JLit::Int(0))),
+            binop(BinOp::Geq, idx.clone(), len.clone()),
+        );
+        let msg = binop(
+            BinOp::Add,
+            binop(
+                BinOp::Add,
+                binop(BinOp::Add, lit("Index "), idx.clone()),
+                lit(" out of bounds for length "),
+            ),
+            len,
+        );
+        let exception = self.bindings.raw("ArrayIndexOutOfBoundsException");
+        self.block.push(JStmt::If(
+            cond,
+            vec![JStmt::ThrowNew(exception, msg)],
+            Vec::new(),
+        ));
+    }
+    /// If bounds checks are on, throws when `x` isn't in the range of a `char`; `x` is used several times, so it should be simple
+    fn check_char_range(&mut self, x: &JTerm) {
+        if !self.bounds_checks {
+            return;
+        }
+        let binop = |op, a, b| JTerm::BinOp(op, Box::new(a), Box::new(b));
+        let mut lit = |s| JTerm::Lit(JLit::Str(self.bindings.raw(s)));
+        let cond = binop(
+            BinOp::Or,
+            binop(BinOp::Lt, x.clone(), JTerm::Lit(JLit::Int(0))),
+            binop(BinOp::Gt, x.clone(), JTerm::Lit(JLit::Int(0xFFFF))),
+        );
+        let msg = binop(
+            BinOp::Add,
+            binop(BinOp::Add, lit("Value "), x.clone()),
+            lit(" out of range for char"),
+        );
+        let exception = self.bindings.raw("IllegalArgumentException");
+        self.block.push(JStmt::If(
+            cond,
+            vec![JStmt::ThrowNew(exception, msg)],
+            Vec::new(),
+        ));
+    }
+    /// After the length of a dynamic array has been increased, reallocates its Java arrays with more capacity if they're too small
+    fn grow_array(&mut self, arrs: &[JTerm], len: &JTerm) {
+        self.realloc_array(arrs, len, None)
+    }
+    /// Reallocates the Java arrays of a dynamic array with capacity `new_cap` (or the grown old capacity) if they're smaller than `len`
+    fn realloc_array(&mut self, arrs: &[JTerm], len: &JTerm, new_cap: Option<&JTerm>) {
+        let cap = JTerm::Prop(
+            Box::new(arrs[0].clone()),
+            Prop::Raw(self.bindings.raw("length")),
+            JTy::I32,
+        );
+        let too_small = JTerm::BinOp(BinOp::Gt, Box::new(len.clone()), Box::new(cap));
+        let mut block = Vec::new();
+        for arr in arrs {
+            let sarr = arr
+                .clone()
+                .to_lval()
+                .expect("growing an array requires an lvalue");
+            // let old = arr;
+            // arr = new T[old.length * growth];
+            // System.arraycopy(old, 0, arr, 0, old.length);
+
+            let old = self.fresh_var(false);
+            let raw = self.bindings.raw("$_old_array");
+            block.push(JStmt::Let(raw, arr.ty(), old, Some(arr.clone())));
+
+            let cap = JTerm::Prop(
+                Box::new(JTerm::Var(old, arr.ty())),
+                Prop::Raw(self.bindings.raw("length")),
+                JTy::I32,
+            );
+            let new_cap = new_cap
+                .cloned()
+                .unwrap_or_else(|| self.grown_cap(cap.clone()));
+            block.push(JStmt::Set(
+                sarr,
+                None,
+                JTerm::ArrayNew(Box::new(new_cap), arr.ty()),
+            ));
+
+            let copy_fn = self.predef("arraycopy");
+            let call = JStmt::MultiCall(
+                None,
+                copy_fn,
+                vec![
+                    JTerm::Var(old, arr.ty()),
+                    JTerm::Lit(JLit::Int(0)),
+                    arr.clone(),
+                    JTerm::Lit(JLit::Int(0)),
+                    cap,
+                ],
+                Vec::new(),
+            );
+            block.push(call);
+        }
+        self.block.push(JStmt::If(too_small, block, Vec::new()));
+    }
+    /// The new capacity for a dynamic array that's run out of space, rounding up if the growth factor isn't a whole number
+    fn grown_cap(&self, cap: JTerm) -> JTerm {
+        let binop = |op, a, b| JTerm::BinOp(op, Box::new(a), Box::new(b));
+        if self.array_growth.fract() == 0.0 {
+            return binop(
+                BinOp::Mul,
+                cap,
+                JTerm::Lit(JLit::Int(self.array_growth as i32)),
+            );
+        }
+        // (cap * num + den - 1) / den, which is cap * growth rounded up
+        let mut num = (self.array_growth * 100.0).round() as i32;
+        let mut den = 100;
+        let (mut a, mut b) = (num, den);
+        while b != 0 {
+            (a, b) = (b, a % b);
+        }
+        num /= a;
+        den /= a;
+        binop(
+            BinOp::Div,
+            binop(
+                BinOp::Add,
+                binop(BinOp::Mul, cap, JTerm::Lit(JLit::Int(num))),
+                JTerm::Lit(JLit::Int(den - 1)),
+            ),
+            JTerm::Lit(JLit::Int(den)),
+        )
+    }
+    /// Whether values of this class are lowered to `int`s, because it's an enum lowered to ints or stored in a pool
+    fn int_enum(&self, class: JClass) -> bool {
+        self.int_enums.contains_key(&class) || self.enum_pools.contains_key(&class)
+    }
+
+    /// Returns `x` from the current function
+    fn ret(&mut self, mut x: Vec<JTerm>) {
+        // Each return value is stored in a shared field before the next one is evaluated, so if evaluating one
+        // calls a function that shares those fields, all the values need to be evaluated first
+        if self.share_returns
+            && x.iter_mut().skip(1).any(|x| {
+                let mut effects = SideEffects(false);
+                x.map(&mut effects);
+                effects.0
+            })
+        {
+            x = x.into_iter().map(|x| self.cache("$_ret", x)).collect();
+        }
+        self.block.push(JStmt::Ret(self.current_fn, x));
+    }
+
+    /// Stores `x` in a local unless it's simple, so it can be used more than once
+    fn cache(&mut self, name: &str, x: JTerm) -> JTerm {
+        if x.simple() {
+            return x;
+        }
+        let raw = self.bindings.raw(name);
+        let var = self.fresh_var(false);
+        let ty = x.ty();
+        self.block.push(JStmt::Let(raw, ty.clone(), var, Some(x)));
+        JTerm::Var(var, ty)
+    }
+
+    /// Gets the Java arrays and length of a dynamic array, which are fields of the array object if arrays are boxed
+    fn unbox_array(&mut self, x: JTerms) -> JTerms {
+        if self.boxed_arrays {
+            let x = x.one();
+            let fields = match x.ty() {
+                JTy::Class(c) => self.class_fields(c).unwrap(),
+                _ => unreachable!(),
+            };
+            self.split(x, fields)
+        } else {
+            x
+        }
+    }
+    /// Splits an array element back into its fields if it's a tuple object
+    fn unpack(&mut self, x: JTerm) -> JTerms {
+        match self.tuple_fields(&x.ty()) {
+            Some(fields) => self.split(x, fields),
+            None => JTerms::One(x),
+        }
+    }
+    fn split(&mut self, mut x: JTerm, fields: Vec<(RawSym, JTy)>) -> JTerms {
+        if !x.simple() {
+            // Don't load the object again for each field
+            let raw = self.bindings.raw("$_elem");
+            let var = self.fresh_var(false);
+            let ty = x.ty();
+            self.block.push(JStmt::Let(raw, ty.clone(), var, Some(x)));
+            x = JTerm::Var(var, ty);
+        }
+        JTerms::Tuple(
+            fields
+                .into_iter()
+                .map(|(r, t)| JTerm::Prop(Box::new(x.clone()), Prop::Raw(r), t))
+                .collect(),
+        )
+    }
+}
+
+impl JTerm {
+    /// Rewrites multiplying or dividing an `int` or `long` by a power of two into a shift, which is a little smaller.
+    /// `x / 2^k` only becomes `x >> k` if `x` can't be negative, since the shift rounds down instead of toward zero.
+    fn strength_reduce(self) -> JTerm {
+        let log2 = |x: &JTerm| match x {
+            JTerm::Lit(JLit::Int(i)) if *i > 1 && (*i as u32).is_power_of_two() => {
+                Some(i.trailing_zeros() as i32)
+            }
+            JTerm::Lit(JLit::Long(i)) if *i > 1 && (*i as u64).is_power_of_two() => {
+                Some(i.trailing_zeros() as i32)
+            }
+            _ => None,
+        };
+        let shift = |op, x: Box<JTerm>, k| JTerm::BinOp(op, x, Box::new(JTerm::Lit(JLit::Int(k))));
+        match self {
+            JTerm::BinOp(op @ (BinOp::Mul | BinOp::Div), a, b)
+                if matches!(a.ty(), JTy::I32 | JTy::I64) && a.ty() == b.ty() =>
+            {
+                match (op, log2(&a), log2(&b)) {
+                    (BinOp::Mul, _, Some(k)) => shift(BinOp::BitShl, a, k),
+                    (BinOp::Mul, Some(k), _) => shift(BinOp::BitShl, b, k),
+                    (BinOp::Div, _, Some(k)) if a.non_negative() => shift(BinOp::BitShr, a, k),
+                    _ => JTerm::BinOp(op, a, b),
+                }
+            }
+            x => x,
+        }
+    }
+
+    /// Whether this `int` or `long` term is known to never be negative
+    fn non_negative(&self) -> bool {
+        match self {
+            JTerm::Lit(JLit::Int(i)) => *i >= 0,
+            JTerm::Lit(JLit::Long(i)) => *i >= 0,
+            // Java array lengths
+            JTerm::Prop(x, _, JTy::I32) => matches!(x.ty(), JTy::Array(_)),
+            JTerm::BinOp(BinOp::BitAnd, a, b) => a.non_negative() || b.non_negative(),
+            // A logical shift by anything but a multiple of the width fills the sign bit with 0
+            JTerm::BinOp(BinOp::BitUshr, a, b) => match (a.ty(), &**b) {
+                (JTy::I64, JTerm::Lit(JLit::Int(k))) => k & 63 != 0,
+                (_, JTerm::Lit(JLit::Int(k))) => k & 31 != 0,
+                _ => false,
+            },
+            JTerm::BinOp(BinOp::BitShr, a, _) => a.non_negative(),
+            JTerm::BinOp(BinOp::Div | BinOp::Mod, a, b) => a.non_negative() && b.non_negative(),
+            _ => false,
+        }
+    }
+
+    /// Whether this term is simple enough to be 1 bytecode instruction.
+    /// Simple instructions can be duplicated freely.
+    fn simple(&self) -> bool {
+        match self {
+            JTerm::Var(_, _)
+            | JTerm::Variant(_, _)
+            | JTerm::Lit(_)
+            | JTerm::Null(_)
+            | JTerm::SIndex(_, _)
+            | JTerm::This(_) => true,
             JTerm::Call(_, _, _, _)
             | JTerm::BinOp(_, _, _)
             | JTerm::Index(_, _, _)
@@ -1319,7 +3787,14 @@ impl JTerm {
             | JTerm::InlineJava(_, _)
             | JTerm::ArrayNew(_, _)
             | JTerm::ClassNew(_, _)
+            | JTerm::Super(_, _)
             | JTerm::Not(_)
+            | JTerm::BitNot(_)
+            | JTerm::Neg(_)
+            | JTerm::Ternary(_, _, _)
+            | JTerm::Cast(_, _)
+            | JTerm::InstanceOf(_, _)
+            | JTerm::Lambda(_, _, _, _)
             | JTerm::Array(_, _) => false,
         }
     }
@@ -1329,24 +3804,39 @@ impl JTerm {
             JTerm::Var(_, t) => t.clone(),
             JTerm::Null(t) => t.clone(),
             JTerm::This(s) => JTy::Class(*s),
+            JTerm::Cast(_, t) => t.clone(),
+            JTerm::InstanceOf(_, _) => JTy::Bool,
             JTerm::Lit(l) => match l {
+                JLit::Byte(_) => JTy::I8,
+                JLit::Short(_) => JTy::I16,
                 JLit::Int(_) => JTy::I32,
                 JLit::Long(_) => JTy::I64,
+                JLit::Float(_) => JTy::F32,
+                JLit::Double(_) => JTy::F64,
                 JLit::Str(_) => JTy::String,
                 JLit::Bool(_) => JTy::Bool,
+                JLit::Char(_) => JTy::Char,
             },
             JTerm::SIndex(v, _) => v[0].ty(),
             JTerm::Not(_) => JTy::Bool,
+            JTerm::BitNot(x) | JTerm::Neg(x) => x.ty(),
+            JTerm::Ternary(_, a, _) => a.ty(),
             JTerm::Call(_, _, _, t) => t.clone(),
             JTerm::Prop(_, _, t) => t.clone(),
-            JTerm::InlineJava(_, t) => t.clone(),
+            JTerm::InlineJava(_, t) | JTerm::Lambda(_, _, _, t) => t.clone(),
             JTerm::Array(_, t) => t.clone(),
             JTerm::ArrayNew(_, t) => t.clone(),
             JTerm::ClassNew(c, _) => JTy::Class(*c),
+            JTerm::Super(_, t) => t.clone(),
             JTerm::Index(_, _, t) => t.clone(),
+            JTerm::BinOp(BinOp::Add, _, b) if b.ty() == JTy::String => JTy::String,
             JTerm::BinOp(op, a, _) => match op.ty() {
                 BinOpType::Comp => JTy::Bool,
-                BinOpType::Arith => a.ty(),
+                // Java promotes chars to ints, but not bytes and shorts, since lowering casts those back
+                BinOpType::Arith => match a.ty() {
+                    JTy::Char => JTy::I32,
+                    t => t,
+                },
                 BinOpType::Logic => JTy::Bool,
             },
             JTerm::Variant(class, _) => JTy::Class(*class),
@@ -1354,6 +3844,39 @@ impl JTerm {
     }
 }
 
+impl JLVal {
+    fn ty(&self, cxt: &Cxt) -> Option<JTy> {
+        match self {
+            JLVal::Var(v) => cxt.tys.get(v).cloned(),
+            JLVal::Idx(l, _) => match l.ty(cxt)? {
+                JTy::Array(t) => Some(*t),
+                _ => None,
+            },
+            JLVal::SIdx(v, _) => v.first()?.ty(cxt),
+            JLVal::Prop(_, Prop::Var(m)) => cxt.tys.get(m).cloned(),
+            JLVal::Prop(x, Prop::Raw(r)) => match x.ty() {
+                JTy::Class(c) => cxt.class_fields(c),
+                _ => None,
+            }?
+            .into_iter()
+            .find(|(f, _)| f == r)
+            .map(|(_, t)| t),
+        }
+    }
+
+    fn to_term(&self, cxt: &Cxt) -> Option<JTerm> {
+        let ty = self.ty(cxt)?;
+        Some(match self {
+            JLVal::Var(v) => JTerm::Var(*v, ty),
+            JLVal::Idx(l, i) => JTerm::Index(Box::new(l.to_term(cxt)?), Box::new(i.clone()), ty),
+            JLVal::SIdx(v, i) => JTerm::SIndex(
+                v.iter().map(|x| x.to_term(cxt)).collect::<Option<_>>()?,
+                Box::new(i.clone()),
+            ),
+            JLVal::Prop(x, p) => JTerm::Prop(Box::new(x.clone()), *p, ty),
+        })
+    }
+}
 impl LValue {
     fn lower(&self, cxt: &mut Cxt, nvals: usize) -> MaybeList<JLVal> {
         match self {
@@ -1362,7 +3885,17 @@ impl LValue {
                 v.map(JLVal::Var)
             }
             LValue::Idx(v, idx, false) => {
-                let v = v.lower(cxt, nvals);
+                let mut v = v.lower(cxt, nvals);
+                let len = if cxt.boxed_arrays {
+                    // Assign to the Java arrays inside the array object, but not the length
+                    let arr = v.one().to_term(cxt).unwrap();
+                    let mut arrs = cxt.unbox_array(JTerms::One(arr)).to_vec();
+                    let len = arrs.pop();
+                    v = MaybeList::Tuple(arrs.into_iter().map(|x| x.to_lval().unwrap()).collect());
+                    len
+                } else {
+                    v.clone().to_vec().last().and_then(|x| x.to_term(cxt))
+                };
                 let mut idx = idx.lower(cxt).one();
                 if !idx.simple() {
                     // Don't recompute idx every time, store it in a local
@@ -1371,6 +3904,26 @@ impl LValue {
                     cxt.block.push(JStmt::Let(raw, JTy::I32, var, Some(idx)));
                     idx = JTerm::Var(var, JTy::I32);
                 }
+                if let Some(len) = len {
+                    cxt.check_bounds(&idx, len);
+                }
+                if !cxt.soa {
+                    // Assign to the fields of a tuple object in place
+                    let arr = v.clone().to_vec().first().and_then(|x| x.to_term(cxt));
+                    if let Some(arr) = arr {
+                        if let JTy::Array(t) = arr.ty() {
+                            if let Some(fields) = cxt.tuple_fields(&t) {
+                                let elem = JTerm::Index(Box::new(arr), Box::new(idx), *t);
+                                return MaybeList::Tuple(
+                                    fields
+                                        .into_iter()
+                                        .map(|(r, _)| JLVal::Prop(elem.clone(), Prop::Raw(r)))
+                                        .collect(),
+                                );
+                            }
+                        }
+                    }
+                }
                 v.map(|x| JLVal::Idx(Box::new(x), idx.clone()))
             }
             LValue::Idx(v, idx, true) => {
@@ -1412,54 +3965,281 @@ impl LValue {
     }
 }
 
+/// The most arms a match on an enum without data can have to be lowered to a chain of `==` comparisons instead of a `switch`.
+/// A `switch` on an enum costs 5 bytecodes plus the call to `ordinal()`, and each comparison costs 3,
+/// so with at most 3 arms (the last arm doesn't need a comparison) the chain is never more expensive.
+const MAX_CHAIN_ARMS: usize = 3;
+
 impl Term {
     fn lower(&self, cxt: &mut Cxt) -> JTerms {
+        // Only applies to this term, not any terms inside it
+        let discard = std::mem::take(&mut cxt.discard);
         JTerms::One(match self {
             Term::Var(s) => {
                 let var = cxt.var(*s).unwrap();
                 return var.map(|var| JTerm::Var(var, cxt.tys.get(&var).unwrap().clone()));
             }
-            Term::Null(t) => JTerm::Null(t.lower(cxt).one()),
-            Term::Selph(t) => {
-                let class = cxt.class(*t).unwrap();
-                if let Some(wrapper) = cxt.enum_wrappers.get(&class) {
-                    JTerm::This(*wrapper)
-                } else {
-                    JTerm::This(class)
+            // Enums lowered to ints use -1 for null, since it's not a variant or an index into a pool
+            Term::Null(Type::Class(c, _)) if cxt.int_enum(cxt.class(*c).unwrap()) => {
+                JTerm::Lit(JLit::Int(-1))
+            }
+            Term::Null(t) => JTerm::Null(t.lower(cxt).one()),
+            Term::OptionSome(x) => {
+                let x = x.lower(cxt);
+                return JTerms::Tuple(
+                    std::iter::once(JTerm::Lit(JLit::Bool(true)))
+                        .chain(x)
+                        .collect(),
+                );
+            }
+            Term::OptionNone(t) => {
+                let t = t.lower(cxt);
+                return JTerms::Tuple(
+                    std::iter::once(JTerm::Lit(JLit::Bool(false)))
+                        .chain(t.into_iter().map(|t| t.zero()))
+                        .collect(),
+                );
+            }
+            Term::Default(t) => return t.lower_default(cxt),
+            Term::Super(_) => panic!("super() is lowered along with its class"),
+            Term::Lambda(args, body, rty, ty) => {
+                let ty = ty.lower(cxt).one();
+                // Java passes primitive type arguments boxed
+                let boxed = match &ty {
+                    JTy::Generic(_, targs) => targs.clone(),
+                    _ => Vec::new(),
+                };
+                let locals: Vec<_> = cxt.vars[cxt.fn_vars.min(cxt.vars.len())..].to_vec();
+
+                // The lambda's body is a separate Java method, so it can't break out of loops or return from the function
+                let block = std::mem::take(&mut cxt.block);
+                let blocks = std::mem::take(&mut cxt.blocks);
+                let lambda_fn = cxt.fresh_fn();
+                let fn_id = std::mem::replace(&mut cxt.current_fn, lambda_fn);
+                cxt.push();
+                let mut params = Vec::new();
+                for (name, t) in args {
+                    let raw = *cxt.bindings.sym_path(*name).stem();
+                    let mut vars = Vec::new();
+                    for t in t.lower(cxt) {
+                        let var = cxt.fresh_var(false);
+                        cxt.tys.insert(var, t.clone());
+                        vars.push(var);
+                        // Unboxed into a local so `==` compares values
+                        if t.primitive() && boxed.contains(&t) {
+                            let param = cxt.fresh_var(false);
+                            let x = JTerm::Var(param, t.clone());
+                            cxt.block.push(JStmt::Let(raw, t.clone(), var, Some(x)));
+                            params.push((raw, param, t));
+                        } else {
+                            params.push((raw, var, t));
+                        }
+                    }
+                    cxt.vars.push((*name, JVars::Tuple(vars)));
+                }
+                let ret = body.lower(cxt);
+                let mut ret = match rty {
+                    Type::Unit => {
+                        for i in ret {
+                            cxt.block.push(JStmt::Term(i));
+                        }
+                        None
+                    }
+                    _ => Some(Box::new(ret.one())),
+                };
+                cxt.pop();
+                let mut body = std::mem::replace(&mut cxt.block, block);
+                cxt.blocks = blocks;
+                cxt.current_fn = fn_id;
+
+                // Java only allows capturing locals that are never assigned again, so captured locals are copied
+                // into new ones first; the type checker makes sure the lambda doesn't assign to them itself
+                let mut counter = UseCounter::default();
+                body.iter_mut().for_each(|s| s.map(&mut counter));
+                if let Some(x) = &mut ret {
+                    x.map(&mut counter);
+                }
+                let mut replacer = Replacer(HashMap::new());
+                for (s, vars) in locals {
+                    let raw = *cxt.bindings.sym_path(s).stem();
+                    for v in vars {
+                        if counter.count.contains_key(&v) || counter.mutated.contains(&v) {
+                            let ty = cxt.tys[&v].clone();
+                            let copy = cxt.fresh_var(false);
+                            cxt.tys.insert(copy, ty.clone());
+                            let x = JTerm::Var(v, ty.clone());
+                            cxt.block.push(JStmt::Let(raw, ty, copy, Some(x)));
+                            replacer.0.insert(v, copy);
+                        }
+                    }
+                }
+                body.iter_mut().for_each(|s| s.map(&mut replacer));
+                if let Some(x) = &mut ret {
+                    x.map(&mut replacer);
+                }
+                JTerm::Lambda(params, body, ret, ty)
+            }
+            Term::Selph(t) => {
+                let class = cxt.class(*t).unwrap();
+                if let Some(wrapper) = cxt.enum_wrappers.get(&class) {
+                    JTerm::This(*wrapper)
+                } else {
+                    JTerm::This(class)
+                }
+            }
+            Term::Not(x) => match x.lower(cxt).one() {
+                // !(a == b) is a != b, and the other way around
+                JTerm::BinOp(BinOp::Eq, a, b) => JTerm::BinOp(BinOp::Neq, a, b),
+                JTerm::BinOp(BinOp::Neq, a, b) => JTerm::BinOp(BinOp::Eq, a, b),
+                JTerm::Not(x) => *x,
+                JTerm::Lit(JLit::Bool(b)) => JTerm::Lit(JLit::Bool(!b)),
+                x => JTerm::Not(Box::new(x)),
+            },
+            Term::Cast(x, t) => {
+                let x = x.lower(cxt).one();
+                let t = t.lower(cxt).one();
+                // The result of a generic function, which Java already knows the type of
+                // unless it's a primitive, which is boxed in Java and needs to be unboxed to make `==` compare values
+                if x.ty().has_var() {
+                    return JTerms::One(match x {
+                        JTerm::Call(o, f, a, _) if !t.primitive() => JTerm::Call(o, f, a, t),
+                        x => JTerm::Cast(Box::new(x), t),
+                    });
+                }
+                if let JTy::Class(_) | JTy::Generic(_, _) = t {
+                    return JTerms::One(if x.ty() == t {
+                        x
+                    } else {
+                        JTerm::Cast(Box::new(x), t)
+                    });
+                }
+                let numeric = |t: &JTy| {
+                    matches!(
+                        t,
+                        JTy::I8 | JTy::I16 | JTy::I32 | JTy::I64 | JTy::F32 | JTy::F64 | JTy::Char
+                    )
+                };
+                if !numeric(&x.ty()) || !numeric(&t) {
+                    panic!(
+                        "backend: casts are only supported between numeric types, not {:?} to {:?}",
+                        x.ty(),
+                        t
+                    );
+                }
+                if x.ty() == t {
+                    return JTerms::One(x);
+                }
+                let mut x = x;
+                if t == JTy::Char && cxt.bounds_checks {
+                    if !x.simple() {
+                        let raw = cxt.bindings.raw("$_char");
+                        let var = cxt.fresh_var(false);
+                        let ty = x.ty();
+                        cxt.block.push(JStmt::Let(raw, ty.clone(), var, Some(x)));
+                        x = JTerm::Var(var, ty);
+                    }
+                    cxt.check_char_range(&x);
+                }
+                JTerm::Cast(Box::new(x), t)
+            }
+            Term::InstanceOf(x, t) => {
+                JTerm::InstanceOf(Box::new(x.lower(cxt).one()), cxt.class(*t).unwrap())
+            }
+            Term::BitNot(x) | Term::Neg(x) => {
+                let x = Box::new(x.lower(cxt).one());
+                let t = x.ty();
+                let x = match self {
+                    Term::BitNot(_) => JTerm::BitNot(x),
+                    _ => JTerm::Neg(x),
+                };
+                match t {
+                    // Like binary operators, these promote bytes and shorts to ints
+                    JTy::I8 | JTy::I16 => JTerm::Cast(Box::new(x), t),
+                    _ => x,
                 }
             }
-            Term::Not(x) => JTerm::Not(Box::new(x.lower(cxt).one())),
             Term::Lit(l, t) => match l {
                 Literal::Int(i) => match t {
+                    Type::I8 => JTerm::Lit(JLit::Byte(*i as i8)),
+                    Type::I16 => JTerm::Lit(JLit::Short(*i as i16)),
                     Type::I32 => JTerm::Lit(JLit::Int(*i as i32)),
                     Type::I64 => JTerm::Lit(JLit::Long(*i)),
                     _ => unreachable!(),
                 },
+                Literal::Float(f) => match t {
+                    Type::F32 => JTerm::Lit(JLit::Float(*f as f32)),
+                    Type::F64 => JTerm::Lit(JLit::Double(*f)),
+                    _ => unreachable!(),
+                },
                 Literal::Str(s) => JTerm::Lit(JLit::Str(*s)),
                 Literal::Bool(b) => JTerm::Lit(JLit::Bool(*b)),
+                Literal::Char(c) => JTerm::Lit(JLit::Char(*c)),
             },
-            Term::Break => {
-                cxt.block.push(JStmt::Break(
-                    cxt.block_label().expect("'break' outside of loop"),
-                ));
+            Term::Break(l) => {
+                if let Some(k) = cxt.loop_block("break", *l) {
+                    cxt.block.push(JStmt::Break(k));
+                }
                 return JTerms::empty();
             }
-            Term::Continue => {
-                cxt.block.push(JStmt::Continue(
-                    cxt.block_label().expect("'continue' outside of loop"),
-                ));
+            Term::Continue(l) => {
+                if let Some(k) = cxt.loop_block("continue", *l) {
+                    cxt.block.push(JStmt::Continue(k));
+                }
                 return JTerms::empty();
             }
             Term::Return(x) => {
                 let x = x.as_ref().map(|x| x.lower(cxt));
-                cxt.block.push(JStmt::Ret(
-                    cxt.current_fn,
-                    x.into_iter().flatten().collect(),
-                ));
+                cxt.ret(x.into_iter().flatten().collect());
                 return JTerms::empty();
             }
             Term::Variant(tid, s, v) => {
                 let class = cxt.class(*tid).unwrap();
+                if let Some(variants) = cxt.int_enums.get(&class) {
+                    let i = variants.iter().position(|x| x == s).unwrap();
+                    return JTerms::One(JTerm::Lit(JLit::Int(i as i32)));
+                }
+                if let Some(pool) = cxt.enum_pools.get(&class) {
+                    let i = pool.variants.iter().position(|x| x == s).unwrap();
+                    let (tag, len) = (pool.tag, pool.len);
+                    let fields = pool.fields[i].clone();
+                    // Constructing another value of this enum in a field could reallocate the arrays,
+                    // so the fields are evaluated before the new value's index is taken
+                    let mut vals = Vec::new();
+                    for x in v {
+                        for x in x.lower(cxt) {
+                            vals.push(cxt.cache("$_field", x));
+                        }
+                    }
+
+                    // let $_variant = len;
+                    // len = len + 1;
+                    let var = cxt.fresh_var(false);
+                    let raw = cxt.bindings.raw("$_variant");
+                    cxt.tys.insert(var, JTy::I32);
+                    let len = JTerm::Var(len, JTy::I32);
+                    cxt.block
+                        .push(JStmt::Let(raw, JTy::I32, var, Some(len.clone())));
+                    let idx = JTerm::Var(var, JTy::I32);
+                    cxt.block.push(JStmt::Set(
+                        len.clone().to_lval().unwrap(),
+                        Some(BinOp::Add),
+                        JTerm::Lit(JLit::Int(1)),
+                    ));
+
+                    // Every array in the pool needs room for the new value, not just this variant's
+                    let arrs: Vec<_> = std::iter::once((tag, JTy::Array(Box::new(JTy::I32))))
+                        .chain(cxt.enum_pools[&class].fields.iter().flatten().cloned())
+                        .map(|(v, t)| JTerm::Var(v, t))
+                        .collect();
+                    cxt.grow_array(&arrs, &len);
+                    let elem = |arr| JLVal::Idx(Box::new(JLVal::Var(arr)), idx.clone());
+                    cxt.block
+                        .push(JStmt::Set(elem(tag), None, JTerm::Lit(JLit::Int(i as i32))));
+                    for ((arr, _), x) in fields.into_iter().zip(vals) {
+                        cxt.block.push(JStmt::Set(elem(arr), None, x));
+                    }
+                    return JTerms::One(idx);
+                }
                 let variant = JTerm::Variant(class, *s);
                 if let Some(wrapper) = cxt.enum_wrappers.get(&class) {
                     let term = JTerm::ClassNew(*wrapper, Vec::new());
@@ -1476,8 +4256,7 @@ impl Term {
                     ));
                     let v: Vec<_> = v.iter().flat_map(|x| x.lower(cxt)).collect();
                     for (n, val) in v.into_iter().enumerate() {
-                        let prop = format!("_enum${}${}", cxt.bindings.resolve_raw(*s), n);
-                        let prop = cxt.bindings.raw(prop);
+                        let prop = cxt.enum_prop(*s, n);
                         cxt.block.push(JStmt::Set(
                             JLVal::Prop(term.clone(), Prop::Raw(prop)),
                             None,
@@ -1486,18 +4265,129 @@ impl Term {
                     }
                     term
                 } else {
+                    // Enums with fields always get a wrapper class or pooled arrays
                     assert_eq!(v.len(), 0);
                     variant
                 }
             }
             Term::Tuple(v) => return JTerms::Tuple(v.iter().flat_map(|x| x.lower(cxt)).collect()),
-            Term::TupleIdx(x, i) => {
+            Term::TupleIdx(x, i, tys) => {
+                // Nested tuples are flattened, so find where this element's components start
+                let start: usize = tys[..*i].iter().map(|t| t.lower(cxt).len()).sum();
+                let len = tys[*i].lower(cxt).len();
                 let x = x.lower(cxt);
-                x.to_vec().swap_remove(*i)
+                return JTerms::Tuple(x.to_vec().into_iter().skip(start).take(len).collect());
+            }
+            Term::StrMethod(s, m) => {
+                let s = s.lower(cxt).one();
+                match m {
+                    StrMethod::CodePointAt(i) => {
+                        let i = i.lower(cxt).one();
+                        let f = cxt.predef("codePointAt");
+                        JTerm::Call(Some(Box::new(s)), f, vec![i], JTy::I32)
+                    }
+                    StrMethod::CharAt(i) => {
+                        let i = i.lower(cxt).one();
+                        let f = cxt.predef("charAt");
+                        JTerm::Call(Some(Box::new(s)), f, vec![i], JTy::Char)
+                    }
+                    StrMethod::Len => {
+                        let f = cxt.predef("length");
+                        JTerm::Call(Some(Box::new(s)), f, Vec::new(), JTy::I32)
+                    }
+                    StrMethod::Substring(a, b) => {
+                        let a = a.lower(cxt).one();
+                        let b = b.lower(cxt).one();
+                        let f = cxt.predef("substring");
+                        JTerm::Call(Some(Box::new(s)), f, vec![a, b], JTy::String)
+                    }
+                    StrMethod::StartsWith(x) | StrMethod::EndsWith(x) | StrMethod::Contains(x) => {
+                        let x = x.lower(cxt).one();
+                        let f = cxt.predef(match m {
+                            StrMethod::StartsWith(_) => "startsWith",
+                            StrMethod::EndsWith(_) => "endsWith",
+                            _ => "contains",
+                        });
+                        JTerm::Call(Some(Box::new(s)), f, vec![x], JTy::Bool)
+                    }
+                }
+            }
+            Term::BuilderMethod(s, m) => {
+                let s = s.lower(cxt).one();
+                match m {
+                    BuilderMethod::Append(x) => {
+                        let x = x.lower(cxt).one();
+                        let f = cxt.predef("append");
+                        let call = JTerm::Call(Some(Box::new(s)), f, vec![x], JTy::StrBuilder);
+                        cxt.block.push(JStmt::Term(call));
+                        return JTerms::empty();
+                    }
+                    BuilderMethod::Finish => {
+                        let f = cxt.predef("toString");
+                        JTerm::Call(Some(Box::new(s)), f, Vec::new(), JTy::String)
+                    }
+                }
+            }
+            Term::Builtin(b, x) => {
+                let x = x.lower(cxt).one();
+                let (f, t) = match b {
+                    Builtin::ParseInt => ("parseInt", JTy::I32),
+                    Builtin::ParseLong => ("parseLong", JTy::I64),
+                    // Java picks the `valueOf` overload for the argument's type
+                    Builtin::ToStr => ("valueOf", JTy::String),
+                };
+                let f = cxt.predef(f);
+                JTerm::Call(None, f, vec![x], t)
+            }
+            Term::MapMethod(m, method) => {
+                let m = m.lower(cxt).one();
+                let vty = match m.ty() {
+                    JTy::Map(_, v) => *v,
+                    _ => unreachable!(),
+                };
+                let (name, args, ty) = match method {
+                    MapMethod::Get(k) => ("get", vec![k], vty.clone()),
+                    MapMethod::Put(k, v) => ("put", vec![k, v], vty.clone()),
+                    MapMethod::ContainsKey(k) => ("containsKey", vec![k], JTy::Bool),
+                    MapMethod::Remove(k) => ("remove", vec![k], vty.clone()),
+                };
+                let args = args
+                    .into_iter()
+                    .map(|x| {
+                        let x = x.lower(cxt).one();
+                        cxt.boxed(x)
+                    })
+                    .collect();
+                let f = cxt.lazy_predef(name);
+                let call = JTerm::Call(Some(Box::new(m)), f, args, ty);
+                match method {
+                    MapMethod::Put(_, _) | MapMethod::Remove(_) => {
+                        cxt.block.push(JStmt::Term(call));
+                        return JTerms::empty();
+                    }
+                    // Primitive values are boxed in the map, so they're unboxed to make `==` compare values
+                    MapMethod::Get(_) if vty.primitive() => JTerm::Cast(Box::new(call), vty),
+                    _ => call,
+                }
             }
             Term::Member(x, m) => {
                 let mut x = x.lower(cxt).one();
-                // TODO get actual type somehow
+                // Members of a generic class have the types it's instantiated with
+                let map: Vec<_> = match x.ty() {
+                    JTy::Generic(c, args) => {
+                        cxt.generic_classes[&c].iter().copied().zip(args).collect()
+                    }
+                    _ => Vec::new(),
+                };
+                let member = |cxt: &Cxt, x: JTerm, v: JVar| {
+                    let ty = cxt.tys.get(&v).unwrap();
+                    let prop = JTerm::Prop(Box::new(x), Prop::Var(v), ty.subst(&map));
+                    // A primitive type argument is boxed in Java, so it's unboxed to make `==` compare values
+                    match (ty, prop.ty()) {
+                        (JTy::TypeVar(_), t) if t.primitive() => JTerm::Cast(Box::new(prop), t),
+                        _ => prop,
+                    }
+                };
                 let m = cxt.var(*m).unwrap();
                 if m.len() > 1 {
                     if !x.simple() {
@@ -1507,16 +4397,9 @@ impl Term {
                         cxt.block.push(JStmt::Let(raw, ty.clone(), var, Some(x)));
                         x = JTerm::Var(var, ty.clone());
                     }
-                    return m.map(|v| {
-                        JTerm::Prop(
-                            Box::new(x.clone()),
-                            Prop::Var(v),
-                            cxt.tys.get(&v).unwrap().clone(),
-                        )
-                    });
+                    return m.map(|v| member(cxt, x.clone(), v));
                 } else {
-                    let m = m.one();
-                    JTerm::Prop(Box::new(x), Prop::Var(m), cxt.tys.get(&m).unwrap().clone())
+                    member(cxt, x, m.one())
                 }
             }
             Term::Constructor(t, args) => {
@@ -1536,37 +4419,43 @@ impl Term {
                 return JTerms::empty();
             }
             Term::Array(v, t, true) if v.is_empty() => {
-                let t = t.lower(cxt);
-                return JTerms::Tuple(
+                let mut t = Type::Array(Box::new(t.clone())).lower_unboxed(cxt).to_vec();
+                // The last type is the length
+                t.pop();
+                // We expand capacity by multiplying it, so don't allow creating an array with 0 capacity
+                let cap = cxt.array_capacity.take().unwrap_or(cxt.array_init_cap);
+                return cxt.box_array(JTerms::Tuple(
                     t.into_iter()
-                        .map(|ty| JTerm::Array(Vec::new(), JTy::Array(Box::new(ty))))
+                        .map(|ty| JTerm::ArrayNew(Box::new(JTerm::Lit(JLit::Int(cap as i32))), ty))
                         .chain(std::iter::once(JTerm::Lit(JLit::Int(0))))
                         .collect(),
-                );
+                ));
             }
-            Term::Array(v, _, true) => {
-                let mut v2 = Vec::new();
+            Term::Array(v, t, true) => {
+                // The element type comes from the array's type rather than the first element, which could be a class implementing it
+                let mut tys = Type::Array(Box::new(t.clone())).lower_unboxed(cxt).to_vec();
+                tys.pop();
+                let mut v2: Vec<_> = tys.into_iter().map(|ty| (ty, Vec::new())).collect();
                 let mut len = 0;
                 for i in v {
                     len += 1;
                     let i = i.lower(cxt);
+                    let i = cxt.pack(i);
 
                     // Split into tuple/struct members to put in arrays
-                    if v2.is_empty() {
-                        v2 = i.into_iter().map(|x| (x.ty(), vec![x])).collect();
-                    } else {
-                        assert_eq!(i.len(), v2.len());
-                        for ((_, arr), elem) in v2.iter_mut().zip(i) {
-                            arr.push(elem);
-                        }
+                    // Elements have the array's element type, type parameters can't be used in arrays, and elements
+                    // that always diverge are type errors, so they always lower to the same number of components
+                    assert_eq!(i.len(), v2.len());
+                    for ((_, arr), elem) in v2.iter_mut().zip(i) {
+                        arr.push(elem);
                     }
                 }
-                return JTerms::Tuple(
+                return cxt.box_array(JTerms::Tuple(
                     v2.into_iter()
-                        .map(|(ty, arr)| JTerm::Array(arr, JTy::Array(Box::new(ty))))
+                        .map(|(ty, arr)| JTerm::Array(arr, ty))
                         .chain(std::iter::once(JTerm::Lit(JLit::Int(len))))
                         .collect(),
-                );
+                ));
             }
             Term::Array(v, _t, false) => {
                 return JTerms::Tuple(v.iter().flat_map(|x| x.lower(cxt)).collect())
@@ -1580,39 +4469,125 @@ impl Term {
                     cxt.block.push(JStmt::Let(raw, JTy::I32, var, Some(len)));
                     len = JTerm::Var(var, JTy::I32);
                 }
-                return JTerms::Tuple(
-                    t.lower(cxt)
-                        .into_iter()
+                let t = t.lower(cxt);
+                if !cxt.soa && t.len() > 1 {
+                    // Fill the array with empty tuples, so the elements start out with default values like they would with separate arrays
+                    let class = cxt.tuple_class(t.to_vec());
+                    let ty = JTy::Array(Box::new(JTy::Class(class)));
+                    let raw = cxt.bindings.raw("$_arr");
+                    let arr = cxt.fresh_var(false);
+                    cxt.block.push(JStmt::Let(
+                        raw,
+                        ty.clone(),
+                        arr,
+                        Some(JTerm::ArrayNew(Box::new(len.clone()), ty.clone())),
+                    ));
+                    let raw = cxt.bindings.raw("$_i");
+                    let i = cxt.fresh_var(false);
+                    let k = cxt.fresh_block();
+                    cxt.block.push(JStmt::RangeFor(
+                        k,
+                        raw,
+                        i,
+                        JTerm::Lit(JLit::Int(0)),
+                        len.clone(),
+                        JTerm::Lit(JLit::Int(1)),
+                        BinOp::Lt,
+                        vec![JStmt::Set(
+                            JLVal::Idx(Box::new(JLVal::Var(arr)), JTerm::Var(i, JTy::I32)),
+                            None,
+                            JTerm::ClassNew(class, Vec::new()),
+                        )],
+                        false,
+                    ));
+                    return cxt.box_array(JTerms::Tuple(vec![JTerm::Var(arr, ty), len]));
+                }
+                return cxt.box_array(JTerms::Tuple(
+                    t.into_iter()
                         .map(|ty| JTerm::ArrayNew(Box::new(len.clone()), JTy::Array(Box::new(ty))))
                         .chain(std::iter::once(len.clone()))
                         .collect(),
-                );
+                ));
+            }
+            Term::ArrayRepeat(x, len) => {
+                // Evaluate the value once, before the loop
+                let x: Vec<_> = x
+                    .lower(cxt)
+                    .into_iter()
+                    .map(|x| cxt.cache("$_fill", x))
+                    .collect();
+                let mut len = len.lower(cxt).one();
+                if !len.simple() {
+                    let raw = cxt.bindings.raw("$_len");
+                    let var = cxt.fresh_var(false);
+                    cxt.block.push(JStmt::Let(raw, JTy::I32, var, Some(len)));
+                    len = JTerm::Var(var, JTy::I32);
+                }
+                // Packing inside the loop means each element gets its own tuple object without struct-of-arrays
+                let elems = cxt.pack(JTerms::Tuple(x)).to_vec();
+                let raw = cxt.bindings.raw("$_i");
+                let i = cxt.fresh_var(false);
+                let k = cxt.fresh_block();
+                let mut arrs = Vec::new();
+                let mut body = Vec::new();
+                for elem in elems {
+                    let ty = JTy::Array(Box::new(elem.ty()));
+                    let raw = cxt.bindings.raw("$_arr");
+                    let arr = cxt.fresh_var(false);
+                    cxt.block.push(JStmt::Let(
+                        raw,
+                        ty.clone(),
+                        arr,
+                        Some(JTerm::ArrayNew(Box::new(len.clone()), ty.clone())),
+                    ));
+                    body.push(JStmt::Set(
+                        JLVal::Idx(Box::new(JLVal::Var(arr)), JTerm::Var(i, JTy::I32)),
+                        None,
+                        elem,
+                    ));
+                    arrs.push(JTerm::Var(arr, ty));
+                }
+                cxt.block.push(JStmt::RangeFor(
+                    k,
+                    raw,
+                    i,
+                    JTerm::Lit(JLit::Int(0)),
+                    len.clone(),
+                    JTerm::Lit(JLit::Int(1)),
+                    BinOp::Lt,
+                    body,
+                    false,
+                ));
+                arrs.push(len);
+                return cxt.box_array(JTerms::Tuple(arrs));
             }
             Term::ArrayIdx(arr, idx, false, _, _) => {
                 let arrs = arr.lower(cxt);
+                let arrs = cxt.unbox_array(arrs);
                 let mut idx = idx.lower(cxt).one();
                 // The last element in the list is the length
                 let narrs = arrs.len() - 1;
-                if narrs > 1 && !idx.simple() {
+                if (narrs > 1 || cxt.bounds_checks) && !idx.simple() {
                     // Don't recompute idx every time, store it in a local
                     let raw = cxt.bindings.raw("$_idx");
                     let var = cxt.fresh_var(false);
                     cxt.block.push(JStmt::Let(raw, JTy::I32, var, Some(idx)));
                     idx = JTerm::Var(var, JTy::I32);
                 }
-                // TODO optional bounds checking
-                return JTerms::Tuple(
-                    arrs.into_iter()
-                        .take(narrs)
-                        .map(|arr| {
-                            let ty = match arr.ty() {
-                                JTy::Array(t) => *t,
-                                _ => unreachable!(),
-                            };
-                            JTerm::Index(Box::new(arr), Box::new(idx.clone()), ty)
-                        })
-                        .collect(),
-                );
+                let len = arrs.clone().to_vec().pop().unwrap();
+                cxt.check_bounds(&idx, len);
+                let elems: Vec<_> = arrs
+                    .into_iter()
+                    .take(narrs)
+                    .map(|arr| {
+                        let ty = match arr.ty() {
+                            JTy::Array(t) => *t,
+                            _ => unreachable!(),
+                        };
+                        JTerm::Index(Box::new(arr), Box::new(idx.clone()), ty)
+                    })
+                    .collect();
+                return JTerms::Tuple(elems.into_iter().flat_map(|x| cxt.unpack(x)).collect());
             }
             Term::ArrayIdx(arr, idx, true, ty, inline) => {
                 let arrs = arr.lower(cxt);
@@ -1634,7 +4609,7 @@ impl Term {
                         for (v, x) in vars.iter().zip(x) {
                             block.push(JStmt::Set(JLVal::Var(*v), None, x.clone()));
                         }
-                        cases.push((cxt.bindings.raw(i.to_string()), block));
+                        cases.push((JCase::Int(i as i32), block));
                     }
                     let k = cxt.fresh_block();
                     cxt.block.push(JStmt::Switch(k, idx, cases, Vec::new()));
@@ -1659,8 +4634,112 @@ impl Term {
                     );
                 }
             }
+            Term::ArraySlice(arr, start, end) => {
+                let arrs = arr.lower(cxt);
+                let mut arrs = cxt.unbox_array(arrs).to_vec();
+                arrs.pop();
+                let start = start.lower(cxt).one();
+                let end = end.lower(cxt).one();
+                let init_cap = JTerm::Lit(JLit::Int(cxt.array_init_cap as i32));
+                if matches!((&start, &end), (JTerm::Lit(a), JTerm::Lit(b)) if a == b) {
+                    // An empty slice is just a new empty array
+                    let empty = arrs
+                        .iter()
+                        .map(|x| JTerm::ArrayNew(Box::new(init_cap.clone()), x.ty()));
+                    let empty = empty.chain(std::iter::once(JTerm::Lit(JLit::Int(0))));
+                    return cxt.box_array(JTerms::Tuple(empty.collect()));
+                }
+
+                let local = |cxt: &mut Cxt, name: &str, x: JTerm| {
+                    let raw = cxt.bindings.raw(name);
+                    let var = cxt.fresh_var(false);
+                    let ty = x.ty();
+                    cxt.tys.insert(var, ty.clone());
+                    cxt.block.push(JStmt::Let(raw, ty.clone(), var, Some(x)));
+                    JTerm::Var(var, ty)
+                };
+                let start = if start.simple() {
+                    start
+                } else {
+                    local(cxt, "$_start", start)
+                };
+                let len = JTerm::BinOp(BinOp::Sub, Box::new(end), Box::new(start.clone()));
+                let len = local(cxt, "$_slice_len", len);
+                // Dynamic arrays can't have 0 capacity, since they grow by multiplying it
+                let cap = JTerm::Ternary(
+                    Box::new(JTerm::BinOp(
+                        BinOp::Eq,
+                        Box::new(len.clone()),
+                        Box::new(JTerm::Lit(JLit::Int(0))),
+                    )),
+                    Box::new(init_cap),
+                    Box::new(len.clone()),
+                );
+
+                let mut slices = Vec::new();
+                for arr in arrs {
+                    let ty = arr.ty();
+                    let slice = JTerm::ArrayNew(Box::new(cap.clone()), ty.clone());
+                    let slice = local(cxt, "$_slice", slice);
+                    let elem_ty = match &ty {
+                        JTy::Array(t) => (**t).clone(),
+                        _ => unreachable!(),
+                    };
+                    if let Some(fields) = cxt.tuple_fields(&elem_ty) {
+                        // Without struct-of-arrays, copy each tuple object so the slice doesn't share them
+                        let raw = cxt.bindings.raw("$_i");
+                        let i = cxt.fresh_var(false);
+                        let k = cxt.fresh_block();
+                        let iv = JTerm::Var(i, JTy::I32);
+                        let idx =
+                            JTerm::BinOp(BinOp::Add, Box::new(start.clone()), Box::new(iv.clone()));
+                        let elem = JTerm::Index(Box::new(arr), Box::new(idx), elem_ty.clone());
+                        let class = match elem_ty {
+                            JTy::Class(c) => c,
+                            _ => unreachable!(),
+                        };
+                        cxt.push_block();
+                        let fields = cxt.split(elem, fields).to_vec();
+                        cxt.block.push(JStmt::Set(
+                            JLVal::Idx(Box::new(slice.clone().to_lval().unwrap()), iv),
+                            None,
+                            JTerm::ClassNew(class, fields),
+                        ));
+                        let body = cxt.pop_block();
+                        cxt.block.push(JStmt::RangeFor(
+                            k,
+                            raw,
+                            i,
+                            JTerm::Lit(JLit::Int(0)),
+                            len.clone(),
+                            JTerm::Lit(JLit::Int(1)),
+                            BinOp::Lt,
+                            body,
+                            false,
+                        ));
+                    } else {
+                        let copy_fn = cxt.predef("arraycopy");
+                        cxt.block.push(JStmt::MultiCall(
+                            None,
+                            copy_fn,
+                            vec![
+                                arr,
+                                start.clone(),
+                                slice.clone(),
+                                JTerm::Lit(JLit::Int(0)),
+                                len.clone(),
+                            ],
+                            Vec::new(),
+                        ));
+                    }
+                    slices.push(slice);
+                }
+                slices.push(len);
+                return cxt.box_array(JTerms::Tuple(slices));
+            }
             Term::ArrayMethod(arr, m) => {
                 let arrs = arr.lower(cxt);
+                let arrs = cxt.unbox_array(arrs);
                 let len = arrs.clone().to_vec().pop().unwrap();
                 match m {
                     ArrayMethod::Len => len,
@@ -1681,105 +4760,303 @@ impl Term {
                         ));
                         // return a[len]
                         let n = arrs.len() - 1;
+                        let elems: Vec<_> = arrs
+                            .into_iter()
+                            .take(n)
+                            .map(|x| {
+                                let ty = match x.ty() {
+                                    JTy::Array(t) => *t,
+                                    _ => unreachable!(),
+                                };
+                                JTerm::Index(Box::new(x), Box::new(len.clone()), ty)
+                            })
+                            .collect();
                         return JTerms::Tuple(
-                            arrs.into_iter()
-                                .take(n)
-                                .map(|x| {
-                                    let ty = match x.ty() {
-                                        JTy::Array(t) => *t,
-                                        _ => unreachable!(),
-                                    };
-                                    JTerm::Index(Box::new(x), Box::new(len.clone()), ty)
-                                })
-                                .collect(),
+                            elems.into_iter().flat_map(|x| cxt.unpack(x)).collect(),
                         );
                     }
                     ArrayMethod::Push(x) => {
+                        // The value is stored before the length changes, so it can use the old length, like `a.push(a.len())`
+                        // Anything with side effects is evaluated first, since it could push to the same array
+                        let x = x.lower(cxt);
+                        let x: Vec<_> = cxt
+                            .pack(x)
+                            .into_iter()
+                            .map(|x| {
+                                let mut effects = SideEffects(false);
+                                x.clone().map(&mut effects);
+                                if effects.0 {
+                                    cxt.cache("$_elem", x)
+                                } else {
+                                    x
+                                }
+                            })
+                            .collect();
+                        // The value has the element type and doesn't diverge, like in an array literal
+                        assert_eq!(x.len(), arrs.len() - 1);
+                        // Check if the array needs expanding
+                        if arrs.len() != 1 {
+                            let mut arrs = arrs.to_vec();
+                            arrs.pop();
+                            // Expand, then set
+                            let new_len = JTerm::BinOp(
+                                BinOp::Add,
+                                Box::new(len.clone()),
+                                Box::new(JTerm::Lit(JLit::Int(1))),
+                            );
+                            cxt.grow_array(&arrs, &new_len);
+                            for (arr, x) in arrs.into_iter().zip(x) {
+                                let sarr = arr.to_lval().expect("push() requires an lvalue");
+                                // arr[len] = x;
+                                cxt.block.push(JStmt::Set(
+                                    JLVal::Idx(Box::new(sarr), len.clone()),
+                                    None,
+                                    x,
+                                ));
+                            }
+                        }
                         let slen = len.clone().to_lval().expect("push() requires an lvalue");
                         cxt.block.push(JStmt::Set(
                             slen,
                             Some(BinOp::Add),
                             JTerm::Lit(JLit::Int(1)),
                         ));
+                        return JTerms::empty();
+                    }
+                    ArrayMethod::Insert(idx, x) => {
+                        let idx = idx.lower(cxt).one();
+                        let idx = cxt.cache("$_idx", idx);
+                        // Evaluate the value before moving anything
                         let x = x.lower(cxt);
-                        assert_eq!(x.len(), arrs.len() - 1);
-                        // Check if the array needs expanding
-                        if arrs.len() != 1 {
-                            let cap = JTerm::Prop(
-                                Box::new(arrs.clone().to_vec().swap_remove(0)),
-                                Prop::Raw(cxt.bindings.raw("length")),
-                                JTy::I32,
-                            );
-                            let too_small =
-                                JTerm::BinOp(BinOp::Gt, Box::new(len.clone()), Box::new(cap));
-                            let mut block = Vec::new();
-                            let mut block2 = Vec::new();
-                            for (arr, x) in arrs.clone().into_iter().zip(x) {
-                                let sarr =
-                                    arr.clone().to_lval().expect("push() requires an lvalue");
-                                // let old = arr;
-                                // arr = new T[old.length * 2];
-                                // System.arraycopy(old, 0, arr, 0, old.length);
-
-                                let old = cxt.fresh_var(false);
-                                let raw = cxt.bindings.raw("$_old_array");
-                                // cxt.vars.push((cxt.bindings.create(lpath(Spanned::hack(raw)), false), JVars::One(old)));
-                                block.push(JStmt::Let(raw, arr.ty(), old, Some(arr.clone())));
-
-                                let cap = JTerm::Prop(
-                                    Box::new(JTerm::Var(old, arr.ty())),
-                                    Prop::Raw(cxt.bindings.raw("length")),
-                                    JTy::I32,
-                                );
-                                let new_cap = JTerm::BinOp(
-                                    BinOp::Mul,
-                                    Box::new(cap.clone()),
-                                    Box::new(JTerm::Lit(JLit::Int(2))),
+                        let x: Vec<_> = cxt
+                            .pack(x)
+                            .into_iter()
+                            .map(|x| cxt.cache("$_elem", x))
+                            .collect();
+                        // The index might refer to the length, so only change the length at the end
+                        let binop = |op, a, b| JTerm::BinOp(op, Box::new(a), Box::new(b));
+                        let one = JTerm::Lit(JLit::Int(1));
+                        let new_len = binop(BinOp::Add, len.clone(), one.clone());
+                        // Inserting at the end is allowed
+                        cxt.check_bounds(&idx, new_len.clone());
+                        let mut arrs = arrs.to_vec();
+                        arrs.pop();
+                        // The value has the element type and doesn't diverge, like in an array literal
+                        assert_eq!(x.len(), arrs.len());
+                        if !arrs.is_empty() {
+                            cxt.grow_array(&arrs, &new_len);
+                        }
+                        // System.arraycopy(arr, idx, arr, idx + 1, len - idx);
+                        // arr[idx] = x;
+                        let count = binop(BinOp::Sub, len.clone(), idx.clone());
+                        for (arr, x) in arrs.into_iter().zip(x) {
+                            let sarr = arr.clone().to_lval().expect("insert() requires an lvalue");
+                            let copy_fn = cxt.predef("arraycopy");
+                            cxt.block.push(JStmt::MultiCall(
+                                None,
+                                copy_fn,
+                                vec![
+                                    arr.clone(),
+                                    idx.clone(),
+                                    arr,
+                                    binop(BinOp::Add, idx.clone(), one.clone()),
+                                    count.clone(),
+                                ],
+                                Vec::new(),
+                            ));
+                            cxt.block.push(JStmt::Set(
+                                JLVal::Idx(Box::new(sarr), idx.clone()),
+                                None,
+                                x,
+                            ));
+                        }
+                        let slen = len.to_lval().expect("insert() requires an lvalue");
+                        cxt.block.push(JStmt::Set(
+                            slen,
+                            Some(BinOp::Add),
+                            JTerm::Lit(JLit::Int(1)),
+                        ));
+                        return JTerms::empty();
+                    }
+                    ArrayMethod::RemoveAt(idx) | ArrayMethod::SwapRemove(idx) => {
+                        let idx = idx.lower(cxt).one();
+                        let idx = cxt.cache("$_idx", idx);
+                        cxt.check_bounds(&idx, len.clone());
+                        let mut arrs = arrs.to_vec();
+                        arrs.pop();
+                        // Save the element before it's overwritten
+                        let elems: Vec<_> = arrs
+                            .iter()
+                            .map(|x| {
+                                let ty = match x.ty() {
+                                    JTy::Array(t) => *t,
+                                    _ => unreachable!(),
+                                };
+                                let raw = cxt.bindings.raw("$_removed");
+                                let var = cxt.fresh_var(false);
+                                let x = JTerm::Index(
+                                    Box::new(x.clone()),
+                                    Box::new(idx.clone()),
+                                    ty.clone(),
                                 );
-                                block.push(JStmt::Set(
-                                    sarr.clone(),
+                                cxt.block.push(JStmt::Let(raw, ty.clone(), var, Some(x)));
+                                JTerm::Var(var, ty)
+                            })
+                            .collect();
+                        let binop = |op, a, b| JTerm::BinOp(op, Box::new(a), Box::new(b));
+                        let one = JTerm::Lit(JLit::Int(1));
+                        if let ArrayMethod::SwapRemove(_) = m {
+                            // arr[idx] = arr[len - 1];
+                            let last = binop(BinOp::Sub, len.clone(), one);
+                            for (arr, elem) in arrs.into_iter().zip(&elems) {
+                                let sarr = arr
+                                    .clone()
+                                    .to_lval()
+                                    .expect("swap_remove() requires an lvalue");
+                                let x =
+                                    JTerm::Index(Box::new(arr), Box::new(last.clone()), elem.ty());
+                                cxt.block.push(JStmt::Set(
+                                    JLVal::Idx(Box::new(sarr), idx.clone()),
                                     None,
-                                    JTerm::ArrayNew(Box::new(new_cap), arr.ty()),
+                                    x,
                                 ));
-
-                                let copy_fn = cxt.predef(Predef::ArrayCopy);
-                                let call = JStmt::MultiCall(
+                            }
+                        } else {
+                            // System.arraycopy(arr, idx + 1, arr, idx, len - idx - 1);
+                            let next = binop(BinOp::Add, idx.clone(), one);
+                            let count = binop(BinOp::Sub, len.clone(), next.clone());
+                            for arr in arrs {
+                                let copy_fn = cxt.predef("arraycopy");
+                                cxt.block.push(JStmt::MultiCall(
                                     None,
                                     copy_fn,
                                     vec![
-                                        JTerm::Var(old, arr.ty()),
-                                        JTerm::Lit(JLit::Int(0)),
                                         arr.clone(),
-                                        JTerm::Lit(JLit::Int(0)),
-                                        cap,
+                                        next.clone(),
+                                        arr,
+                                        idx.clone(),
+                                        count.clone(),
                                     ],
                                     Vec::new(),
-                                );
-                                block.push(call);
-
-                                // arr[len-1] = x;
-                                let idx = JTerm::BinOp(
-                                    BinOp::Sub,
-                                    Box::new(len.clone()),
-                                    Box::new(JTerm::Lit(JLit::Int(1))),
-                                );
-                                block2.push(JStmt::Set(JLVal::Idx(Box::new(sarr), idx), None, x));
+                                ));
                             }
-                            // Expand, then set
-                            cxt.block.push(JStmt::If(too_small, block, Vec::new()));
-                            cxt.block.append(&mut block2);
+                        }
+                        let slen = len.to_lval().expect("removing requires an lvalue");
+                        cxt.block.push(JStmt::Set(
+                            slen,
+                            Some(BinOp::Sub),
+                            JTerm::Lit(JLit::Int(1)),
+                        ));
+                        return JTerms::Tuple(
+                            elems.into_iter().flat_map(|x| cxt.unpack(x)).collect(),
+                        );
+                    }
+                    ArrayMethod::Reserve(n) => {
+                        let n = n.lower(cxt).one();
+                        let n = cxt.cache("$_cap", n);
+                        let mut arrs = arrs.to_vec();
+                        arrs.pop();
+                        if !arrs.is_empty() {
+                            cxt.realloc_array(&arrs, &n, Some(&n));
                         }
                         return JTerms::empty();
                     }
+                    ArrayMethod::IndexOf(x) | ArrayMethod::Contains(x) => {
+                        let x: Vec<_> = x
+                            .lower(cxt)
+                            .into_iter()
+                            .map(|x| cxt.cache("$_needle", x))
+                            .collect();
+                        let (raw, ty, init) = match m {
+                            ArrayMethod::IndexOf(_) => ("$_index", JTy::I32, JLit::Int(-1)),
+                            _ => ("$_found", JTy::Bool, JLit::Bool(false)),
+                        };
+                        let raw = cxt.bindings.raw(raw);
+                        let result = cxt.fresh_var(false);
+                        cxt.block
+                            .push(JStmt::Let(raw, ty.clone(), result, Some(JTerm::Lit(init))));
+
+                        let raw = cxt.bindings.raw("$_i");
+                        let i = cxt.fresh_var(false);
+                        let k = cxt.fresh_block();
+                        let iv = JTerm::Var(i, JTy::I32);
+                        let mut arrs = arrs.to_vec();
+                        arrs.pop();
+                        cxt.push_block();
+                        // Compare each component of the element, so tuples are equal if all their members are
+                        let mut elem = Vec::new();
+                        for arr in arrs {
+                            let ty = match arr.ty() {
+                                JTy::Array(t) => *t,
+                                _ => unreachable!(),
+                            };
+                            let e = JTerm::Index(Box::new(arr), Box::new(iv.clone()), ty);
+                            elem.extend(cxt.unpack(e));
+                        }
+                        // The value has the element type and doesn't diverge, like in an array literal
+                        assert_eq!(elem.len(), x.len());
+                        let cond = elem
+                            .into_iter()
+                            .zip(x)
+                            .map(|(a, b)| JTerm::BinOp(BinOp::Eq, Box::new(a), Box::new(b)))
+                            .reduce(|a, b| JTerm::BinOp(BinOp::And, Box::new(a), Box::new(b)))
+                            .unwrap_or(JTerm::Lit(JLit::Bool(true)));
+                        let found = match m {
+                            ArrayMethod::IndexOf(_) => iv,
+                            _ => JTerm::Lit(JLit::Bool(true)),
+                        };
+                        cxt.block.push(JStmt::If(
+                            cond,
+                            vec![JStmt::Set(JLVal::Var(result), None, found), JStmt::Break(k)],
+                            Vec::new(),
+                        ));
+                        let body = cxt.pop_block();
+                        cxt.block.push(JStmt::RangeFor(
+                            k,
+                            raw,
+                            i,
+                            JTerm::Lit(JLit::Int(0)),
+                            len,
+                            JTerm::Lit(JLit::Int(1)),
+                            BinOp::Lt,
+                            body,
+                            false,
+                        ));
+                        JTerm::Var(result, ty)
+                    }
                 }
             }
             Term::Call(o, f, a) => {
                 let fn_id = cxt.fun(*f).unwrap();
                 let o = o.as_ref().map(|x| Box::new(x.lower(cxt).one()));
-                let args = a.iter().flat_map(|x| x.lower(cxt)).collect();
+                let mut args: Vec<_> = a
+                    .iter()
+                    .flat_map(|x| {
+                        let x = x.lower(cxt);
+                        match x.as_one().map(JTerm::ty) {
+                            // Extern functions get the Java arrays and length, like they're returned
+                            Some(JTy::Class(c))
+                                if cxt.array_classes.contains(&c)
+                                    && cxt.extern_fns.contains(&fn_id) =>
+                            {
+                                cxt.unbox_array(x)
+                            }
+                            _ => x,
+                        }
+                    })
+                    .collect();
+                // Parameters that were left out get their default values, after the other arguments
+                if let Some((first, defaults)) = cxt.defaults.get(f) {
+                    let defaults: Vec<_> = defaults[a.len() - first..]
+                        .iter()
+                        .map(|x| x.cloned(cxt.bindings))
+                        .collect();
+                    args.extend(defaults.iter().flat_map(|x| x.lower(cxt)));
+                }
                 let rtys = cxt.fn_ret_tys.get(&fn_id).unwrap().clone();
                 if let Some((atys, body)) = cxt.inline_fns.get(&fn_id) {
                     let body = body.cloned(cxt.bindings);
+                    let atys = atys.clone();
                     let mut syms = HashMap::new();
                     for ((s, t), x) in atys
                         .iter()
@@ -1818,7 +5095,7 @@ impl Term {
                             JTy::I32,
                         );
 
-                        return JTerms::Tuple(vec![JTerm::Var(arr, t), len]);
+                        return cxt.box_array(JTerms::Tuple(vec![JTerm::Var(arr, t), len]));
                     }
                     MaybeList::One(rty) => JTerm::Call(o, fn_id, args, rty),
                     MaybeList::Tuple(v) => {
@@ -1829,8 +5106,9 @@ impl Term {
                             let var = cxt.fresh_var(false);
                             cxt.tys.insert(var, ty.clone());
                             let raw = cxt.bindings.raw(format!(
-                                "{}$_call_ret{}",
+                                "{}{}_call_ret{}",
                                 cxt.bindings.resolve_raw(*cxt.bindings.fn_name(*f).stem()),
+                                cxt.separator,
                                 i
                             ));
                             terms.push(JTerm::Var(var, ty.clone()));
@@ -1842,19 +5120,38 @@ impl Term {
                     }
                 }
             }
-            Term::BinOp(op, a, b) => JTerm::BinOp(
-                *op,
-                Box::new(a.lower(cxt).one()),
-                Box::new(b.lower(cxt).one()),
-            ),
+            Term::BinOp(op, a, b) => {
+                let (a, b) = (a.lower(cxt), b.lower(cxt));
+                if a.as_one().is_none() || b.as_one().is_none() {
+                    cxt.error(BackendError::TupleOperand(op.repr()));
+                    // Nothing is generated after an error, so the value doesn't matter
+                    return JTerms::One(JTerm::Lit(JLit::Bool(false)));
+                }
+                let (a, b) = (a.one(), b.one());
+                if let (JTerm::Lit(a), JTerm::Lit(b)) = (&a, &b) {
+                    if let Some(l) = a.fold(*op, *b) {
+                        return JTerms::One(JTerm::Lit(l));
+                    }
+                }
+                let x = JTerm::BinOp(*op, Box::new(a), Box::new(b)).strength_reduce();
+                match x.ty() {
+                    // Java does arithmetic on bytes and shorts as ints, so narrow the result back
+                    t @ (JTy::I8 | JTy::I16) => JTerm::Cast(Box::new(x), t),
+                    _ => x,
+                }
+            }
             Term::Block(v, e) => {
                 cxt.push();
-                for i in v {
+                for (n, i) in v.iter().enumerate() {
+                    cxt.array_capacity = i.initial_pushes(&v[n + 1..]);
                     i.lower(cxt);
                 }
                 let r = e
                     .as_ref()
-                    .map(|x| x.lower(cxt))
+                    .map(|x| {
+                        cxt.discard = discard;
+                        x.lower(cxt)
+                    })
                     .unwrap_or(JTerms::Tuple(Vec::new()));
                 cxt.pop();
                 return r;
@@ -1862,52 +5159,90 @@ impl Term {
             Term::If(cond, a, b) => {
                 let cond = cond.lower(cxt).one();
 
+                let mut vars = None;
                 cxt.push_block();
+                cxt.discard = discard;
                 let a = a.lower(cxt);
-                let ty = a.ty();
-
-                let vars: Vec<_> = ty
-                    .clone()
-                    .into_iter()
-                    .enumerate()
-                    .map(|(i, t)| {
-                        (
-                            cxt.fresh_var(false),
-                            cxt.bindings.raw(format!("_then${}", i)),
-                            t,
-                        )
-                    })
-                    .collect();
-                for ((var, _, ty), a) in vars.iter().zip(a) {
-                    cxt.tys.insert(*var, ty.clone());
-                    cxt.block.push(JStmt::Set(JLVal::Var(*var), None, a));
-                }
-                let a = cxt.pop_block();
-
-                let b = if let Some(b) = b {
-                    cxt.push_block();
-                    let b = b.lower(cxt);
-                    for ((var, _, _), b) in vars.iter().zip(b) {
-                        cxt.block.push(JStmt::Set(JLVal::Var(*var), None, b));
+                let (a, b) = match (a, b) {
+                    // If both branches are a single simple term with no statements, we can use `?:`
+                    (a, Some(b))
+                        if !discard
+                            && cxt.block_empty()
+                            && a.as_one().is_some_and(JTerm::simple) =>
+                    {
+                        let a = a.one();
+                        cxt.pop_block();
+                        cxt.push_block();
+                        cxt.discard = discard;
+                        match b.lower(cxt) {
+                            b if cxt.block_empty()
+                                && b.as_one().is_some_and(|b| b.simple() && b.ty() == a.ty()) =>
+                            {
+                                cxt.pop_block();
+                                return JTerms::One(JTerm::Ternary(
+                                    Box::new(cond),
+                                    Box::new(a),
+                                    Box::new(b.one()),
+                                ));
+                            }
+                            b => {
+                                // The result variables come from `a`, since `b` might diverge and not have a value
+                                let b_stmts = cxt.pop_block();
+                                cxt.push_block();
+                                cxt.branch_result(JTerms::One(a), &mut vars, discard);
+                                let a = cxt.pop_block();
+                                cxt.push_block();
+                                cxt.block.extend(b_stmts);
+                                cxt.branch_result(b, &mut vars, discard);
+                                (a, cxt.pop_block())
+                            }
+                        }
+                    }
+                    (a, b) => {
+                        cxt.branch_result(a, &mut vars, discard);
+                        let a = cxt.pop_block();
+
+                        let b = if let Some(b) = b {
+                            cxt.push_block();
+                            cxt.discard = discard;
+                            let b = b.lower(cxt);
+                            cxt.branch_result(b, &mut vars, discard);
+                            cxt.pop_block()
+                        } else {
+                            Vec::new()
+                        };
+                        (a, b)
                     }
-                    cxt.pop_block()
-                } else {
-                    Vec::new()
                 };
 
-                let mut ret = Vec::new();
-                for (var, raw, ty) in vars {
-                    cxt.block.push(JStmt::Let(raw, ty.clone(), var, None));
-                    ret.push(JTerm::Var(var, ty));
-                }
+                let ret = cxt.declare_results(vars);
                 cxt.block.push(JStmt::If(cond, a, b));
 
-                return JTerms::Tuple(ret);
+                return ret;
             }
             Term::Match(tid, x, branches) => {
                 let mut x = x.lower(cxt).one();
-                let scrut = if let Some(_wrapper) = cxt.enum_wrappers.get(&cxt.class(*tid).unwrap())
-                {
+                let class = cxt.class(*tid).unwrap();
+                // Enums lowered to ints and pooled enums are matched on by the index of each variant
+                let pool = cxt.enum_pools.get(&class);
+                let pool_fields = pool.map(|p| p.fields.clone());
+                let int_variants = cxt
+                    .int_enums
+                    .get(&class)
+                    .or(pool.map(|p| &p.variants))
+                    .cloned();
+                let index = |variant: RawSym| {
+                    let variants = int_variants.as_ref().unwrap();
+                    variants.iter().position(|x| *x == variant).unwrap() as i32
+                };
+                let chain = (cxt.enums.contains(&class) || cxt.int_enums.contains_key(&class))
+                    && branches.len() <= MAX_CHAIN_ARMS;
+                let scrut = if let Some(pool) = cxt.enum_pools.get(&class) {
+                    // The tag and fields are read from the pool arrays at index x
+                    let tag = JTerm::Var(pool.tag, JTy::Array(Box::new(JTy::I32)));
+                    x = cxt.cache("$_scrutinee", x);
+                    JTerm::Index(Box::new(tag), Box::new(x.clone()), JTy::I32)
+                } else if let Some(_wrapper) = cxt.enum_wrappers.get(&class) {
                     if !x.simple() {
                         // Don't recompute x every time, store it in a local
                         let raw = cxt.bindings.raw("$_scrutinee");
@@ -1921,6 +5256,16 @@ impl Term {
                         Prop::Raw(cxt.bindings.raw("$type")),
                         JTy::Class(cxt.class(*tid).unwrap()),
                     )
+                } else if chain {
+                    // x is compared to each variant, so store it in a local
+                    if !x.simple() {
+                        let raw = cxt.bindings.raw("$_scrutinee");
+                        let var = cxt.fresh_var(false);
+                        let ty = x.ty();
+                        cxt.block.push(JStmt::Let(raw, ty.clone(), var, Some(x)));
+                        x = JTerm::Var(var, ty);
+                    }
+                    x.clone()
                 } else {
                     // will only be used once, as the scrutinee
                     x.clone()
@@ -1928,7 +5273,7 @@ impl Term {
 
                 let mut v = Vec::new();
                 let mut default = None;
-                let mut vars: Option<Vec<_>> = None;
+                let mut vars = None;
                 for (variant, captures, body) in branches {
                     cxt.push_block();
 
@@ -1939,11 +5284,17 @@ impl Term {
 
                             let t = t.lower(cxt);
                             for t in t {
-                                let prop =
-                                    format!("_enum${}${}", cxt.bindings.resolve_raw(*variant), n);
-                                let prop = cxt.bindings.raw(prop);
-                                let x =
-                                    JTerm::Prop(Box::new(x.clone()), Prop::Raw(prop), t.clone());
+                                let x = if let Some(fields) = &pool_fields {
+                                    let (arr, aty) = fields[index(*variant) as usize][n].clone();
+                                    JTerm::Index(
+                                        Box::new(JTerm::Var(arr, aty)),
+                                        Box::new(x.clone()),
+                                        t.clone(),
+                                    )
+                                } else {
+                                    let prop = cxt.enum_prop(*variant, n);
+                                    JTerm::Prop(Box::new(x.clone()), Prop::Raw(prop), t.clone())
+                                };
                                 n += 1;
 
                                 let var = cxt.fresh_var(cxt.bindings.public(*s));
@@ -1961,29 +5312,9 @@ impl Term {
                         }
                     }
 
+                    cxt.discard = discard;
                     let body = body.lower(cxt);
-                    if vars.is_none() {
-                        let ty = body.ty();
-                        vars = Some(
-                            ty.clone()
-                                .into_iter()
-                                .enumerate()
-                                .map(|(i, t)| {
-                                    (
-                                        cxt.fresh_var(false),
-                                        cxt.bindings.raw(format!("_then${}", i)),
-                                        t,
-                                    )
-                                })
-                                .collect(),
-                        );
-                        for (var, _, ty) in vars.as_ref().unwrap() {
-                            cxt.tys.insert(*var, ty.clone());
-                        }
-                    }
-                    for ((var, _, _), t) in vars.as_ref().unwrap().iter().zip(body) {
-                        cxt.block.push(JStmt::Set(JLVal::Var(*var), None, t));
-                    }
+                    cxt.branch_result(body, &mut vars, discard);
                     let block = cxt.pop_block();
 
                     match variant {
@@ -1998,28 +5329,161 @@ impl Term {
                     }
                 }
 
-                let mut ret = Vec::new();
-                for (var, raw, ty) in vars.unwrap() {
-                    cxt.block.push(JStmt::Let(raw, ty.clone(), var, None));
-                    ret.push(JTerm::Var(var, ty));
+                let ret = cxt.declare_results(vars);
+                if chain {
+                    // The match is exhaustive, so without a default the last arm doesn't need a comparison
+                    let mut rest = match default {
+                        Some(default) => default,
+                        None => v.pop().map(|(_, block)| block).unwrap_or_default(),
+                    };
+                    for (variant, block) in v.into_iter().rev() {
+                        let variant = if int_variants.is_some() {
+                            JTerm::Lit(JLit::Int(index(variant)))
+                        } else {
+                            JTerm::Variant(class, variant)
+                        };
+                        let cond =
+                            JTerm::BinOp(BinOp::Eq, Box::new(scrut.clone()), Box::new(variant));
+                        rest = vec![JStmt::If(cond, block, rest)];
+                    }
+                    cxt.block.extend(rest);
+                } else {
+                    let k = cxt.fresh_block();
+                    let v = v
+                        .into_iter()
+                        .map(|(s, b)| match int_variants {
+                            Some(_) => (JCase::Int(index(s)), b),
+                            None => (JCase::Variant(s), b),
+                        })
+                        .collect();
+                    cxt.block
+                        .push(JStmt::Switch(k, scrut, v, default.unwrap_or_default()));
+                }
+
+                return ret;
+            }
+            Term::MatchInt(x, branches) => {
+                let x = x.lower(cxt).one();
+
+                let mut v = Vec::new();
+                let mut default = Vec::new();
+                let mut vars = None;
+                for (i, body) in branches {
+                    cxt.push_block();
+                    cxt.discard = discard;
+                    let body = body.lower(cxt);
+                    cxt.branch_result(body, &mut vars, discard);
+                    let block = cxt.pop_block();
+                    match i {
+                        Some(i) => v.push((JCase::Int(*i), block)),
+                        None => default = block,
+                    }
                 }
+
+                let ret = cxt.declare_results(vars);
                 let k = cxt.fresh_block();
-                cxt.block
-                    .push(JStmt::Switch(k, scrut, v, default.unwrap_or_default()));
+                cxt.block.push(JStmt::Switch(k, x, v, default));
+                return ret;
+            }
+            Term::MatchOption(x, branches) => {
+                let mut x = x.lower(cxt).to_vec();
+                // The flag and value are used in different branches, so store them in locals
+                for x in &mut x {
+                    if !x.simple() {
+                        let raw = cxt.bindings.raw("$_scrutinee");
+                        let var = cxt.fresh_var(false);
+                        let ty = x.ty();
+                        cxt.block
+                            .push(JStmt::Let(raw, ty.clone(), var, Some(x.clone())));
+                        *x = JTerm::Var(var, ty);
+                    }
+                }
+                let flag = x.remove(0);
+
+                let mut some = None;
+                let mut none = None;
+                let mut default = None;
+                let mut vars = None;
+                for (variant, captures, body) in branches {
+                    cxt.push_block();
+
+                    for (s, _) in captures {
+                        let mut svars = Vec::new();
+                        for x in &x {
+                            let var = cxt.fresh_var(cxt.bindings.public(*s));
+                            let ty = x.ty();
+                            cxt.tys.insert(var, ty.clone());
+                            cxt.block.push(JStmt::Let(
+                                *cxt.bindings.sym_path(*s).stem(),
+                                ty,
+                                var,
+                                Some(x.clone()),
+                            ));
+                            svars.push(var);
+                        }
+                        cxt.vars.push((*s, JVars::Tuple(svars)));
+                    }
 
-                return JTerms::Tuple(ret);
+                    cxt.discard = discard;
+                    let body = body.lower(cxt);
+                    cxt.branch_result(body, &mut vars, discard);
+                    let block = cxt.pop_block();
+
+                    match variant.map(|s| cxt.bindings.resolve_raw(s)) {
+                        Some("Some") => some = Some(block),
+                        Some(_) => none = Some(block),
+                        None => default = Some(block),
+                    }
+                }
+
+                let ret = cxt.declare_results(vars);
+                let some = some.or_else(|| default.clone()).unwrap();
+                let none = none.or(default).unwrap();
+                cxt.block.push(JStmt::If(flag, some, none));
+
+                return ret;
             }
         })
     }
 }
 impl Statement {
+    /// This statement without the source location it might be wrapped in
+    fn unspanned(&self) -> &Statement {
+        match self {
+            Statement::Src(_, _, s) => s.unspanned(),
+            s => s,
+        }
+    }
+
+    /// If this statement creates an empty dynamic array and the following statements push to it
+    /// a fixed number of times and then never again, returns that number.
+    /// The array can then be created with exactly enough capacity instead of the default.
+    fn initial_pushes(&self, rest: &[Statement]) -> Option<usize> {
+        let s = match self.unspanned() {
+            Statement::Let(s, _, Term::Array(v, _, true)) if v.is_empty() => *s,
+            _ => return None,
+        };
+        let is_push = |t: &Term| match t {
+            Term::ArrayMethod(a, ArrayMethod::Push(_)) => matches!(**a, Term::Var(v) if v == s),
+            _ => false,
+        };
+        let n = rest
+            .iter()
+            .take_while(|x| matches!(x.unspanned(), Statement::Term(t) if is_push(t)))
+            .count();
+        if n == 0 || rest[n..].iter().any(|x| x.any(&mut |t| is_push(t))) {
+            None
+        } else {
+            Some(n)
+        }
+    }
+
     fn lower(&self, cxt: &mut Cxt) {
         match self {
             Statement::Term(x) => {
+                cxt.discard = true;
                 let terms = x.lower(cxt);
-                for i in terms {
-                    cxt.block.push(JStmt::Term(i));
-                }
+                cxt.discard_terms(terms);
             }
             Statement::Let(n, t, x) => {
                 let x = x.lower(cxt);
@@ -2028,6 +5492,9 @@ impl Statement {
 
                 for (x, t) in x.into_iter().zip(t) {
                     let var = cxt.fresh_var(cxt.bindings.public(*n));
+                    if cxt.bindings.final_(*n) {
+                        cxt.finals.insert(var);
+                    }
                     cxt.tys.insert(var, t.clone());
                     cxt.block.push(JStmt::Let(
                         *cxt.bindings.sym_path(*n).stem(),
@@ -2041,29 +5508,111 @@ impl Statement {
                 cxt.vars.push((*n, JVars::Tuple(vars)));
             }
             Statement::While(cond, block) => {
+                let label = cxt.loop_label.take();
                 let cond = cond.lower(cxt).one();
 
                 let k = cxt.fresh_block();
-                cxt.push_loop(k);
+                cxt.push_loop(k, label);
+                for i in block {
+                    i.lower(cxt);
+                }
+                let block = cxt.pop_block();
+
+                cxt.block.push(JStmt::While(k, cond, block));
+            }
+            Statement::Loop(block) => {
+                let label = cxt.loop_label.take();
+                let k = cxt.fresh_block();
+                cxt.push_loop(k, label);
                 for i in block {
                     i.lower(cxt);
                 }
                 let block = cxt.pop_block();
 
+                let cond = JTerm::Lit(JLit::Bool(true));
                 cxt.block.push(JStmt::While(k, cond, block));
             }
+            Statement::DoWhile(block, cond) => {
+                let label = cxt.loop_label.take();
+                let k = cxt.fresh_block();
+                cxt.push_loop(k, label);
+                for i in block {
+                    i.lower(cxt);
+                }
+                // Anything the condition needs to evaluate goes at the end of the body, so it runs each iteration
+                let cond = cond.lower(cxt).one();
+                let block = cxt.pop_block();
+
+                cxt.block.push(JStmt::DoWhile(k, block, cond));
+            }
+            Statement::Labeled(l, s) => {
+                cxt.loop_label = Some(*l);
+                s.lower(cxt);
+            }
+            Statement::Src(file, span, s) => {
+                if cxt.debug_comments {
+                    cxt.block.push(JStmt::Src(*file, *span));
+                }
+                s.lower(cxt);
+            }
             Statement::For(s, iter, block) => {
+                let label = cxt.loop_label.take();
                 match iter {
-                    ForIter::Range(a, b, unroll) => {
-                        let a = a.lower(cxt).one();
-                        let b = b.lower(cxt).one();
+                    ForIter::Range(a, b, step, unroll, inclusive, rev) => {
+                        let mut a = a.lower(cxt).one();
+                        let mut b = b.lower(cxt).one();
+                        if *rev {
+                            // (a..b).rev() counts down from the end to the start, so it's b-1..=a step -1
+                            // The end is evaluated first now, so the start needs to be stored if that matters
+                            if !b.simple() {
+                                a = cxt.cache("start", a);
+                            }
+                            let last = match b {
+                                _ if *inclusive => b,
+                                JTerm::Lit(JLit::Int(i)) => {
+                                    JTerm::Lit(JLit::Int(i.wrapping_sub(1)))
+                                }
+                                b => JTerm::BinOp(
+                                    BinOp::Sub,
+                                    Box::new(b),
+                                    Box::new(JTerm::Lit(JLit::Int(1))),
+                                ),
+                            };
+                            b = std::mem::replace(&mut a, last);
+                        }
+                        let step = match step {
+                            Some(step) => step.lower(cxt).one(),
+                            None => JTerm::Lit(JLit::Int(1)),
+                        };
+                        let mut op = if *inclusive { BinOp::Leq } else { BinOp::Lt };
+                        let step = match step {
+                            _ if *rev => {
+                                op = BinOp::Geq;
+                                JTerm::Lit(JLit::Int(-1))
+                            }
+                            // Counting down
+                            JTerm::Lit(JLit::Int(i)) if i < 0 => {
+                                op = if *inclusive { BinOp::Geq } else { BinOp::Gt };
+                                step
+                            }
+                            JTerm::Lit(_) => step,
+                            // The step is evaluated once before the loop, after the start and end
+                            step => {
+                                a = cxt.cache("start", a);
+                                b = cxt.cache("end", b);
+                                let raw = cxt.bindings.raw("step");
+                                let var = cxt.fresh_var(false);
+                                cxt.block.push(JStmt::Let(raw, JTy::I32, var, Some(step)));
+                                JTerm::Var(var, JTy::I32)
+                            }
+                        };
 
                         let v = cxt.fresh_var(cxt.bindings.public(*s));
                         cxt.tys.insert(v, JTy::I32);
                         cxt.vars.push((*s, JVars::One(v)));
 
                         let k = cxt.fresh_block();
-                        cxt.push_loop(k);
+                        cxt.push_loop(k, label);
                         for i in block {
                             i.lower(cxt);
                         }
@@ -2075,6 +5624,8 @@ impl Statement {
                             v,
                             a,
                             b,
+                            step,
+                            op,
                             block,
                             *unroll,
                         ));
@@ -2099,7 +5650,7 @@ impl Statement {
 
                         // Generate an unrolled loop in the current block
                         let k = cxt.fresh_block();
-                        cxt.push_loop(k);
+                        cxt.push_loop(k, label);
                         let mut vals = arr.to_vec();
                         while !vals.is_empty() {
                             for &v in &vars {
@@ -2117,6 +5668,7 @@ impl Statement {
                     }
                     ForIter::Array(arr) => {
                         let arr = arr.lower(cxt);
+                        let arr = cxt.unbox_array(arr);
                         let t = arr.ty();
 
                         let start = JTerm::Lit(JLit::Int(0));
@@ -2126,9 +5678,9 @@ impl Statement {
                         cxt.tys.insert(ix_var, JTy::I32);
 
                         let k = cxt.fresh_block();
-                        cxt.push_loop(k);
+                        cxt.push_loop(k, label);
                         // let s = arr[i];
-                        let mut vars = Vec::new();
+                        let mut elems = Vec::new();
                         for (x, t) in arr.clone().into_iter().zip(t) {
                             let t = match t {
                                 JTy::Array(t) => *t,
@@ -2136,11 +5688,19 @@ impl Statement {
                                 JTy::I32 => break,
                                 _ => unreachable!(),
                             };
-                            let x = JTerm::Index(
+                            elems.push(JTerm::Index(
                                 Box::new(x),
                                 Box::new(JTerm::Var(ix_var, JTy::I32)),
-                                t.clone(),
-                            );
+                                t,
+                            ));
+                        }
+                        let mut vars = Vec::new();
+                        for x in elems
+                            .into_iter()
+                            .flat_map(|x| cxt.unpack(x))
+                            .collect::<Vec<_>>()
+                        {
+                            let t = x.ty();
                             let var = cxt.fresh_var(cxt.bindings.public(*s));
                             cxt.tys.insert(var, t.clone());
                             cxt.block.push(JStmt::Let(
@@ -2163,19 +5723,239 @@ impl Statement {
                             ix_var,
                             start,
                             len,
+                            JTerm::Lit(JLit::Int(1)),
+                            BinOp::Lt,
                             block,
                             false,
                         ));
                     }
                 }
             }
+            Statement::Throw(x) => {
+                let x = x.lower(cxt).one();
+                // Only extern classes can be thrown, and their Java name is the name of the type
+                if let JTy::Class(class) = x.ty() {
+                    let (tid, _) = *cxt.types.iter().rfind(|(_, c)| *c == class).unwrap();
+                    let name = *cxt.bindings.type_name(tid).stem();
+                    if !cxt.thrown.contains(&name) {
+                        cxt.thrown.push(name);
+                    }
+                }
+                cxt.block.push(JStmt::Throw(x));
+            }
+            Statement::Assert(cond, msg) => {
+                if !cxt.assertions_enabled {
+                    return;
+                }
+                let cond = cond.lower(cxt).one();
+                // The message is only built if the assertion fails
+                cxt.push_block();
+                let msg = msg.lower(cxt).one();
+                let exception = cxt.bindings.raw("RuntimeException");
+                cxt.block.push(JStmt::ThrowNew(exception, msg));
+                let block = cxt.pop_block();
+                let cond = match cond {
+                    JTerm::Not(x) => *x,
+                    x => JTerm::Not(Box::new(x)),
+                };
+                cxt.block.push(JStmt::If(cond, block, Vec::new()));
+            }
+            Statement::Try(body, handlers) => {
+                // Exceptions caught here don't need to be in the function's `throws` clause
+                let thrown = std::mem::take(&mut cxt.thrown);
+                cxt.push_block();
+                for i in body {
+                    i.lower(cxt);
+                }
+                let body = cxt.pop_block();
+                let body_thrown = std::mem::replace(&mut cxt.thrown, thrown);
+
+                let mut handlers2 = Vec::new();
+                let mut caught = Vec::new();
+                for (tid, s, block) in handlers {
+                    let class = cxt.class(*tid).unwrap();
+                    caught.push(*cxt.bindings.type_name(*tid).stem());
+                    cxt.push_block();
+                    let var = cxt.fresh_var(cxt.bindings.public(*s));
+                    cxt.tys.insert(var, JTy::Class(class));
+                    cxt.vars.push((*s, JVars::Tuple(vec![var])));
+                    for i in block {
+                        i.lower(cxt);
+                    }
+                    let block = cxt.pop_block();
+                    handlers2.push((class, *cxt.bindings.sym_path(*s).stem(), var, block));
+                }
+                for e in body_thrown {
+                    if !caught.contains(&e) && !cxt.thrown.contains(&e) {
+                        cxt.thrown.push(e);
+                    }
+                }
+
+                cxt.block.push(JStmt::Try(body, handlers2));
+            }
             Statement::InlineJava(s) => {
                 cxt.block.push(JStmt::InlineJava(*s));
             }
         }
     }
 }
+/// If the block ends in an `if` or `switch` followed by returning a variable, the branches that set that variable last
+/// return the value directly instead, so a call in one of them ends up directly in a `return`.
+fn push_returns(block: &mut Vec<JStmt>, f: JFnId) {
+    let t = match block.last() {
+        Some(JStmt::Ret(f2, v)) if *f2 == f => match v.as_slice() {
+            [JTerm::Var(t, _)] => *t,
+            _ => return,
+        },
+        _ => return,
+    };
+    let n = block.len();
+    let branches = match block.get_mut(n.wrapping_sub(2)) {
+        Some(JStmt::If(_, a, b)) => vec![a, b],
+        Some(JStmt::Switch(_, _, cases, default)) => cases
+            .iter_mut()
+            .map(|(_, b)| b)
+            .chain(Some(default))
+            .collect(),
+        _ => return,
+    };
+    for b in branches {
+        if matches!(b.last(), Some(JStmt::Set(JLVal::Var(t2), None, _)) if *t2 == t) {
+            let x = match b.pop() {
+                Some(JStmt::Set(_, _, x)) => x,
+                _ => unreachable!(),
+            };
+            b.push(JStmt::Ret(f, vec![x]));
+            push_returns(b, f);
+        }
+    }
+    // Branches that don't set it still fall through to the original `return`, unless there aren't any
+    if block[n - 2].terminates() {
+        block.pop();
+    }
+}
+
+/// Calls `site` on each call `f` makes to itself in tail position, with the arguments, so it can be replaced with a jump.
+/// That's either `return f(...);`, or in a void function a call right before a `return;` or at the end of the function.
+fn tail_calls(
+    block: &mut [JStmt],
+    f: JFnId,
+    at_end: bool,
+    site: &mut impl FnMut(&mut JStmt, Vec<JTerm>),
+) {
+    let len = block.len();
+    for i in 0..len {
+        let last = i + 1 == len && at_end;
+        let ret_next =
+            matches!(block.get(i + 1), Some(JStmt::Ret(f2, v)) if *f2 == f && v.is_empty());
+        match &mut block[i] {
+            JStmt::Ret(f2, v) if *f2 == f => {
+                if let [JTerm::Call(None, f3, args, _)] = v.as_slice() {
+                    if *f3 == f {
+                        let args = args.clone();
+                        site(&mut block[i], args);
+                    }
+                }
+            }
+            JStmt::MultiCall(None, f3, args, rets)
+                if *f3 == f && rets.is_empty() && (last || ret_next) =>
+            {
+                let args = args.clone();
+                site(&mut block[i], args);
+            }
+            JStmt::If(_, a, b) => {
+                tail_calls(a, f, last, site);
+                tail_calls(b, f, last, site);
+            }
+            JStmt::Switch(_, _, cases, default) => {
+                for (_, b) in cases {
+                    tail_calls(b, f, last, site);
+                }
+                tail_calls(default, f, last, site);
+            }
+            JStmt::Multi(b) => tail_calls(b, f, last, site),
+            JStmt::While(_, _, b)
+            | JStmt::DoWhile(_, b, _)
+            | JStmt::RangeFor(_, _, _, _, _, _, _, b, _) => tail_calls(b, f, false, site),
+            JStmt::Try(body, handlers) => {
+                tail_calls(body, f, false, site);
+                for (_, _, _, b) in handlers {
+                    tail_calls(b, f, false, site);
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+impl<'a> Cxt<'a> {
+    /// Turns a function that calls itself in tail position into a loop, where each of those calls
+    /// sets the arguments to their new values and jumps back to the start instead of making a new call frame.
+    fn tail_loop(
+        &mut self,
+        f: JFnId,
+        args: &[(RawSym, JVar, JTy)],
+        body: Vec<JStmt>,
+    ) -> Vec<JStmt> {
+        let mut new = body.clone();
+        push_returns(&mut new, f);
+        let mut found = false;
+        tail_calls(&mut new, f, true, &mut |_, _| found = true);
+        if !found {
+            return body;
+        }
+
+        let k = self.fresh_block();
+        let raw = self.bindings.raw("$_arg");
+        tail_calls(&mut new, f, true, &mut |s, new_args| {
+            // All the new values are evaluated before any arguments are changed, since they can depend on the old ones
+            let mut jump = Vec::new();
+            let mut sets = Vec::new();
+            for ((_, var, ty), x) in args.iter().zip(new_args) {
+                match x {
+                    JTerm::Var(v, _) if v == *var => continue,
+                    // Literals and locals other than the arguments stay the same when the arguments are set
+                    JTerm::Lit(_) => sets.push(JStmt::Set(JLVal::Var(*var), None, x)),
+                    JTerm::Var(v, _) if args.iter().all(|(_, a, _)| *a != v) => {
+                        sets.push(JStmt::Set(JLVal::Var(*var), None, x))
+                    }
+                    x => {
+                        let tmp = self.fresh_var(false);
+                        self.tys.insert(tmp, ty.clone());
+                        jump.push(JStmt::Let(raw, ty.clone(), tmp, Some(x)));
+                        sets.push(JStmt::Set(
+                            JLVal::Var(*var),
+                            None,
+                            JTerm::Var(tmp, ty.clone()),
+                        ));
+                    }
+                }
+            }
+            jump.append(&mut sets);
+            jump.push(JStmt::Continue(k));
+            *s = JStmt::Multi(jump);
+        });
+        // A void function can reach the end of the body, which has to return instead of looping
+        if !matches!(new.last(), Some(s) if s.terminates()) {
+            new.push(JStmt::Ret(f, Vec::new()));
+        }
+        vec![JStmt::While(k, JTerm::Lit(JLit::Bool(true)), new)]
+    }
+}
 impl Fn {
+    /// Saves the default values of the parameters, so calls that leave them out can fill them in
+    fn declare_defaults(&self, cxt: &mut Cxt) {
+        if !self.defaults.is_empty() {
+            let defaults = self
+                .defaults
+                .iter()
+                .map(|x| x.cloned(cxt.bindings))
+                .collect();
+            cxt.defaults
+                .insert(self.id, (self.args.len() - self.defaults.len(), defaults));
+        }
+    }
+
     fn lower(&self, cxt: &mut Cxt) -> JFn {
         if self.inline {
             panic!("Don't lower inline functions");
@@ -2183,9 +5963,11 @@ impl Fn {
         let mut block = Vec::new();
         let fn_id = cxt.fun(self.id).unwrap();
         std::mem::swap(&mut block, &mut cxt.block);
+        let mut thrown = std::mem::take(&mut cxt.thrown);
 
         cxt.push();
         cxt.current_fn = fn_id;
+        let old_vars = std::mem::replace(&mut cxt.fn_vars, cxt.vars.len());
         let mut args = Vec::new();
         for (name, ty) in &self.args {
             let mut vars = Vec::new();
@@ -2205,20 +5987,33 @@ impl Fn {
                     cxt.block.push(JStmt::Term(i))
                 }
             }
-            (ret, _) => cxt.block.push(JStmt::Ret(fn_id, ret.into())),
+            (ret, _) => cxt.ret(ret.into()),
         }
         cxt.pop();
+        cxt.fn_vars = old_vars;
 
         std::mem::swap(&mut block, &mut cxt.block);
+        std::mem::swap(&mut thrown, &mut cxt.thrown);
+        let block = cxt.tail_loop(fn_id, &args, block);
+        let mut throws = self.throws.clone();
+        for e in thrown {
+            if !throws.contains(&e) {
+                throws.push(e);
+            }
+        }
         let ret_ty = self.ret_ty.lower(cxt);
         JFn {
             name: *cxt.bindings.fn_name(self.id).stem(),
             fn_id,
+            type_params: self.type_params.clone(),
             ret_tys: ret_ty.into(),
             args,
             body: block,
-            public: self.public,
-            throws: self.throws.clone(),
+            visibility: Visibility::from_pub(self.public),
+            is_static: self.is_static,
+            annotations: self.annotations.clone(),
+            unchecked: cxt.unchecked && !throws.is_empty(),
+            throws: if cxt.unchecked { Vec::new() } else { throws },
         }
     }
 }
@@ -2234,8 +6029,22 @@ impl Item {
                 }
             }
             Item::Enum(tid, variants, ext, _members, methods) => {
-                if !ext {
-                    let class = cxt.class(*tid).unwrap();
+                let class = cxt.class(*tid).unwrap();
+                if let Some(pool) = cxt.enum_pools.get(&class) {
+                    // The pool starts with the same capacity as an empty dynamic array
+                    let cap = JTerm::Lit(JLit::Int(cxt.array_init_cap as i32));
+                    let arrays = std::iter::once((pool.tag, JTy::Array(Box::new(JTy::I32))))
+                        .chain(pool.fields.iter().flatten().cloned())
+                        .map(|(v, t)| {
+                            let x = JTerm::ArrayNew(Box::new(cap.clone()), t.clone());
+                            (v, t, Some(x))
+                        });
+                    let len = (pool.len, JTy::I32, Some(JTerm::Lit(JLit::Int(0))));
+                    cxt.items.push(JItem::Let(
+                        arrays.chain(std::iter::once(len)).collect(),
+                        Vec::new(),
+                    ));
+                } else if !ext && !cxt.int_enums.contains_key(&class) {
                     let variants = variants
                         .iter()
                         .map(|(s, t)| (*s, t.iter().flat_map(|x| x.lower(cxt)).collect()))
@@ -2251,9 +6060,9 @@ impl Item {
                     ));
                 }
             }
-            Item::Class(tid, members, methods) => {
+            Item::Class(tid, members, methods, implements, extends) => {
                 let class = cxt.class(*tid).unwrap();
-                let members = members
+                let mut members: Vec<_> = members
                     .iter()
                     .map(|(r, t, x)| {
                         cxt.push_block();
@@ -2284,13 +6093,45 @@ impl Item {
                     .collect();
                 let methods = methods.iter().filter(|x| !x.inline).map(|x| x.lower(cxt)).collect();
 
-                cxt.items.push(JItem::Class(class, members, methods));
+                let implements = implements.iter().map(|t| t.lower(cxt).one()).collect();
+                let extends = extends.as_ref().map(|(t, call)| {
+                    let t = t.lower(cxt).one();
+                    // The super() call has to come before the members are initialized
+                    if let Some(Term::Super(args)) = call {
+                        cxt.push_block();
+                        let args = args.iter().flat_map(|x| x.lower(cxt)).collect();
+                        cxt.block.push(JStmt::Term(JTerm::Super(args, t.clone())));
+                        let block = cxt.pop_block();
+                        members.insert(0, (Vec::new(), block));
+                    }
+                    t
+                });
+                let class = JItem::Class(class, members, methods, implements, extends);
+
+                cxt.items.push(class);
+            }
+            Item::Interface(tid, methods) => {
+                let class = cxt.class(*tid).unwrap();
+                let methods = methods
+                    .iter()
+                    .map(|f| {
+                        let args = f
+                            .args
+                            .iter()
+                            .map(|(r, t)| (*r, t.lower(cxt).one()))
+                            .collect();
+                        let ret = f.ret_ty.lower(cxt).into_iter().next();
+                        (f.name, args, ret)
+                    })
+                    .collect();
+                cxt.items.push(JItem::Interface(class, methods));
             }
             Item::ExternFn(_) => (),
             Item::ExternClass(_, _) => (),
             Item::Let(name, ty, None) => {
                 let var = cxt.var(*name).unwrap();
                 let ty = ty.lower(cxt);
+                // The variable was declared from the same type
                 assert_eq!(var.len(), ty.len());
                 cxt.items.push(JItem::Let(
                     var.into_iter().zip(ty).map(|(v, t)| (v, t, None)).collect(),
@@ -2302,6 +6143,7 @@ impl Item {
                 let var = cxt.var(*name).unwrap();
                 let ty = ty.lower(cxt);
                 let x = x.lower(cxt);
+                // The variable was declared from the same type, and the value was checked against it
                 assert_eq!(var.len(), ty.len());
                 assert_eq!(ty.len(), x.len());
                 let block = cxt.pop_block();
@@ -2318,17 +6160,29 @@ impl Item {
     }
 }
 impl Type {
-    fn lower(&self, cxt: &Cxt) -> JTys {
+    fn lower(&self, cxt: &mut Cxt) -> JTys {
         JTys::One(match self {
+            Type::I8 => JTy::I8,
+            Type::I16 => JTy::I16,
             Type::I32 => JTy::I32,
             Type::I64 => JTy::I64,
+            Type::F32 => JTy::F32,
+            Type::F64 => JTy::F64,
             Type::Bool => JTy::Bool,
+            Type::Char => JTy::Char,
             Type::Str => JTy::String,
+            Type::StrBuilder => JTy::StrBuilder,
             Type::Unit => return JTys::empty(),
-            Type::Class(c) => {
+            Type::Class(c, args) if !args.is_empty() => JTy::Generic(
+                cxt.class(*c).unwrap(),
+                args.iter().flat_map(|x| x.lower(cxt)).collect(),
+            ),
+            Type::Class(c, _) => {
                 let class = cxt.class(*c).unwrap();
                 if let Some(wrapper) = cxt.enum_wrappers.get(&class) {
                     JTy::Class(*wrapper)
+                } else if cxt.int_enum(class) {
+                    JTy::I32
                 } else {
                     JTy::Class(class)
                 }
@@ -2337,20 +6191,74 @@ impl Type {
             // Automatic struct-of-arrays
             // This actually has basically no effect on bytecode count - in testing, it only made a one instruction difference
             // It also includes the length at the end so that the array is dynamic
+            Type::Array(_) => {
+                let t = self.lower_unboxed(cxt);
+                if cxt.boxed_arrays {
+                    return JTys::One(JTy::Class(cxt.array_class(t.to_vec())));
+                }
+                return t;
+            }
+            Type::SArray(t, i) => {
+                return JTys::Tuple(std::iter::repeat(t.lower(cxt)).take(*i).flatten().collect())
+            }
+            // An option is a flag for whether the value is present, followed by the value itself,
+            // which is the zero value of its type if it's not present
+            Type::Option(t) => {
+                return JTys::Tuple(std::iter::once(JTy::Bool).chain(t.lower(cxt)).collect())
+            }
+            Type::Param(r) => JTy::TypeVar(*r),
+            Type::Map(k, v) => JTy::Map(Box::new(k.lower(cxt).one()), Box::new(v.lower(cxt).one())),
+        })
+    }
+}
+
+impl Type {
+    /// Like `lower()`, but a dynamic array is always its Java arrays and length, even if arrays are boxed
+    fn lower_unboxed(&self, cxt: &mut Cxt) -> JTys {
+        match self {
             Type::Array(t) => {
-                return JTys::Tuple(
-                    t.lower(cxt)
-                        .into_iter()
+                let t = t.lower(cxt);
+                if !cxt.soa && t.len() > 1 {
+                    let class = cxt.tuple_class(t.to_vec());
+                    return JTys::Tuple(vec![JTy::Array(Box::new(JTy::Class(class))), JTy::I32]);
+                }
+                JTys::Tuple(
+                    t.into_iter()
                         .map(Box::new)
                         .map(JTy::Array)
                         .chain(std::iter::once(JTy::I32))
                         .collect(),
                 )
             }
+            _ => self.lower(cxt),
+        }
+    }
+
+    /// The terms for `default::<T>()`.
+    /// This is the same as `JTy::null()`, except strings are empty and dynamic arrays are allocated,
+    /// since a null dynamic array would break `push()`.
+    fn lower_default(&self, cxt: &mut Cxt) -> JTerms {
+        match self {
+            Type::Array(t) => Term::Array(Vec::new(), (**t).clone(), true).lower(cxt),
+            Type::Tuple(v) => JTerms::Tuple(v.iter().flat_map(|x| x.lower_default(cxt)).collect()),
             Type::SArray(t, i) => {
-                return JTys::Tuple(std::iter::repeat(t.lower(cxt)).take(*i).flatten().collect())
+                JTerms::Tuple((0..*i).flat_map(|_| t.lower_default(cxt)).collect())
             }
-        })
+            // Like null, since 0 is a variant or a value in a pool
+            Type::Class(c, _) if cxt.int_enum(cxt.class(*c).unwrap()) => {
+                JTerms::One(JTerm::Lit(JLit::Int(-1)))
+            }
+            _ => self.lower(cxt).map(|t| match t {
+                JTy::String => JTerm::Lit(JLit::Str(cxt.bindings.raw(""))),
+                JTy::StrBuilder => {
+                    JTerm::InlineJava(cxt.bindings.raw("new StringBuilder()"), JTy::StrBuilder)
+                }
+                t @ JTy::Map(_, _) => {
+                    JTerm::InlineJava(cxt.bindings.raw("new java.util.HashMap<>()"), t)
+                }
+                t => t.zero(),
+            }),
+        }
     }
 }
 
@@ -2385,6 +6293,27 @@ impl<F: FnMut(&mut JStmt)> Visitor for VStmt<F> {
         self.0(t);
     }
 }
+/// Finds the first loop that couldn't be unrolled or static array index that isn't constant, which can't be generated
+struct Unresolved(Option<BackendError>);
+impl Visitor for Unresolved {
+    fn visit_term(&mut self, t: &mut JTerm) {
+        if matches!(t, JTerm::SIndex(_, i) if !matches!(**i, JTerm::Lit(JLit::Int(_)))) {
+            self.0.get_or_insert(BackendError::StaticIndex);
+        }
+    }
+
+    fn visit_lval(&mut self, l: &mut JLVal) {
+        if matches!(l, JLVal::SIdx(_, i) if !matches!(i, JTerm::Lit(JLit::Int(_)))) {
+            self.0.get_or_insert(BackendError::StaticIndex);
+        }
+    }
+
+    fn visit_stmt(&mut self, s: &mut JStmt) {
+        if let JStmt::RangeFor(_, _, _, _, _, _, _, _, true) = s {
+            self.0.get_or_insert(BackendError::CantUnroll);
+        }
+    }
+}
 struct VLVal<F: FnMut(&mut JLVal)>(F);
 impl<F: FnMut(&mut JLVal)> Visitor for VLVal<F> {
     fn visit_lval(&mut self, t: &mut JLVal) {
@@ -2414,7 +6343,7 @@ impl JTerm {
                 v.iter_mut().for_each(|x| x.map(f));
             }
             JTerm::ArrayNew(v, _) => v.map(f),
-            JTerm::ClassNew(_, v) => {
+            JTerm::ClassNew(_, v) | JTerm::Super(v, _) => {
                 v.iter_mut().for_each(|x| x.map(f));
             }
             JTerm::Index(x, y, _) => {
@@ -2426,9 +6355,23 @@ impl JTerm {
                 y.map(f);
             }
             JTerm::Not(x) => x.map(f),
+            JTerm::BitNot(x) => x.map(f),
+            JTerm::Neg(x) => x.map(f),
+            JTerm::Ternary(c, a, b) => {
+                c.map(f);
+                a.map(f);
+                b.map(f);
+            }
+            JTerm::Cast(x, _) | JTerm::InstanceOf(x, _) => x.map(f),
             JTerm::Null(_) => (),
             JTerm::This(_) => (),
             JTerm::InlineJava(_, _) => (),
+            JTerm::Lambda(_, body, x, _) => {
+                body.iter_mut().for_each(|s| s.map(f));
+                if let Some(x) = x {
+                    x.map(f);
+                }
+            }
         }
         f.visit_term(self);
     }
@@ -2451,6 +6394,65 @@ impl JLVal {
     }
 }
 impl JStmt {
+    /// Whether this statement does nothing, e.g. because it was removed by an optimization
+    fn is_empty(&self) -> bool {
+        match self {
+            JStmt::Multi(v) => v.iter().all(JStmt::is_empty),
+            // A comment doesn't do anything on its own
+            JStmt::Src(_, _) => true,
+            _ => false,
+        }
+    }
+
+    /// Whether control never continues past this statement, so Java rejects anything after it as unreachable
+    fn terminates(&self) -> bool {
+        match self {
+            JStmt::Ret(_, _)
+            | JStmt::Break(_)
+            | JStmt::Continue(_)
+            | JStmt::ThrowNew(_, _)
+            | JStmt::Throw(_) => true,
+            JStmt::If(_, a, b) => {
+                a.iter().any(JStmt::terminates) && b.iter().any(JStmt::terminates)
+            }
+            JStmt::Multi(v) => v.iter().any(JStmt::terminates),
+            // There's always a `default:`, and branches that end don't get a `break` out of the `switch`
+            JStmt::Switch(_, _, cases, default) => {
+                cases.iter().all(|(_, b)| b.iter().any(JStmt::terminates))
+                    && default.iter().any(JStmt::terminates)
+            }
+            JStmt::Try(body, handlers) => {
+                body.iter().any(JStmt::terminates)
+                    && handlers
+                        .iter()
+                        .all(|(_, _, _, b)| b.iter().any(JStmt::terminates))
+            }
+            // An infinite loop only ends with a `break` out of it
+            JStmt::While(k, JTerm::Lit(JLit::Bool(true)), body) => {
+                let mut breaks = false;
+                for s in body.clone().iter_mut() {
+                    s.map(&mut VStmt(|s| breaks |= *s == JStmt::Break(*k)));
+                }
+                !breaks
+            }
+            // The body always runs, so the loop ends if the body does (unless it continues) or if it never stops
+            JStmt::DoWhile(k, body, cond) => {
+                let mut breaks = false;
+                let mut continues = false;
+                for s in body.clone().iter_mut() {
+                    s.map(&mut VStmt(|s| {
+                        breaks |= *s == JStmt::Break(*k);
+                        continues |= *s == JStmt::Continue(*k);
+                    }));
+                }
+                !breaks
+                    && (*cond == JTerm::Lit(JLit::Bool(true))
+                        || (!continues && body.iter().any(JStmt::terminates)))
+            }
+            _ => false,
+        }
+    }
+
     fn map(&mut self, f: &mut impl Visitor) {
         match self {
             JStmt::Multi(b) => b.iter_mut().for_each(|x| x.map(f)),
@@ -2478,9 +6480,20 @@ impl JStmt {
                 x.map(f);
                 a.iter_mut().for_each(|x| x.map(f));
             }
-            JStmt::RangeFor(_, _, _, x, y, b, _) => {
+            JStmt::DoWhile(_, a, x) => {
+                a.iter_mut().for_each(|x| x.map(f));
+                x.map(f);
+            }
+            JStmt::Try(a, b) => {
+                a.iter_mut().for_each(|x| x.map(f));
+                b.iter_mut()
+                    .flat_map(|(_, _, _, b)| b.iter_mut())
+                    .for_each(|x| x.map(f));
+            }
+            JStmt::RangeFor(_, _, _, x, y, z, _, b, _) => {
                 x.map(f);
                 y.map(f);
+                z.map(f);
                 b.iter_mut().for_each(|x| x.map(f));
             }
             JStmt::Continue(_) => (),
@@ -2492,7 +6505,8 @@ impl JStmt {
                 x.as_mut().map(|x| x.map(f));
                 v.iter_mut().for_each(|x| x.map(f));
             }
-            JStmt::InlineJava(_) => (),
+            JStmt::InlineJava(_) | JStmt::Src(_, _) => (),
+            JStmt::ThrowNew(_, x) | JStmt::Throw(x) => x.map(f),
         }
         f.visit_stmt(self);
     }
@@ -2503,11 +6517,24 @@ struct UseCounter {
     count: HashMap<JVar, usize>,
     mutated: HashSet<JVar>,
     defined: HashSet<JVar>,
+    /// Variables whose elements are set, like `v[i] = x`
+    stored: HashSet<JVar>,
+    /// Variables which are set to something other than a new array, so they could share it with something else
+    aliased: HashSet<JVar>,
 }
 impl Visitor for UseCounter {
     fn visit_term(&mut self, t: &mut JTerm) {
         match t {
             JTerm::Var(v, _) => *self.count.entry(*v).or_default() += 1,
+            // Captured locals can only be "mutated" by writing to their elements, which is seen outside the lambda,
+            // so it counts as a use
+            JTerm::Lambda(_, body, _, _) => {
+                let mut inner = UseCounter::default();
+                body.clone().iter_mut().for_each(|s| s.map(&mut inner));
+                for v in inner.mutated.difference(&inner.defined) {
+                    *self.count.entry(*v).or_default() += 1;
+                }
+            }
             _ => (),
         }
     }
@@ -2518,33 +6545,68 @@ impl Visitor for UseCounter {
                 // *self.count.entry(*v).or_default() += 1;
                 self.mutated.insert(*v);
             }
+            JLVal::Idx(l, _) => {
+                if let Some(v) = l.root_var() {
+                    self.stored.insert(v);
+                }
+            }
             _ => (),
         }
     }
 
     fn visit_stmt(&mut self, t: &mut JStmt) {
+        let fresh = |x: &JTerm| matches!(x, JTerm::Array(_, _) | JTerm::ArrayNew(_, _));
         match t {
-            JStmt::Let(_, _, v, _) => {
+            JStmt::Let(_, _, v, x) => {
                 self.defined.insert(*v);
+                if x.as_ref().map_or(false, |x| !fresh(x)) {
+                    self.aliased.insert(*v);
+                }
+            }
+            JStmt::Set(JLVal::Var(v), _, x) if !fresh(x) => {
+                self.aliased.insert(*v);
             }
-            JStmt::RangeFor(_, _, v, _, _, _, _) => {
+            JStmt::RangeFor(_, _, v, _, _, _, _, _, _) => {
                 self.defined.insert(*v);
             }
             JStmt::MultiCall(_, _, _, rs) => {
                 for (_, v, _) in rs {
                     self.defined.insert(*v);
+                    self.aliased.insert(*v);
                 }
             }
             _ => (),
         }
     }
 }
+/// Whether a block definitely doesn't change a variable; calls and inline Java could change fields, so they count too
+struct Unchanged(JVar, bool);
+impl Visitor for Unchanged {
+    fn visit_term(&mut self, t: &mut JTerm) {
+        if matches!(t, JTerm::Call(_, _, _, _) | JTerm::InlineJava(_, _)) {
+            self.1 = false;
+        }
+    }
+
+    fn visit_lval(&mut self, t: &mut JLVal) {
+        if *t == JLVal::Var(self.0) {
+            self.1 = false;
+        }
+    }
+
+    fn visit_stmt(&mut self, s: &mut JStmt) {
+        if matches!(s, JStmt::MultiCall(_, _, _, _) | JStmt::InlineJava(_)) {
+            self.1 = false;
+        }
+    }
+}
 struct SideEffects(bool);
 impl Visitor for SideEffects {
     fn visit_term(&mut self, t: &mut JTerm) {
         self.0 |= match t {
             JTerm::Call(_, _, _, _) => true,
             JTerm::ClassNew(_, _) => true,
+            JTerm::Super(_, _) => true,
             JTerm::InlineJava(_, _) => true,
 
             JTerm::Var(_, _)
@@ -2557,8 +6619,14 @@ impl Visitor for SideEffects {
             | JTerm::Index(_, _, _)
             | JTerm::SIndex(_, _)
             | JTerm::Not(_)
+            | JTerm::BitNot(_)
+            | JTerm::Neg(_)
+            | JTerm::Ternary(_, _, _)
+            | JTerm::Cast(_, _)
+            | JTerm::InstanceOf(_, _)
             | JTerm::Null(_)
-            | JTerm::This(_) => false,
+            | JTerm::This(_)
+            | JTerm::Lambda(_, _, _, _) => false,
         };
     }
 
@@ -2574,13 +6642,17 @@ impl Visitor for SideEffects {
             JStmt::Ret(_, _) => true,
             JStmt::MultiCall(_, _, _, _) => true,
             JStmt::InlineJava(_) => true,
+            JStmt::ThrowNew(_, _) | JStmt::Throw(_) => true,
+            JStmt::Src(_, _) => false,
 
             JStmt::Let(_, _, _, _)
             | JStmt::Term(_)
             | JStmt::If(_, _, _)
             | JStmt::Switch(_, _, _, _)
             | JStmt::While(_, _, _)
-            | JStmt::RangeFor(_, _, _, _, _, _, _)
+            | JStmt::DoWhile(_, _, _)
+            | JStmt::RangeFor(_, _, _, _, _, _, _, _, _)
+            | JStmt::Try(_, _)
             | JStmt::Multi(_) => false,
         };
     }
@@ -2595,7 +6667,7 @@ impl JItem {
         match self {
             JItem::Fn(x) => vec![&mut x.body],
             JItem::Enum(_, _, _, methods) => methods.iter_mut().map(|x| &mut x.body).collect(),
-            JItem::Class(_, members, methods) => {
+            JItem::Class(_, members, methods, _, _) => {
                 let mut r = Vec::new();
                 for (_, b) in members {
                     r.push(b);
@@ -2605,100 +6677,763 @@ impl JItem {
                 }
                 r
             }
-            JItem::Let(_, b) => vec![b],
+            JItem::Let(_, b) => vec![b],
+            JItem::Tuple(_, _) | JItem::Interface(_, _) => Vec::new(),
+        }
+    }
+
+    /// Like `blocks`, but with the arguments of the function each block belongs to, if any
+    fn fn_blocks(&mut self) -> Vec<(Vec<JVar>, &mut Vec<JStmt>)> {
+        let args = |x: &JFn| x.args.iter().map(|(_, v, _)| *v).collect();
+        match self {
+            JItem::Fn(x) => vec![(args(x), &mut x.body)],
+            JItem::Enum(_, _, _, methods) => {
+                methods.iter_mut().map(|x| (args(x), &mut x.body)).collect()
+            }
+            JItem::Class(_, members, methods, _, _) => {
+                let mut r = Vec::new();
+                for (_, b) in members {
+                    r.push((Vec::new(), b));
+                }
+                for i in methods {
+                    r.push((args(i), &mut i.body));
+                }
+                r
+            }
+            JItem::Let(_, b) => vec![(Vec::new(), b)],
+            JItem::Tuple(_, _) | JItem::Interface(_, _) => Vec::new(),
+        }
+    }
+
+    fn map(&mut self, f: &mut impl Visitor) {
+        match self {
+            JItem::Fn(x) => x.map(f),
+            JItem::Enum(_, _, _, methods) => methods.iter_mut().for_each(|x| x.map(f)),
+            JItem::Class(_, members, methods, _, _) => {
+                for (v, b) in members {
+                    for (_, _, x) in v {
+                        if let Some(x) = x {
+                            x.map(f);
+                        }
+                    }
+                    b.iter_mut().for_each(|x| x.map(f));
+                }
+                methods.iter_mut().for_each(|x| x.map(f));
+            }
+            JItem::Let(v, b) => {
+                for (_, _, x) in v {
+                    if let Some(x) = x {
+                        x.map(f);
+                    }
+                }
+                b.iter_mut().for_each(|x| x.map(f));
+            }
+            JItem::Tuple(_, _) | JItem::Interface(_, _) => (),
+        }
+    }
+}
+impl<'a> Cxt<'a> {
+    fn replace(&mut self, v: JVar, x: &JTerm) {
+        for i in &mut self.items {
+            i.map(&mut VTerm(|t| match t {
+                JTerm::Var(y, _) if *y == v => {
+                    *t = x.clone();
+                }
+                _ => (),
+            }));
+        }
+    }
+
+    /// Finds the string literals used more than once in the current items, and creates a variable for each of them
+    fn repeated_strings(&mut self) -> Vec<(JVar, RawSym)> {
+        let mut counts: HashMap<RawSym, usize> = HashMap::new();
+        let mut order = Vec::new();
+        for i in &mut self.items {
+            i.map(&mut VTerm(|t| {
+                if let JTerm::Lit(JLit::Str(s)) = t {
+                    let count = counts.entry(*s).or_insert(0);
+                    if *count == 0 {
+                        order.push(*s);
+                    }
+                    *count += 1;
+                }
+            }));
+        }
+        order
+            .into_iter()
+            .filter(|s| counts[s] > 1)
+            .map(|s| (self.fresh_var(false), s))
+            .collect()
+    }
+
+    fn opt(&mut self) {
+        // Constant propagation
+        for item in &mut self.items {
+            for block in item.blocks() {
+                let mut env = Env::new(self.bindings, self.next);
+                for s in block {
+                    s.prop(&mut env);
+                }
+                self.next = env.next;
+            }
+        }
+
+        // Statements after a `return`, `break`, etc. can still be lowered, but Java doesn't allow them
+        for item in &mut self.items {
+            for block in item.blocks() {
+                remove_dead(block);
+            }
+        }
+
+        // Move loop-invariant terms out of loops
+        let raw = self.bindings.raw("$_inv");
+        for item in &mut self.items {
+            for (args, block) in item.fn_blocks() {
+                let mut counter = UseCounter::default();
+                block.iter_mut().for_each(|s| s.map(&mut counter));
+                let mut locals = counter.defined;
+                locals.extend(args);
+                hoist_invariants(block, &locals, &self.volatile, raw, &mut self.next);
+            }
+        }
+
+        // Cache repeated field and array accesses in locals
+        let raw = self.bindings.raw("$_elem");
+        for item in &mut self.items {
+            for block in item.blocks() {
+                cache_elems(block, raw, &mut self.next);
+            }
+        }
+
+        // Remove unused variables
+        let mut counter = UseCounter::default();
+        for i in &mut self.items {
+            i.map(&mut counter);
+        }
+        // Setting an element of an array that could be shared is visible elsewhere, so it counts as a use
+        for v in counter.stored.intersection(&counter.aliased) {
+            *counter.count.entry(*v).or_default() += 1;
+        }
+        for i in &mut self.items {
+            i.map(&mut VStmt(|t| match t {
+                JStmt::Let(_, _, v, Some(x)) => {
+                    if !v.1 && counter.count.get(v).map_or(true, |x| *x == 0) {
+                        let mut effects = SideEffects(false);
+                        x.map(&mut effects);
+                        if !effects.0 {
+                            *t = JStmt::Multi(Vec::new());
+                        }
+                    }
+                }
+                JStmt::Let(_, _, v, None) => {
+                    if !v.1 && counter.count.get(v).map_or(true, |x| *x == 0) {
+                        *t = JStmt::Multi(Vec::new());
+                    }
+                }
+                // `x = x;` can be left over after inlining and constant propagation
+                JStmt::Set(JLVal::Var(v), None, JTerm::Var(v2, _)) if v == v2 => {
+                    *t = JStmt::Multi(Vec::new());
+                }
+                JStmt::Set(l, _, x) => {
+                    if let Some(v) = l.root_var() {
+                        if !v.1
+                            && counter.count.get(&v).map_or(true, |x| *x == 0)
+                            && counter.defined.contains(&v)
+                        {
+                            let mut effects = SideEffects(false);
+                            x.map(&mut effects);
+                            if !effects.0 {
+                                *t = JStmt::Multi(Vec::new());
+                            }
+                        }
+                    }
+                }
+                // An `if` with nothing left in either branch doesn't need to exist
+                JStmt::If(x, a, b)
+                    if a.iter().all(JStmt::is_empty) && b.iter().all(JStmt::is_empty) =>
+                {
+                    let mut effects = SideEffects(false);
+                    x.map(&mut effects);
+                    if !effects.0 {
+                        *t = JStmt::Multi(Vec::new());
+                    }
+                }
+                _ => (),
+            }));
+        }
+    }
+}
+
+/// Removes the statements in each block that come after one that never continues, like a `return`.
+/// Each branch of an `if` is checked separately, so only statements that can never run are removed.
+fn remove_dead(block: &mut Vec<JStmt>) {
+    for s in block.iter_mut() {
+        match s {
+            JStmt::If(_, a, b) => {
+                remove_dead(a);
+                remove_dead(b);
+            }
+            JStmt::Switch(_, _, cases, default) => {
+                for (_, b) in cases {
+                    remove_dead(b);
+                }
+                remove_dead(default);
+            }
+            JStmt::Try(body, handlers) => {
+                remove_dead(body);
+                for (_, _, _, b) in handlers {
+                    remove_dead(b);
+                }
+            }
+            JStmt::While(_, _, b)
+            | JStmt::DoWhile(_, b, _)
+            | JStmt::RangeFor(_, _, _, _, _, _, _, b, _)
+            | JStmt::Multi(b) => remove_dead(b),
+            _ => (),
+        }
+    }
+    if let Some(i) = block.iter().position(JStmt::terminates) {
+        block.truncate(i + 1);
+    }
+}
+
+/// Whether a term only reads variables, fields and array elements, so evaluating it again gives the same value
+/// as long as none of them are written in between.
+/// Arithmetic is allowed in indices, like `a[i + 1]`, but not division, which could throw.
+fn is_access(t: &JTerm) -> bool {
+    match t {
+        JTerm::Var(_, _) | JTerm::Lit(_) | JTerm::This(_) => true,
+        JTerm::Prop(x, _, _) => is_access(x),
+        JTerm::Index(a, i, _) => is_access(a) && is_access(i),
+        JTerm::BinOp(op, a, b) => {
+            !matches!(op, BinOp::Div | BinOp::Mod | BinOp::And | BinOp::Or)
+                && is_access(a)
+                && is_access(b)
+        }
+        _ => false,
+    }
+}
+
+/// Stores field and array accesses which are repeated in a row, like `a[i].x`, `a[i].y`, `a[i].z`, in a local.
+/// Storing a value takes two more instructions than using it once, and each later use saves all but the one
+/// instruction for loading the local, so this only pays off for `a[i]` three or more times, `a.x` four or more, etc.
+/// Inner accesses are cached first, so `a.b.c` and `a.b.d` can share a local for `a.b`.
+fn cache_elems(block: &mut Vec<JStmt>, raw: RawSym, next: &mut u64) {
+    for s in block.iter_mut() {
+        match s {
+            JStmt::If(_, a, b) => {
+                cache_elems(a, raw, next);
+                cache_elems(b, raw, next);
+            }
+            JStmt::Switch(_, _, cases, default) => {
+                for (_, b) in cases {
+                    cache_elems(b, raw, next);
+                }
+                cache_elems(default, raw, next);
+            }
+            JStmt::Try(body, handlers) => {
+                cache_elems(body, raw, next);
+                for (_, _, _, b) in handlers {
+                    cache_elems(b, raw, next);
+                }
+            }
+            JStmt::While(_, _, b)
+            | JStmt::DoWhile(_, b, _)
+            | JStmt::RangeFor(_, _, _, _, _, _, _, b, _)
+            | JStmt::Multi(b) => cache_elems(b, raw, next),
+            _ => (),
+        }
+    }
+
+    let mut i = 0;
+    while i < block.len() {
+        let mut candidates = Vec::new();
+        block[i].map_own(&mut VTerm(|t| {
+            if matches!(t, JTerm::Index(_, _, _) | JTerm::Prop(_, _, _))
+                && is_access(t)
+                && !candidates.contains(t)
+            {
+                candidates.push(t.clone());
+            }
+        }));
+        // Inner accesses are visited first, and caching them changes the outer ones, so start over after each one
+        let mut hoisted = false;
+
+        for t in candidates {
+            // Find how far the value stays the same, and how many times it's used until then
+            let mut count = 0;
+            let mut end = i;
+            while end < block.len() {
+                let mut clobbers = Clobbers::default();
+                block[end].map(&mut clobbers);
+                let clobbered = clobbers.clobbers(&t);
+                if clobbered && !block[end].effects_last() {
+                    break;
+                }
+                let mut occurrences = Occurrences(&t, 0);
+                block[end].map_own(&mut occurrences);
+                if end == i && occurrences.1 == 0 {
+                    // Only used conditionally here, so we can't evaluate it any earlier
+                    break;
+                }
+                count += occurrences.1;
+                end += 1;
+                if clobbered {
+                    break;
+                }
+            }
+
+            let cost = t.ops();
+            if count > 1 && (count - 1) * (cost - 1) > 2 {
+                let ty = t.ty();
+                *next += 1;
+                let var = JVar(*next, false);
+                for s in &mut block[i..end] {
+                    s.map_own(&mut VTerm(|x| {
+                        if *x == t {
+                            *x = JTerm::Var(var, ty.clone());
+                        }
+                    }));
+                }
+                block.insert(i, JStmt::Let(raw, ty, var, Some(t)));
+                i += 1;
+                hoisted = true;
+                break;
+            }
+        }
+
+        if !hoisted {
+            i += 1;
+        }
+    }
+}
+
+/// Whether a term can be evaluated at any time without side effects or exceptions, so it can be moved before a loop
+/// that might not have evaluated it at all.
+fn is_pure(t: &JTerm) -> bool {
+    match t {
+        JTerm::Var(_, _)
+        | JTerm::Lit(_)
+        | JTerm::Null(_)
+        | JTerm::Variant(_, _)
+        | JTerm::This(_) => true,
+        // Integer division by zero throws
+        JTerm::BinOp(BinOp::Div | BinOp::Mod, _, _) => false,
+        // Objects are compared with `a.equals(b)`, which throws if `a` is null
+        JTerm::BinOp(BinOp::Eq | BinOp::Neq, a, b)
+            if !a.ty().primitive()
+                && !matches!(**a, JTerm::Null(_))
+                && !matches!(**b, JTerm::Null(_)) =>
+        {
+            false
+        }
+        JTerm::BinOp(_, a, b) => is_pure(a) && is_pure(b),
+        JTerm::Not(x) | JTerm::BitNot(x) | JTerm::Neg(x) => is_pure(x),
+        // Class casts throw if the object isn't an instance of the class
+        JTerm::Cast(x, t) => t.primitive() && is_pure(x),
+        JTerm::InstanceOf(x, _) => is_pure(x),
+        JTerm::Ternary(c, a, b) => is_pure(c) && is_pure(a) && is_pure(b),
+        _ => false,
+    }
+}
+
+/// Calls `f` on `t` and each of its subterms that's evaluated whenever `t` is,
+/// so not the branches of a `?:`, the right side of `&&` or `||`, or the body of a lambda.
+fn unconditional_terms(t: &JTerm, f: &mut impl FnMut(&JTerm)) {
+    match t {
+        JTerm::Var(_, _)
+        | JTerm::Lit(_)
+        | JTerm::Variant(_, _)
+        | JTerm::Null(_)
+        | JTerm::This(_)
+        | JTerm::InlineJava(_, _)
+        | JTerm::Lambda(_, _, _, _) => (),
+        JTerm::Call(o, _, a, _) => {
+            if let Some(o) = o {
+                unconditional_terms(o, f);
+            }
+            a.iter().for_each(|x| unconditional_terms(x, f));
+        }
+        JTerm::BinOp(BinOp::And | BinOp::Or, a, _) => unconditional_terms(a, f),
+        JTerm::BinOp(_, a, b) | JTerm::Index(a, b, _) => {
+            unconditional_terms(a, f);
+            unconditional_terms(b, f);
+        }
+        JTerm::Array(v, _) | JTerm::ClassNew(_, v) | JTerm::Super(v, _) => {
+            v.iter().for_each(|x| unconditional_terms(x, f));
+        }
+        JTerm::SIndex(v, i) => {
+            v.iter().for_each(|x| unconditional_terms(x, f));
+            unconditional_terms(i, f);
+        }
+        JTerm::Prop(x, _, _)
+        | JTerm::ArrayNew(x, _)
+        | JTerm::Not(x)
+        | JTerm::BitNot(x)
+        | JTerm::Neg(x)
+        | JTerm::Cast(x, _)
+        | JTerm::InstanceOf(x, _)
+        | JTerm::Ternary(x, _, _) => unconditional_terms(x, f),
+    }
+    f(t);
+}
+
+/// Calls `f` on the terms in `block` that are evaluated every time it runs, like `unconditional_terms()`.
+/// Returns false if `block` might jump somewhere else before the end, so the statements after it might not run.
+fn unconditional_block(block: &[JStmt], f: &mut impl FnMut(&JTerm)) -> bool {
+    for s in block {
+        match s {
+            JStmt::Let(_, _, _, Some(x))
+            | JStmt::Term(x)
+            | JStmt::ThrowNew(_, x)
+            | JStmt::Throw(x)
+            // Only the condition of an `if`, `switch` or `while` is always evaluated
+            | JStmt::If(x, _, _)
+            | JStmt::Switch(_, x, _, _)
+            | JStmt::While(_, x, _) => unconditional_terms(x, f),
+            JStmt::Set(l, _, x) => {
+                let mut l = l;
+                loop {
+                    match l {
+                        JLVal::Var(_) => break,
+                        JLVal::Idx(a, i) => {
+                            unconditional_terms(i, f);
+                            l = a;
+                        }
+                        JLVal::SIdx(_, i) => {
+                            unconditional_terms(i, f);
+                            break;
+                        }
+                        JLVal::Prop(a, _) => {
+                            unconditional_terms(a, f);
+                            break;
+                        }
+                    }
+                }
+                unconditional_terms(x, f);
+            }
+            JStmt::RangeFor(_, _, _, a, b, step, _, _, _) => {
+                unconditional_terms(a, f);
+                unconditional_terms(b, f);
+                unconditional_terms(step, f);
+            }
+            JStmt::Ret(_, v) => v.iter().for_each(|x| unconditional_terms(x, f)),
+            JStmt::MultiCall(o, _, v, _) => {
+                if let Some(o) = o {
+                    unconditional_terms(o, f);
+                }
+                v.iter().for_each(|x| unconditional_terms(x, f));
+            }
+            JStmt::Multi(b) if !unconditional_block(b, f) => return false,
+            _ => (),
+        }
+        let mut jumps = false;
+        s.clone().map(&mut VStmt(|s| {
+            jumps |= matches!(
+                s,
+                JStmt::Break(_)
+                    | JStmt::Continue(_)
+                    | JStmt::Ret(_, _)
+                    | JStmt::ThrowNew(_, _)
+                    | JStmt::Throw(_)
+                    | JStmt::InlineJava(_)
+            )
+        }));
+        if jumps {
+            return false;
+        }
+    }
+    true
+}
+
+/// Moves terms that have the same value in every iteration of a `while` or `for` loop, like `w * h - 1`,
+/// into a local before the loop.
+/// Only terms that can't throw and are evaluated on every iteration are moved, and only if they're at least three
+/// instructions, since the local is one.
+/// `locals` are the variables of the function the block is in, which calls can't change, and `volatile` are fields
+/// that another thread could change at any time.
+fn hoist_invariants(
+    block: &mut Vec<JStmt>,
+    locals: &HashSet<JVar>,
+    volatile: &HashSet<JVar>,
+    raw: RawSym,
+    next: &mut u64,
+) {
+    for s in block.iter_mut() {
+        match s {
+            JStmt::If(_, a, b) => {
+                hoist_invariants(a, locals, volatile, raw, next);
+                hoist_invariants(b, locals, volatile, raw, next);
+            }
+            JStmt::Switch(_, _, cases, default) => {
+                for (_, b) in cases {
+                    hoist_invariants(b, locals, volatile, raw, next);
+                }
+                hoist_invariants(default, locals, volatile, raw, next);
+            }
+            JStmt::Try(body, handlers) => {
+                hoist_invariants(body, locals, volatile, raw, next);
+                for (_, _, _, b) in handlers {
+                    hoist_invariants(b, locals, volatile, raw, next);
+                }
+            }
+            JStmt::While(_, _, b)
+            | JStmt::DoWhile(_, b, _)
+            | JStmt::RangeFor(_, _, _, _, _, _, _, b, _)
+            | JStmt::Multi(b) => hoist_invariants(b, locals, volatile, raw, next),
+            _ => (),
+        }
+    }
+
+    let mut i = 0;
+    while i < block.len() {
+        if !matches!(
+            block[i],
+            JStmt::While(_, _, _) | JStmt::RangeFor(_, _, _, _, _, _, _, _, _)
+        ) {
+            i += 1;
+            continue;
+        }
+
+        let mut clobbers = Clobbers::default();
+        let mut counter = UseCounter::default();
+        block[i].map(&mut clobbers);
+        block[i].map(&mut counter);
+        let mut defined = counter.defined;
+        block[i].map(&mut VStmt(|s| {
+            if let JStmt::Try(_, handlers) = s {
+                defined.extend(handlers.iter().map(|(_, _, v, _)| *v));
+            }
+        }));
+        let invariant = |t: &JTerm| {
+            let mut vars = UseCounter::default();
+            t.clone().map(&mut vars);
+            vars.count.keys().all(|v| {
+                !defined.contains(v)
+                    && !clobbers.vars.contains(v)
+                    && !volatile.contains(v)
+                    && (!clobbers.calls || locals.contains(v))
+            })
+        };
+
+        // The start of a `for` loop is only evaluated once anyway
+        let mut candidates: Vec<JTerm> = Vec::new();
+        let mut visit = |t: &JTerm| {
+            if !t.simple()
+                && t.ops() >= 3
+                && !matches!(t.ty(), JTy::I8 | JTy::I16)
+                && is_pure(t)
+                && invariant(t)
+                && !candidates.contains(t)
+            {
+                candidates.push(t.clone());
+            }
+        };
+        match &block[i] {
+            JStmt::While(_, cond, body) => {
+                unconditional_terms(cond, &mut visit);
+                unconditional_block(body, &mut visit);
+            }
+            JStmt::RangeFor(_, _, _, _, end, step, _, body, _) => {
+                unconditional_terms(end, &mut visit);
+                unconditional_terms(step, &mut visit);
+                unconditional_block(body, &mut visit);
+            }
+            _ => unreachable!(),
+        }
+        // Inline Java could change anything, even locals
+        if clobbers.java {
+            candidates.clear();
+        }
+
+        // Bigger terms first, since each one may contain smaller ones that don't need their own local
+        candidates.sort_by_key(|t| std::cmp::Reverse(t.ops()));
+        let mut hoisted = Vec::new();
+        for t in candidates {
+            let ty = t.ty();
+            *next += 1;
+            let var = JVar(*next, false);
+            let mut found = false;
+            let mut replace = VTerm(|x| {
+                if *x == t {
+                    *x = JTerm::Var(var, ty.clone());
+                    found = true;
+                }
+            });
+            match &mut block[i] {
+                JStmt::While(_, cond, body) => {
+                    cond.map(&mut replace);
+                    body.iter_mut().for_each(|s| s.map(&mut replace));
+                }
+                JStmt::RangeFor(_, _, _, _, end, step, _, body, _) => {
+                    end.map(&mut replace);
+                    step.map(&mut replace);
+                    body.iter_mut().for_each(|s| s.map(&mut replace));
+                }
+                _ => unreachable!(),
+            }
+            if found {
+                hoisted.push(JStmt::Let(raw, ty, var, Some(t)));
+            }
+        }
+
+        let n = hoisted.len();
+        block.splice(i..i, hoisted);
+        i += n + 1;
+    }
+}
+
+/// Everything a statement does that could change the value of a term
+#[derive(Default)]
+struct Clobbers {
+    vars: HashSet<JVar>,
+    /// Any array element being written could be aliased by any other array
+    elems: bool,
+    /// Any field being written could belong to any other object of the same class
+    fields: bool,
+    /// Calls could change anything
+    calls: bool,
+    /// Inline Java could even change locals
+    java: bool,
+}
+impl Clobbers {
+    fn clobbers(&self, t: &JTerm) -> bool {
+        if self.calls || self.elems {
+            return true;
+        }
+        let mut counter = UseCounter::default();
+        t.clone().map(&mut counter);
+        if counter.count.keys().any(|v| self.vars.contains(v)) {
+            return true;
+        }
+        // Member variables can be read as `this.x` but written as just `x`
+        let mut clobbered = false;
+        t.clone().map(&mut VTerm(|x| {
+            if let JTerm::Prop(_, p, _) = x {
+                clobbered |= self.fields || matches!(p, Prop::Var(v) if self.vars.contains(v));
+            }
+        }));
+        clobbered
+    }
+}
+impl Visitor for Clobbers {
+    fn visit_term(&mut self, t: &mut JTerm) {
+        match t {
+            JTerm::Call(_, _, _, _) | JTerm::ClassNew(_, _) | JTerm::Super(_, _) => {
+                self.calls = true
+            }
+            // Terms in the body use the lambda's arguments, so they can't be moved out of it
+            JTerm::InlineJava(_, _) | JTerm::Lambda(_, _, _, _) => {
+                self.calls = true;
+                self.java = true;
+            }
+            _ => (),
         }
     }
 
-    fn map(&mut self, f: &mut impl Visitor) {
-        match self {
-            JItem::Fn(x) => x.map(f),
-            JItem::Enum(_, _, _, methods) => methods.iter_mut().for_each(|x| x.map(f)),
-            JItem::Class(_, members, methods) => {
-                for (v, b) in members {
-                    for (_, _, x) in v {
-                        if let Some(x) = x {
-                            x.map(f);
-                        }
-                    }
-                    b.iter_mut().for_each(|x| x.map(f));
-                }
-                methods.iter_mut().for_each(|x| x.map(f));
+    fn visit_lval(&mut self, l: &mut JLVal) {
+        match l {
+            JLVal::Var(v) => {
+                self.vars.insert(*v);
             }
-            JItem::Let(v, b) => {
-                for (_, _, x) in v {
-                    if let Some(x) = x {
-                        x.map(f);
-                    }
-                }
-                b.iter_mut().for_each(|x| x.map(f));
+            JLVal::Idx(_, _) => self.elems = true,
+            JLVal::Prop(_, _) => self.fields = true,
+            _ => (),
+        }
+    }
+
+    fn visit_stmt(&mut self, s: &mut JStmt) {
+        match s {
+            JStmt::MultiCall(_, _, _, _) => self.calls = true,
+            JStmt::InlineJava(_) => {
+                self.calls = true;
+                self.java = true;
             }
+            _ => (),
         }
     }
 }
-impl<'a> Cxt<'a> {
-    fn replace(&mut self, v: JVar, x: &JTerm) {
-        for i in &mut self.items {
-            i.map(&mut VTerm(|t| match t {
-                JTerm::Var(y, _) if *y == v => {
-                    *t = x.clone();
-                }
-                _ => (),
-            }));
+
+/// Counts the occurrences of a term that are always evaluated, i.e. not on the right side of `&&` or `||`
+struct Occurrences<'a>(&'a JTerm, usize);
+impl Visitor for Occurrences<'_> {
+    fn visit_term(&mut self, t: &mut JTerm) {
+        if t == self.0 {
+            self.1 += 1;
+        }
+        if let JTerm::BinOp(BinOp::And | BinOp::Or, _, b) = t {
+            let mut inner = Occurrences(self.0, 0);
+            b.map(&mut inner);
+            self.1 -= inner.1;
         }
     }
+}
 
-    fn opt(&mut self) {
-        // Constant propagation
-        for item in &mut self.items {
-            for block in item.blocks() {
-                let mut env = Env::new(self.bindings, self.next);
-                for s in block {
-                    s.prop(&mut env);
+impl JStmt {
+    /// Like `map`, but only visits the terms this statement evaluates exactly once, not nested blocks or loop conditions
+    fn map_own(&mut self, f: &mut impl Visitor) {
+        match self {
+            JStmt::Let(_, _, _, Some(x)) => x.map(f),
+            JStmt::Set(l, _, x) => {
+                l.map(f);
+                x.map(f);
+            }
+            JStmt::Term(x)
+            | JStmt::If(x, _, _)
+            | JStmt::Switch(_, x, _, _)
+            | JStmt::ThrowNew(_, x)
+            | JStmt::Throw(x) => x.map(f),
+            JStmt::Ret(_, v) => v.iter_mut().for_each(|x| x.map(f)),
+            JStmt::MultiCall(x, _, v, _) => {
+                if let Some(x) = x {
+                    x.map(f);
                 }
-                self.next = env.next;
+                v.iter_mut().for_each(|x| x.map(f));
             }
+            JStmt::Let(_, _, _, None)
+            | JStmt::While(_, _, _)
+            | JStmt::DoWhile(_, _, _)
+            | JStmt::RangeFor(_, _, _, _, _, _, _, _, _)
+            | JStmt::Try(_, _)
+            | JStmt::Continue(_)
+            | JStmt::Break(_)
+            | JStmt::InlineJava(_)
+            | JStmt::Src(_, _)
+            | JStmt::Multi(_) => (),
         }
+    }
 
-        // Remove unused variables
-        let mut counter = UseCounter::default();
-        for i in &mut self.items {
-            i.map(&mut counter);
+    /// Whether everything this statement does happens after its own terms are evaluated,
+    /// e.g. a call with simple arguments or an assignment
+    fn effects_last(&self) -> bool {
+        fn no_calls<'a>(v: impl IntoIterator<Item = &'a JTerm>) -> bool {
+            let mut clobbers = Clobbers::default();
+            for x in v {
+                x.clone().map(&mut clobbers);
+            }
+            !clobbers.calls
         }
-        for i in &mut self.items {
-            i.map(&mut VStmt(|t| match t {
-                JStmt::Let(_, _, v, Some(x)) => {
-                    if !v.1 && counter.count.get(v).map_or(true, |x| *x == 0) {
-                        let mut effects = SideEffects(false);
-                        x.map(&mut effects);
-                        if !effects.0 {
-                            *t = JStmt::Multi(Vec::new());
-                        }
-                    }
-                }
-                JStmt::Let(_, _, v, None) => {
-                    if !v.1 && counter.count.get(v).map_or(true, |x| *x == 0) {
-                        *t = JStmt::Multi(Vec::new());
-                    }
-                }
-                JStmt::Set(l, _, x) => {
-                    if let Some(v) = l.root_var() {
-                        if !v.1
-                            && counter.count.get(&v).map_or(true, |x| *x == 0)
-                            && counter.defined.contains(&v)
-                        {
-                            let mut effects = SideEffects(false);
-                            x.map(&mut effects);
-                            if !effects.0 {
-                                *t = JStmt::Multi(Vec::new());
-                            }
-                        }
-                    }
-                }
-                _ => (),
-            }));
+        fn outer_call(x: &JTerm) -> bool {
+            match x {
+                JTerm::Call(o, _, a, _) => no_calls(o.iter().map(|x| &**x).chain(a)),
+                x => no_calls([x]),
+            }
+        }
+        match self {
+            JStmt::Let(_, _, _, x) => x.iter().all(outer_call),
+            JStmt::Set(l, _, x) => {
+                let mut l = l.clone();
+                let mut clobbers = Clobbers::default();
+                l.map(&mut clobbers);
+                !clobbers.calls && outer_call(x)
+            }
+            JStmt::Term(x) => outer_call(x),
+            JStmt::MultiCall(o, _, a, _) => no_calls(o.iter().map(|x| &**x).chain(a)),
+            _ => false,
         }
     }
 }
+
 impl JLVal {
     fn root_var(&self) -> Option<JVar> {
         match self {
@@ -2745,7 +7480,7 @@ impl Visitor for Replacer {
                     *v = *v2;
                 }
             }
-            JStmt::RangeFor(_, _, v, _, _, _, _) => {
+            JStmt::RangeFor(_, _, v, _, _, _, _, _, _) => {
                 if let Some(v2) = self.0.get(v) {
                     *v = *v2;
                 }
@@ -2766,6 +7501,22 @@ impl Visitor for Replacer {
 // This can't actually use the Visitor infrastructure, because it depends on control flow
 
 impl JTerm {
+    /// Whether this term always has the same value while `block` runs, so it can be evaluated again instead of stored.
+    /// Only literals and variables count, since anything else would take more bytecode to evaluate each time.
+    fn invariant(&self, block: &[JStmt]) -> bool {
+        match self {
+            JTerm::Lit(_) => true,
+            JTerm::Var(v, _) => {
+                let mut unchanged = Unchanged(*v, true);
+                for s in block {
+                    s.clone().map(&mut unchanged);
+                }
+                unchanged.1
+            }
+            _ => false,
+        }
+    }
+
     fn ops(&self) -> usize {
         match self {
             JTerm::Var(_, _) => 1,
@@ -2777,12 +7528,15 @@ impl JTerm {
             JTerm::Array(_, _) => 100,
             JTerm::ArrayNew(_, _) => 100,
             JTerm::ClassNew(_, _) => 100,
+            JTerm::Super(_, _) => 100,
             JTerm::Index(a, i, _) => a.ops() + i.ops() + 1,
             JTerm::SIndex(_, _) => 1,
-            JTerm::Not(a) => a.ops() + 1,
+            JTerm::Not(a) | JTerm::BitNot(a) | JTerm::Neg(a) => a.ops() + 1,
+            JTerm::Ternary(c, a, b) => c.ops() + a.ops().max(b.ops()) + 1,
+            JTerm::Cast(a, _) | JTerm::InstanceOf(a, _) => a.ops() + 1,
             JTerm::Null(_) => 1,
             JTerm::This(_) => 1,
-            JTerm::InlineJava(_, _) => 100,
+            JTerm::InlineJava(_, _) | JTerm::Lambda(_, _, _, _) => 100,
         }
     }
 
@@ -2800,7 +7554,13 @@ impl JTerm {
             JTerm::ArrayNew(l, _) => l.start_valid(env),
             JTerm::Index(a, i, _) => a.start_valid(env) && i.start_valid(env),
             JTerm::SIndex(v, i) => v.iter().all(|x| x.start_valid(env)) && i.start_valid(env),
-            JTerm::Not(a) => a.start_valid(env),
+            JTerm::Not(a) | JTerm::BitNot(a) | JTerm::Neg(a) | JTerm::Cast(a, _) => {
+                a.start_valid(env)
+            }
+            JTerm::InstanceOf(a, _) => a.start_valid(env),
+            JTerm::Ternary(c, a, b) => {
+                c.start_valid(env) && a.start_valid(env) && b.start_valid(env)
+            }
             JTerm::Null(_) => true,
             _ => false,
         }
@@ -2817,7 +7577,9 @@ impl JTerm {
             JTerm::ArrayNew(l, _) => l.is_valid(env),
             JTerm::Index(a, i, _) => a.is_valid(env) && i.is_valid(env),
             JTerm::SIndex(v, i) => v.iter().all(|x| x.is_valid(env)) && i.is_valid(env),
-            JTerm::Not(a) => a.is_valid(env),
+            JTerm::Not(a) | JTerm::BitNot(a) | JTerm::Neg(a) | JTerm::Cast(a, _) => a.is_valid(env),
+            JTerm::InstanceOf(a, _) => a.is_valid(env),
+            JTerm::Ternary(c, a, b) => c.is_valid(env) && a.is_valid(env) && b.is_valid(env),
             JTerm::Null(_) => true,
             _ => false,
         }
@@ -2828,6 +7590,8 @@ enum CVal {
     Null(JTy),
     Int(i32),
     Long(i64),
+    Float(f32),
+    Double(f64),
     Bool(bool),
     String(RawSym),
     Array {
@@ -2840,24 +7604,39 @@ enum CVal {
     Term(JTerm),
 }
 impl CVal {
+    /// Rust's `as` between numeric types has the same semantics as Java's casts
+    fn cast(self, t: &JTy) -> Option<CVal> {
+        use CVal::*;
+        let (i, l, f, d) = match self {
+            Int(x) => (x, x as i64, x as f32, x as f64),
+            Long(x) => (x as i32, x, x as f32, x as f64),
+            Float(x) => (x as i32, x as i64, x, x as f64),
+            Double(x) => (x as i32, x as i64, x as f32, x),
+            Term(JTerm::Lit(JLit::Char(x))) => (x as i32, x as i64, x as f32, x as f64),
+            _ => return None,
+        };
+        Some(match t {
+            JTy::Char => Term(JTerm::Lit(JLit::Char(i as u16))),
+            JTy::I32 => Int(i),
+            JTy::I64 => Long(l),
+            JTy::F32 => Float(f),
+            JTy::F64 => Double(d),
+            _ => return None,
+        })
+    }
+
     fn to_term(&self, env: &Env) -> Option<JTerm> {
         match self {
             CVal::Null(ty) => Some(JTerm::Null(ty.clone())),
             CVal::Int(b) => Some(JTerm::Lit(JLit::Int(*b))),
             CVal::Long(b) => Some(JTerm::Lit(JLit::Long(*b))),
+            CVal::Float(b) => Some(JTerm::Lit(JLit::Float(*b))),
+            CVal::Double(b) => Some(JTerm::Lit(JLit::Double(*b))),
             CVal::Bool(b) => Some(JTerm::Lit(JLit::Bool(*b))),
             CVal::Variant(class, r) => Some(JTerm::Variant(*class, *r)),
             CVal::String(r) => Some(JTerm::Lit(JLit::Str(*r))),
-            CVal::Array { idxs, end: _, len } => {
-                let mut v = Vec::new();
-                let mut ty = None;
-                for i in 0..len.clone()? {
-                    let t = idxs.get(&i)?.to_term(env)?;
-                    ty = Some(t.ty());
-                    v.push(t);
-                }
-                Some(JTerm::Array(v, ty?))
-            }
+            // A new array literal would be a different object, so mutating it wouldn't affect the original
+            CVal::Array { .. } => None,
             CVal::Class(_) => None,
             CVal::Term(t) => {
                 if t.is_valid(env) {
@@ -2874,19 +7653,12 @@ impl CVal {
             CVal::Null(ty) => Some(JTerm::Null(ty.clone())),
             CVal::Int(b) => Some(JTerm::Lit(JLit::Int(*b))),
             CVal::Long(b) => Some(JTerm::Lit(JLit::Long(*b))),
+            CVal::Float(b) => Some(JTerm::Lit(JLit::Float(*b))),
+            CVal::Double(b) => Some(JTerm::Lit(JLit::Double(*b))),
             CVal::Bool(b) => Some(JTerm::Lit(JLit::Bool(*b))),
             CVal::Variant(class, r) => Some(JTerm::Variant(*class, *r)),
             CVal::String(r) => Some(JTerm::Lit(JLit::Str(*r))),
-            CVal::Array { idxs, end: _, len } => {
-                let mut v = Vec::new();
-                let mut ty = None;
-                for i in 0..len.clone()? {
-                    let t = idxs.get(&i)?.to_term_partial()?;
-                    ty = Some(t.ty());
-                    v.push(t);
-                }
-                Some(JTerm::Array(v, ty?))
-            }
+            CVal::Array { .. } => None,
             CVal::Class(_) => None,
             CVal::Term(t) => Some(t.clone()),
         }
@@ -3167,7 +7939,7 @@ impl JStmt {
                     match op {
                         Some(op) => {
                             if let Some(y) = l.get(env) {
-                                if let Some(x) = op.prop(x, y.clone()) {
+                                if let Some(x) = op.prop(y.clone(), x) {
                                     l.set(env, Some(x));
                                 } else {
                                     l.set(env, None);
@@ -3187,7 +7959,15 @@ impl JStmt {
             }
             JStmt::If(cond, a, b) => {
                 let cond = cond.prop(env);
-                if let Some(CVal::Bool(cond)) = cond {
+                // Java doesn't allow any statements after an unconditional `throw`, so those keep the `if`
+                let throws = |v: &[JStmt]| {
+                    v.iter()
+                        .any(|x| matches!(x, JStmt::ThrowNew(_, _) | JStmt::Throw(_)))
+                };
+                if let Some(cond) = cond.and_then(|x| match x {
+                    CVal::Bool(c) if !throws(if c { a } else { b }) => Some(c),
+                    _ => None,
+                }) {
                     let tmp = if cond { a } else { b };
                     let mut block = Vec::new();
                     std::mem::swap(tmp, &mut block);
@@ -3210,10 +7990,15 @@ impl JStmt {
             }
             JStmt::Switch(_, x, cases, other) => {
                 let x = x.prop(env);
-                if let Some(CVal::Variant(_, r)) = x {
+                let case = match x {
+                    Some(CVal::Variant(_, r)) => Some(JCase::Variant(r)),
+                    Some(CVal::Int(i)) => Some(JCase::Int(i)),
+                    _ => None,
+                };
+                if let Some(case) = case {
                     let tmp = cases
                         .iter_mut()
-                        .find(|(c, _)| *c == r)
+                        .find(|(c, _)| *c == case)
                         .map(|(_, b)| b)
                         .unwrap_or(other);
                     let mut block = Vec::new();
@@ -3238,26 +8023,82 @@ impl JStmt {
                 }
             }
             JStmt::While(_, cond, block) => {
+                // A loop that never runs is dead code, and javac rejects `while (false)` anyway
+                if let Some(CVal::Bool(false)) = cond.clone().prop(&mut env.clone()) {
+                    *self = JStmt::Multi(Vec::new());
+                    return;
+                }
                 let mut counter = UseCounter::default();
                 for s in block.iter_mut() {
                     s.map(&mut counter);
                 }
+                for i in &counter.mutated {
+                    env.env.remove(i);
+                    env.not_modified.remove(i);
+                }
+                cond.prop(env);
+                for i in block {
+                    i.prop(env);
+                }
+                // The loop might not run at all, or run more than once, so the values from the body don't last past it
                 for i in counter.mutated {
                     env.env.remove(&i);
                     env.not_modified.remove(&i);
                 }
-                cond.prop(env);
+            }
+            JStmt::DoWhile(_, block, cond) => {
+                let mut counter = UseCounter::default();
+                for s in block.iter_mut() {
+                    s.map(&mut counter);
+                }
+                for i in &counter.mutated {
+                    env.env.remove(i);
+                    env.not_modified.remove(i);
+                }
                 for i in block {
                     i.prop(env);
                 }
+                cond.prop(env);
+                // The body might run more than once, so the values from it don't last past the loop
+                for i in counter.mutated {
+                    env.env.remove(&i);
+                    env.not_modified.remove(&i);
+                }
+            }
+            JStmt::Try(body, handlers) => {
+                let mut counter = UseCounter::default();
+                for s in body.iter_mut() {
+                    s.map(&mut counter);
+                }
+                // A handler can start after any statement in the body, so it can't rely on anything the body sets
+                let mut before = env.clone();
+                for i in &counter.mutated {
+                    before.env.remove(i);
+                    before.not_modified.remove(i);
+                }
+                before.clobber_globals();
+                for i in body {
+                    i.prop(env);
+                }
+                for (_, _, var, block) in handlers {
+                    let mut env2 = before.clone();
+                    env2.locals.insert(*var);
+                    for i in block {
+                        i.prop(&mut env2);
+                    }
+                    env.union(&env2);
+                }
             }
-            JStmt::RangeFor(_, raw, v, a, b, block, unroll) => {
+            JStmt::RangeFor(_, raw, v, a, b, step, op, block, unroll) => {
                 let a = a.prop(env);
                 let b = b.prop(env);
+                let step = step.prop(env);
                 env.locals.insert(*v);
                 if *unroll {
-                    match (a, b) {
-                        (Some(CVal::Int(a)), Some(CVal::Int(b))) => {
+                    match (a, b, step) {
+                        (Some(CVal::Int(a)), Some(CVal::Int(b)), Some(CVal::Int(step)))
+                            if step != 0 =>
+                        {
                             let mut counter = UseCounter::default();
                             for s in block.iter_mut() {
                                 s.map(&mut counter);
@@ -3265,7 +8106,14 @@ impl JStmt {
 
                             let mut stmts = Vec::new();
                             stmts.push(JStmt::Let(*raw, JTy::I32, *v, None));
-                            for i in a..b {
+                            let mut i = a;
+                            while match op {
+                                BinOp::Lt => i < b,
+                                BinOp::Leq => i <= b,
+                                BinOp::Gt => i > b,
+                                BinOp::Geq => i >= b,
+                                _ => unreachable!(),
+                            } {
                                 stmts.push(JStmt::Set(
                                     JLVal::Var(*v),
                                     None,
@@ -3283,6 +8131,11 @@ impl JStmt {
                                     s.map(&mut replacer);
                                     stmts.push(s);
                                 }
+                                // Stop before the loop variable overflows
+                                i = match i.checked_add(step) {
+                                    Some(i) => i,
+                                    None => break,
+                                };
                             }
                             *self = JStmt::Multi(stmts);
                             return self.prop(env);
@@ -3295,13 +8148,17 @@ impl JStmt {
                 for s in block.iter_mut() {
                     s.map(&mut counter);
                 }
-                for i in counter.mutated {
-                    env.env.remove(&i);
-                    env.not_modified.remove(&i);
+                for i in &counter.mutated {
+                    env.env.remove(i);
+                    env.not_modified.remove(i);
                 }
                 for i in block {
                     i.prop(env);
                 }
+                for i in counter.mutated {
+                    env.env.remove(&i);
+                    env.not_modified.remove(&i);
+                }
             }
             JStmt::Continue(_) => (),
             JStmt::Break(_) => (),
@@ -3321,6 +8178,10 @@ impl JStmt {
                 env.clobber_globals();
             }
             JStmt::InlineJava(_) => env.clobber_public(),
+            JStmt::ThrowNew(_, x) | JStmt::Throw(x) => {
+                x.prop(env);
+            }
+            JStmt::Src(_, _) => (),
             JStmt::Multi(v) => {
                 for i in v {
                     i.prop(env);
@@ -3329,16 +8190,43 @@ impl JStmt {
         }
     }
 }
+impl JLit {
+    /// Computes `self op b` if both are numbers or booleans, the same way Java would
+    fn fold(self, op: BinOp, b: JLit) -> Option<JLit> {
+        let cval = |l| match l {
+            JLit::Int(i) => Some(CVal::Int(i)),
+            JLit::Long(i) => Some(CVal::Long(i)),
+            JLit::Float(f) => Some(CVal::Float(f)),
+            JLit::Double(d) => Some(CVal::Double(d)),
+            JLit::Bool(b) => Some(CVal::Bool(b)),
+            // Chars are promoted to ints like in Java, but arithmetic on bytes and shorts needs a cast back, and strings aren't folded
+            JLit::Char(c) => Some(CVal::Int(c as i32)),
+            JLit::Byte(_) | JLit::Short(_) | JLit::Str(_) => None,
+        };
+        Some(match op.prop(cval(self)?, cval(b)?)? {
+            CVal::Int(i) => JLit::Int(i),
+            CVal::Long(i) => JLit::Long(i),
+            CVal::Float(f) => JLit::Float(f),
+            CVal::Double(d) => JLit::Double(d),
+            CVal::Bool(b) => JLit::Bool(b),
+            _ => return None,
+        })
+    }
+}
 impl BinOp {
     fn prop(self, a: CVal, b: CVal) -> Option<CVal> {
         use CVal::*;
         match (a, b) {
+            // Java arithmetic wraps on overflow
             (Int(a), Int(b)) => Some(match self {
-                BinOp::Add => Int(a + b),
-                BinOp::Sub => Int(a - b),
-                BinOp::Mul => Int(a * b),
-                BinOp::Div => Int(a / b),
-                BinOp::Mod => Int(a % b),
+                BinOp::Add => Int(a.wrapping_add(b)),
+                BinOp::Sub => Int(a.wrapping_sub(b)),
+                BinOp::Mul => Int(a.wrapping_mul(b)),
+                // Leave division by zero to throw at runtime
+                BinOp::Div | BinOp::Mod if b == 0 => return None,
+                // Java's `/` and `%` truncate toward zero like Rust's, and MIN / -1 wraps
+                BinOp::Div => Int(a.wrapping_div(b)),
+                BinOp::Mod => Int(a.wrapping_rem(b)),
                 BinOp::Gt => Bool(a > b),
                 BinOp::Lt => Bool(a < b),
                 BinOp::Eq => Bool(a == b),
@@ -3348,16 +8236,19 @@ impl BinOp {
                 BinOp::BitAnd => Int(a & b),
                 BinOp::BitOr => Int(a | b),
                 BinOp::BitXor => Int(a ^ b),
-                BinOp::BitShr => Int(a >> b),
-                BinOp::BitShl => Int(a << b),
+                // Java masks the shift amount to the bit width, which is what `wrapping_sh*` does
+                BinOp::BitShr => Int(a.wrapping_shr(b as u32)),
+                BinOp::BitShl => Int(a.wrapping_shl(b as u32)),
+                BinOp::BitUshr => Int((a as u32).wrapping_shr(b as u32) as _),
                 BinOp::And | BinOp::Or => unreachable!(),
             }),
             (Long(a), Long(b)) => Some(match self {
-                BinOp::Add => Long(a + b),
-                BinOp::Sub => Long(a - b),
-                BinOp::Mul => Long(a * b),
-                BinOp::Div => Long(a / b),
-                BinOp::Mod => Long(a % b),
+                BinOp::Add => Long(a.wrapping_add(b)),
+                BinOp::Sub => Long(a.wrapping_sub(b)),
+                BinOp::Mul => Long(a.wrapping_mul(b)),
+                BinOp::Div | BinOp::Mod if b == 0 => return None,
+                BinOp::Div => Long(a.wrapping_div(b)),
+                BinOp::Mod => Long(a.wrapping_rem(b)),
                 BinOp::Gt => Bool(a > b),
                 BinOp::Lt => Bool(a < b),
                 BinOp::Eq => Bool(a == b),
@@ -3367,10 +8258,46 @@ impl BinOp {
                 BinOp::BitAnd => Long(a & b),
                 BinOp::BitOr => Long(a | b),
                 BinOp::BitXor => Long(a ^ b),
-                BinOp::BitShr => Long(a >> b),
-                BinOp::BitShl => Long(a << b),
+                BinOp::BitShr => Long(a.wrapping_shr(b as u32)),
+                BinOp::BitShl => Long(a.wrapping_shl(b as u32)),
+                BinOp::BitUshr => Long((a as u64).wrapping_shr(b as u32) as _),
                 BinOp::And | BinOp::Or => unreachable!(),
             }),
+            (Float(a), Float(b)) => Some(match self {
+                BinOp::Add => Float(a + b),
+                BinOp::Sub => Float(a - b),
+                BinOp::Mul => Float(a * b),
+                BinOp::Div => Float(a / b),
+                BinOp::Mod => Float(a % b),
+                BinOp::Gt => Bool(a > b),
+                BinOp::Lt => Bool(a < b),
+                BinOp::Eq => Bool(a == b),
+                BinOp::Neq => Bool(a != b),
+                BinOp::Geq => Bool(a >= b),
+                BinOp::Leq => Bool(a <= b),
+                _ => return None,
+            }),
+            (Long(a), Int(b)) if self.is_shift() => Some(match self {
+                BinOp::BitShr => Long(a.wrapping_shr(b as u32)),
+                BinOp::BitShl => Long(a.wrapping_shl(b as u32)),
+                BinOp::BitUshr => Long((a as u64).wrapping_shr(b as u32) as i64),
+                _ => unreachable!(),
+            }),
+            (Int(a), Long(b)) if self.is_shift() => self.prop(Int(a), Int(b as i32)),
+            (Double(a), Double(b)) => Some(match self {
+                BinOp::Add => Double(a + b),
+                BinOp::Sub => Double(a - b),
+                BinOp::Mul => Double(a * b),
+                BinOp::Div => Double(a / b),
+                BinOp::Mod => Double(a % b),
+                BinOp::Gt => Bool(a > b),
+                BinOp::Lt => Bool(a < b),
+                BinOp::Eq => Bool(a == b),
+                BinOp::Neq => Bool(a != b),
+                BinOp::Geq => Bool(a >= b),
+                BinOp::Leq => Bool(a <= b),
+                _ => return None,
+            }),
             (Bool(a), Bool(b)) => Some(Bool(match self {
                 BinOp::Eq => a == b,
                 BinOp::Neq => a != b,
@@ -3387,19 +8314,48 @@ impl BinOp {
                 BinOp::Neq => a != b,
                 _ => return None,
             })),
+            (Variant(_, a), Variant(_, b)) => Some(Bool(match self {
+                BinOp::Eq => a == b,
+                BinOp::Neq => a != b,
+                _ => return None,
+            })),
             (a, b) => {
                 let a = a.to_term_partial()?;
                 let b = b.to_term_partial()?;
-                Some(CVal::Term(JTerm::BinOp(self, Box::new(a), Box::new(b))))
+                let x = JTerm::BinOp(self, Box::new(a), Box::new(b));
+                Some(CVal::Term(match x.ty() {
+                    t @ (JTy::I8 | JTy::I16) if self.ty() == BinOpType::Arith => {
+                        JTerm::Cast(Box::new(x), t)
+                    }
+                    _ => x,
+                }))
             }
             // _ => None,
         }
     }
 }
 impl JTerm {
+    /// Like `prop()`, but for an array which is only being indexed or having its length read.
+    /// Anywhere else, like a call argument or another variable, the array could be aliased and mutated,
+    /// so `prop()` forgets its contents.
+    fn prop_array(&mut self, env: &mut Env) -> Option<CVal> {
+        match self {
+            JTerm::Var(v, _) => env.var(*v),
+            JTerm::Prop(x, p, _) => match x.prop_array(env) {
+                Some(CVal::Class(m)) => m.get(p).cloned(),
+                _ => None,
+            },
+            _ => self.prop(env),
+        }
+    }
+
     fn prop(&mut self, env: &mut Env) -> Option<CVal> {
         let r = match self {
             JTerm::Var(v, _) => match env.var(*v) {
+                Some(CVal::Array { .. }) => {
+                    env.env.remove(v);
+                    None
+                }
                 Some(val) => {
                     if let Some(t) = val.to_term(env) {
                         *self = t;
@@ -3409,8 +8365,12 @@ impl JTerm {
                 None => None,
             },
             JTerm::Lit(l) => Some(match l {
+                // Bytes, shorts and chars can still be propagated, but arithmetic on them isn't folded
+                JLit::Byte(_) | JLit::Short(_) | JLit::Char(_) => CVal::Term(JTerm::Lit(*l)),
                 JLit::Int(i) => CVal::Int(*i),
                 JLit::Long(i) => CVal::Long(*i),
+                JLit::Float(f) => CVal::Float(*f),
+                JLit::Double(d) => CVal::Double(*d),
                 JLit::Str(s) => CVal::String(*s),
                 JLit::Bool(b) => CVal::Bool(*b),
             }),
@@ -3424,8 +8384,14 @@ impl JTerm {
                 // TODO check for side effects
                 None
             }
-            JTerm::Prop(x, p, _) => match x.prop(env) {
-                Some(CVal::Class(m)) => m.get(p).cloned(),
+            JTerm::Prop(x, p, _) => match x.prop_array(env) {
+                Some(CVal::Class(m)) => match m.get(p) {
+                    Some(CVal::Array { .. }) => {
+                        env.clobber_specific_members(p, None);
+                        None
+                    }
+                    v => v.cloned(),
+                },
                 Some(CVal::Array { len, .. }) => {
                     assert_eq!(*p, Prop::Raw(env.bindings.get_raw("length").unwrap()));
                     len.map(|i| CVal::Int(i as i32))
@@ -3477,8 +8443,14 @@ impl JTerm {
                 }
                 Some(CVal::Class(HashMap::new()))
             }
+            JTerm::Super(args, _) => {
+                for i in args {
+                    i.prop(env);
+                }
+                None
+            }
             JTerm::Index(arr, idx, _) => {
-                let arr = arr.prop(env);
+                let arr = arr.prop_array(env);
                 let idx = idx.prop(env)?;
                 match arr? {
                     CVal::Array { idxs, .. } => match idx {
@@ -3504,6 +8476,49 @@ impl JTerm {
                 CVal::Bool(b) => Some(CVal::Bool(!b)),
                 _ => None,
             },
+            JTerm::BitNot(x) => match x.prop(env)? {
+                CVal::Int(i) => Some(CVal::Int(!i)),
+                CVal::Long(i) => Some(CVal::Long(!i)),
+                _ => None,
+            },
+            JTerm::Ternary(c, a, b) => {
+                let c = c.prop(env);
+                let a2 = a.prop(env);
+                let b2 = b.prop(env);
+                match c {
+                    Some(CVal::Bool(true)) => {
+                        *self = (**a).clone();
+                        a2
+                    }
+                    Some(CVal::Bool(false)) => {
+                        *self = (**b).clone();
+                        b2
+                    }
+                    _ => None,
+                }
+            }
+            JTerm::Neg(x) => match x.prop(env)? {
+                CVal::Int(i) => Some(CVal::Int(i.wrapping_neg())),
+                CVal::Long(i) => Some(CVal::Long(i.wrapping_neg())),
+                CVal::Float(f) => Some(CVal::Float(-f)),
+                CVal::Double(f) => Some(CVal::Double(-f)),
+                _ => None,
+            },
+            JTerm::Cast(x, t) => {
+                let val = x.prop(env);
+                // Propagating into the operands can narrow the result already
+                if matches!(&**x, JTerm::Cast(_, t2) if t2 == t) {
+                    let x = (**x).clone();
+                    *self = x;
+                    None
+                } else {
+                    val.and_then(|val| val.cast(t))
+                }
+            }
+            JTerm::InstanceOf(x, _) => {
+                x.prop(env);
+                None
+            }
             JTerm::Null(ty) => Some(CVal::Null(ty.clone())),
             // TODO `this` in constprop for members and stuff
             JTerm::This(_) => None,
@@ -3512,6 +8527,22 @@ impl JTerm {
                 env.clobber_public();
                 None
             }
+            JTerm::Lambda(_, body, x, _) => {
+                // The body runs whenever the lambda is called, so it starts out not knowing anything
+                let mut env2 = Env::new(env.bindings, env.next);
+                for s in body.iter_mut() {
+                    s.prop(&mut env2);
+                }
+                if let Some(x) = x {
+                    x.prop(&mut env2);
+                }
+                env.next = env2.next;
+                remove_dead(body);
+                if body.iter().any(JStmt::terminates) {
+                    *x = None;
+                }
+                None
+            }
         };
         match r {
             Some(x) => Some(x),
@@ -79,6 +79,12 @@ pub struct Bindings {
     string_pool: Vec<String>,
     nums: HashMap<RawSym, u32>,
     pubs: HashSet<Sym>,
+    volatiles: HashSet<Sym>,
+    finals: HashSet<Sym>,
+    type_params: HashMap<TypeId, Vec<RawSym>>,
+    static_methods: HashMap<FnId, TypeId>,
+    defaults: HashMap<FnId, usize>,
+    varargs: HashMap<FnId, bool>,
     types: Vec<RawPath>,
     fns: Vec<RawPath>,
     syms: Vec<RawPath>,
@@ -146,6 +152,64 @@ impl Bindings {
         self.pubs.contains(&s)
     }
 
+    /// Marks a field as `volatile` in the generated Java
+    pub fn set_volatile(&mut self, s: Sym) {
+        self.volatiles.insert(s);
+    }
+
+    pub fn volatile(&self, s: Sym) -> bool {
+        self.volatiles.contains(&s)
+    }
+
+    /// Marks a variable as `final`, so it can't be reassigned and is declared `final` in the generated Java
+    pub fn set_final(&mut self, s: Sym) {
+        self.finals.insert(s);
+    }
+
+    pub fn final_(&self, s: Sym) -> bool {
+        self.finals.contains(&s)
+    }
+
+    /// Marks a method of the class `t` as static, so it's called as `Class::method()` instead of on an object
+    pub fn set_static(&mut self, f: FnId, t: TypeId) {
+        self.static_methods.insert(f, t);
+    }
+
+    /// The class a static method belongs to, or `None` if it isn't a static method
+    pub fn static_class(&self, f: FnId) -> Option<TypeId> {
+        self.static_methods.get(&f).copied()
+    }
+
+    /// Records that the last `n` parameters of `f` have default values, so calls can leave them out
+    pub fn set_defaults(&mut self, f: FnId, n: usize) {
+        self.defaults.insert(f, n);
+    }
+
+    /// How many of the last parameters of `f` have default values
+    pub fn defaults(&self, f: FnId) -> usize {
+        self.defaults.get(&f).copied().unwrap_or(0)
+    }
+
+    /// Records that the last parameter of `f` takes any number of arguments.
+    /// `spread` means they're passed to Java one by one instead of as an array, for extern functions.
+    pub fn set_varargs(&mut self, f: FnId, spread: bool) {
+        self.varargs.insert(f, spread);
+    }
+
+    /// `Some(spread)` if `f` has varargs, see `set_varargs()`
+    pub fn varargs(&self, f: FnId) -> Option<bool> {
+        self.varargs.get(&f).copied()
+    }
+
+    pub fn set_type_params(&mut self, t: TypeId, params: Vec<RawSym>) {
+        self.type_params.insert(t, params);
+    }
+
+    /// The type parameters of a generic class, or nothing if it isn't generic
+    pub fn type_params(&self, t: TypeId) -> &[RawSym] {
+        self.type_params.get(&t).map_or(&[], |x| x)
+    }
+
     /// Create a new symbol. It's guaranteed to be unique to all other symbols created with create()
     pub fn create(&mut self, raw: RawPath, public: bool) -> Sym {
         self.syms.push(raw);
@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use crate::term::*;
 
@@ -44,6 +44,7 @@ pub fn declare_mod_p2(
     extra_items: Vec<Item>,
     bindings: &mut Bindings,
     file_id: FileId,
+    separator: &str,
 ) -> Result<(ModType, Vec<Item>), Error> {
     let mut cxt = Cxt::from_type(
         t,
@@ -52,6 +53,7 @@ pub fn declare_mod_p2(
         bindings,
         file_id,
     );
+    cxt.separator = separator.to_string();
 
     // Declare
     for i in m {
@@ -176,6 +178,7 @@ pub fn elab_mod(
     extra_items: Vec<Item>,
     bindings: &mut Bindings,
     file_id: FileId,
+    boxed_arrays: bool,
 ) -> Result<Vec<Item>, Error> {
     let mut cxt = Cxt::from_type(
         t,
@@ -184,6 +187,7 @@ pub fn elab_mod(
         bindings,
         file_id,
     );
+    cxt.boxed_arrays = boxed_arrays;
     let mut v = Vec::new();
 
     // Define
@@ -259,6 +263,18 @@ struct Cxt<'b> {
     bindings: &'b mut Bindings,
     mods: HashMap<RawSym, ModType>,
     extra_items: Vec<Item>,
+    /// Dynamic array parameters of the current function, which the caller won't see resized
+    array_params: Vec<Sym>,
+    /// Whether dynamic arrays are passed as objects, so the caller does see them resized
+    boxed_arrays: bool,
+    /// What the backend puts between the parts of generated names, like `_enum$A$0`
+    separator: String,
+    /// The type parameters of the current function, if it's generic
+    type_params: Vec<RawSym>,
+    /// Where the current function's locals start in `vars`
+    fn_vars: usize,
+    /// Locals from outside the lambda(s) we're in, which are captured by value and can't be assigned to
+    captured: Vec<Sym>,
     file_id: FileId,
 }
 impl<'b> Cxt<'b> {
@@ -273,6 +289,12 @@ impl<'b> Cxt<'b> {
             bindings,
             mods: HashMap::new(),
             extra_items: Vec::new(),
+            array_params: Vec::new(),
+            boxed_arrays: false,
+            separator: "$".into(),
+            type_params: Vec::new(),
+            fn_vars: usize::MAX,
+            captured: Vec::new(),
             file_id,
         }
     }
@@ -299,6 +321,12 @@ impl<'b> Cxt<'b> {
             bindings,
             mods,
             extra_items,
+            array_params: Vec::new(),
+            boxed_arrays: false,
+            separator: "$".into(),
+            type_params: Vec::new(),
+            fn_vars: usize::MAX,
+            captured: Vec::new(),
             file_id,
         }
     }
@@ -396,6 +424,22 @@ impl<'b> Cxt<'b> {
             .unwrap()
     }
 
+    /// Whether `path` refers to the built-in item `name`, like `Option` or `Some`.
+    /// These are only checked after user-defined items, so they can be shadowed.
+    fn is_prelude(&self, path: &RawPath, name: &str) -> bool {
+        path.len() == 1 && self.bindings.resolve_raw(*path.stem()) == name
+    }
+
+    /// Whether assigning to `l` would reassign a `final` variable.
+    /// Static arrays become separate variables, so setting an element of one counts.
+    fn assigns_final(&self, l: &LValue) -> bool {
+        match l {
+            LValue::Var(s) => self.bindings.final_(*s),
+            LValue::Idx(l, _, true) => self.assigns_final(l),
+            LValue::Idx(_, _, false) | LValue::Member(_, _) => false,
+        }
+    }
+
     /// Start a new scope
     fn push(&mut self, rty: Option<Type>) {
         self.vars.push();
@@ -439,21 +483,92 @@ impl<'b> Cxt<'b> {
     }
 }
 
+/// What each type parameter of a generic class or function is instantiated with
+type TypeArgs = Vec<(RawSym, Type)>;
+
 enum TypeError {
     NotFound(RawPath),
     /// Unify(span, found, expected)
     Unify(Span, Type, Type),
     WrongArity(Span, usize, usize),
+    /// WrongTypeArgs(span, found, expected)
+    WrongTypeArgs(Span, usize, usize),
     NoMethods(Span, Type),
     NoVariants(Span, Type),
     MissingPattern(Span, Vec<RawSym>),
+    /// A pattern that can't match the scrutinee, like an integer literal in a match on an enum
+    /// WrongPattern(pattern span, scrutinee type)
+    WrongPattern(Span, Type),
+    /// A match on an integer without an `else` branch
+    MissingDefault(Span),
     Duplicate(Span, RawSym),
+    /// DuplicateVariant(variant span, variant)
+    DuplicateVariant(Span, RawSym),
+    /// EnumFieldClash(variant span, generated field name)
+    EnumFieldClash(Span, String),
     NotArray(Span, Type),
     NoMembers(Span, Type),
     TupleOutOfBounds(Span, Type, usize),
     NotLValue(Span),
+    /// A method that changes the length of an array or reallocates it, called on something that can't be assigned to
+    /// MethodNotLValue(span, method)
+    MethodNotLValue(Span, RawSym),
     TypeNeeded(Span),
     SelfOutsideClass(Span),
+    NotTryable(Span, Type),
+    /// TryReturn(span, return type)
+    TryReturn(Span, Type),
+    /// InvalidCast(span, from, to)
+    InvalidCast(Span, Type, Type),
+    /// CharOutOfRange(span, value), for an int literal cast to `char`
+    CharOutOfRange(Span, i64),
+    /// `instanceof` on or with something other than a class
+    InvalidInstanceOf(Span, Type),
+    /// A range like `(a..b)` somewhere other than a for loop
+    RangeOutsideFor(Span),
+    /// An inclusive range whose end is the maximum or minimum i32, so the loop variable would overflow
+    InclusiveOverflow(Span),
+    /// An element added to a dynamic array that always `return`s, `break`s or `continue`s, so there's nothing to store
+    DivergingElement(Span),
+    /// `throw` or `catch` with something other than an extern class
+    ThrowNotException(Span, Type),
+    /// A type parameter somewhere Java generics can't express it, like in a dynamic array or a tuple return type
+    InvalidTypeParam(Span),
+    /// An `inline` function with type parameters, which would need them in the caller
+    InlineGeneric(Span),
+    /// A type parameter instantiated with something that isn't a Java reference type
+    /// NotReference(argument span, type)
+    NotReference(Span, Type),
+    /// A `Map` key or value type that can't be stored in a Java `HashMap`
+    InvalidMapType(Span, Type),
+    /// A lambda whose expected type isn't an interface or extern class with exactly one method
+    NotFunctional(Span, Type),
+    /// Assigning to or resizing a local captured by a lambda, inside the lambda
+    AssignCaptured(Span),
+    /// Assigning to or resizing a binding declared `final`
+    AssignFinal(Span),
+    /// Calling a static method on an object, instead of as `Class::method()`
+    StaticMethod(Span, RawSym),
+    /// A class literal of something other than a class defined in ByteC
+    InvalidClassLit(Span, Type),
+    /// A class literal that sets the same field twice
+    DuplicateField(Span, RawSym),
+    /// A class literal without a value for a field that doesn't have an initial value
+    /// MissingField(class literal span, field)
+    MissingField(Span, RawSym),
+    /// A class implementing something that isn't an interface or an extern class
+    NotInterface(Span, Type),
+    /// MissingMethod(class span, interface, method)
+    MissingMethod(Span, Type, RawSym),
+    /// A method with a different type than the one in the interface it implements or the method it overrides
+    /// MethodMismatch(method span, interface or superclass)
+    MethodMismatch(Span, Type),
+    /// An interface method taking or returning something that isn't a single Java value
+    InterfaceMethodType(Span, Type),
+    /// A class extending something that isn't an extern class
+    NotExtendable(Span, Type),
+    /// `super(...)` in a class that doesn't extend anything
+    SuperWithoutExtends(Span),
 }
 impl TypeError {
     fn to_error(self, bindings: &Bindings) -> Error {
@@ -478,6 +593,13 @@ impl TypeError {
                     .add(if ia == 1 { " argument" } else { " arguments" }),
                 span,
             ),
+            TypeError::WrongTypeArgs(span, ia, ea) => Spanned::new(
+                Doc::start("Wrong number of type arguments: expected ")
+                    .add(ea)
+                    .add(" but found ")
+                    .add(ia),
+                span,
+            ),
             TypeError::NoMethods(span, ty) => Spanned::new(
                 Doc::start("Value of type ")
                     .chain(ty.pretty(bindings))
@@ -497,12 +619,32 @@ impl TypeError {
                 )),
                 span,
             ),
+            TypeError::WrongPattern(span, ty) => Spanned::new(
+                Doc::start("Pattern can't match a value of type ").chain(ty.pretty(bindings)),
+                span,
+            ),
+            TypeError::MissingDefault(span) => Spanned::new(
+                Doc::start("Inexhaustive match: a match on an integer needs an 'else' branch"),
+                span,
+            ),
             TypeError::Duplicate(span, name) => Spanned::new(
                 Doc::start("Duplicate definition of item named '")
                     .add(bindings.resolve_raw(name))
                     .add("'"),
                 span,
             ),
+            TypeError::DuplicateVariant(span, name) => Spanned::new(
+                Doc::start("Duplicate variant '")
+                    .add(bindings.resolve_raw(name))
+                    .add("' in enum"),
+                span,
+            ),
+            TypeError::EnumFieldClash(span, field) => Spanned::new(
+                Doc::start("This variant's members would be stored in the field '")
+                    .add(field)
+                    .add("', which is already used by another variant, member or method"),
+                span,
+            ),
             TypeError::NotArray(span, t) => Spanned::new(
                 Doc::start("Expected an array, got value of type ").chain(t.pretty(bindings)),
                 span,
@@ -524,6 +666,12 @@ impl TypeError {
                 Doc::start("Can only assign to variables and array indices"),
                 span,
             ),
+            TypeError::MethodNotLValue(span, m) => Spanned::new(
+                Doc::start("Can only call '")
+                    .add(bindings.resolve_raw(m))
+                    .add("()' on variables, members and array indices, since it can change or reallocate the array"),
+                span,
+            ),
             TypeError::TypeNeeded(span) => Spanned::new(
                 Doc::start("Type of expression could not be inferred, type annotation needed"),
                 span,
@@ -532,6 +680,141 @@ impl TypeError {
                 Doc::start("'self' can't be used outside of class or enum"),
                 span,
             ),
+            TypeError::NotTryable(span, t) => Spanned::new(
+                Doc::start("Value of type ")
+                    .chain(t.pretty(bindings))
+                    .add(" isn't an Option or nullable and can't be used with '?'"),
+                span,
+            ),
+            TypeError::RangeOutsideFor(span) => Spanned::new(
+                Doc::start("Ranges can only be used in for loops"),
+                span,
+            ),
+            TypeError::InclusiveOverflow(span) => Spanned::new(
+                Doc::start("An inclusive range to the largest or smallest i32 would loop forever, since the loop variable overflows"),
+                span,
+            ),
+            TypeError::DivergingElement(span) => Spanned::new(
+                Doc::start("An array element needs a value, it can't always return, break or continue"),
+                span,
+            ),
+            TypeError::ThrowNotException(span, ty) => Spanned::new(
+                Doc::start("Only extern exception classes can be thrown or caught, not ")
+                    .chain(ty.pretty(bindings)),
+                span,
+            ),
+            TypeError::InvalidTypeParam(span) => Spanned::new(
+                Doc::start("Type parameters can't be used in dynamic arrays, or in return types with more than one value like tuples and options"),
+                span,
+            ),
+            TypeError::InlineGeneric(span) => Spanned::new(
+                Doc::start("Inline functions can't have type parameters"),
+                span,
+            ),
+            TypeError::NotReference(span, ty) => Spanned::new(
+                Doc::start("Type parameters can only be classes or strings, not ")
+                    .chain(ty.pretty(bindings)),
+                span,
+            ),
+            TypeError::NotFunctional(span, ty) => Spanned::new(
+                Doc::start("Lambdas can only be used as interfaces or extern classes with exactly one method, not ")
+                    .chain(ty.pretty(bindings)),
+                span,
+            ),
+            TypeError::AssignCaptured(span) => Spanned::new(
+                Doc::start("Lambdas capture local variables by value, so they can't be assigned to or resized inside the lambda"),
+                span,
+            ),
+            TypeError::AssignFinal(span) => Spanned::new(
+                Doc::start("Bindings declared 'final' can't be assigned to or resized"),
+                span,
+            ),
+            TypeError::StaticMethod(span, m) => Spanned::new(
+                Doc::start("'")
+                    .add(bindings.resolve_raw(m))
+                    .add("' is a static method, so it's called on the class, like 'Class::")
+                    .add(bindings.resolve_raw(m))
+                    .add("()'"),
+                span,
+            ),
+            TypeError::InvalidClassLit(span, ty) => Spanned::new(
+                Doc::start("Only classes defined in ByteC can be created with a class literal, not ")
+                    .chain(ty.pretty(bindings)),
+                span,
+            ),
+            TypeError::DuplicateField(span, name) => Spanned::new(
+                Doc::start("Field '")
+                    .add(bindings.resolve_raw(name))
+                    .add("' is given more than once"),
+                span,
+            ),
+            TypeError::MissingField(span, name) => Spanned::new(
+                Doc::start("Missing field '")
+                    .add(bindings.resolve_raw(name))
+                    .add("', which needs a value since it doesn't have an initial value"),
+                span,
+            ),
+            TypeError::NotInterface(span, ty) => Spanned::new(
+                Doc::start("Classes can only implement interfaces and extern classes, not ")
+                    .chain(ty.pretty(bindings)),
+                span,
+            ),
+            TypeError::MissingMethod(span, ty, m) => Spanned::new(
+                Doc::start("Class is missing method '")
+                    .add(bindings.resolve_raw(m))
+                    .add("' from interface ")
+                    .chain(ty.pretty(bindings)),
+                span,
+            ),
+            TypeError::MethodMismatch(span, ty) => Spanned::new(
+                Doc::start("Method type doesn't match its declaration in ")
+                    .chain(ty.pretty(bindings)),
+                span,
+            ),
+            TypeError::NotExtendable(span, ty) => Spanned::new(
+                Doc::start("Classes can only extend extern classes, not ").chain(ty.pretty(bindings)),
+                span,
+            ),
+            TypeError::SuperWithoutExtends(span) => Spanned::new(
+                Doc::start("'super' can only be called in a class that extends another class"),
+                span,
+            ),
+            TypeError::InterfaceMethodType(span, ty) => Spanned::new(
+                Doc::start("Interface methods can't take or return tuples, arrays or options, like ")
+                    .chain(ty.pretty(bindings)),
+                span,
+            ),
+            TypeError::InvalidMapType(span, ty) => Spanned::new(
+                Doc::start("Map keys and values can only be classes, strings, numbers or bools, not ")
+                    .chain(ty.pretty(bindings)),
+                span,
+            ),
+            TypeError::InvalidCast(span, from, to) => Spanned::new(
+                Doc::start("Can't cast ")
+                    .chain(from.pretty(bindings))
+                    .add(" to ")
+                    .chain(to.pretty(bindings))
+                    .add(", only casts between numeric types or related classes are allowed"),
+                span,
+            ),
+            TypeError::CharOutOfRange(span, i) => Spanned::new(
+                Doc::start("Can't cast ")
+                    .add(i)
+                    .add(" to char, which only goes from 0 to 65535"),
+                span,
+            ),
+            TypeError::InvalidInstanceOf(span, ty) => Spanned::new(
+                Doc::start("instanceof can only be used with classes, not ")
+                    .chain(ty.pretty(bindings)),
+                span,
+            ),
+            TypeError::TryReturn(span, t) => Spanned::new(
+                Doc::start(
+                    "'?' can only be used in a function returning an Option, a nullable type, or the same enum as the Ok/Err value, but this one returns ",
+                )
+                .chain(t.pretty(bindings)),
+                span,
+            ),
         }
     }
 }
@@ -539,33 +822,426 @@ impl TypeError {
 impl<'b> Cxt<'b> {
     fn elab_type(&self, ty: &PreType) -> Result<Type, TypeError> {
         match ty {
+            PreType::I8 => Ok(Type::I8),
+            PreType::I16 => Ok(Type::I16),
             PreType::I32 => Ok(Type::I32),
             PreType::I64 => Ok(Type::I64),
+            PreType::F32 => Ok(Type::F32),
+            PreType::F64 => Ok(Type::F64),
             PreType::Bool => Ok(Type::Bool),
+            PreType::Char => Ok(Type::Char),
             PreType::Str => Ok(Type::Str),
+            PreType::StrBuilder => Ok(Type::StrBuilder),
             PreType::Tuple(v) if v.is_empty() => Ok(Type::Unit),
-            PreType::Class(name) => self
-                .class(name)
-                .map(Type::Class)
-                .ok_or(TypeError::NotFound(name.clone())),
+            PreType::Class(name) if name.len() == 1 && self.type_params.contains(&name.stem()) => {
+                Ok(Type::Param(*name.stem()))
+            }
+            PreType::Class(name) => {
+                let class = self.class(name).ok_or(TypeError::NotFound(name.clone()))?;
+                let n = self.bindings.type_params(class).len();
+                if n != 0 {
+                    return Err(TypeError::WrongTypeArgs(name.span(), 0, n));
+                }
+                Ok(Type::Class(class, Vec::new()))
+            }
+            PreType::Generic(name, args) if self.is_prelude(name, "Option") => {
+                if args.len() != 1 {
+                    return Err(TypeError::WrongArity(name.span(), args.len(), 1));
+                }
+                Ok(Type::Option(Box::new(self.elab_type(&args[0])?)))
+            }
+            PreType::Generic(name, args)
+                if self.is_prelude(name, "Map") && self.class(name).is_none() =>
+            {
+                if args.len() != 2 {
+                    return Err(TypeError::WrongTypeArgs(name.span(), args.len(), 2));
+                }
+                let k = self.elab_type(&args[0])?;
+                let v = self.elab_type(&args[1])?;
+                // Primitives are boxed, but anything that's more than one value in Java can't be stored
+                for t in [&k, &v] {
+                    if !t.is_reference() && !t.is_numeric() && *t != Type::Bool {
+                        return Err(TypeError::InvalidMapType(name.span(), t.clone()));
+                    }
+                }
+                Ok(Type::Map(Box::new(k), Box::new(v)))
+            }
+            PreType::Generic(name, args) => {
+                let class = self.class(name).ok_or(TypeError::NotFound(name.clone()))?;
+                let n = self.bindings.type_params(class).len();
+                if args.len() != n {
+                    return Err(TypeError::WrongTypeArgs(name.span(), args.len(), n));
+                }
+                let args = args
+                    .iter()
+                    .map(|x| self.elab_type(x))
+                    .collect::<Result<Vec<_>, _>>()?;
+                // Only extern classes take primitive type arguments, which Java boxes, like `ArrayList<Integer>`
+                let ext = self.class_info(class).ext;
+                if let Some(t) = args
+                    .iter()
+                    .find(|x| !x.is_reference() && !(ext && (x.is_numeric() || **x == Type::Bool)))
+                {
+                    return Err(TypeError::NotReference(name.span(), t.clone()));
+                }
+                Ok(Type::Class(class, args))
+            }
             PreType::Tuple(v) => v
                 .iter()
                 .map(|x| self.elab_type(x))
                 .collect::<Result<Vec<_>, _>>()
                 .map(Type::Tuple),
-            PreType::Array(t) => Ok(Type::Array(Box::new(self.elab_type(t)?))),
+            PreType::Array(t) => {
+                // Java can't create arrays of a generic type
+                if let Some(name) = self.type_param_in(t) {
+                    return Err(TypeError::InvalidTypeParam(name.span()));
+                }
+                Ok(Type::Array(Box::new(self.elab_type(t)?)))
+            }
             PreType::SArray(t, i) => Ok(Type::SArray(Box::new(self.elab_type(t)?), *i)),
         }
     }
 
+    /// Finds a use of one of the current function's type parameters in `ty`
+    fn type_param_in<'a>(&self, ty: &'a PreType) -> Option<&'a RawPath> {
+        match ty {
+            PreType::Class(name) if name.len() == 1 && self.type_params.contains(&name.stem()) => {
+                Some(name)
+            }
+            PreType::Generic(_, v) | PreType::Tuple(v) => {
+                v.iter().find_map(|x| self.type_param_in(x))
+            }
+            PreType::Array(t) | PreType::SArray(t, _) => self.type_param_in(t),
+            _ => None,
+        }
+    }
+
+    /// Matches the type of a generic function's parameter against the type of its argument,
+    /// adding the types that its type parameters are instantiated with to `map`
+    fn bind_type_params(
+        &self,
+        span: Span,
+        param: &Type,
+        arg: &Type,
+        map: &mut Vec<(RawSym, Type)>,
+    ) -> Result<(), TypeError> {
+        match (param, arg) {
+            (Type::Param(r), _) => match map.iter().find(|(r2, _)| r2 == r) {
+                Some((_, t)) if t == arg => Ok(()),
+                Some((_, t)) => Err(TypeError::Unify(span, arg.clone(), t.clone())),
+                None if arg.is_reference() => {
+                    map.push((*r, arg.clone()));
+                    Ok(())
+                }
+                None => Err(TypeError::NotReference(span, arg.clone())),
+            },
+            (Type::Tuple(a), Type::Tuple(b)) if a.len() == b.len() => {
+                for (a, b) in a.iter().zip(b) {
+                    self.bind_type_params(span, a, b, map)?;
+                }
+                Ok(())
+            }
+            (Type::Class(c, a), Type::Class(d, b)) if c == d && a.len() == b.len() => {
+                for (a, b) in a.iter().zip(b) {
+                    self.bind_type_params(span, a, b, map)?;
+                }
+                Ok(())
+            }
+            // A class passed as an interface it implements or a class it extends
+            (Type::Class(c, _), Type::Class(d, _))
+                if c != d && self.supertype(arg, *c).is_some() =>
+            {
+                let sup = self.supertype(arg, *c).unwrap();
+                self.bind_type_params(span, param, &sup, map)
+            }
+            (Type::Array(a), Type::Array(b)) | (Type::Option(a), Type::Option(b)) => {
+                self.bind_type_params(span, a, b, map)
+            }
+            (Type::Map(k, v), Type::Map(k2, v2)) => {
+                self.bind_type_params(span, k, k2, map)?;
+                self.bind_type_params(span, v, v2, map)
+            }
+            (Type::SArray(a, i), Type::SArray(b, j)) if i == j => {
+                self.bind_type_params(span, a, b, map)
+            }
+            _ if param == arg => Ok(()),
+            _ => Err(TypeError::Unify(span, arg.clone(), param.subst(map))),
+        }
+    }
+
+    /// The type of `self` in `class`, which uses the class's own type parameters as its type arguments
+    fn class_type(&self, class: TypeId) -> Type {
+        let params = self.bindings.type_params(class);
+        Type::Class(class, params.iter().map(|r| Type::Param(*r)).collect())
+    }
+
+    /// The type parameters of `class` paired with the type arguments it's used with
+    fn type_args(&self, class: TypeId, targs: &[Type]) -> TypeArgs {
+        self.bindings
+            .type_params(class)
+            .iter()
+            .copied()
+            .zip(targs.iter().cloned())
+            .collect()
+    }
+
+    /// Finds the interface or superclass `class` among the ones `ty` implements or extends, directly or not,
+    /// with `ty`'s type arguments filled in
+    fn supertype(&self, ty: &Type, class: TypeId) -> Option<Type> {
+        let (c, targs) = match ty {
+            Type::Class(c, targs) => (*c, targs),
+            _ => return None,
+        };
+        let map = self.type_args(c, targs);
+        let info = self.class_info(c);
+        info.implements
+            .iter()
+            .chain(&info.extends)
+            .map(|t| t.subst(&map))
+            .find_map(|t| match t {
+                Type::Class(i, _) if i == class => Some(t),
+                t => self.supertype(&t, class),
+            })
+    }
+
+    /// Whether `ty` is a class that implements or extends `sup`, so it can be used as one
+    fn subtype(&self, ty: &Type, sup: &Type) -> bool {
+        match sup {
+            Type::Class(c, _) => self.supertype(ty, *c).as_ref() == Some(sup),
+            _ => false,
+        }
+    }
+
+    /// Whether `from` can be cast to the class `to`, which Java checks at runtime.
+    /// ByteC classes can't be extended, so an object can only be cast to one if it's already that class.
+    fn class_cast(&self, from: &Type, to: &Type) -> bool {
+        match (from, to) {
+            (Type::Class(a, _), Type::Class(b, _))
+                if self.class_info(*a).variants.is_none()
+                    && self.class_info(*b).variants.is_none() =>
+            {
+                let open = |c: TypeId| self.class_info(c).ext || self.class_info(c).interface;
+                from == to
+                    || self.subtype(from, to)
+                    || (open(*a) && (open(*b) || self.subtype(to, from)))
+            }
+            _ => false,
+        }
+    }
+
+    /// Finds a method of the class `ty` or a class it extends, along with the type arguments of the class it's from
+    fn method(&self, ty: &Type, name: RawSym) -> Option<(FnId, FnType, TypeArgs)> {
+        let (c, targs) = match ty {
+            Type::Class(c, targs) => (*c, targs),
+            _ => return None,
+        };
+        let map = self.type_args(c, targs);
+        let info = self.class_info(c);
+        match info.methods.iter().find(|(s, _, _)| *s == name) {
+            Some((_, fid, fty)) => Some((*fid, fty.clone(), map)),
+            None => self.method(&info.extends.as_ref()?.subst(&map), name),
+        }
+    }
+
+    /// Finds a member of the class `ty` or a class it extends, with its type arguments filled in
+    fn member(&self, ty: &Type, name: RawSym) -> Option<(Sym, Type)> {
+        let (c, targs) = match ty {
+            Type::Class(c, targs) => (*c, targs),
+            _ => return None,
+        };
+        let map = self.type_args(c, targs);
+        let info = self.class_info(c);
+        match info.members.iter().find(|(s, _, _)| *s == name) {
+            Some((_, s, t)) => Some((*s, t.subst(&map))),
+            None => self.member(&info.extends.as_ref()?.subst(&map), name),
+        }
+    }
+
+    /// Checks that a call to `f` with `n` arguments has the right number, which can leave out parameters with default values.
+    fn check_arity(&self, span: Span, f: FnId, n: usize, expected: usize) -> Result<(), TypeError> {
+        if n > expected || n + self.bindings.defaults(f) < expected {
+            Err(TypeError::WrongArity(span, n, expected))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Checks the arguments of a call against the parameter types `atys`.
+    /// Type parameters that aren't in `map` yet are inferred from the arguments, left to right.
+    fn check_args(
+        &mut self,
+        a: &[SPre],
+        atys: Vec<Type>,
+        map: &mut Vec<(RawSym, Type)>,
+    ) -> Result<Vec<Term>, TypeError> {
+        let mut a2 = Vec::new();
+        for (a, t) in a.iter().zip(atys) {
+            if t.params().iter().all(|r| map.iter().any(|(r2, _)| r2 == r)) {
+                a2.push(self.check(a, t.subst(map))?);
+            } else {
+                let (x, xt) = self.infer(a)?;
+                self.bind_type_params(a.span, &t, &xt, map)?;
+                a2.push(x);
+            }
+        }
+        Ok(a2)
+    }
+
+    /// Checks the arguments of a call to a function whose last parameter is `name: T...`.
+    /// Any extra arguments are collected into a `[T]`, or passed along one by one if `spread` is set.
+    fn check_varargs(
+        &mut self,
+        span: Span,
+        a: &[SPre],
+        mut atys: Vec<Type>,
+        spread: bool,
+        map: &mut Vec<(RawSym, Type)>,
+    ) -> Result<Vec<Term>, TypeError> {
+        let elem = match atys.pop() {
+            Some(Type::Array(t)) => *t,
+            _ => unreachable!("varargs parameter isn't a dynamic array"),
+        };
+        let n = atys.len();
+        if a.len() < n {
+            return Err(TypeError::WrongArity(span, a.len(), n));
+        }
+        let mut a2 = self.check_args(&a[..n], atys, map)?;
+        let rest = self.check_args(&a[n..], vec![elem.clone(); a.len() - n], map)?;
+        if spread {
+            a2.extend(rest);
+        } else {
+            a2.push(Term::Array(rest, elem, true));
+        }
+        Ok(a2)
+    }
+
+    /// Checks a class literal like `Point { x: 1, y: 2 }`, which creates the class and then sets each field in order.
+    /// The type arguments of a generic class are in `map` if they're known from the expected type, and are inferred from the fields otherwise.
+    fn class_lit(
+        &mut self,
+        span: Span,
+        path: &RawPath,
+        fields: &[(Spanned<RawSym>, SPre)],
+        mut map: TypeArgs,
+    ) -> Result<(Term, Type), TypeError> {
+        let c = self
+            .class(path)
+            .ok_or_else(|| TypeError::NotFound(path.clone()))?;
+        let info = self.class_info(c);
+        if info.ext || info.interface || info.variants.is_some() {
+            return Err(TypeError::InvalidClassLit(span, self.class_type(c)));
+        }
+        let members = info.members.clone();
+        let initialized = info.initialized.clone();
+
+        let mut sets: Vec<(Sym, Term)> = Vec::new();
+        for (name, x) in fields {
+            let (s, t) = members
+                .iter()
+                .find(|(r, _, _)| *r == **name)
+                .map(|(_, s, t)| (*s, t.clone()))
+                .ok_or_else(|| TypeError::NotFound(lpath(*name)))?;
+            if sets.iter().any(|(s2, _)| *s2 == s) {
+                return Err(TypeError::DuplicateField(name.span, **name));
+            }
+            let x = self
+                .check_args(std::slice::from_ref(x), vec![t], &mut map)?
+                .pop()
+                .unwrap();
+            sets.push((s, x));
+        }
+        if let Some((r, _, _)) = members
+            .iter()
+            .find(|(_, s, _)| !initialized.contains(s) && !sets.iter().any(|(s2, _)| s2 == s))
+        {
+            return Err(TypeError::MissingField(span, *r));
+        }
+
+        let targs = self
+            .bindings
+            .type_params(c)
+            .iter()
+            .map(|r| match map.iter().find(|(r2, _)| r2 == r) {
+                Some((_, t)) => Ok(t.clone()),
+                None => Err(TypeError::TypeNeeded(span)),
+            })
+            .collect::<Result<_, _>>()?;
+        let ty = Type::Class(c, targs);
+
+        // It's `{ let x = Point(); x.x = 1; x.y = 2; x }`
+        let raw = self.bindings.raw("$_class");
+        let s = self.create(Spanned::new(raw, span), ty.clone(), false);
+        let mut block = vec![Statement::Let(
+            s,
+            ty.clone(),
+            Term::Constructor(c, Vec::new()),
+        )];
+        for (m, x) in sets {
+            let l = LValue::Member(Box::new(Term::Var(s)), m);
+            block.push(Statement::Term(Term::Set(l, None, Box::new(x))));
+        }
+        Ok((Term::Block(block, Some(Box::new(Term::Var(s)))), ty))
+    }
+
+    /// Gives the result of a call the return type `rty` with the type parameters in `map` filled in
+    fn instantiate(
+        &self,
+        span: Span,
+        call: Term,
+        rty: &Type,
+        map: &[(RawSym, Type)],
+    ) -> Result<(Term, Type), TypeError> {
+        if !rty.has_param() {
+            return Ok((call, rty.clone()));
+        }
+        if rty
+            .params()
+            .iter()
+            .any(|r| !map.iter().any(|(r2, _)| r2 == r))
+        {
+            return Err(TypeError::TypeNeeded(span));
+        }
+        let rty = rty.subst(map);
+        // The cast tells the backend which type the result is used as
+        Ok((Term::Cast(Box::new(call), rty.clone()), rty))
+    }
+
     fn declare_item_p1(&mut self, item: &PreItem) -> Result<(), TypeError> {
         match item {
             PreItem::InlineJava(_) => Ok(()),
             PreItem::Fn(_) => Ok(()),
             PreItem::ExternFn(_) => Ok(()),
-            PreItem::Let(_, _, _, _) => Ok(()),
-            PreItem::Class { path, .. } => {
-                self.create_class(path.clone(), ClassInfo::default());
+            PreItem::Let(_, _, _, _, _, _) => Ok(()),
+            PreItem::Class {
+                path,
+                type_params,
+                ext,
+                ..
+            } => {
+                // Whether it's extern is needed for checking its type arguments before p2
+                let info = ClassInfo {
+                    ext: *ext,
+                    ..ClassInfo::default()
+                };
+                let class = self.create_class(path.clone(), info);
+                if !type_params.is_empty() {
+                    self.bindings
+                        .set_type_params(class, type_params.iter().map(|x| **x).collect());
+                }
+                Ok(())
+            }
+            PreItem::Interface {
+                path, type_params, ..
+            } => {
+                let info = ClassInfo {
+                    interface: true,
+                    ..ClassInfo::default()
+                };
+                let class = self.create_class(path.clone(), info);
+                if !type_params.is_empty() {
+                    self.bindings
+                        .set_type_params(class, type_params.iter().map(|x| **x).collect());
+                }
                 Ok(())
             }
             PreItem::Use(path, wildcard) => {
@@ -593,12 +1269,52 @@ impl<'b> Cxt<'b> {
         match item {
             PreItem::Class {
                 path,
+                type_params,
                 methods,
                 members,
                 constructor,
                 variants,
                 ext,
+                implements,
+                extends,
+                super_args,
             } => {
+                // Members and methods can use the class's type parameters
+                self.type_params = type_params.iter().map(|x| **x).collect();
+                let extends = extends
+                    .as_ref()
+                    .map(|t| match self.elab_type(t)? {
+                        Type::Class(c, targs) if self.class_info(c).ext => {
+                            Ok(Type::Class(c, targs))
+                        }
+                        t => Err(TypeError::NotExtendable(path.span(), t)),
+                    })
+                    .transpose()?;
+                if let (Some(args), None) = (super_args, &extends) {
+                    return Err(TypeError::SuperWithoutExtends(args.span));
+                }
+                let implements = implements
+                    .iter()
+                    .map(|t| match self.elab_type(t)? {
+                        Type::Class(c, targs)
+                            if self.class_info(c).interface || self.class_info(c).ext =>
+                        {
+                            Ok(Type::Class(c, targs))
+                        }
+                        t => Err(TypeError::NotInterface(path.span(), t)),
+                    })
+                    .collect::<Result<_, _>>()?;
+                // Each variant becomes an enum constant, and its members become `_enum$<variant>$<n>` fields on the wrapper class,
+                // so two variants with the same name would generate invalid Java
+                for (i, (s, _)) in variants.iter().flatten().enumerate() {
+                    if variants.iter().flatten().take(i).any(|(s2, _)| **s2 == **s) {
+                        return Err(TypeError::DuplicateVariant(s.span, **s));
+                    }
+                }
+                if let (Some(variants), false) = (variants, ext) {
+                    self.check_enum_fields(variants, members, methods)?;
+                }
+                let class = self.class(path).unwrap();
                 let methods = methods
                     .iter()
                     .map(|f| {
@@ -614,6 +1330,9 @@ impl<'b> Cxt<'b> {
                                 let rty = self.elab_type(&f.ret_ty)?;
                                 let ty = FnType(args, rty.clone());
                                 let id = self.bindings.add_fn(lpath(f.name));
+                                if f.is_static {
+                                    self.bindings.set_static(id, class);
+                                }
                                 // Make sure the mapping gets through to the backend.
                                 // This technically has the wrong type since it doesn't include the object,
                                 // but it doesn't matter because the function is only accessible as a method.
@@ -635,8 +1354,24 @@ impl<'b> Cxt<'b> {
                                     args2.push((self.bindings.create(lpath(*s), true), t));
                                 }
                                 let rty = self.elab_type(&f.ret_ty)?;
+                                if rty.has_param()
+                                    && matches!(
+                                        rty,
+                                        Type::Tuple(_) | Type::Option(_) | Type::SArray(_, _)
+                                    )
+                                {
+                                    return Err(TypeError::InvalidTypeParam(f.name.span));
+                                }
+                                // Java doesn't let static methods use the class's type parameters
+                                if f.is_static && args.iter().chain([&rty]).any(|t| t.has_param()) {
+                                    return Err(TypeError::InvalidTypeParam(f.name.span));
+                                }
                                 let ty = FnType(args, rty.clone());
                                 let id = self.bindings.add_fn(lpath(f.name));
+                                if f.is_static {
+                                    self.bindings.set_static(id, class);
+                                }
+                                self.bindings.set_defaults(id, f.defaults.len());
                                 // // Make sure the mapping gets through to the backend.
                                 // // This technically has the wrong type since it doesn't include the object,
                                 // // but it doesn't matter because the function is only accessible as a method.
@@ -652,11 +1387,19 @@ impl<'b> Cxt<'b> {
                         }
                     })
                     .collect::<Result<_, _>>()?;
+                let mut initialized = Vec::new();
                 let members = members
                     .iter()
-                    .map(|(s, public, t, _val)| {
+                    .map(|(s, public, volatile, t, val)| {
                         let t = self.elab_type(t)?;
-                        Ok((**s, self.bindings.create(lpath(*s), *public), t))
+                        let sym = self.bindings.create(lpath(*s), *public);
+                        if *volatile {
+                            self.bindings.set_volatile(sym);
+                        }
+                        if val.is_some() {
+                            initialized.push(sym);
+                        }
+                        Ok((**s, sym, t))
                     })
                     .collect::<Result<_, _>>()?;
                 let mut constructor = constructor
@@ -670,14 +1413,19 @@ impl<'b> Cxt<'b> {
                 let info = ClassInfo {
                     methods,
                     members,
+                    initialized,
                     constructor,
+                    ext: *ext,
+                    interface: false,
+                    implements,
+                    extends,
                     variants: variants
                         .as_ref()
                         .map(|x| {
                             x.iter()
                                 .map(|(s, t)| {
                                     Ok((
-                                        *s,
+                                        **s,
                                         t.iter()
                                             .map(|x| self.elab_type(x))
                                             .collect::<Result<_, _>>()?,
@@ -688,6 +1436,47 @@ impl<'b> Cxt<'b> {
                         .transpose()?,
                 };
                 *self.class_info_mut(id) = info;
+                self.type_params = Vec::new();
+                Ok(())
+            }
+            PreItem::Interface {
+                path,
+                type_params,
+                methods,
+            } => {
+                self.type_params = type_params.iter().map(|x| **x).collect();
+                let mut methods2 = Vec::new();
+                for f in methods {
+                    let mut args = Vec::new();
+                    let mut args2 = Vec::new();
+                    for (s, t, _) in &f.args {
+                        let t = self.elab_type(t)?;
+                        args.push(t.clone());
+                        args2.push((self.bindings.create(lpath(*s), true), t));
+                    }
+                    let rty = self.elab_type(&f.ret_ty)?;
+                    // Each argument and the return value have to be one Java value, so implementations get the same signature
+                    if let Some(t) = args.iter().chain(std::iter::once(&rty)).find(|t| {
+                        matches!(
+                            t,
+                            Type::Tuple(_) | Type::Array(_) | Type::SArray(_, _) | Type::Option(_)
+                        )
+                    }) {
+                        return Err(TypeError::InterfaceMethodType(f.name.span, t.clone()));
+                    }
+                    let id = self.bindings.add_fn(lpath(f.name));
+                    // Calls through the interface use the plain method name, like extern methods
+                    self.extra_items.push(Item::ExternFn(ExternFn {
+                        id,
+                        ret_ty: rty.clone(),
+                        args: args2,
+                        mapping: f.mapping,
+                    }));
+                    methods2.push((*f.name, id, FnType(args, rty)));
+                }
+                let id = self.class(path).unwrap();
+                self.class_info_mut(id).methods = methods2;
+                self.type_params = Vec::new();
                 Ok(())
             }
             PreItem::Use(path, wildcard) => {
@@ -721,13 +1510,28 @@ impl<'b> Cxt<'b> {
         match item {
             PreItem::InlineJava(_) => Ok(()),
             PreItem::Fn(f) => {
+                if f.inline && !f.type_params.is_empty() {
+                    return Err(TypeError::InlineGeneric(f.name.span));
+                }
+                self.type_params = f.type_params.iter().map(|x| **x).collect();
                 let mut args = Vec::new();
                 for (_s, t, _) in &f.args {
                     let t = self.elab_type(t)?;
                     args.push(t);
                 }
                 let rty = self.elab_type(&f.ret_ty)?;
-                self.create_fn(f.name, FnType(args, rty))?;
+                self.type_params = Vec::new();
+                // Multiple return values are passed in static fields, which can't have a generic type
+                if rty.has_param()
+                    && matches!(rty, Type::Tuple(_) | Type::Option(_) | Type::SArray(_, _))
+                {
+                    return Err(TypeError::InvalidTypeParam(f.name.span));
+                }
+                let id = self.create_fn(f.name, FnType(args, rty))?;
+                self.bindings.set_defaults(id, f.defaults.len());
+                if f.varargs {
+                    self.bindings.set_varargs(id, false);
+                }
                 Ok(())
             }
             PreItem::ExternFn(f) => {
@@ -737,10 +1541,13 @@ impl<'b> Cxt<'b> {
                     args.push(t);
                 }
                 let rty = self.elab_type(&f.ret_ty)?;
-                self.create_fn(f.name, FnType(args, rty))?;
+                let id = self.create_fn(f.name, FnType(args, rty))?;
+                if f.varargs {
+                    self.bindings.set_varargs(id, true);
+                }
                 Ok(())
             }
-            PreItem::Let(name, ty, x, public) => {
+            PreItem::Let(name, ty, x, public, volatile, final_) => {
                 if self.var(&lpath(*name)).is_some() {
                     return Err(TypeError::Duplicate(name.span, **name));
                 }
@@ -751,10 +1558,16 @@ impl<'b> Cxt<'b> {
                             .1
                     }
                 };
-                self.create(*name, ty, *public);
+                let s = self.create(*name, ty, *public);
+                if *volatile {
+                    self.bindings.set_volatile(s);
+                }
+                if *final_ {
+                    self.bindings.set_final(s);
+                }
                 Ok(())
             }
-            PreItem::Class { .. } => Ok(()),
+            PreItem::Class { .. } | PreItem::Interface { .. } => Ok(()),
             PreItem::Use(path, wildcard) => {
                 // Add remaining types
                 if !*wildcard {
@@ -813,7 +1626,7 @@ impl<'b> Cxt<'b> {
                     if let Some(c) = self.class(path) {
                         let info = self.class_info(c);
                         if let Some(v) = info.variants.clone() {
-                            let ty = Type::Class(c);
+                            let ty = Type::Class(c, Vec::new());
                             for (i, tys) in v {
                                 // TODO import non-empty variants as functions
                                 if tys.is_empty() {
@@ -821,7 +1634,10 @@ impl<'b> Cxt<'b> {
                                 }
                             }
                         } else {
-                            return Err(TypeError::NoVariants(path.span(), Type::Class(c)));
+                            return Err(TypeError::NoVariants(
+                                path.span(),
+                                Type::Class(c, Vec::new()),
+                            ));
                         }
                     } else {
                         return Err(TypeError::NotFound(path.clone()));
@@ -842,34 +1658,107 @@ impl<'b> Cxt<'b> {
     ) -> Result<Fn, TypeError> {
         let PreFn {
             name: _,
+            type_params,
             ret_ty: _,
             args,
             body,
             public,
             throws,
             inline,
+            annotations,
+            is_static,
+            defaults,
+            varargs: _,
         } = f;
 
+        // Default values are checked outside the function, since they're evaluated at the call site
+        let classes = std::mem::take(&mut self.in_classes);
+        let defaults = defaults
+            .iter()
+            .zip(&atys[atys.len() - defaults.len()..])
+            .map(|(x, t)| self.check(x, t.clone()))
+            .collect::<Result<_, _>>();
+        self.in_classes = classes;
+        let defaults = defaults?;
+
+        // Static methods don't have a `self`
+        let class = if *is_static {
+            self.in_classes.pop()
+        } else {
+            None
+        };
+        let old_vars = std::mem::replace(&mut self.fn_vars, self.vars.symbols.len());
+        let old_captured = std::mem::take(&mut self.captured);
         self.push(Some(rty.clone()));
         let mut args2 = Vec::new();
+        let mut array_params = Vec::new();
         for ((a, _, public), t) in args.iter().zip(atys) {
             let a = self.create(*a, t.clone(), *public);
+            if matches!(t, Type::Array(_)) && !self.boxed_arrays {
+                array_params.push(a);
+            }
             args2.push((a, t));
         }
-        let body = self.check(body, rty.clone())?;
+        let old_params = std::mem::replace(&mut self.array_params, array_params);
+        let body = self.check(body, rty.clone());
+        self.array_params = old_params;
+        self.fn_vars = old_vars;
+        self.captured = old_captured;
+        if let Some(class) = class {
+            self.in_classes.push(class);
+        }
+        let body = body?;
         self.pop();
 
         Ok(Fn {
             id: fid,
+            type_params: type_params.iter().map(|x| **x).collect(),
             ret_ty: rty,
             args: args2,
             public: *public,
             body,
             throws: throws.clone(),
             inline: *inline,
+            annotations: annotations.clone(),
+            is_static: *is_static,
+            defaults,
         })
     }
 
+    /// The members of each variant are stored in fields named `_enum$<variant>$<n>` on the enum's wrapper class,
+    /// which can clash with each other or with the enum's own members and methods, depending on `--name-separator`
+    fn check_enum_fields(
+        &self,
+        variants: &[(Spanned<RawSym>, Vec<PreType>)],
+        members: &[PreMember],
+        methods: &[PreFnEither],
+    ) -> Result<(), TypeError> {
+        let mut taken: HashSet<String> = members
+            .iter()
+            .map(|(r, _, _, _, _)| **r)
+            .chain(methods.iter().map(|f| match f {
+                PreFnEither::Local(f) => *f.name,
+                PreFnEither::Extern(f) => *f.name,
+            }))
+            .map(|r| self.bindings.resolve_raw(r).to_string())
+            .collect();
+        taken.insert("$type".to_string());
+        for (s, args) in variants {
+            for n in 0..args.len() {
+                let field = format!(
+                    "_enum{sep}{}{sep}{}",
+                    self.bindings.resolve_raw(**s),
+                    n,
+                    sep = self.separator
+                );
+                if !taken.insert(field.clone()) {
+                    return Err(TypeError::EnumFieldClash(s.span, field));
+                }
+            }
+        }
+        Ok(())
+    }
+
     fn check_item(&mut self, item: &PreItem) -> Result<Vec<Item>, TypeError> {
         match item {
             PreItem::InlineJava(s) => Ok(vec![Item::InlineJava(*s)]),
@@ -877,7 +1766,10 @@ impl<'b> Cxt<'b> {
                 let (fid, fty) = self.fun(&lpath(f.name)).unwrap();
                 let fty = fty.clone();
 
-                let f = self.check_fn(f, fid, fty)?;
+                self.type_params = f.type_params.iter().map(|x| **x).collect();
+                let f = self.check_fn(f, fid, fty);
+                self.type_params = Vec::new();
+                let f = f?;
 
                 Ok(vec![Item::Fn(f)])
             }
@@ -887,6 +1779,8 @@ impl<'b> Cxt<'b> {
                     ret_ty: _,
                     args,
                     mapping,
+                    is_static: _,
+                    varargs: _,
                 } = f;
                 let (fid, fty) = self.fun(&lpath(*name)).unwrap();
                 let FnType(atys, rty) = fty.clone();
@@ -907,7 +1801,7 @@ impl<'b> Cxt<'b> {
                     mapping: *mapping,
                 })])
             }
-            PreItem::Let(name, _, x, _) => {
+            PreItem::Let(name, _, x, _, _, _) => {
                 let (s, t) = self.var(&lpath(*name)).unwrap();
                 let t = t.clone();
                 let x = x.as_ref().map(|x| self.check(x, t.clone())).transpose()?;
@@ -922,18 +1816,21 @@ impl<'b> Cxt<'b> {
             } => {
                 let class = self.class(path).unwrap();
                 let info = self.class_info(class).clone();
-                Ok(vec![Item::ExternClass(
+                self.type_params = self.bindings.type_params(class).to_vec();
+                let r = Ok(vec![Item::ExternClass(
                     class,
                     members
                         .iter()
-                        .map(|(r, _, t, _)| {
+                        .map(|(r, _, _, t, _)| {
                             let t = self.elab_type(t)?;
                             let (_, s, _) =
                                 info.members.iter().find(|(r2, _, _)| *r2 == **r).unwrap();
                             Ok((*s, t))
                         })
                         .collect::<Result<_, _>>()?,
-                )])
+                )]);
+                self.type_params = Vec::new();
+                r
             }
             PreItem::Class {
                 path,
@@ -942,15 +1839,99 @@ impl<'b> Cxt<'b> {
                 members,
                 constructor,
                 ext: false,
+                super_args,
+                ..
             } => {
                 let class = self.class(path).unwrap();
                 self.in_classes.push(class);
+                self.type_params = self.bindings.type_params(class).to_vec();
                 let info = self.class_info(class).clone();
+                // Methods overriding a superclass method also keep their names, and are marked with `@Override`
+                let mut overridden = Vec::new();
+                for f in methods {
+                    let f = match f {
+                        PreFnEither::Local(f) => f,
+                        PreFnEither::Extern(_) => continue,
+                    };
+                    let base = info.extends.as_ref();
+                    if let Some((_, FnType(batys, brty), map)) =
+                        base.and_then(|base| self.method(base, *f.name))
+                    {
+                        let (_, _, FnType(atys, rty)) =
+                            info.methods.iter().find(|(r, _, _)| *r == *f.name).unwrap();
+                        if f.is_static
+                            || atys.len() != batys.len()
+                            || atys.iter().zip(&batys).any(|(a, b)| *a != b.subst(&map))
+                            || *rty != brty.subst(&map)
+                        {
+                            return Err(TypeError::MethodMismatch(
+                                f.name.span,
+                                base.unwrap().clone(),
+                            ));
+                        }
+                        overridden.push(*f.name);
+                    }
+                }
+                let extends = match &info.extends {
+                    Some(base) => {
+                        let call = match super_args {
+                            Some(args) => {
+                                let (c, targs) = match base {
+                                    Type::Class(c, targs) => (*c, targs),
+                                    _ => unreachable!(),
+                                };
+                                let atys =
+                                    self.class_info(c).constructor.clone().unwrap_or_default();
+                                if args.len() != atys.len() {
+                                    return Err(TypeError::WrongArity(
+                                        args.span,
+                                        args.len(),
+                                        atys.len(),
+                                    ));
+                                }
+                                let mut map = self.type_args(c, targs);
+                                Some(Term::Super(self.check_args(args, atys, &mut map)?))
+                            }
+                            None => None,
+                        };
+                        Some((base.clone(), call))
+                    }
+                    None => None,
+                };
+                // Methods implementing an interface method keep their names in Java, so calls through the interface reach them
+                let mut implemented = Vec::new();
+                for ity in &info.implements {
+                    let (i, targs) = match ity {
+                        Type::Class(i, targs) => (*i, targs),
+                        _ => unreachable!(),
+                    };
+                    let map = self.type_args(i, targs);
+                    for (r, _, FnType(iatys, irty)) in &self.class_info(i).methods {
+                        let f = methods.iter().find_map(|f| match f {
+                            PreFnEither::Local(f) if *f.name == *r => Some(f),
+                            _ => None,
+                        });
+                        let f = f.ok_or_else(|| {
+                            TypeError::MissingMethod(path.span(), ity.clone(), *r)
+                        })?;
+                        let (_, _, FnType(atys, rty)) =
+                            info.methods.iter().find(|(r2, _, _)| r2 == r).unwrap();
+                        if f.is_static
+                            || atys.len() != iatys.len()
+                            || atys.iter().zip(iatys).any(|(a, b)| *a != b.subst(&map))
+                            || *rty != irty.subst(&map)
+                        {
+                            return Err(TypeError::MethodMismatch(f.name.span, ity.clone()));
+                        }
+                        implemented.push(*r);
+                    }
+                }
+                let override_ = self.bindings.raw("Override");
                 let r = Ok(vec![Item::Class(
                     class,
                     members
                         .iter()
-                        .map(|(r, _, t, val)| {
+                        .map(|(r, _, _, t, val)| {
                             let t = self.elab_type(t)?;
                             let val = val
                                 .as_ref()
@@ -968,20 +1949,51 @@ impl<'b> Cxt<'b> {
                             PreFnEither::Local(f) => {
                                 let (_, fid, fty) =
                                     info.methods.iter().find(|(r, _, _)| *r == *f.name).unwrap();
-                                Some(self.check_fn(f, *fid, fty.clone()))
+                                Some(self.check_fn(f, *fid, fty.clone()).map(|mut f2| {
+                                    let overrides = overridden.contains(&*f.name);
+                                    if overrides && !f2.annotations.contains(&override_) {
+                                        f2.annotations.push(override_);
+                                    }
+                                    f2.public |= overrides || implemented.contains(&*f.name);
+                                    f2
+                                }))
                             }
                         })
                         .collect::<Result<_, _>>()?,
+                    info.implements.clone(),
+                    extends,
                 )]);
                 self.in_classes.pop();
+                self.type_params = Vec::new();
                 r
             }
-            PreItem::Class {
-                path,
-                variants: Some(variants),
-                members,
-                methods,
-                ext,
+            PreItem::Interface { path, methods, .. } => {
+                let class = self.class(path).unwrap();
+                let info = self.class_info(class);
+                Ok(vec![Item::Interface(
+                    class,
+                    methods
+                        .iter()
+                        .zip(&info.methods)
+                        .map(|(f, (name, _, FnType(atys, ret_ty)))| FnSig {
+                            name: *name,
+                            args: f
+                                .args
+                                .iter()
+                                .map(|(s, _, _)| **s)
+                                .zip(atys.clone())
+                                .collect(),
+                            ret_ty: ret_ty.clone(),
+                        })
+                        .collect(),
+                )])
+            }
+            PreItem::Class {
+                path,
+                variants: Some(variants),
+                members,
+                methods,
+                ext,
                 ..
             } => {
                 let class = self.class(path).unwrap();
@@ -992,7 +2004,7 @@ impl<'b> Cxt<'b> {
                         .iter()
                         .map(|(s, t)| {
                             Ok((
-                                *s,
+                                **s,
                                 t.iter()
                                     .map(|x| self.elab_type(x))
                                     .collect::<Result<_, _>>()?,
@@ -1002,7 +2014,7 @@ impl<'b> Cxt<'b> {
                     *ext,
                     members
                         .iter()
-                        .map(|(r, _, t, _)| {
+                        .map(|(r, _, _, t, _)| {
                             let t = self.elab_type(t)?;
                             let (_, s, _) =
                                 info.members.iter().find(|(r2, _, _)| *r2 == **r).unwrap();
@@ -1040,7 +2052,7 @@ impl<'b> Cxt<'b> {
                     if let Some(c) = self.class(path) {
                         let info = self.class_info(c);
                         if let Some(v) = info.variants.clone() {
-                            let ty = Type::Class(c);
+                            let ty = Type::Class(c, Vec::new());
                             let mut block = Vec::new();
                             for (i, tys) in v {
                                 // TODO import non-empty variants as functions
@@ -1064,11 +2076,19 @@ impl<'b> Cxt<'b> {
     }
 
     fn check_stmt(&mut self, stmt: &PreStatement) -> Result<Option<Statement>, TypeError> {
+        let s = self.check_stmt_(stmt)?;
+        Ok(match stmt.span() {
+            Some(span) => s.map(|s| Statement::Src(self.file_id, span, Box::new(s))),
+            None => s,
+        })
+    }
+    fn check_stmt_(&mut self, stmt: &PreStatement) -> Result<Option<Statement>, TypeError> {
         match stmt {
             PreStatement::Item(
                 item @ (PreItem::Fn(_)
                 | PreItem::ExternFn(_)
                 | PreItem::Class { .. }
+                | PreItem::Interface { .. }
                 | PreItem::Use(_, _)),
             ) => {
                 self.declare_item_p1(item)?;
@@ -1081,7 +2101,14 @@ impl<'b> Cxt<'b> {
                 Ok(None)
             }
             PreStatement::Item(PreItem::InlineJava(s)) => Ok(Some(Statement::InlineJava(*s))),
-            PreStatement::Item(PreItem::Let(name, ty, value, public)) => {
+            PreStatement::Item(PreItem::Let(name, ty, value, public, volatile, final_)) => {
+                if *volatile {
+                    Spanned::new(
+                        Doc::start("'volatile' has no effect on local variables"),
+                        name.span,
+                    )
+                    .emit(Severity::Warning, self.file_id);
+                }
                 let value = value.as_ref().expect("statement let must have a value");
                 let (x, t) = match ty {
                     Some(t) => {
@@ -1092,6 +2119,9 @@ impl<'b> Cxt<'b> {
                     None => self.infer(value)?,
                 };
                 let n = self.create(*name, t.clone(), *public);
+                if *final_ {
+                    self.bindings.set_final(n);
+                }
                 Ok(Some(Statement::Let(n, t, x)))
             }
             PreStatement::Term(t) => self.infer(t).map(|(x, _)| Some(Statement::Term(x))),
@@ -1106,13 +2136,130 @@ impl<'b> Cxt<'b> {
 
                 Ok(Some(Statement::While(cond, block2)))
             }
+            PreStatement::Loop(block) => {
+                let mut block2 = Vec::new();
+                self.push(None);
+                for i in block {
+                    if let Some(x) = self.check_stmt(i)? {
+                        block2.push(x);
+                    }
+                }
+                self.pop();
+
+                Ok(Some(Statement::Loop(block2)))
+            }
+            PreStatement::Labeled(l, s) => Ok(self
+                .check_stmt(s)?
+                .map(|s| Statement::Labeled(*l, Box::new(s)))),
+            PreStatement::DoWhile(block, cond) => {
+                let mut block2 = Vec::new();
+                self.push(None);
+                for i in block {
+                    if let Some(x) = self.check_stmt(i)? {
+                        block2.push(x);
+                    }
+                }
+                self.pop();
+                // Variables from the body aren't in scope in the condition, like Java
+                let cond = self.check(cond, Type::Bool)?;
+
+                Ok(Some(Statement::DoWhile(block2, cond)))
+            }
+            PreStatement::Throw(px) => {
+                let (x, t) = self.infer(px)?;
+                match t {
+                    Type::Class(tid, _)
+                        if self.class_info(tid).ext && self.class_info(tid).variants.is_none() =>
+                    {
+                        Ok(Some(Statement::Throw(x)))
+                    }
+                    t => Err(TypeError::ThrowNotException(px.span, t)),
+                }
+            }
+            PreStatement::Assert(cond, msg) => {
+                let cond = self.check(cond, Type::Bool)?;
+                let msg = self.check(msg, Type::Str)?;
+                Ok(Some(Statement::Assert(cond, msg)))
+            }
+            PreStatement::Try(body, handlers) => {
+                let mut body2 = Vec::new();
+                self.push(None);
+                for i in body {
+                    if let Some(x) = self.check_stmt(i)? {
+                        body2.push(x);
+                    }
+                }
+                self.pop();
+
+                let mut handlers2 = Vec::new();
+                for (name, ty, block) in handlers {
+                    let tid = match self.elab_type(ty)? {
+                        Type::Class(tid, _)
+                            if self.class_info(tid).ext
+                                && self.class_info(tid).variants.is_none() =>
+                        {
+                            tid
+                        }
+                        t => return Err(TypeError::ThrowNotException(name.span, t)),
+                    };
+                    self.push(None);
+                    let s = self.create(*name, Type::Class(tid, Vec::new()), false);
+                    let mut block2 = Vec::new();
+                    for i in block {
+                        if let Some(x) = self.check_stmt(i)? {
+                            block2.push(x);
+                        }
+                    }
+                    self.pop();
+                    handlers2.push((tid, s, block2));
+                }
+
+                Ok(Some(Statement::Try(body2, handlers2)))
+            }
             PreStatement::For(s, public, unroll, pa, b, block) => {
                 let (iter, t) = match b {
                     // Range
-                    Some(b) => {
+                    Some((pb, inclusive, pstep, rev)) => {
                         let a = self.check(pa, Type::I32)?;
-                        let b = self.check(b, Type::I32)?;
-                        (ForIter::Range(Box::new(a), Box::new(b), *unroll), Type::I32)
+                        let b = self.check(pb, Type::I32)?;
+                        let step = match pstep {
+                            Some(pstep) => {
+                                let step = self.check(pstep, Type::I32)?;
+                                if matches!(step, Term::Lit(Literal::Int(0), _)) {
+                                    Spanned::new(
+                                        Doc::start("A range with a step of 0 never ends"),
+                                        pstep.span,
+                                    )
+                                    .emit(Severity::Warning, self.file_id);
+                                }
+                                Some(Box::new(step))
+                            }
+                            None => None,
+                        };
+                        // Counting down, the loop ends at the start of a reversed range
+                        let down = *rev
+                            || matches!(step.as_deref(), Some(Term::Lit(Literal::Int(x), _)) if *x < 0);
+                        let (end, limit, span) = match (*rev, down) {
+                            (true, _) => (&a, i32::MIN, pa.span),
+                            (false, true) => (&b, i32::MIN, pb.span),
+                            (false, false) => (&b, i32::MAX, pb.span),
+                        };
+                        if *inclusive
+                            && matches!(end, Term::Lit(Literal::Int(x), _) if *x == limit as i64)
+                        {
+                            return Err(TypeError::InclusiveOverflow(span));
+                        }
+                        (
+                            ForIter::Range(
+                                Box::new(a),
+                                Box::new(b),
+                                step,
+                                *unroll,
+                                *inclusive,
+                                *rev,
+                            ),
+                            Type::I32,
+                        )
                     }
                     // Array
                     None => {
@@ -1142,10 +2289,66 @@ impl<'b> Cxt<'b> {
                 let x = self.check(x, Type::Bool)?;
                 Ok((Term::Not(Box::new(x)), Type::Bool))
             }
-            Pre::Null => Err(TypeError::TypeNeeded(pre.span)),
+            Pre::BitNot(x) => {
+                let (x, t) = self.infer(x)?;
+                match t {
+                    Type::I8 | Type::I16 | Type::I32 | Type::I64 => {
+                        Ok((Term::BitNot(Box::new(x)), t))
+                    }
+                    t => Err(TypeError::Unify(pre.span, t, Type::I32)),
+                }
+            }
+            Pre::Cast(x, t) => {
+                let (x, from) = self.infer(x)?;
+                let t = self.elab_type(t)?;
+                if (!from.is_numeric() || !t.is_numeric()) && !self.class_cast(&from, &t) {
+                    return Err(TypeError::InvalidCast(pre.span, from, t));
+                }
+                // Other values are only checked at runtime with `--bounds-checks`
+                if t == Type::Char {
+                    let i = match &x {
+                        Term::Lit(Literal::Int(i), _) => Some(*i),
+                        Term::Neg(x) => match &**x {
+                            Term::Lit(Literal::Int(i), _) => Some(-*i),
+                            _ => None,
+                        },
+                        _ => None,
+                    };
+                    if let Some(i) = i.filter(|i| !(0..=0xFFFF).contains(i)) {
+                        return Err(TypeError::CharOutOfRange(pre.span, i));
+                    }
+                }
+                Ok((Term::Cast(Box::new(x), t.clone()), t))
+            }
+            Pre::InstanceOf(x, t) => {
+                let (x, from) = self.infer(x)?;
+                match from {
+                    Type::Class(c, _) if self.class_info(c).variants.is_none() => (),
+                    t => return Err(TypeError::InvalidInstanceOf(pre.span, t)),
+                }
+                let tid = match self.elab_type(t)? {
+                    Type::Class(c, _) if self.class_info(c).variants.is_none() => c,
+                    t => return Err(TypeError::InvalidInstanceOf(pre.span, t)),
+                };
+                Ok((Term::InstanceOf(Box::new(x), tid), Type::Bool))
+            }
+            Pre::Neg(x) => {
+                let (x, t) = self.infer(x)?;
+                match t {
+                    Type::I8 | Type::I16 | Type::I32 | Type::I64 | Type::F32 | Type::F64 => {
+                        Ok((Term::Neg(Box::new(x)), t))
+                    }
+                    t => Err(TypeError::Unify(pre.span, t, Type::I32)),
+                }
+            }
+            Pre::Null | Pre::Lambda(_, _) => Err(TypeError::TypeNeeded(pre.span)),
+            Pre::Default(t) => {
+                let t = self.elab_type(t)?;
+                Ok((Term::Default(t.clone()), t))
+            }
             Pre::Selph => {
                 if let Some(ty) = self.in_classes.last() {
-                    Ok((Term::Selph(*ty), Type::Class(*ty)))
+                    Ok((Term::Selph(*ty), self.class_type(*ty)))
                 } else {
                     Err(TypeError::SelfOutsideClass(pre.span))
                 }
@@ -1155,6 +2358,9 @@ impl<'b> Cxt<'b> {
                 .map(|(s, t)| (Term::Var(s), t.clone()))
                 .ok_or(TypeError::NotFound(raw.clone()))
                 .or_else(|e| {
+                    if self.is_prelude(raw, "None") {
+                        return Err(TypeError::TypeNeeded(pre.span));
+                    }
                     if raw.len() > 1 {
                         let mut v = raw.0.clone();
                         let last = v.pop().unwrap();
@@ -1163,10 +2369,45 @@ impl<'b> Cxt<'b> {
                         if let Some(class) = self.class(&a) {
                             let variants = self.class_info(class).variants.as_ref();
                             if variants.map_or(true, |v| v.iter().all(|(x, _)| *x != *b)) {
+                                // `Enum::count` and `Enum::values`, unless the enum has a variant with that name
+                                if let Some(variants) = variants {
+                                    match self.bindings.resolve_raw(*b) {
+                                        "count" => {
+                                            return Ok((
+                                                Term::Lit(
+                                                    Literal::Int(variants.len() as i64),
+                                                    Type::I32,
+                                                ),
+                                                Type::I32,
+                                            ))
+                                        }
+                                        "values" if variants.iter().all(|(_, t)| t.is_empty()) => {
+                                            let v = variants
+                                                .iter()
+                                                .map(|(x, _)| Term::Variant(class, *x, Vec::new()))
+                                                .collect();
+                                            return Ok((
+                                                Term::Array(
+                                                    v,
+                                                    Type::Class(class, Vec::new()),
+                                                    false,
+                                                ),
+                                                Type::SArray(
+                                                    Box::new(Type::Class(class, Vec::new())),
+                                                    variants.len(),
+                                                ),
+                                            ));
+                                        }
+                                        _ => (),
+                                    }
+                                }
                                 return Err(TypeError::NotFound(lpath(b)));
                             }
 
-                            return Ok((Term::Variant(class, *b, Vec::new()), Type::Class(class)));
+                            return Ok((
+                                Term::Variant(class, *b, Vec::new()),
+                                Type::Class(class, Vec::new()),
+                            ));
                         } else if let Some(ty) = self.module(*a.stem()) {
                             let (_, s, t) = ty
                                 .vars
@@ -1187,12 +2428,21 @@ impl<'b> Cxt<'b> {
                     // Default to i32
                     None => Ok((Term::Lit(*l, Type::I32), Type::I32)),
                 },
+                Literal::Float(_) => match t {
+                    Some(t) => {
+                        let t = self.elab_type(t)?;
+                        Ok((Term::Lit(*l, t.clone()), t))
+                    }
+                    // Default to f64
+                    None => Ok((Term::Lit(*l, Type::F64), Type::F64)),
+                },
                 Literal::Str(_) => Ok((Term::Lit(*l, Type::Str), Type::Str)),
                 Literal::Bool(_) => Ok((Term::Lit(*l, Type::Bool), Type::Bool)),
+                Literal::Char(_) => Ok((Term::Lit(*l, Type::Char), Type::Char)),
             },
             // These default to (), but can be coerced to any type - see check()
-            Pre::Break => Ok((Term::Break, Type::Unit)),
-            Pre::Continue => Ok((Term::Continue, Type::Unit)),
+            Pre::Break(l) => Ok((Term::Break(*l), Type::Unit)),
+            Pre::Continue(l) => Ok((Term::Continue(*l), Type::Unit)),
             Pre::Return(x) => {
                 let rty = self.ret_ty();
                 if x.is_none() && rty != Type::Unit {
@@ -1205,6 +2455,7 @@ impl<'b> Cxt<'b> {
                     .map(Box::new);
                 Ok((Term::Return(x), Type::Unit))
             }
+            Pre::Range(_, _, _) => Err(TypeError::RangeOutsideFor(pre.span)),
             Pre::Tuple(v) => {
                 let mut terms = Vec::new();
                 let mut tys = Vec::new();
@@ -1217,18 +2468,17 @@ impl<'b> Cxt<'b> {
             }
             Pre::TupleIdx(px, i) => {
                 let (x, t) = self.infer(px)?;
-                let t = match t {
-                    Type::Tuple(mut v) => {
-                        if *i < v.len() {
-                            v.swap_remove(*i)
-                        } else {
-                            return Err(TypeError::TupleOutOfBounds(pre.span, Type::Tuple(v), *i));
-                        }
+                let v = match t {
+                    Type::Tuple(v) if *i < v.len() => v,
+                    Type::Tuple(v) => {
+                        return Err(TypeError::TupleOutOfBounds(pre.span, Type::Tuple(v), *i))
                     }
                     t => return Err(TypeError::NoMembers(px.span, t)),
                 };
-                Ok((Term::TupleIdx(Box::new(x), *i), t))
+                let t = v[*i].clone();
+                Ok((Term::TupleIdx(Box::new(x), *i, v), t))
             }
+            Pre::ClassLit(f, a) => self.class_lit(pre.span, f, a, Vec::new()),
             Pre::Array(v) => {
                 let mut ty = None;
                 let mut v2 = Vec::new();
@@ -1257,6 +2507,13 @@ impl<'b> Cxt<'b> {
                 let (ty, sta) = match aty {
                     Type::Array(t) => (*t, false),
                     Type::SArray(t, _) => (*t, true),
+                    Type::Str => {
+                        let idx = self.check(idx, Type::I32)?;
+                        return Ok((
+                            Term::StrMethod(Box::new(arr), StrMethod::CharAt(Box::new(idx))),
+                            Type::Char,
+                        ));
+                    }
                     t => return Err(TypeError::NotArray(parr.span, t)),
                 };
                 let idx = self.check(idx, Type::I32)?;
@@ -1265,14 +2522,33 @@ impl<'b> Cxt<'b> {
                     ty,
                 ))
             }
+            Pre::ArraySlice(parr, a, b) => {
+                let (arr, aty) = self.infer(parr)?;
+                if !matches!(aty, Type::Array(_)) {
+                    return Err(TypeError::NotArray(parr.span, aty));
+                }
+                let a = self.check(a, Type::I32)?;
+                let b = self.check(b, Type::I32)?;
+                Ok((
+                    Term::ArraySlice(Box::new(arr), Box::new(a), Box::new(b)),
+                    aty,
+                ))
+            }
             Pre::ArrayNew(_) => Err(TypeError::TypeNeeded(pre.span)),
+            Pre::ArrayRepeat(x, l) => {
+                let (x, t) = self.infer(x)?;
+                let l = self.check(l, Type::I32)?;
+                Ok((
+                    Term::ArrayRepeat(Box::new(x), Box::new(l)),
+                    Type::Array(Box::new(t)),
+                ))
+            }
             Pre::Member(px, m) => {
                 let (x, t) = self.infer(px)?;
                 match t {
-                    Type::Class(t) => {
-                        let info = self.class_info(t);
-                        if let Some((_, s, t)) = info.members.iter().find(|(s, _, _)| *s == **m) {
-                            Ok((Term::Member(Box::new(x), *s), t.clone()))
+                    Type::Class(_, _) => {
+                        if let Some((s, t)) = self.member(&t, **m) {
+                            Ok((Term::Member(Box::new(x), s), t))
                         } else {
                             Err(TypeError::NotFound(lpath(*m)))
                         }
@@ -1283,6 +2559,12 @@ impl<'b> Cxt<'b> {
             Pre::Set(pl, op, x) => {
                 let (l, t) = self.infer(pl)?;
                 let l = l.to_lval().ok_or(TypeError::NotLValue(pl.span))?;
+                if matches!(&l, LValue::Var(s) if self.captured.contains(s)) {
+                    return Err(TypeError::AssignCaptured(pl.span));
+                }
+                if self.assigns_final(&l) {
+                    return Err(TypeError::AssignFinal(pl.span));
+                }
                 let x = self.check(x, t)?;
 
                 Ok((Term::Set(l, *op, Box::new(x)), Type::Unit))
@@ -1290,34 +2572,89 @@ impl<'b> Cxt<'b> {
             Pre::Call(f, a) => {
                 if let Some((fid, FnType(atys, rty))) = self.fun(f) {
                     let rty = rty.clone();
-                    if a.len() != atys.len() {
-                        return Err(TypeError::WrongArity(pre.span, a.len(), atys.len()));
-                    }
-                    let mut a2 = Vec::new();
-                    for (a, t) in a.iter().zip(atys.clone()) {
-                        a2.push(self.check(a, t)?);
-                    }
-                    Ok((Term::Call(None, fid, a2), rty))
+                    let mut map = Vec::new();
+                    let a2 = match self.bindings.varargs(fid) {
+                        Some(spread) => {
+                            self.check_varargs(pre.span, a, atys.clone(), spread, &mut map)?
+                        }
+                        None => {
+                            self.check_arity(pre.span, fid, a.len(), atys.len())?;
+                            self.check_args(a, atys.clone(), &mut map)?
+                        }
+                    };
+                    self.instantiate(pre.span, Term::Call(None, fid, a2), &rty, &map)
                 } else if let Some(t) = self.class(f) {
                     let info = self.class_info(t);
-                    if let Some(atys) = &info.constructor {
+                    if let Some(atys) = info.constructor.clone() {
                         if a.len() != atys.len() {
                             return Err(TypeError::WrongArity(pre.span, a.len(), atys.len()));
                         }
-                        let mut a2 = Vec::new();
-                        for (a, t) in a.iter().zip(atys.clone()) {
-                            a2.push(self.check(a, t)?);
-                        }
-                        Ok((Term::Constructor(t, a2), Type::Class(t)))
+                        let mut map = Vec::new();
+                        let a2 = self.check_args(a, atys, &mut map)?;
+                        // A generic class's type arguments need to be inferred from the constructor arguments here,
+                        // `check()` handles the case where they're known from the expected type
+                        let targs = self
+                            .bindings
+                            .type_params(t)
+                            .iter()
+                            .map(|r| match map.iter().find(|(r2, _)| r2 == r) {
+                                Some((_, t)) => Ok(t.clone()),
+                                None => Err(TypeError::TypeNeeded(pre.span)),
+                            })
+                            .collect::<Result<_, _>>()?;
+                        Ok((Term::Constructor(t, a2), Type::Class(t, targs)))
                     } else {
                         Err(TypeError::NotFound(f.clone()))
                     }
+                } else if self.is_prelude(f, "Some") {
+                    if a.len() != 1 {
+                        return Err(TypeError::WrongArity(pre.span, a.len(), 1));
+                    }
+                    let (x, t) = self.infer(&a[0])?;
+                    Ok((Term::OptionSome(Box::new(x)), Type::Option(Box::new(t))))
+                } else if self.is_prelude(f, "parseInt") || self.is_prelude(f, "parseLong") {
+                    if a.len() != 1 {
+                        return Err(TypeError::WrongArity(pre.span, a.len(), 1));
+                    }
+                    let (b, t) = if self.is_prelude(f, "parseInt") {
+                        (Builtin::ParseInt, Type::I32)
+                    } else {
+                        (Builtin::ParseLong, Type::I64)
+                    };
+                    let x = self.check(&a[0], Type::Str)?;
+                    Ok((Term::Builtin(b, Box::new(x)), t))
+                } else if self.is_prelude(f, "str") {
+                    if a.len() != 1 {
+                        return Err(TypeError::WrongArity(pre.span, a.len(), 1));
+                    }
+                    let (x, t) = self.infer(&a[0])?;
+                    if !t.is_numeric() && t != Type::Bool {
+                        return Err(TypeError::Unify(a[0].span, t, Type::I32));
+                    }
+                    Ok((Term::Builtin(Builtin::ToStr, Box::new(x)), Type::Str))
                 } else if f.len() > 1 {
                     let mut v = f.0.clone();
                     let last = v.pop().unwrap();
                     let c = RawPath(v, last);
                     let b = f.1;
                     if let Some(class) = self.class(&c) {
+                        let info = self.class_info(class);
+                        if let Some((_, fid, FnType(atys, rty))) =
+                            info.methods.iter().find(|(r, fid, _)| {
+                                *r == *b && self.bindings.static_class(*fid) == Some(class)
+                            })
+                        {
+                            let (fid, atys, rty) = (*fid, atys.clone(), rty.clone());
+                            self.check_arity(pre.span, fid, a.len(), atys.len())?;
+                            let mut map = Vec::new();
+                            let a2 = self.check_args(a, atys, &mut map)?;
+                            return self.instantiate(
+                                pre.span,
+                                Term::Call(None, fid, a2),
+                                &rty,
+                                &map,
+                            );
+                        }
                         let variants = self.class_info(class).variants.as_ref();
                         let atys = match variants.iter().flat_map(|x| *x).find(|(x, _)| *x == *b) {
                             Some((_, a)) => a,
@@ -1332,7 +2669,7 @@ impl<'b> Cxt<'b> {
                             a2.push(self.check(a, t)?);
                         }
 
-                        return Ok((Term::Variant(class, *b, a2), Type::Class(class)));
+                        return Ok((Term::Variant(class, *b, a2), Type::Class(class, Vec::new())));
                     } else {
                         Err(TypeError::NotFound(f.clone()))
                     }
@@ -1342,24 +2679,46 @@ impl<'b> Cxt<'b> {
             }
             Pre::Method(o_, f, a) => {
                 let (o, t) = self.infer(o_)?;
+                let resizes = matches!(t, Type::Array(_))
+                    && matches!(
+                        self.bindings.resolve_raw(**f),
+                        "pop"
+                            | "clear"
+                            | "push"
+                            | "insert"
+                            | "remove_at"
+                            | "swap_remove"
+                            | "reserve"
+                    );
+                if resizes && matches!(&o, Term::Var(s) if self.captured.contains(s)) {
+                    return Err(TypeError::AssignCaptured(o_.span));
+                }
+                if resizes && matches!(&o, Term::Var(s) if self.bindings.final_(*s)) {
+                    return Err(TypeError::AssignFinal(o_.span));
+                }
+                if resizes && matches!(&o, Term::Var(s) if self.array_params.contains(s)) {
+                    Spanned::new(
+                        Doc::start("Resizing an array parameter doesn't change the caller's array, return it instead if the caller needs it"),
+                        o_.span,
+                    )
+                    .emit(Severity::Warning, self.file_id);
+                }
                 match t {
-                    Type::Class(c) => {
-                        let methods = &self.class_info(c).methods;
-                        let (_, fid, FnType(atys, rty)) = methods
-                            .iter()
-                            .find(|(s, _, _)| *s == **f)
-                            .ok_or(TypeError::NotFound(lpath(*f)))?;
-                        let fid = *fid;
-
-                        let rty = rty.clone();
-                        if a.len() != atys.len() {
-                            return Err(TypeError::WrongArity(pre.span, a.len(), atys.len()));
-                        }
-                        let mut a2 = Vec::new();
-                        for (a, t) in a.iter().zip(atys.clone()) {
-                            a2.push(self.check(a, t)?);
+                    Type::Class(_, _) => {
+                        let (fid, FnType(atys, rty), mut map) =
+                            self.method(&t, **f).ok_or(TypeError::NotFound(lpath(*f)))?;
+                        if self.bindings.static_class(fid).is_some() {
+                            return Err(TypeError::StaticMethod(pre.span, **f));
                         }
-                        Ok((Term::Call(Some(Box::new(o)), fid, a2), rty))
+
+                        self.check_arity(pre.span, fid, a.len(), atys.len())?;
+                        let a2 = self.check_args(a, atys.clone(), &mut map)?;
+                        self.instantiate(
+                            pre.span,
+                            Term::Call(Some(Box::new(o)), fid, a2),
+                            &rty,
+                            &map,
+                        )
                     }
                     Type::SArray(_, l) => match self.bindings.resolve_raw(**f) {
                         "len" => {
@@ -1370,6 +2729,21 @@ impl<'b> Cxt<'b> {
                         }
                         _ => return Err(TypeError::NotFound(lpath(*f))),
                     },
+                    Type::Array(_)
+                        if !o.is_lval()
+                            && matches!(
+                                self.bindings.resolve_raw(**f),
+                                "pop"
+                                    | "clear"
+                                    | "push"
+                                    | "insert"
+                                    | "remove_at"
+                                    | "swap_remove"
+                                    | "reserve"
+                            ) =>
+                    {
+                        Err(TypeError::MethodNotLValue(o_.span, **f))
+                    }
                     Type::Array(t) => match self.bindings.resolve_raw(**f) {
                         "len" => {
                             if a.len() != 0 {
@@ -1393,34 +2767,216 @@ impl<'b> Cxt<'b> {
                             if a.len() != 1 {
                                 return Err(TypeError::WrongArity(pre.span, a.len(), 1));
                             }
-                            let x = self.check(&a[0], *t)?;
+                            let x = self.check_elem(&a[0], *t)?;
                             Ok((
                                 Term::ArrayMethod(Box::new(o), ArrayMethod::Push(Box::new(x))),
                                 Type::Unit,
                             ))
                         }
+                        "insert" => {
+                            if a.len() != 2 {
+                                return Err(TypeError::WrongArity(pre.span, a.len(), 2));
+                            }
+                            let i = self.check(&a[0], Type::I32)?;
+                            let x = self.check_elem(&a[1], *t)?;
+                            Ok((
+                                Term::ArrayMethod(
+                                    Box::new(o),
+                                    ArrayMethod::Insert(Box::new(i), Box::new(x)),
+                                ),
+                                Type::Unit,
+                            ))
+                        }
+                        "remove_at" => {
+                            if a.len() != 1 {
+                                return Err(TypeError::WrongArity(pre.span, a.len(), 1));
+                            }
+                            let i = self.check(&a[0], Type::I32)?;
+                            Ok((
+                                Term::ArrayMethod(Box::new(o), ArrayMethod::RemoveAt(Box::new(i))),
+                                *t,
+                            ))
+                        }
+                        "swap_remove" => {
+                            if a.len() != 1 {
+                                return Err(TypeError::WrongArity(pre.span, a.len(), 1));
+                            }
+                            let i = self.check(&a[0], Type::I32)?;
+                            Ok((
+                                Term::ArrayMethod(
+                                    Box::new(o),
+                                    ArrayMethod::SwapRemove(Box::new(i)),
+                                ),
+                                *t,
+                            ))
+                        }
+                        "reserve" => {
+                            if a.len() != 1 {
+                                return Err(TypeError::WrongArity(pre.span, a.len(), 1));
+                            }
+                            let n = self.check(&a[0], Type::I32)?;
+                            Ok((
+                                Term::ArrayMethod(Box::new(o), ArrayMethod::Reserve(Box::new(n))),
+                                Type::Unit,
+                            ))
+                        }
+                        "index_of" => {
+                            if a.len() != 1 {
+                                return Err(TypeError::WrongArity(pre.span, a.len(), 1));
+                            }
+                            let x = self.check_elem(&a[0], *t)?;
+                            Ok((
+                                Term::ArrayMethod(Box::new(o), ArrayMethod::IndexOf(Box::new(x))),
+                                Type::I32,
+                            ))
+                        }
+                        "contains" => {
+                            if a.len() != 1 {
+                                return Err(TypeError::WrongArity(pre.span, a.len(), 1));
+                            }
+                            let x = self.check_elem(&a[0], *t)?;
+                            Ok((
+                                Term::ArrayMethod(Box::new(o), ArrayMethod::Contains(Box::new(x))),
+                                Type::Bool,
+                            ))
+                        }
                         _ => return Err(TypeError::NotFound(lpath(*f))),
                     },
+                    Type::Str => match self.bindings.resolve_raw(**f) {
+                        "code_point_at" => {
+                            if a.len() != 1 {
+                                return Err(TypeError::WrongArity(pre.span, a.len(), 1));
+                            }
+                            let i = self.check(&a[0], Type::I32)?;
+                            Ok((
+                                Term::StrMethod(Box::new(o), StrMethod::CodePointAt(Box::new(i))),
+                                Type::I32,
+                            ))
+                        }
+                        "len" => {
+                            if !a.is_empty() {
+                                return Err(TypeError::WrongArity(pre.span, a.len(), 0));
+                            }
+                            Ok((Term::StrMethod(Box::new(o), StrMethod::Len), Type::I32))
+                        }
+                        "substring" => {
+                            if a.len() != 2 {
+                                return Err(TypeError::WrongArity(pre.span, a.len(), 2));
+                            }
+                            let start = self.check(&a[0], Type::I32)?;
+                            let end = self.check(&a[1], Type::I32)?;
+                            Ok((
+                                Term::StrMethod(
+                                    Box::new(o),
+                                    StrMethod::Substring(Box::new(start), Box::new(end)),
+                                ),
+                                Type::Str,
+                            ))
+                        }
+                        m @ ("starts_with" | "ends_with" | "contains") => {
+                            if a.len() != 1 {
+                                return Err(TypeError::WrongArity(pre.span, a.len(), 1));
+                            }
+                            let m: fn(Box<Term>) -> StrMethod = match m {
+                                "starts_with" => StrMethod::StartsWith,
+                                "ends_with" => StrMethod::EndsWith,
+                                _ => StrMethod::Contains,
+                            };
+                            let x = self.check(&a[0], Type::Str)?;
+                            Ok((Term::StrMethod(Box::new(o), m(Box::new(x))), Type::Bool))
+                        }
+                        _ => Err(TypeError::NotFound(lpath(*f))),
+                    },
+                    Type::StrBuilder => match self.bindings.resolve_raw(**f) {
+                        "append" => {
+                            if a.len() != 1 {
+                                return Err(TypeError::WrongArity(pre.span, a.len(), 1));
+                            }
+                            let (x, t) = self.infer(&a[0])?;
+                            if !t.is_numeric() && t != Type::Bool && t != Type::Str {
+                                return Err(TypeError::Unify(a[0].span, t, Type::Str));
+                            }
+                            Ok((
+                                Term::BuilderMethod(
+                                    Box::new(o),
+                                    BuilderMethod::Append(Box::new(x)),
+                                ),
+                                Type::Unit,
+                            ))
+                        }
+                        "finish" => {
+                            if !a.is_empty() {
+                                return Err(TypeError::WrongArity(pre.span, a.len(), 0));
+                            }
+                            Ok((
+                                Term::BuilderMethod(Box::new(o), BuilderMethod::Finish),
+                                Type::Str,
+                            ))
+                        }
+                        _ => Err(TypeError::NotFound(lpath(*f))),
+                    },
+                    Type::Map(kt, vt) => {
+                        let arity = match self.bindings.resolve_raw(**f) {
+                            "get" | "containsKey" | "remove" => 1,
+                            "put" => 2,
+                            _ => return Err(TypeError::NotFound(lpath(*f))),
+                        };
+                        if a.len() != arity {
+                            return Err(TypeError::WrongArity(pre.span, a.len(), arity));
+                        }
+                        let k = Box::new(self.check(&a[0], (*kt).clone())?);
+                        let o = Box::new(o);
+                        Ok(match self.bindings.resolve_raw(**f) {
+                            "get" => (Term::MapMethod(o, MapMethod::Get(k)), *vt),
+                            "containsKey" => {
+                                (Term::MapMethod(o, MapMethod::ContainsKey(k)), Type::Bool)
+                            }
+                            "remove" => (Term::MapMethod(o, MapMethod::Remove(k)), Type::Unit),
+                            _ => {
+                                let v = Box::new(self.check(&a[1], *vt)?);
+                                (Term::MapMethod(o, MapMethod::Put(k, v)), Type::Unit)
+                            }
+                        })
+                    }
                     t => return Err(TypeError::NoMethods(o_.span, t)),
                 }
             }
             Pre::BinOp(op, a, b) => {
-                let (a, bt, rt) = match op.ty() {
+                let (a, bt, mut rt) = match op.ty() {
                     BinOpType::Comp => {
                         let (a, t) = self.infer(a)?;
                         (a, t, Type::Bool)
                     }
-                    BinOpType::Arith => {
-                        let (a, t) = self.infer(a)?;
-                        (a, t.clone(), t)
-                    }
+                    BinOpType::Arith => match self.infer(a)? {
+                        // Like Java, arithmetic on chars is done on ints
+                        (a, Type::Char) => (a, Type::I32, Type::I32),
+                        (a, t) => (a, t.clone(), t),
+                    },
                     BinOpType::Logic => (self.check(a, Type::Bool)?, Type::Bool, Type::Bool),
                 };
                 let b = if rt == Type::Str && op.ty() == BinOpType::Arith {
                     let (b, _) = self.infer(b)?;
                     b
+                } else if op.is_shift() {
+                    self.shift_amount(b)?
                 } else {
-                    self.check(b, bt)?
+                    let b2 = if op.ty() == BinOpType::Arith {
+                        self.check_promoted(b, bt)
+                    } else {
+                        self.check(b, bt)
+                    };
+                    match b2 {
+                        Ok(b) => b,
+                        // Adding a string on the right of a number concatenates them too, like Java
+                        Err(TypeError::Unify(span, Type::Str, _))
+                            if *op == BinOp::Add && span == b.span =>
+                        {
+                            rt = Type::Str;
+                            let (b, _) = self.infer(b)?;
+                            b
+                        }
+                        Err(e) => return Err(e),
+                    }
                 };
                 Ok((Term::BinOp(*op, Box::new(a), Box::new(b)), rt))
             }
@@ -1457,113 +3013,458 @@ impl<'b> Cxt<'b> {
 
                 Ok((Term::If(Box::new(cond), Box::new(yes), no), ty))
             }
-            Pre::Match(x, branches) => {
-                let xspan = x.span;
-                let (x, xty) = self.infer(x)?;
-
-                let (tid, variants) = match &xty {
-                    Type::Class(tid) => (
-                        *tid,
-                        self.class_info(*tid)
-                            .variants
-                            .as_ref()
-                            .ok_or(TypeError::NoVariants(xspan, xty))?,
-                    ),
-                    _ => return Err(TypeError::NoVariants(xspan, xty)),
+            Pre::Match(x, branches) => self.elab_match(x, branches, None),
+            Pre::Try(px) => {
+                let (x, t) = self.infer(px)?;
+                if let Some((tid, ok_ty, err_tys)) = self.result_variants(&t) {
+                    return self.elab_try_result(pre.span, x, tid, ok_ty, err_tys);
+                }
+                // What to return if `x` is missing: `None` or `null` depending on the return type
+                let ret = match self.ret_ty() {
+                    Type::Option(rty) => Term::OptionNone(*rty),
+                    rty @ (Type::Class(_, _) | Type::Str) => Term::Null(rty),
+                    rty => return Err(TypeError::TryReturn(pre.span, rty)),
                 };
-                let mut covered: Vec<_> = variants.iter().map(|x| (x.clone(), false)).collect();
-
-                let mut v = Vec::new();
-                let mut rty = None;
-                let mut had_default = false;
-                for (s, captures, body) in branches {
-                    let mut captures2 = Vec::new();
-                    if let Some(s2) = **s {
-                        let ((_, ref atys), b) = covered
-                            .iter_mut()
-                            .find(|((x, _), _)| *x == s2)
-                            .ok_or(TypeError::NotFound(lpath(Spanned::new(s2, s.span))))?;
-                        if *b {
-                            Spanned::new(
-                                Doc::start("Duplicate branch for pattern ")
-                                    .add(self.bindings.resolve_raw(s2)),
-                                s.span,
-                            )
-                            .emit(Severity::Warning, self.file_id);
-                        } else {
-                            if atys.len() != captures.len() {
-                                return Err(TypeError::WrongArity(
-                                    s.span,
-                                    captures.len(),
-                                    atys.len(),
-                                ));
-                            } else {
-                                for (&(raw, public), ty) in captures.iter().zip(atys) {
-                                    let s = self.create(raw, ty.clone(), public);
-                                    captures2.push((s, ty.clone()));
-                                }
-                            }
-                            *b = true;
-                        }
+                let ret = Term::Return(Some(Box::new(ret)));
+
+                let raw = self.bindings.raw("$_try");
+                match t {
+                    Type::Option(t) => {
+                        // x? is `match x { Some(x) => x, None => return None }`
+                        let s = self.create(Spanned::new(raw, pre.span), (*t).clone(), false);
+                        let branches = vec![
+                            (
+                                Some(self.bindings.raw("Some")),
+                                vec![(s, (*t).clone())],
+                                Term::Var(s),
+                            ),
+                            (Some(self.bindings.raw("None")), Vec::new(), ret),
+                        ];
+                        Ok((Term::MatchOption(Box::new(x), branches), *t))
+                    }
+                    Type::Class(_, _) | Type::Str => {
+                        // x? is `{ let x = x; if x != null { x } else { return null } }`
+                        let s = self.create(Spanned::new(raw, pre.span), t.clone(), false);
+                        let cond = Term::BinOp(
+                            BinOp::Neq,
+                            Box::new(Term::Var(s)),
+                            Box::new(Term::Null(t.clone())),
+                        );
+                        let term = Term::Block(
+                            vec![Statement::Let(s, t.clone(), x)],
+                            Some(Box::new(Term::If(
+                                Box::new(cond),
+                                Box::new(Term::Var(s)),
+                                Some(Box::new(ret)),
+                            ))),
+                        );
+                        Ok((term, t))
+                    }
+                    t => Err(TypeError::NotTryable(px.span, t)),
+                }
+            }
+        }
+    }
+
+    /// If `t` is an enum with an `Ok` variant with one member and an `Err` variant, like a Rust `Result`,
+    /// returns the enum and the types of the members of `Ok` and `Err`
+    fn result_variants(&mut self, t: &Type) -> Option<(TypeId, Type, Vec<Type>)> {
+        let tid = match t {
+            Type::Class(tid, _) => *tid,
+            _ => return None,
+        };
+        let (ok, err) = (self.bindings.raw("Ok"), self.bindings.raw("Err"));
+        let variants = self.class_info(tid).variants.as_ref()?;
+        if variants.len() != 2 {
+            return None;
+        }
+        let ok_ty = variants
+            .iter()
+            .find(|(s, tys)| *s == ok && tys.len() == 1)
+            .map(|(_, tys)| tys[0].clone())?;
+        let err_tys = variants.iter().find(|(s, _)| *s == err)?.1.clone();
+        Some((tid, ok_ty, err_tys))
+    }
+
+    /// `x?` on a `Result`-like enum is `match x { Ok(x) => x, Err(e) => return Err(e) }`,
+    /// so the function has to return the same enum
+    fn elab_try_result(
+        &mut self,
+        span: Span,
+        x: Term,
+        tid: TypeId,
+        ok_ty: Type,
+        err_tys: Vec<Type>,
+    ) -> Result<(Term, Type), TypeError> {
+        let rty = self.ret_ty();
+        if !matches!(rty, Type::Class(t, _) if t == tid) {
+            return Err(TypeError::TryReturn(span, rty));
+        }
+
+        let raw = self.bindings.raw("$_try");
+        let ok = self.create(Spanned::new(raw, span), ok_ty.clone(), false);
+        let errs: Vec<_> = err_tys
+            .into_iter()
+            .map(|t| (self.create(Spanned::new(raw, span), t.clone(), false), t))
+            .collect();
+        let err = self.bindings.raw("Err");
+        let ret = Term::Variant(tid, err, errs.iter().map(|(s, _)| Term::Var(*s)).collect());
+        let branches = vec![
+            (
+                Some(self.bindings.raw("Ok")),
+                vec![(ok, ok_ty.clone())],
+                Term::Var(ok),
+            ),
+            (Some(err), errs, Term::Return(Some(Box::new(ret)))),
+        ];
+        Ok((Term::Match(tid, Box::new(x), branches), ok_ty))
+    }
+
+    /// Elaborates a match, checking the branches against `rty` if it's given
+    fn elab_match(
+        &mut self,
+        x: &SPre,
+        branches: &[PreMatchBranch],
+        rty: Option<Type>,
+    ) -> Result<(Term, Type), TypeError> {
+        let xspan = x.span;
+        let (x, xty) = self.infer(x)?;
+        if xty == Type::I32 {
+            return self.elab_match_int(xspan, x, branches, rty);
+        }
+
+        let (tid, variants) = match &xty {
+            Type::Class(tid, _) => (
+                Some(*tid),
+                self.class_info(*tid)
+                    .variants
+                    .clone()
+                    .ok_or_else(|| TypeError::NoVariants(xspan, xty.clone()))?,
+            ),
+            Type::Option(t) => (
+                None,
+                vec![
+                    (self.bindings.raw("Some"), vec![(**t).clone()]),
+                    (self.bindings.raw("None"), Vec::new()),
+                ],
+            ),
+            _ => return Err(TypeError::NoVariants(xspan, xty)),
+        };
+        let mut covered: Vec<_> = variants.into_iter().map(|x| (x, false)).collect();
+
+        let mut v = Vec::new();
+        let mut rty = rty;
+        let mut had_default = false;
+        for (s, captures, body) in branches {
+            let mut captures2 = Vec::new();
+            if had_default {
+                Spanned::new(
+                    Doc::start("Unreachable match branch, it comes after the default branch"),
+                    s.span,
+                )
+                .emit(Severity::Warning, self.file_id);
+                continue;
+            }
+            if let PrePattern::Int(_) = **s {
+                return Err(TypeError::WrongPattern(s.span, xty.clone()));
+            }
+            if let PrePattern::Variant(s2) = **s {
+                let ((_, ref atys), b) = covered
+                    .iter_mut()
+                    .find(|((x, _), _)| *x == s2)
+                    .ok_or(TypeError::NotFound(lpath(Spanned::new(s2, s.span))))?;
+                if *b {
+                    Spanned::new(
+                        Doc::start("Duplicate branch for pattern ")
+                            .add(self.bindings.resolve_raw(s2))
+                            .add(", this one is unreachable"),
+                        s.span,
+                    )
+                    .emit(Severity::Warning, self.file_id);
+                    continue;
+                } else {
+                    // A variant without captures matches without binding its members
+                    if !captures.is_empty() && atys.len() != captures.len() {
+                        return Err(TypeError::WrongArity(s.span, captures.len(), atys.len()));
                     } else {
-                        if !captures.is_empty() {
-                            return Err(TypeError::WrongArity(s.span, captures.len(), 0));
-                        }
-                        if had_default {
-                            Spanned::new(Doc::start("Duplicate default branch in pattern match, this one is unreachable"),
-                                s.span,
-                            ).emit(Severity::Warning, self.file_id);
-                            continue;
-                        } else {
-                            had_default = true;
+                        for (&(raw, public), ty) in captures.iter().zip(atys) {
+                            let s = self.create(raw, ty.clone(), public);
+                            captures2.push((s, ty.clone()));
                         }
                     }
+                    *b = true;
+                }
+            } else {
+                if !captures.is_empty() {
+                    return Err(TypeError::WrongArity(s.span, captures.len(), 0));
+                }
+                if rty.is_some() && covered.iter().all(|(_, b)| *b) {
+                    Spanned::new(
+                        Doc::start("Unreachable default branch, all variants are already covered"),
+                        s.span,
+                    )
+                    .emit(Severity::Warning, self.file_id);
+                    continue;
+                }
+                had_default = true;
+            }
 
-                    let body = match &rty {
-                        None => {
-                            let (body, ty) = self.infer(body)?;
-                            rty = Some(ty);
-                            body
-                        }
-                        Some(rty) => self.check(body, rty.clone())?,
-                    };
+            let body = match &rty {
+                None => {
+                    let (body, ty) = self.infer(body)?;
+                    rty = Some(ty);
+                    body
+                }
+                Some(rty) => self.check(body, rty.clone())?,
+            };
+
+            let variant = match **s {
+                PrePattern::Variant(s) => Some(s),
+                _ => None,
+            };
+            v.push((variant, captures2, body));
+        }
 
-                    v.push((**s, captures2, body));
+        if !had_default {
+            let mut missing = Vec::new();
+            for ((s, _), b) in covered {
+                if !b {
+                    missing.push(s);
                 }
+            }
+            if !missing.is_empty() {
+                return Err(TypeError::MissingPattern(xspan, missing));
+            }
+        }
 
-                if !had_default {
-                    let mut missing = Vec::new();
-                    for ((s, _), b) in covered {
-                        if !b {
-                            missing.push(s);
-                        }
-                    }
-                    if !missing.is_empty() {
-                        return Err(TypeError::MissingPattern(xspan, missing));
+        let term = match tid {
+            Some(tid) => Term::Match(tid, Box::new(x), v),
+            None => Term::MatchOption(Box::new(x), v),
+        };
+        Ok((term, rty.unwrap()))
+    }
+
+    /// Elaborates a match on an `i32`, where the patterns are integer literals and there must be a default branch
+    fn elab_match_int(
+        &mut self,
+        xspan: Span,
+        x: Term,
+        branches: &[PreMatchBranch],
+        rty: Option<Type>,
+    ) -> Result<(Term, Type), TypeError> {
+        let mut v = Vec::new();
+        let mut rty = rty;
+        let mut covered = Vec::new();
+        let mut had_default = false;
+        for (s, _, body) in branches {
+            if had_default {
+                Spanned::new(
+                    Doc::start("Unreachable match branch, it comes after the default branch"),
+                    s.span,
+                )
+                .emit(Severity::Warning, self.file_id);
+                continue;
+            }
+            let i = match **s {
+                PrePattern::Int(i) => {
+                    let i =
+                        i32::try_from(i).map_err(|_| TypeError::WrongPattern(s.span, Type::I32))?;
+                    if covered.contains(&i) {
+                        Spanned::new(
+                            Doc::start("Duplicate branch for pattern ")
+                                .add(i)
+                                .add(", this one is unreachable"),
+                            s.span,
+                        )
+                        .emit(Severity::Warning, self.file_id);
+                        continue;
                     }
+                    covered.push(i);
+                    Some(i)
+                }
+                PrePattern::Variant(_) => return Err(TypeError::WrongPattern(s.span, Type::I32)),
+                PrePattern::Default => {
+                    had_default = true;
+                    None
                 }
+            };
 
-                Ok((Term::Match(tid, Box::new(x), v), rty.unwrap()))
+            let body = match &rty {
+                None => {
+                    let (body, ty) = self.infer(body)?;
+                    rty = Some(ty);
+                    body
+                }
+                Some(rty) => self.check(body, rty.clone())?,
+            };
+
+            v.push((i, body));
+        }
+
+        if !had_default {
+            return Err(TypeError::MissingDefault(xspan));
+        }
+        Ok((Term::MatchInt(Box::new(x), v), rty.unwrap()))
+    }
+
+    /// The right operand of a shift can be any integer type, independent of the left operand.
+    fn shift_amount(&mut self, pre: &SPre) -> Result<Term, TypeError> {
+        let (x, t) = self.infer(pre)?;
+        match t {
+            Type::I8 | Type::I16 | Type::I32 | Type::I64 => Ok(x),
+            t => Err(TypeError::Unify(pre.span, t, Type::I32)),
+        }
+    }
+
+    /// Checks an operand of an arithmetic operator, where a `char` can be used as an `i32` like in Java
+    fn check_promoted(&mut self, pre: &SPre, ty: Type) -> Result<Term, TypeError> {
+        match self.check(pre, ty) {
+            Err(TypeError::Unify(span, Type::Char, Type::I32)) if span == pre.span => {
+                Ok(self.infer(pre)?.0)
             }
+            r => r,
         }
     }
 
+    /// Checks a value that will be split into the components of a dynamic array, which each need a value
+    fn check_elem(&mut self, pre: &SPre, ty: Type) -> Result<Term, TypeError> {
+        let x = self.check(pre, ty)?;
+        if x.diverges() {
+            return Err(TypeError::DivergingElement(pre.span));
+        }
+        Ok(x)
+    }
+
     fn check(&mut self, pre: &SPre, ty: Type) -> Result<Term, TypeError> {
         match (&***pre, &ty) {
-            (Pre::BinOp(op, a, b), _) if op.ty() == BinOpType::Arith => {
-                let a = self.check(a, ty.clone())?;
-                let b = if ty == Type::Str {
-                    let (b, _) = self.infer(b)?;
-                    b
+            // Concatenation can have a string on either side, so it needs to be inferred
+            (Pre::BinOp(op, a, b), _) if op.ty() == BinOpType::Arith && ty != Type::Str => {
+                let a = self.check_promoted(a, ty.clone())?;
+                let b = if op.is_shift() {
+                    self.shift_amount(b)?
                 } else {
-                    self.check(b, ty)?
+                    self.check_promoted(b, ty)?
                 };
                 Ok(Term::BinOp(*op, Box::new(a), Box::new(b)))
             }
-            (Pre::Lit(l @ Literal::Int(_), None), Type::I32 | Type::I64) => Ok(Term::Lit(*l, ty)),
+            (Pre::BitNot(x), Type::I8 | Type::I16 | Type::I32 | Type::I64) => {
+                Ok(Term::BitNot(Box::new(self.check(x, ty)?)))
+            }
+            (Pre::Neg(x), Type::I8 | Type::I16 | Type::I32 | Type::I64 | Type::F32 | Type::F64) => {
+                Ok(Term::Neg(Box::new(self.check(x, ty)?)))
+            }
+            (Pre::Lit(l @ Literal::Int(_), None), Type::I8 | Type::I16 | Type::I32 | Type::I64) => {
+                Ok(Term::Lit(*l, ty))
+            }
+            // Int literals can be used as floats, but there's no implicit conversion otherwise
+            (Pre::Lit(Literal::Int(i), None), Type::F32 | Type::F64) => {
+                Ok(Term::Lit(Literal::Float(*i as f64), ty))
+            }
+            (Pre::Lit(l @ Literal::Float(_), None), Type::F32) => Ok(Term::Lit(*l, ty)),
 
             (Pre::Null, _) => Ok(Term::Null(ty)),
 
+            (Pre::Lambda(args, body), _) => {
+                // The expected type has to be a functional interface, which we take to mean an interface or extern class with one method
+                let (atys, rty) = match &ty {
+                    Type::Class(c, targs)
+                        if (self.class_info(*c).ext || self.class_info(*c).interface)
+                            && self.class_info(*c).methods.len() == 1 =>
+                    {
+                        let map = self.type_args(*c, targs);
+                        let FnType(atys, rty) = &self.class_info(*c).methods[0].2;
+                        let atys: Vec<_> = atys.iter().map(|t| t.subst(&map)).collect();
+                        (atys, rty.subst(&map))
+                    }
+                    _ => return Err(TypeError::NotFunctional(pre.span, ty)),
+                };
+                if args.len() != atys.len() {
+                    return Err(TypeError::WrongArity(pre.span, args.len(), atys.len()));
+                }
+
+                for ((n, t), aty) in args.iter().zip(&atys) {
+                    if let Some(t) = t {
+                        let t = self.elab_type(t)?;
+                        if t != *aty {
+                            return Err(TypeError::Unify(n.span, t, aty.clone()));
+                        }
+                    }
+                }
+
+                let start = self.fn_vars.min(self.vars.symbols.len());
+                let mut captured: Vec<_> = self.vars.symbols[start..]
+                    .iter()
+                    .map(|(_, s, _)| *s)
+                    .collect();
+                captured.extend(self.captured.iter().copied());
+                let old_captured = std::mem::replace(&mut self.captured, captured);
+
+                self.push(Some(rty.clone()));
+                let mut args2 = Vec::new();
+                for ((n, _), aty) in args.iter().zip(atys) {
+                    args2.push((self.create(*n, aty.clone(), false), aty));
+                }
+                let body = self.check(body, rty.clone());
+                self.pop();
+                self.captured = old_captured;
+
+                Ok(Term::Lambda(args2, Box::new(body?), rty, ty))
+            }
+
+            (Pre::Block(v, Some(e)), _) => {
+                self.push(None);
+
+                let mut v2 = Vec::new();
+                for i in v {
+                    if let Some(x) = self.check_stmt(i)? {
+                        v2.push(x);
+                    }
+                }
+                let e = self.check(e, ty)?;
+
+                self.pop();
+                Ok(Term::Block(v2, Some(Box::new(e))))
+            }
+            (Pre::If(cond, yes, Some(no)), _) => {
+                let cond = self.check(cond, Type::Bool)?;
+                let yes = self.check(yes, ty.clone())?;
+                let no = self.check(no, ty)?;
+                Ok(Term::If(Box::new(cond), Box::new(yes), Some(Box::new(no))))
+            }
+            (Pre::Match(x, branches), _) => self.elab_match(x, branches, Some(ty)).map(|(x, _)| x),
+
+            (Pre::Var(raw), Type::Option(t))
+                if self.var(raw).is_none() && self.is_prelude(raw, "None") =>
+            {
+                Ok(Term::OptionNone((**t).clone()))
+            }
+            (Pre::Call(f, a), Type::Option(t))
+                if a.len() == 1 && self.fun(f).is_none() && self.is_prelude(f, "Some") =>
+            {
+                let x = self.check(&a[0], (**t).clone())?;
+                Ok(Term::OptionSome(Box::new(x)))
+            }
+
+            (Pre::ClassLit(f, a), Type::Class(c, targs))
+                if !targs.is_empty() && self.class(f) == Some(*c) =>
+            {
+                let map = self.type_args(*c, targs);
+                Ok(self.class_lit(pre.span, f, a, map)?.0)
+            }
+
+            // A generic class's type arguments can come from the expected type, like `let b: Box<str> = Box();`
+            (Pre::Call(f, a), Type::Class(c, targs))
+                if !targs.is_empty() && self.fun(f).is_none() && self.class(f) == Some(*c) =>
+            {
+                let atys = self.class_info(*c).constructor.clone().unwrap_or_default();
+                if a.len() != atys.len() {
+                    return Err(TypeError::WrongArity(pre.span, a.len(), atys.len()));
+                }
+                let mut map = self.type_args(*c, targs);
+                let a2 = self.check_args(a, atys, &mut map)?;
+                Ok(Term::Constructor(*c, a2))
+            }
+
             (Pre::Tuple(a), Type::Tuple(b)) => a
                 .iter()
                 .zip(b)
@@ -1574,7 +3475,7 @@ impl<'b> Cxt<'b> {
             (Pre::Array(v), Type::Array(t)) => {
                 let mut v2 = Vec::new();
                 for i in v {
-                    v2.push(self.check(i, (**t).clone())?);
+                    v2.push(self.check_elem(i, (**t).clone())?);
                 }
                 Ok(Term::Array(v2, (**t).clone(), true))
             }
@@ -1582,11 +3483,16 @@ impl<'b> Cxt<'b> {
                 let l = self.check(l, Type::I32)?;
                 Ok(Term::ArrayNew(Box::new(l), (**t).clone()))
             }
+            (Pre::ArrayRepeat(x, l), Type::Array(t)) => {
+                let x = self.check(x, (**t).clone())?;
+                let l = self.check(l, Type::I32)?;
+                Ok(Term::ArrayRepeat(Box::new(x), Box::new(l)))
+            }
 
             // These technically return the never type `!`, but that's too complicated for bytec
             // Instead, they just coerce to anything they're checked against, but default to ()
-            (Pre::Break, _) => Ok(Term::Break),
-            (Pre::Continue, _) => Ok(Term::Continue),
+            (Pre::Break(l), _) => Ok(Term::Break(*l)),
+            (Pre::Continue(l), _) => Ok(Term::Continue(*l)),
             (Pre::Return(x), _) => {
                 let rty = self.ret_ty();
                 if x.is_none() && rty != Type::Unit {
@@ -1602,7 +3508,7 @@ impl<'b> Cxt<'b> {
 
             _ => {
                 let (term, ity) = self.infer(pre)?;
-                if ty == ity {
+                if ty == ity || self.subtype(&ity, &ty) {
                     Ok(term)
                 } else {
                     Err(TypeError::Unify(pre.span, ity, ty))
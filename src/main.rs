@@ -17,8 +17,146 @@ fn main() {
     let mut paths = Vec::new();
     let mut bindings = crate::binding::Bindings::default();
     let mut defs = HashMap::new();
+    let mut soa = true;
+    let mut intern_strings = false;
+    let mut unchecked = false;
+    let mut boxed_arrays = false;
+    let mut bounds_checks = false;
+    let mut assertions = false;
+    let mut int_enums = false;
+    let mut pooled_enums = false;
+    let mut share_returns = false;
+    let mut pack_bool_returns = false;
+    let mut array_init_cap = None;
+    let mut array_growth = None;
+    let mut indent_style = None;
+    let mut max_indent = None;
+    let mut minify = false;
+    let mut separator = None;
+    let mut debug_comments = false;
+    let mut estimate_cost = false;
+    let mut predefs = Vec::new();
+    #[cfg(feature = "serde")]
+    let mut emit_json = false;
     for i in args {
-        if i.starts_with("-D") {
+        if i == "--no-soa" {
+            soa = false;
+        } else if i == "--intern-strings" {
+            intern_strings = true;
+        } else if i == "--unchecked-exceptions" {
+            unchecked = true;
+        } else if i == "--boxed-arrays" {
+            boxed_arrays = true;
+        } else if i == "--bounds-checks" {
+            bounds_checks = true;
+        } else if i == "--assertions" {
+            assertions = true;
+        } else if i == "--int-enums" {
+            int_enums = true;
+        } else if i == "--pooled-enums" {
+            pooled_enums = true;
+        } else if i == "--share-returns" {
+            share_returns = true;
+        } else if i == "--pack-bool-returns" {
+            pack_bool_returns = true;
+        } else if let Some(n) = i.strip_prefix("--array-init-cap=") {
+            match n.parse::<usize>() {
+                Ok(n) if n > 0 => array_init_cap = Some(n),
+                _ => {
+                    Doc::start("error")
+                        .style(Style::BoldRed)
+                        .add(": --array-init-cap must be a positive integer, not '")
+                        .add(n)
+                        .add("'")
+                        .style(Style::Bold)
+                        .emit();
+                    std::process::exit(1)
+                }
+            }
+        } else if let Some(n) = i.strip_prefix("--array-growth=") {
+            match n.parse::<f64>() {
+                Ok(n) if n > 1.0 => array_growth = Some(n),
+                _ => {
+                    Doc::start("error")
+                        .style(Style::BoldRed)
+                        .add(": --array-growth must be a number greater than 1, not '")
+                        .add(n)
+                        .add("'")
+                        .style(Style::Bold)
+                        .emit();
+                    std::process::exit(1)
+                }
+            }
+        } else if let Some(n) = i.strip_prefix("--indent=") {
+            match n {
+                "tab" => indent_style = Some(backend::IndentStyle::Tabs),
+                n => match n.parse::<usize>() {
+                    Ok(n) => indent_style = Some(backend::IndentStyle::Spaces(n)),
+                    _ => {
+                        Doc::start("error")
+                            .style(Style::BoldRed)
+                            .add(": --indent must be 'tab' or a number of spaces, not '")
+                            .add(n)
+                            .add("'")
+                            .style(Style::Bold)
+                            .emit();
+                        std::process::exit(1)
+                    }
+                },
+            }
+        } else if let Some(n) = i.strip_prefix("--max-indent=") {
+            match n {
+                "none" => max_indent = Some(None),
+                n => match n.parse::<usize>() {
+                    Ok(n) => max_indent = Some(Some(n)),
+                    _ => {
+                        Doc::start("error")
+                            .style(Style::BoldRed)
+                            .add(": --max-indent must be 'none' or a number of levels, not '")
+                            .add(n)
+                            .add("'")
+                            .style(Style::Bold)
+                            .emit();
+                        std::process::exit(1)
+                    }
+                },
+            }
+        } else if i == "--minify" {
+            minify = true;
+        } else if i == "--debug-comments" {
+            debug_comments = true;
+        } else if i == "--estimate-cost" {
+            estimate_cost = true;
+        } else if let Some(n) = i.strip_prefix("--name-separator=") {
+            if backend::valid_separator(n) {
+                separator = Some(n.to_string());
+            } else {
+                Doc::start("error")
+                    .style(Style::BoldRed)
+                    .add(": --name-separator must be made of letters, digits, '_' and '$', not '")
+                    .add(n)
+                    .add("'")
+                    .style(Style::Bold)
+                    .emit();
+                std::process::exit(1)
+            }
+        } else if let Some(sig) = i.strip_prefix("--predef=") {
+            predefs.push(sig.to_string());
+        } else if i == "--emit-ast-json" {
+            #[cfg(feature = "serde")]
+            {
+                emit_json = true;
+            }
+            #[cfg(not(feature = "serde"))]
+            {
+                Doc::start("error")
+                    .style(Style::BoldRed)
+                    .add(": --emit-ast-json requires building bytec with the `serde` feature")
+                    .style(Style::Bold)
+                    .emit();
+                std::process::exit(1)
+            }
+        } else if i.starts_with("-D") {
             if let Some(idx) = i.find('=') {
                 let k = &i[2..idx];
                 let v = &i[idx + 1..];
@@ -59,12 +197,17 @@ fn main() {
         if input.is_file() {
             files.push(input.clone());
         } else {
+            // Directory entries come in whatever order the filesystem gives them, but the order of modules decides
+            // the numbers in mangled names, so sort them to get the same output everywhere
+            let mut entries = Vec::new();
             for i in input.read_dir().unwrap() {
                 let i = i.unwrap();
                 if i.file_name().to_str().unwrap().ends_with(".bt") {
-                    files.push(i.path());
+                    entries.push(i.path());
                 }
             }
+            entries.sort();
+            files.extend(entries);
         }
     }
     let package = if output.ends_with(".java") {
@@ -76,9 +219,7 @@ fn main() {
     .unwrap();
 
     let mut nfiles = 0;
-    let mut mods = Vec::new();
-    let mut p1 = Vec::new();
-    let mut had_err = false;
+    let mut sources = Vec::new();
     for input in files {
         let mut file = File::open(&input).unwrap_or_else(|_| {
             Doc::start("error")
@@ -96,6 +237,23 @@ fn main() {
         nfiles += 1;
         let mut buf = String::new();
         file.read_to_string(&mut buf).unwrap();
+        sources.push((file_id, input, buf));
+    }
+    // Predefined functions go in their own module with one `--predef` flag per line, so errors point at the flag.
+    // It comes first so the other modules can import it, and doesn't count towards `nfiles` since it has no Java file
+    let predef_id = term::FileId(nfiles, bindings.raw("--predef"));
+    if !predefs.is_empty() {
+        let buf = predefs
+            .iter()
+            .map(|sig| format!("extern fn {};\n", sig))
+            .collect();
+        sources.insert(0, (predef_id, PathBuf::from("--predef"), buf));
+    }
+
+    let mut mods = Vec::new();
+    let mut p1 = Vec::new();
+    let mut had_err = false;
+    for (file_id, input, buf) in sources {
         {
             term::INPUT_SOURCE
                 .write()
@@ -130,15 +288,22 @@ fn main() {
     let mut p2 = Vec::new();
     let mut mods2 = Vec::new();
     for (v, t, items, file_id, input_path) in p1 {
-        let (t, items) =
-            match crate::elaborate::declare_mod_p2(&v, t, &mods, items, &mut bindings, file_id) {
-                Ok(x) => x,
-                Err(x) => {
-                    had_err = true;
-                    x.emit(term::Severity::Error, file_id);
-                    continue;
-                }
-            };
+        let (t, items) = match crate::elaborate::declare_mod_p2(
+            &v,
+            t,
+            &mods,
+            items,
+            &mut bindings,
+            file_id,
+            separator.as_deref().unwrap_or("$"),
+        ) {
+            Ok(x) => x,
+            Err(x) => {
+                had_err = true;
+                x.emit(term::Severity::Error, file_id);
+                continue;
+            }
+        };
         mods2.push((file_id.1, t.clone()));
         p2.push((v, t, items, file_id, input_path));
     }
@@ -147,16 +312,26 @@ fn main() {
     }
     let mut p3 = Vec::new();
     let mut mods3 = Vec::new();
-    for (v, t, items, file_id, input_path) in p2 {
+    let mut predef_fns = Vec::new();
+    for (v, mut t, items, file_id, input_path) in p2 {
+        // Every module imports the predefined functions before declaring its own, so one with the same name is a duplicate
+        t.fns.splice(0..0, predef_fns.iter().cloned());
         let (t, items) =
             match crate::elaborate::declare_mod_p3(&v, t, &mods2, items, &mut bindings, file_id) {
                 Ok(x) => x,
                 Err(x) => {
-                    had_err = true;
                     x.emit(term::Severity::Error, file_id);
+                    // The other modules would just have errors wherever they use the predefined functions
+                    if file_id == predef_id {
+                        std::process::exit(1)
+                    }
+                    had_err = true;
                     continue;
                 }
             };
+        if file_id == predef_id {
+            predef_fns = t.fns.clone();
+        }
         mods3.push((file_id.1, t.clone()));
         p3.push((v, t, items, file_id, input_path));
     }
@@ -183,7 +358,15 @@ fn main() {
     }
     let mut elabed = Vec::new();
     for (v, t, items, file_id, input_path) in p4 {
-        let v = match crate::elaborate::elab_mod(&v, t, &mods4, items, &mut bindings, file_id) {
+        let v = match crate::elaborate::elab_mod(
+            &v,
+            t,
+            &mods4,
+            items,
+            &mut bindings,
+            file_id,
+            boxed_arrays,
+        ) {
             Ok(v) => v,
             Err(x) => {
                 had_err = true;
@@ -191,6 +374,11 @@ fn main() {
                 continue;
             }
         };
+        if file_id == predef_id {
+            // The backend still needs the Java paths of the predefined functions
+            elabed.push((v, input_path, file_id));
+            continue;
+        }
 
         if output.ends_with(".java") {
             output.parent().map(|p| std::fs::create_dir_all(p).unwrap());
@@ -223,30 +411,100 @@ fn main() {
             out_path.to_str().unwrap()
         );
 
-        elabed.push((v, out_path))
+        elabed.push((v, out_path, file_id))
     }
     if had_err {
         std::process::exit(1)
     }
     let mut ir_mods = Vec::new();
     let mut cxt = backend::Cxt::new(&mut bindings, package);
-    for (v, _) in &elabed {
+    cxt.set_soa(soa);
+    cxt.set_intern_strings(intern_strings);
+    cxt.set_unchecked_exceptions(unchecked);
+    cxt.set_boxed_arrays(boxed_arrays);
+    cxt.set_bounds_checks(bounds_checks);
+    cxt.set_assertions_enabled(assertions);
+    cxt.set_enums_as_ints(int_enums);
+    cxt.set_pooled_enums(pooled_enums);
+    cxt.set_share_returns(share_returns);
+    cxt.set_pack_bool_returns(pack_bool_returns);
+    if let Some(cap) = array_init_cap {
+        cxt.set_array_init_cap(cap);
+    }
+    if let Some(growth) = array_growth {
+        cxt.set_array_growth(growth);
+    }
+    if let Some(style) = indent_style {
+        cxt.set_indent_style(style);
+    }
+    if let Some(max) = max_indent {
+        cxt.set_max_indent(max);
+    }
+    cxt.set_minify(minify);
+    cxt.set_debug_comments(debug_comments);
+    if let Some(sep) = separator {
+        cxt.set_separator(&sep);
+    }
+    for (v, _, _) in &elabed {
         crate::backend::declare_p1(v, &mut cxt);
     }
-    for (v, out_path) in elabed {
-        ir_mods.push((
-            crate::backend::declare_p2(
-                v,
-                &mut cxt,
-                out_path.file_stem().unwrap().to_str().unwrap(),
-            ),
-            out_path,
-        ));
-    }
-    for (m, out_path) in &ir_mods {
+    for (v, out_path, file_id) in elabed {
+        match crate::backend::declare_p2(
+            v,
+            &mut cxt,
+            out_path.file_stem().unwrap().to_str().unwrap(),
+        ) {
+            Ok(m) => ir_mods.push((m, (out_path, file_id))),
+            Err(e) => {
+                e.emit(file_id);
+                std::process::exit(1)
+            }
+        }
+    }
+    for (m, (out_path, file_id)) in &ir_mods {
         use std::io::Write;
 
-        let java = m.codegen(&mut cxt, &ir_mods);
+        if *file_id == predef_id {
+            continue;
+        }
+
+        #[cfg(feature = "serde")]
+        if emit_json {
+            let json = match m.codegen_json(&mut cxt, &ir_mods) {
+                Ok(json) => json,
+                Err(e) => {
+                    e.emit(*file_id);
+                    std::process::exit(1)
+                }
+            };
+            let mut out_file = File::create(out_path.with_extension("json")).unwrap();
+            write!(out_file, "{}", json).unwrap();
+            continue;
+        }
+
+        if estimate_cost {
+            let cost = match m.estimate_cost(&mut cxt, &ir_mods) {
+                Ok(cost) => cost,
+                Err(e) => {
+                    e.emit(*file_id);
+                    std::process::exit(1)
+                }
+            };
+            println!("{}:", out_path.file_stem().unwrap().to_str().unwrap());
+            for (name, cost) in cost.fns {
+                println!("{:8}  {}", cost, name);
+            }
+            println!("{:8}  total", cost.total);
+            continue;
+        }
+
+        let java = match m.codegen(&mut cxt, &ir_mods) {
+            Ok(java) => java,
+            Err(e) => {
+                e.emit(*file_id);
+                std::process::exit(1)
+            }
+        };
         let mut out_file = File::create(out_path).unwrap();
         write!(out_file, "{}", java).unwrap();
     }
@@ -11,19 +11,37 @@ pub enum Tok {
     LitI(i64),
     // 3.5
     LitF(f64),
+    // 3.5f
+    LitF32(f32),
     // "Hello world!\n"
     LitS(String),
+    // 'a'
+    LitC(u16),
+    // f"pos=({x}, {y})", as the text before each interpolated expression and the expression's position, then the text at the end
+    LitFmt(Vec<(String, usize)>, String),
     // extern { ... }
     ExternBlock(RawSym),
+    // @Override or @SuppressWarnings("unchecked"), without the @
+    Annotation(RawSym),
 
     // fn
     Fn,
+    // i8
+    I8,
+    // i16
+    I16,
     // i32
     I32,
     // i64
     I64,
+    // f32
+    F32,
+    // f64
+    F64,
     // str
     Str,
+    // StrBuilder
+    StrBuilder,
     // let
     Let,
     // extern
@@ -36,8 +54,12 @@ pub enum Tok {
     Else,
     // bool
     Bool,
+    // char
+    Char,
     // class
     Class,
+    // interface
+    Interface,
     // match
     Match,
     // enum
@@ -50,12 +72,24 @@ pub enum Tok {
     Loop,
     // while
     While,
+    // do
+    Do,
+    // 'outer
+    Label(RawSym),
     // break
     Break,
     // continue
     Continue,
     // return
     Return,
+    // throw
+    Throw,
+    // try
+    Try,
+    // catch
+    Catch,
+    // assert
+    Assert,
     // for
     For,
     // in
@@ -70,6 +104,10 @@ pub enum Tok {
     Null,
     // self
     Selph,
+    // super
+    Super,
+    // extends
+    Extends,
     // #define
     Define,
     // #ifdef
@@ -78,6 +116,16 @@ pub enum Tok {
     Unroll,
     // inline
     Inline,
+    // default
+    Default,
+    // volatile
+    Volatile,
+    // final
+    Final,
+    // as
+    As,
+    // instanceof
+    InstanceOf,
 
     // +
     Add,
@@ -115,12 +163,18 @@ pub enum Tok {
     BitShr,
     // <<
     BitShl,
+    // >>>
+    BitUshr,
     // &&
     And,
     // ||
     Or,
     // !
     Not,
+    // ~
+    BitNot,
+    // ?
+    Question,
 
     // (
     OpenParen,
@@ -146,6 +200,10 @@ pub enum Tok {
     Dot,
     // ..
     DotDot,
+    // ..=
+    DotDotEq,
+    // ...
+    Ellipsis,
 }
 struct Lexer<'a> {
     input: &'a str,
@@ -169,6 +227,158 @@ impl<'a> Lexer<'a> {
         Some(c)
     }
 
+    /// Reads the rest of an escape sequence in a string or char literal, where `self.pos` is after the `\\`.
+    fn escape(&mut self, start: usize, quote: char) -> Result<char, Error> {
+        match self.nextc() {
+            Some('\\') => Ok('\\'),
+            Some('n') => Ok('\n'),
+            Some('t') => Ok('\t'),
+            Some('r') => Ok('\r'),
+            Some('0') => Ok('\0'),
+            Some(c @ ('"' | '\'')) => Ok(c),
+            Some('u') if self.peek() == Some('{') => {
+                // \u{1F600}, like Rust
+                let hex_start = self.pos + 1;
+                while matches!(self.peek(), Some(c) if c != '}' && c != quote) {
+                    self.pos += 1;
+                }
+                let c = u32::from_str_radix(&self.input[hex_start..self.pos], 16)
+                    .ok()
+                    .and_then(char::from_u32);
+                match (c, self.nextc()) {
+                    (Some(c), Some('}')) => Ok(c),
+                    _ => Err(Spanned::new(
+                        Doc::start("Invalid unicode escape"),
+                        Span(hex_start - 3, self.pos),
+                    )),
+                }
+            }
+            Some(c) => Err(Spanned::new(
+                Doc::start(format!("Invalid escape '\\{}'", c)),
+                Span(self.pos - 2, self.pos),
+            )),
+            None => Err(Spanned::new(
+                Doc::start(format!("expected closing {:?}", quote)),
+                Span(start, self.pos),
+            )),
+        }
+    }
+
+    /// Whether the `'` at `self.pos` is followed by a single character and another `'`
+    fn is_char_lit(&self) -> bool {
+        let mut chars = self.input[self.pos + 1..].chars();
+        chars.next().is_some_and(|c| c != '\'') && chars.next() == Some('\'')
+    }
+
+    /// Lexes a char literal like `'a'` or `'\\n'`, where `self.pos` is at the opening `'`.
+    /// Java chars are UTF-16 code units, so the character has to be in the Basic Multilingual Plane.
+    fn char_lit(&mut self) -> Result<Spanned<Tok>, Error> {
+        let start = self.pos;
+        self.pos += 1;
+        let c = match self.input[self.pos..].chars().next() {
+            Some('\\') => {
+                self.pos += 1;
+                self.escape(start, '\'')?
+            }
+            Some(c) => {
+                self.pos += c.len_utf8();
+                c
+            }
+            None => unreachable!("only called when a closing quote follows"),
+        };
+        if self.nextc() != Some('\'') {
+            return Err(Spanned::new(
+                Doc::start("expected closing '\''"),
+                Span(start, self.pos),
+            ));
+        }
+        match u16::try_from(c as u32) {
+            Ok(c) => Ok(Spanned::new(Tok::LitC(c), Span(start, self.pos))),
+            Err(_) => Err(Spanned::new(
+                Doc::start("Char literals must fit in a Java char, use a string for characters outside the Basic Multilingual Plane"),
+                Span(start, self.pos),
+            )),
+        }
+    }
+
+    /// Lexes a string literal, where `self.pos` is at the opening `"`.
+    /// In an interpolated string, `{` starts an expression that the parser reads later, and `{{` and `}}` are literal braces.
+    fn string(&mut self, start: usize, interpolated: bool) -> Result<Spanned<Tok>, Error> {
+        self.nextc();
+        let mut parts = Vec::new();
+        let mut buf = String::new();
+        loop {
+            match self.nextc() {
+                Some('"') => break,
+                Some('\\') => buf.push(self.escape(start, '"')?),
+                Some(c @ ('{' | '}')) if interpolated && self.peek() == Some(c) => {
+                    self.pos += 1;
+                    buf.push(c);
+                }
+                Some('{') if interpolated => {
+                    parts.push((std::mem::take(&mut buf), self.pos));
+                    self.skip_interpolated(start)?;
+                }
+                Some('}') if interpolated => {
+                    return Err(Spanned::new(
+                        Doc::start("unmatched '}' in string, use '}}' for a literal brace"),
+                        Span(self.pos - 1, self.pos),
+                    ))
+                }
+                Some(c) if !c.is_ascii() => {
+                    // `nextc()` only reads one byte, so decode the rest of the UTF-8 character
+                    let c = self.input[self.pos - 1..].chars().next().unwrap();
+                    self.pos += c.len_utf8() - 1;
+                    buf.push(c);
+                }
+                Some(c) => buf.push(c),
+                None => {
+                    return Err(Spanned::new(
+                        Doc::start("expected closing '\"'"),
+                        Span(start, self.pos),
+                    ))
+                }
+            }
+        }
+        let tok = if interpolated {
+            Tok::LitFmt(parts, buf)
+        } else {
+            Tok::LitS(buf)
+        };
+        Ok(Spanned::new(tok, Span(start, self.pos)))
+    }
+
+    /// Skips past the closing `}` of an expression in an interpolated string, which can have braces and strings of its own
+    fn skip_interpolated(&mut self, start: usize) -> Result<(), Error> {
+        let mut depth = 1;
+        loop {
+            match self.nextc() {
+                Some('{') => depth += 1,
+                Some('}') => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break Ok(());
+                    }
+                }
+                Some('"') => loop {
+                    match self.nextc() {
+                        Some('"') => break,
+                        Some('\\') => self.pos += 1,
+                        Some(_) => (),
+                        None => break,
+                    }
+                },
+                Some(_) => (),
+                None => {
+                    break Err(Spanned::new(
+                        Doc::start("expected '}' in string"),
+                        Span(start, self.pos),
+                    ))
+                }
+            }
+        }
+    }
+
     fn is_ident_char(c: char) -> bool {
         c.is_alphanumeric() || c == '_'
     }
@@ -181,25 +391,37 @@ impl<'a> Lexer<'a> {
         let name = &self.input[start..self.pos];
         let tok = match name {
             "fn" => Tok::Fn,
+            "i8" => Tok::I8,
+            "i16" => Tok::I16,
             "i32" => Tok::I32,
             "i64" => Tok::I64,
+            "f32" => Tok::F32,
+            "f64" => Tok::F64,
             "str" => Tok::Str,
+            "StrBuilder" => Tok::StrBuilder,
             "let" => Tok::Let,
             "extern" => Tok::Extern,
             "pub" => Tok::Pub,
             "if" => Tok::If,
             "else" => Tok::Else,
             "bool" => Tok::Bool,
+            "char" => Tok::Char,
             "class" => Tok::Class,
+            "interface" => Tok::Interface,
             "match" => Tok::Match,
             "enum" => Tok::Enum,
             "true" => Tok::True,
             "false" => Tok::False,
             "loop" => Tok::Loop,
             "while" => Tok::While,
+            "do" => Tok::Do,
             "break" => Tok::Break,
             "continue" => Tok::Continue,
             "return" => Tok::Return,
+            "throw" => Tok::Throw,
+            "try" => Tok::Try,
+            "catch" => Tok::Catch,
+            "assert" => Tok::Assert,
             "for" => Tok::For,
             "in" => Tok::In,
             "use" => Tok::Use,
@@ -207,10 +429,17 @@ impl<'a> Lexer<'a> {
             "throws" => Tok::Throws,
             "null" => Tok::Null,
             "self" => Tok::Selph,
+            "super" => Tok::Super,
+            "extends" => Tok::Extends,
             "define" => Tok::Define,
             "ifdef" => Tok::IfDef,
             "unroll" => Tok::Unroll,
             "inline" => Tok::Inline,
+            "default" => Tok::Default,
+            "volatile" => Tok::Volatile,
+            "final" => Tok::Final,
+            "as" => Tok::As,
+            "instanceof" => Tok::InstanceOf,
             _ => Tok::Name(self.bindings.raw(name)),
         };
 
@@ -285,16 +514,43 @@ impl<'a> Lexer<'a> {
                     self.nextc();
                     base = 2;
                 }
+                Some('o') => {
+                    self.nextc();
+                    base = 8;
+                }
                 _ => (),
             }
         }
+        let ndigits = buf.len();
+        // The last character of the literal, which must be a digit before and after a `_`
+        let mut last = buf.chars().last().filter(|_| base == 10);
+        let underscore = |pos: usize| {
+            Spanned::new(
+                Doc::start("'_' can only be used between digits"),
+                Span(pos, pos + 1),
+            )
+        };
+        // `x.1.0` is two tuple indices, not `x.` and a float
+        let index = self.input[..start].ends_with('.');
         let mut float = false;
+        let mut f32 = false;
         while let Some(next) = self.peek() {
+            if last == Some('_') && !next.is_digit(base) && next != '_' {
+                return Err(underscore(self.pos - 1));
+            }
             if next.is_digit(base) {
                 buf.push(next);
                 self.nextc();
             } else if next == '_' {
+                if !last.is_some_and(|c| c.is_digit(base)) {
+                    return Err(underscore(self.pos));
+                }
+                self.nextc();
+            } else if next == 'f' && base == 10 {
+                // Like Java, an `f` suffix makes it a 32-bit float
                 self.nextc();
+                f32 = true;
+                break;
             } else if next.is_alphanumeric() {
                 return Err(Spanned::new(
                     Doc::start("Invalid digit for int literal: '")
@@ -302,20 +558,41 @@ impl<'a> Lexer<'a> {
                         .add("'"),
                     Span(self.pos, self.pos + 1),
                 ));
-            } else if next == '.' && !float && self.peekn(1) != Some('.') {
+            } else if next == '.' && base == 10 && !float && !index && self.peekn(1) != Some('.') {
                 float = true;
                 buf.push(next);
                 self.nextc();
             } else {
                 break;
             }
+            last = Some(next);
+        }
+        if last == Some('_') {
+            return Err(underscore(self.pos - 1));
         }
         Ok(Spanned::new(
-            if float {
+            if f32 {
+                Tok::LitF32(
+                    f32::from_str(&buf)
+                        .map_err(|e| Spanned::new(Doc::start(e), Span(start, self.pos)))?,
+                )
+            } else if float {
                 Tok::LitF(
                     f64::from_str(&buf)
                         .map_err(|e| Spanned::new(Doc::start(e), Span(start, self.pos)))?,
                 )
+            } else if base != 10 && buf.len() == ndigits {
+                return Err(Spanned::new(
+                    Doc::start("expected digits after base prefix"),
+                    Span(start, self.pos),
+                ));
+            } else if base != 10 {
+                // Like Java, hex, octal and binary literals can set the sign bit, so `0xFFFFFFFF` is -1 as an `i32`
+                let digits = buf.trim_start_matches('-');
+                let i = u64::from_str_radix(digits, base)
+                    .map_err(|e| Spanned::new(Doc::start(e), Span(start, self.pos)))?
+                    as i64;
+                Tok::LitI(if neg { i.wrapping_neg() } else { i })
             } else {
                 Tok::LitI(
                     i64::from_str_radix(&buf, base)
@@ -350,9 +627,18 @@ impl<'a> Iterator for Lexer<'a> {
             '<' if self.peekn(1) == Some('=') => self.single_n(Tok::Leq, 2),
             '=' if self.peekn(1) == Some('>') => self.single_n(Tok::WideArrow, 2),
             ':' if self.peekn(1) == Some(':') => self.single_n(Tok::DoubleColon, 2),
+            '.' if self.peekn(1) == Some('.') && self.peekn(2) == Some('=') => {
+                self.single_n(Tok::DotDotEq, 3)
+            }
+            '.' if self.peekn(1) == Some('.') && self.peekn(2) == Some('.') => {
+                self.single_n(Tok::Ellipsis, 3)
+            }
             '.' if self.peekn(1) == Some('.') => self.single_n(Tok::DotDot, 2),
             '&' if self.peekn(1) == Some('&') => self.single_n(Tok::And, 2),
             '|' if self.peekn(1) == Some('|') => self.single_n(Tok::Or, 2),
+            '>' if self.peekn(1) == Some('>') && self.peekn(2) == Some('>') => {
+                self.single_n(Tok::BitUshr, 3)
+            }
             '>' if self.peekn(1) == Some('>') => self.single_n(Tok::BitShr, 2),
             '<' if self.peekn(1) == Some('<') => self.single_n(Tok::BitShl, 2),
 
@@ -374,54 +660,61 @@ impl<'a> Iterator for Lexer<'a> {
             ',' => self.single(Tok::Comma),
             '.' => self.single(Tok::Dot),
             '!' => self.single(Tok::Not),
+            '~' => self.single(Tok::BitNot),
+            '?' => self.single(Tok::Question),
 
-            '"' => {
+            // 'a' is a char literal, and 'a without the closing quote is a label
+            '\'' if self.peekn(1) == Some('\\') || self.is_char_lit() => Some(self.char_lit()),
+            '\'' if matches!(self.peekn(1), Some(x) if x.is_alphabetic() || x == '_') => {
                 let start = self.pos;
-                self.nextc();
-                let mut buf = String::new();
-                loop {
-                    match self.nextc() {
-                        Some('"') => {
-                            break Some(Ok(Spanned::new(Tok::LitS(buf), Span(start, self.pos))))
-                        }
-                        Some('\\') => {
-                            // Escape
-                            match self.nextc() {
-                                Some('\\') => {
-                                    buf.push('\\');
-                                }
-                                Some('n') => {
-                                    buf.push('\n');
-                                }
-                                Some('t') => {
-                                    buf.push('\t');
-                                }
-                                Some('"') => {
-                                    buf.push('"');
-                                }
-                                Some(c) => {
-                                    break Some(Err(Spanned::new(
-                                        Doc::start(format!("Invalid escape '\\{}'", c)),
-                                        Span(self.pos - 2, self.pos),
-                                    )))
-                                }
-                                None => {
-                                    break Some(Err(Spanned::new(
-                                        Doc::start("expected closing '\"'"),
-                                        Span(start, self.pos),
-                                    )))
+                self.pos += 1;
+                while matches!(self.peek(), Some(x) if Lexer::is_ident_char(x)) {
+                    self.pos += 1;
+                }
+                let name = self.bindings.raw(&self.input[start + 1..self.pos]);
+                Some(Ok(Spanned::new(Tok::Label(name), Span(start, self.pos))))
+            }
+
+            // Java annotations are passed through verbatim, including any arguments
+            '@' if matches!(self.peekn(1), Some(x) if x.is_alphabetic() || x == '_') => {
+                let start = self.pos;
+                self.pos += 1;
+                while matches!(self.peek(), Some(x) if Lexer::is_ident_char(x) || x == '.') {
+                    self.pos += 1;
+                }
+                if self.peek() == Some('(') {
+                    let mut nparens = 0;
+                    loop {
+                        match self.nextc() {
+                            Some('(') => nparens += 1,
+                            Some(')') => {
+                                nparens -= 1;
+                                if nparens == 0 {
+                                    break;
                                 }
                             }
-                        }
-                        Some(c) => buf.push(c),
-                        None => {
-                            break Some(Err(Spanned::new(
-                                Doc::start("expected closing '\"'"),
-                                Span(start, self.pos),
-                            )))
+                            Some(_) => (),
+                            None => {
+                                return Some(Err(Spanned::new(
+                                    Doc::start("expected ')'"),
+                                    Span(self.pos - 1, self.pos),
+                                )))
+                            }
                         }
                     }
                 }
+                let ann = self.bindings.raw(&self.input[start + 1..self.pos]);
+                Some(Ok(Spanned::new(
+                    Tok::Annotation(ann),
+                    Span(start, self.pos),
+                )))
+            }
+
+            '"' => Some(self.string(self.pos, false)),
+            'f' if self.peekn(1) == Some('"') => {
+                let start = self.pos;
+                self.pos += 1;
+                Some(self.string(start, true))
             }
 
             x if x.is_whitespace() => {
@@ -716,6 +1009,7 @@ impl<'a> Parser<'a> {
                 Some(Tok::BitXor) => BinOp::BitXor,
                 Some(Tok::BitShr) => BinOp::BitShr,
                 Some(Tok::BitShl) => BinOp::BitShl,
+                Some(Tok::BitUshr) => BinOp::BitUshr,
                 _ => break,
             };
             self.next();
@@ -767,7 +1061,7 @@ impl<'a> Parser<'a> {
     }
 
     fn factor(&mut self) -> Result<Option<SPre>, Error> {
-        let mut t = match self.method()? {
+        let mut t = match self.cast()? {
             Some(t) => t,
             None => return Ok(None),
         };
@@ -789,7 +1083,7 @@ impl<'a> Parser<'a> {
                 break;
             }
 
-            let rhs = self.method()?.ok_or(self.err("expected expression"))?;
+            let rhs = self.cast()?.ok_or(self.err("expected expression"))?;
             let span = Span(t.span.0, rhs.span.1);
             t = Box::new(Spanned::new(Pre::BinOp(op, t, rhs), span));
         }
@@ -797,24 +1091,47 @@ impl<'a> Parser<'a> {
         Ok(Some(t))
     }
 
+    fn cast(&mut self) -> Result<Option<SPre>, Error> {
+        let mut t = match self.method()? {
+            Some(t) => t,
+            None => return Ok(None),
+        };
+
+        loop {
+            let f = match self.peek().as_deref() {
+                Some(Tok::As) => Pre::Cast,
+                Some(Tok::InstanceOf) => Pre::InstanceOf,
+                _ => break,
+            };
+            self.next();
+            let ty = self.ty()?.ok_or(self.err("expected type"))?;
+            let span = Span(t.span.0, self.lexer.pos);
+            t = Box::new(Spanned::new(f(t, ty), span));
+        }
+
+        Ok(Some(t))
+    }
+
     fn method(&mut self) -> Result<Option<SPre>, Error> {
-        let not = match self.peek().as_deref() {
-            Some(Tok::Not) => {
-                let start = self.lexer.pos;
-                self.next();
-                Some(start)
-            }
-            Some(Tok::Sub) => {
-                let zero = Box::new(Spanned::new(Pre::Lit(Literal::Int(0), None), self.span()));
-                self.next();
-                let x = self.method()?.ok_or(self.err("expected expression"))?;
-                return Ok(Some(Box::new(Spanned::new(
-                    Pre::BinOp(BinOp::Sub, zero, x),
-                    self.span(),
-                ))));
-            }
+        let prefix: Option<fn(SPre) -> Pre> = match self.peek().as_deref() {
+            Some(Tok::Not) => Some(Pre::Not),
+            Some(Tok::BitNot) => Some(Pre::BitNot),
+            Some(Tok::Sub) => Some(Pre::Neg),
             _ => None,
         };
+        if let Some(prefix) = prefix {
+            let start = self.lexer.pos;
+            self.next();
+            // Prefix operators can be stacked, like `!!x` or `-~x`
+            let t = match self.method()? {
+                Some(t) => t,
+                None => return Ok(None),
+            };
+            return Ok(Some(Box::new(Spanned::new(
+                prefix(t),
+                Span(start, self.lexer.pos),
+            ))));
+        }
 
         let mut t = match self.atom()? {
             Some(t) => t,
@@ -851,22 +1168,105 @@ impl<'a> Parser<'a> {
                         false
                     };
                     let idx = self.term()?.ok_or(self.err("expected expression"))?;
+                    if !inline && self.peek().as_deref() == Some(&Tok::DotDot) {
+                        self.next();
+                        let end = self.term()?.ok_or(self.err("expected expression"))?;
+                        self.expect(Tok::CloseBracket, "closing ']'")?;
+                        let span = Span(t.span.0, self.lexer.pos);
+                        t = Box::new(Spanned::new(Pre::ArraySlice(t, idx, end), span));
+                        continue;
+                    }
                     self.expect(Tok::CloseBracket, "closing ']'")?;
                     let span = Span(t.span.0, self.lexer.pos);
                     t = Box::new(Spanned::new(Pre::ArrayIdx(t, idx, inline), span));
                 }
+                Some(Tok::Question) => {
+                    self.next();
+                    let span = Span(t.span.0, self.lexer.pos);
+                    t = Box::new(Spanned::new(Pre::Try(t), span));
+                }
                 _ => break,
             }
         }
 
-        if let Some(start) = not {
-            Ok(Some(Box::new(Spanned::new(
-                Pre::Not(t),
-                Span(start, self.lexer.pos),
-            ))))
-        } else {
-            Ok(Some(t))
+        Ok(Some(t))
+    }
+
+    /// Parses the `(a, pub b)` after a variant in a pattern, if there is one
+    fn captures(&mut self) -> Result<Vec<(Spanned<RawSym>, bool)>, Error> {
+        let mut captures = Vec::new();
+        if self.peek().as_deref() == Some(&Tok::OpenParen) {
+            self.next();
+            loop {
+                if self.peek().as_deref() == Some(&Tok::CloseParen) {
+                    self.next();
+                    break;
+                }
+
+                let mut public = false;
+                if self.peek().as_deref() == Some(&Tok::Pub) {
+                    self.next();
+                    public = true;
+                }
+
+                let name = self.ident().ok_or(self.err("expected name"))?;
+                captures.push((name, public));
+                if self.peek().as_deref() == Some(&Tok::Comma) {
+                    self.next();
+                } else {
+                    self.expect(Tok::CloseParen, "closing ')'")?;
+                    break;
+                }
+            }
         }
+        Ok(captures)
+    }
+
+    /// Parses the rest of `if let Variant(a, b) = x { ... } else { ... }` after the `if`.
+    /// It's a `match` with a default branch, which is empty if there's no `else`.
+    fn if_let(&mut self, start: usize) -> Result<SPre, Error> {
+        self.expect(Tok::Let, "'let'")?;
+        let name = self.ident().ok_or(self.err("expected variant name"))?;
+        let captures = self.captures()?;
+        self.expect(Tok::Equals, "'='")?;
+        let scrutinee = self
+            .term()?
+            .ok_or(self.err("expected expression to match on"))?;
+
+        if self.peek().as_deref() != Some(&Tok::OpenBrace) {
+            return Err(self.err("expected '{' after if let"));
+        }
+        let a = self.term()?.unwrap();
+
+        let b = if self.peek().as_deref() == Some(&Tok::Else) {
+            let espan = self.span();
+            self.next();
+            if !matches!(self.peek().as_deref(), Some(Tok::If | Tok::OpenBrace)) {
+                return Err(self.err("expected '{' after 'else'"));
+            }
+            (espan, self.term()?.unwrap())
+        } else {
+            let span = Span(self.lexer.pos, self.lexer.pos);
+            (
+                span,
+                Box::new(Spanned::new(Pre::Block(Vec::new(), None), span)),
+            )
+        };
+
+        Ok(Box::new(Spanned::new(
+            Pre::Match(
+                scrutinee,
+                vec![
+                    (
+                        Spanned::new(PrePattern::Variant(*name), name.span),
+                        captures,
+                        a,
+                    ),
+                    (Spanned::new(PrePattern::Default, b.0), Vec::new(), b.1),
+                ],
+            ),
+            Span(start, self.lexer.pos),
+        )))
     }
 
     fn call_args(&mut self) -> Result<Vec<SPre>, Error> {
@@ -900,6 +1300,27 @@ impl<'a> Parser<'a> {
                     self.span(),
                 ))))
             }
+            Some(Tok::LitF(f)) => {
+                self.next();
+                Ok(Some(Box::new(Spanned::new(
+                    Pre::Lit(Literal::Float(*f), None),
+                    self.span(),
+                ))))
+            }
+            Some(Tok::LitF32(f)) => {
+                self.next();
+                Ok(Some(Box::new(Spanned::new(
+                    Pre::Lit(Literal::Float(*f as f64), Some(PreType::F32)),
+                    self.span(),
+                ))))
+            }
+            Some(Tok::LitC(c)) => {
+                self.next();
+                Ok(Some(Box::new(Spanned::new(
+                    Pre::Lit(Literal::Char(*c), None),
+                    self.span(),
+                ))))
+            }
             Some(Tok::LitS(s)) => {
                 let q = self.next().unwrap();
                 Ok(Some(Box::new(Spanned::new(
@@ -907,6 +1328,31 @@ impl<'a> Parser<'a> {
                     q.span,
                 ))))
             }
+            Some(Tok::LitFmt(parts, end)) => {
+                // f"a{x}b" is "a" + x + "b", which always starts with a string so `+` concatenates
+                let q = self.next().unwrap();
+                let after = self.lexer.pos;
+                let lit = |s: &str, lexer: &mut Lexer| {
+                    Box::new(Spanned::new(
+                        Pre::Lit(Literal::Str(lexer.bindings.raw(s)), None),
+                        q.span,
+                    ))
+                };
+                let text = |i: usize| parts.get(i).map_or(end.as_str(), |(s, _)| s.as_str());
+                let mut t = lit(text(0), &mut self.lexer);
+                for (i, (_, pos)) in parts.iter().enumerate() {
+                    self.lexer.pos = *pos;
+                    let x = self.term()?.ok_or(self.err("expected expression"))?;
+                    self.expect(Tok::CloseBrace, "'}' after expression in string")?;
+                    t = Box::new(Spanned::new(Pre::BinOp(BinOp::Add, t, x), q.span));
+                    if !text(i + 1).is_empty() {
+                        let s = lit(text(i + 1), &mut self.lexer);
+                        t = Box::new(Spanned::new(Pre::BinOp(BinOp::Add, t, s), q.span));
+                    }
+                }
+                self.lexer.pos = after;
+                Ok(Some(t))
+            }
             Some(Tok::True) => {
                 self.next();
                 Ok(Some(Box::new(Spanned::new(
@@ -929,13 +1375,69 @@ impl<'a> Parser<'a> {
                 self.next();
                 Ok(Some(Box::new(Spanned::new(Pre::Selph, self.span()))))
             }
-            Some(Tok::Break) => {
+            Some(Tok::BitOr | Tok::Or) => {
+                // |a, b: T| x or || x
+                let t = self.next().unwrap();
+                let start = t.span.0;
+                let mut args = Vec::new();
+                if *t == Tok::BitOr {
+                    while self.peek().as_deref() != Some(&Tok::BitOr) {
+                        let n = self
+                            .ident()
+                            .ok_or(self.err("expected argument name or '|'"))?;
+                        let ty = if self.peek().as_deref() == Some(&Tok::Colon) {
+                            self.next();
+                            Some(self.ty()?.ok_or(self.err("expected argument type"))?)
+                        } else {
+                            None
+                        };
+                        args.push((n, ty));
+                        match self.peek().as_deref() {
+                            Some(Tok::Comma) => {
+                                self.next();
+                            }
+                            Some(Tok::BitOr) => (),
+                            _ => return Err(self.err("expected ',' or '|'")),
+                        }
+                    }
+                    self.next();
+                }
+                let body = self.term()?.ok_or(self.err("expected lambda body"))?;
+                Ok(Some(Box::new(Spanned::new(
+                    Pre::Lambda(args, body),
+                    Span(start, self.lexer.pos),
+                ))))
+            }
+            Some(Tok::Default) => {
+                // default::<T>()
+                let start = self.span().0;
                 self.next();
-                Ok(Some(Box::new(Spanned::new(Pre::Break, self.span()))))
+                self.expect(Tok::DoubleColon, "'::' after 'default'")?;
+                self.expect(Tok::Lt, "'<'")?;
+                let ty = self.ty()?.ok_or(self.err("expected type"))?;
+                self.expect(Tok::Gt, "closing '>'")?;
+                self.expect(Tok::OpenParen, "'('")?;
+                self.expect(Tok::CloseParen, "closing ')'")?;
+                Ok(Some(Box::new(Spanned::new(
+                    Pre::Default(ty),
+                    Span(start, self.lexer.pos),
+                ))))
+            }
+            Some(Tok::Break) => {
+                let start = self.next().unwrap().span.0;
+                let label = self.label();
+                Ok(Some(Box::new(Spanned::new(
+                    Pre::Break(label),
+                    Span(start, self.lexer.pos),
+                ))))
             }
             Some(Tok::Continue) => {
-                self.next();
-                Ok(Some(Box::new(Spanned::new(Pre::Continue, self.span()))))
+                let start = self.next().unwrap().span.0;
+                let label = self.label();
+                Ok(Some(Box::new(Spanned::new(
+                    Pre::Continue(label),
+                    Span(start, self.lexer.pos),
+                ))))
             }
             Some(Tok::Return) => {
                 self.next();
@@ -945,6 +1447,9 @@ impl<'a> Parser<'a> {
             Some(Tok::If) => {
                 let start = self.lexer.pos;
                 self.next();
+                if self.peek().as_deref() == Some(&Tok::Let) {
+                    return self.if_let(start).map(Some);
+                }
                 let cond = self.term()?.ok_or(self.err("expected if condition"))?;
 
                 // Make sure there's a block next, but don't consume the {
@@ -978,50 +1483,59 @@ impl<'a> Parser<'a> {
 
                 self.expect(Tok::OpenBrace, "'{'")?;
 
+                // `_` is the same as `else`
+                let wildcard = self.lexer.bindings.raw("_");
                 let mut branches = Vec::new();
                 loop {
                     let needs_semicolon = match self.peek().as_deref() {
+                        Some(t) if *t == Tok::Else || *t == Tok::Name(wildcard) => {
+                            let espan = self.span();
+                            self.next();
+                            self.expect(Tok::WideArrow, "'=>'")?;
+                            let term = self.term()?.ok_or(self.err("expected expression"))?;
+                            let n = term.needs_semicolon();
+                            branches.push((
+                                Spanned::new(PrePattern::Default, espan),
+                                Vec::new(),
+                                term,
+                            ));
+                            n
+                        }
                         Some(Tok::Name(_)) => {
                             let name = self.ident().unwrap();
-
-                            let mut captures = Vec::new();
-                            if self.peek().as_deref() == Some(&Tok::OpenParen) {
-                                self.next();
-                                loop {
-                                    if self.peek().as_deref() == Some(&Tok::CloseParen) {
-                                        self.next();
-                                        break;
-                                    }
-
-                                    let mut public = false;
-                                    if self.peek().as_deref() == Some(&Tok::Pub) {
-                                        public = true;
-                                    }
-
-                                    let name = self.ident().ok_or(self.err("expected name"))?;
-                                    captures.push((name, public));
-                                    if self.peek().as_deref() == Some(&Tok::Comma) {
-                                        self.next();
-                                    } else {
-                                        self.expect(Tok::CloseParen, "closing ')'")?;
-                                        break;
-                                    }
-                                }
-                            }
+                            let captures = self.captures()?;
 
                             self.expect(Tok::WideArrow, "'=>'")?;
                             let term = self.term()?.ok_or(self.err("expected expression"))?;
                             let n = term.needs_semicolon();
-                            branches.push((Spanned::new(Some(*name), name.span), captures, term));
+                            branches.push((
+                                Spanned::new(PrePattern::Variant(*name), name.span),
+                                captures,
+                                term,
+                            ));
                             n
                         }
-                        Some(Tok::Else) => {
-                            let espan = self.span();
-                            self.next();
+                        Some(Tok::LitI(_) | Tok::Sub) => {
+                            let start = self.span().0;
+                            let neg = self.peek().as_deref() == Some(&Tok::Sub);
+                            if neg {
+                                self.next();
+                            }
+                            let i = match self.peek().as_deref() {
+                                Some(Tok::LitI(i)) => *i,
+                                _ => return Err(self.err("expected integer literal")),
+                            };
+                            let pspan = Span(start, self.next().unwrap().span.1);
+                            let i = if neg { -i } else { i };
+
                             self.expect(Tok::WideArrow, "'=>'")?;
                             let term = self.term()?.ok_or(self.err("expected expression"))?;
                             let n = term.needs_semicolon();
-                            branches.push((Spanned::new(None, espan), Vec::new(), term));
+                            branches.push((
+                                Spanned::new(PrePattern::Int(i), pspan),
+                                Vec::new(),
+                                term,
+                            ));
                             n
                         }
                         Some(Tok::CloseBrace) => {
@@ -1048,6 +1562,22 @@ impl<'a> Parser<'a> {
                     Span(start, self.lexer.pos),
                 ))))
             }
+            // `str(x)`, which is a function even though `str` is a type
+            Some(Tok::Str) => {
+                let span = self.next().unwrap().span;
+                let name = RawPath(
+                    Vec::new(),
+                    Spanned::new(self.lexer.bindings.raw("str"), span),
+                );
+                if self.peek().as_deref() != Some(&Tok::OpenParen) {
+                    return Err(self.err("expected '(' after 'str'"));
+                }
+                let args = self.call_args()?;
+                Ok(Some(Box::new(Spanned::new(
+                    Pre::Call(name, args),
+                    Span(span.0, self.lexer.pos),
+                ))))
+            }
             // variable or function or method call
             Some(Tok::Name(_)) => {
                 let name = self.path().unwrap();
@@ -1060,6 +1590,13 @@ impl<'a> Parser<'a> {
                             Span(var.span.0, self.lexer.pos),
                         ))))
                     }
+                    Some(Tok::OpenBrace) if self.class_lit_ahead() => {
+                        let fields = self.class_lit_fields()?;
+                        Ok(Some(Box::new(Spanned::new(
+                            Pre::ClassLit(name, fields),
+                            Span(var.span.0, self.lexer.pos),
+                        ))))
+                    }
                     _ => Ok(Some(var)),
                 }
             }
@@ -1075,6 +1612,19 @@ impl<'a> Parser<'a> {
                     }
 
                     v.push(self.term()?.ok_or(self.err("expected term"))?);
+                    if v.len() == 1
+                        && matches!(self.peek().as_deref(), Some(Tok::DotDot | Tok::DotDotEq))
+                    {
+                        // (a..b), for `for i in (a..b).rev()`
+                        let inclusive = self.peek().as_deref() == Some(&Tok::DotDotEq);
+                        self.next();
+                        let b = self.term()?.ok_or(self.err("expected expression"))?;
+                        self.expect(Tok::CloseParen, "closing ')'")?;
+                        return Ok(Some(Box::new(Spanned::new(
+                            Pre::Range(v.pop().unwrap(), b, inclusive),
+                            Span(start, self.lexer.pos),
+                        ))));
+                    }
                     if self.peek().as_deref() == Some(&Tok::Comma) {
                         self.next();
                     } else {
@@ -1113,7 +1663,17 @@ impl<'a> Parser<'a> {
                         break;
                     }
 
-                    v.push(self.term()?.ok_or(self.err("expected term"))?);
+                    let x = self.term()?.ok_or(self.err("expected term"))?;
+                    if v.is_empty() && self.peek().as_deref() == Some(&Tok::Semicolon) {
+                        self.next();
+                        let len = self.term()?.ok_or(self.err("expected expression"))?;
+                        self.expect(Tok::CloseBracket, "closing ']'")?;
+                        return Ok(Some(Box::new(Spanned::new(
+                            Pre::ArrayRepeat(x, len),
+                            Span(start, self.lexer.pos),
+                        ))));
+                    }
+                    v.push(x);
                     if self.peek().as_deref() == Some(&Tok::Comma) {
                         self.next();
                     } else {
@@ -1192,6 +1752,14 @@ impl<'a> Parser<'a> {
 
     fn ty(&mut self) -> Result<Option<PreType>, Error> {
         match self.peek().as_deref() {
+            Some(Tok::I8) => {
+                self.next();
+                Ok(Some(PreType::I8))
+            }
+            Some(Tok::I16) => {
+                self.next();
+                Ok(Some(PreType::I16))
+            }
             Some(Tok::I32) => {
                 self.next();
                 Ok(Some(PreType::I32))
@@ -1200,14 +1768,30 @@ impl<'a> Parser<'a> {
                 self.next();
                 Ok(Some(PreType::I64))
             }
+            Some(Tok::F32) => {
+                self.next();
+                Ok(Some(PreType::F32))
+            }
+            Some(Tok::F64) => {
+                self.next();
+                Ok(Some(PreType::F64))
+            }
             Some(Tok::Bool) => {
                 self.next();
                 Ok(Some(PreType::Bool))
             }
+            Some(Tok::Char) => {
+                self.next();
+                Ok(Some(PreType::Char))
+            }
             Some(Tok::Str) => {
                 self.next();
                 Ok(Some(PreType::Str))
             }
+            Some(Tok::StrBuilder) => {
+                self.next();
+                Ok(Some(PreType::StrBuilder))
+            }
             Some(Tok::OpenBracket) => {
                 self.next();
                 let inner = self.ty()?.ok_or(self.err("expected type"))?;
@@ -1256,27 +1840,79 @@ impl<'a> Parser<'a> {
             }
             Some(Tok::Name(_)) => {
                 let path = self.path().unwrap();
-                Ok(Some(PreType::Class(path)))
+                if self.peek().as_deref() == Some(&Tok::Lt) {
+                    self.next();
+                    let mut v = Vec::new();
+                    loop {
+                        v.push(self.ty()?.ok_or(self.err("expected type argument"))?);
+                        match self.peek() {
+                            Some(t) if *t == Tok::Comma => {
+                                self.next();
+                            }
+                            // In `A<B<C>>`, the `>>` closes both, so the second `>` is left for the outer type
+                            Some(t) if *t == Tok::BitShr => {
+                                let Span(start, end) = t.span;
+                                self.next = Some(Spanned::new(Tok::Gt, Span(start + 1, end)));
+                                break;
+                            }
+                            _ => {
+                                self.expect(Tok::Gt, "closing '>'")?;
+                                break;
+                            }
+                        }
+                    }
+                    Ok(Some(PreType::Generic(path, v)))
+                } else {
+                    Ok(Some(PreType::Class(path)))
+                }
             }
             _ => Ok(None),
         }
     }
 
+    /// Parses the type parameters of a function or class if there are any, like `<T, U>`
+    fn type_params(&mut self) -> Result<Vec<Spanned<RawSym>>, Error> {
+        let mut type_params = Vec::new();
+        if self.peek().as_deref() == Some(&Tok::Lt) {
+            self.next();
+            loop {
+                type_params.push(
+                    self.ident()
+                        .ok_or(self.err("expected type parameter name"))?,
+                );
+                if self.peek().as_deref() == Some(&Tok::Comma) {
+                    self.next();
+                } else {
+                    self.expect(Tok::Gt, "closing '>'")?;
+                    break;
+                }
+            }
+        }
+        Ok(type_params)
+    }
+
     /// Parses the part of a function after the `fn` but before the `=` or `{`
-    /// For example, `add(pub x: i32, y: i32): i32`
+    /// For example, `add(pub x: i32, y: i32): i32`, `pick<T>(a: T, b: T): T` or `step(x: i32, by: i32 = 1): i32`
+    /// The default values are for the last parameters
     fn prototype(
         &mut self,
     ) -> Result<
         (
             Spanned<RawSym>,
+            Vec<Spanned<RawSym>>,
             Vec<(Spanned<RawSym>, PreType, bool)>,
+            Vec<SPre>,
+            bool,
             PreType,
         ),
         Error,
     > {
         let name = self.ident().ok_or(self.err("expected function name"))?;
+        let type_params = self.type_params()?;
         self.expect(Tok::OpenParen, "'('")?;
         let mut args = Vec::new();
+        let mut defaults = Vec::new();
+        let mut varargs = false;
         while self.peek().as_deref() != Some(&Tok::CloseParen) {
             let public = if self.peek().as_deref() == Some(&Tok::Pub) {
                 self.next();
@@ -1291,7 +1927,28 @@ impl<'a> Parser<'a> {
             self.expect(Tok::Colon, "':'")?;
 
             let t = self.ty()?.ok_or(self.err("expected argument type"))?;
+            if self.peek().as_deref() == Some(&Tok::Ellipsis) {
+                self.next();
+                if !defaults.is_empty() {
+                    return Err(self.err("a function can't have both default values and varargs"));
+                }
+                if self.peek().as_deref() != Some(&Tok::CloseParen) {
+                    return Err(
+                        self.err("expected ')', the '...' parameter has to be the last one")
+                    );
+                }
+                args.push((n, PreType::Array(Box::new(t)), public));
+                varargs = true;
+                continue;
+            }
             args.push((n, t, public));
+            if self.peek().as_deref() == Some(&Tok::Equals) {
+                self.next();
+                defaults.push(self.term()?.ok_or(self.err("expected default value"))?);
+            } else if !defaults.is_empty() {
+                return Err(self
+                    .err("expected '=', parameters after one with a default value need one too"));
+            }
             match self.peek().as_deref() {
                 Some(Tok::Comma) => {
                     self.next();
@@ -1307,7 +1964,51 @@ impl<'a> Parser<'a> {
         } else {
             PreType::Tuple(Vec::new())
         };
-        Ok((name, args, ret_type))
+        Ok((name, type_params, args, defaults, varargs, ret_type))
+    }
+
+    /// Whether the `{` that was just peeked starts a class literal like `Point { x: 1 }` instead of a block,
+    /// as in `if x { ... }`. A block can't start with a name followed by a ':', so that's what decides it.
+    fn class_lit_ahead(&mut self) -> bool {
+        let pos = self.lexer.pos;
+        let name = matches!(
+            self.lexer.next(),
+            Some(Ok(Spanned {
+                inner: Tok::Name(_),
+                ..
+            }))
+        );
+        let colon = name
+            && matches!(
+                self.lexer.next(),
+                Some(Ok(Spanned {
+                    inner: Tok::Colon,
+                    ..
+                }))
+            );
+        self.lexer.pos = pos;
+        colon
+    }
+
+    /// Parses the `{ x: a, y: b }` part of a class literal
+    fn class_lit_fields(&mut self) -> Result<Vec<(Spanned<RawSym>, SPre)>, Error> {
+        self.expect(Tok::OpenBrace, "'{'")?;
+        let mut fields = Vec::new();
+        while self.peek().as_deref() != Some(&Tok::CloseBrace) {
+            let name = self.ident().ok_or(self.err("expected field name or '}'"))?;
+            self.expect(Tok::Colon, "':'")?;
+            let x = self.term()?.ok_or(self.err("expected field value"))?;
+            fields.push((name, x));
+            match self.peek().as_deref() {
+                Some(Tok::Comma) => {
+                    self.next();
+                }
+                Some(Tok::CloseBrace) => (),
+                _ => return Err(self.err("expected ',' or '}'")),
+            }
+        }
+        self.expect(Tok::CloseBrace, "closing '}'")?;
+        Ok(fields)
     }
 
     /// Parses an enum declaration, starting right after the `enum` keyword
@@ -1336,7 +2037,7 @@ impl<'a> Parser<'a> {
                         }
                     }
                 }
-                v.push((*name, args));
+                v.push((name, args));
                 if self.peek().as_deref() == Some(&Tok::Comma) {
                     self.next();
                     continue;
@@ -1351,10 +2052,19 @@ impl<'a> Parser<'a> {
         let mut members = Vec::new();
         if self.peek().as_deref() == Some(&Tok::Semicolon) {
             self.next();
-            let (methods2, members2, cons) = self.class_members(ext)?;
+            let (methods2, members2, cons, super_args) = self.class_members(ext)?;
             if cons.is_some() {
                 return Err(self.err("enum cannot have a constructor"));
             }
+            if super_args.is_some() {
+                return Err(self.err("enum cannot call 'super'"));
+            }
+            if methods2.iter().any(|f| match f {
+                PreFnEither::Local(f) => f.is_static,
+                PreFnEither::Extern(f) => f.is_static,
+            }) {
+                return Err(self.err("enum methods can't be static"));
+            }
             methods = methods2;
             members = members2;
         }
@@ -1362,11 +2072,15 @@ impl<'a> Parser<'a> {
 
         Ok(PreItem::Class {
             path: name,
+            type_params: Vec::new(),
             methods,
             members,
             variants: Some(v),
             ext,
             constructor: None,
+            implements: Vec::new(),
+            extends: None,
+            super_args: None,
         })
     }
 
@@ -1376,17 +2090,20 @@ impl<'a> Parser<'a> {
     ) -> Result<
         (
             Vec<PreFnEither>,
-            Vec<(Spanned<RawSym>, bool, PreType, Option<SPre>)>,
+            Vec<PreMember>,
             Option<Vec<PreType>>,
+            Option<Spanned<Vec<SPre>>>,
         ),
         Error,
     > {
         let mut methods = Vec::new();
         let mut members = Vec::new();
         let mut constructor = None;
+        let mut super_args = None;
 
         while *self.peek().ok_or(self.err("expected closing '}'"))? != Tok::CloseBrace {
             let ifdef = self.ifdef()?;
+            let annotations = self.annotations()?;
             match self.peek().as_deref() {
                 Some(Tok::Constructor) => {
                     if constructor.is_some() {
@@ -1418,6 +2135,20 @@ impl<'a> Parser<'a> {
                         constructor = Some(args);
                     }
                 }
+                // super(a, b); calls the superclass constructor
+                Some(Tok::Super) if !ext => {
+                    if super_args.is_some() {
+                        return Err(self.err("duplicate 'super' call"));
+                    }
+                    let start = self.span();
+                    self.next();
+                    let args = self.call_args()?;
+                    let span = Span(start.0, self.lexer.pos);
+                    self.expect(Tok::Semicolon, "';'")?;
+                    if ifdef.resolve(self) {
+                        super_args = Some(Spanned::new(args, span));
+                    }
+                }
                 Some(Tok::Let) => {
                     self.next();
                     let public = ext
@@ -1427,6 +2158,7 @@ impl<'a> Parser<'a> {
                         } else {
                             false
                         };
+                    let volatile = self.volatile();
                     let name = self.ident().ok_or(self.err("expected name"))?;
                     self.expect(Tok::Colon, "':'")?;
                     let ty = self.ty()?.ok_or(self.err("expected type"))?;
@@ -1439,12 +2171,13 @@ impl<'a> Parser<'a> {
 
                     self.expect(Tok::Semicolon, "';'")?;
                     if ifdef.resolve(self) {
-                        members.push((name, public, ty, body));
+                        members.push((name, public, volatile, ty, body));
                     }
                 }
                 Some(Tok::Fn) => {
                     self.next();
 
+                    let is_static = self.contextual("static");
                     let inline = if self.peek().as_deref() == Some(&Tok::Inline) {
                         self.next();
                         true
@@ -1452,8 +2185,20 @@ impl<'a> Parser<'a> {
                         false
                     };
 
-                    let (name, args, ret_ty) = self.prototype()?;
+                    let (name, type_params, args, defaults, varargs, ret_ty) = self.prototype()?;
+                    if !type_params.is_empty() {
+                        return Err(self.err("methods can't have type parameters"));
+                    }
+                    if varargs {
+                        return Err(self.err("methods can't have varargs"));
+                    }
                     if ext {
+                        if !annotations.is_empty() {
+                            return Err(self.err("extern methods can't have annotations"));
+                        }
+                        if !defaults.is_empty() {
+                            return Err(self.err("extern methods can't have default values"));
+                        }
                         let mapping = if *self.peek().ok_or(self.err("expected ';'"))?
                             == Tok::Equals
                         {
@@ -1474,6 +2219,8 @@ impl<'a> Parser<'a> {
                             ret_ty,
                             args,
                             mapping,
+                            is_static,
+                            varargs: false,
                         };
                         if ifdef.resolve(self) {
                             methods.push(PreFnEither::Extern(f));
@@ -1502,12 +2249,17 @@ impl<'a> Parser<'a> {
 
                         let f = PreFn {
                             name,
+                            type_params: Vec::new(),
                             ret_ty,
                             args,
                             public: false,
                             body,
                             throws,
                             inline,
+                            annotations,
+                            is_static,
+                            defaults,
+                            varargs: false,
                         };
                         if ifdef.resolve(self) {
                             methods.push(PreFnEither::Local(f));
@@ -1517,44 +2269,151 @@ impl<'a> Parser<'a> {
                 _ => return Err(self.err("expected item or closing '}'")),
             }
         }
-        Ok((methods, members, constructor))
+        Ok((methods, members, constructor, super_args))
+    }
+
+    /// Parses the annotations before a function, which have to be followed by `fn`
+    fn annotations(&mut self) -> Result<Vec<RawSym>, Error> {
+        let mut annotations = Vec::new();
+        while let Some(Tok::Annotation(s)) = self.peek().as_deref() {
+            self.next();
+            annotations.push(*s);
+        }
+        if !annotations.is_empty() && self.peek().as_deref() != Some(&Tok::Fn) {
+            return Err(self.err("annotations can only be used on functions"));
+        }
+        Ok(annotations)
+    }
+
+    /// Parses an optional `volatile` modifier on a `let`
+    fn volatile(&mut self) -> bool {
+        self.modifier(Tok::Volatile)
+    }
+
+    /// Parses an optional contextual keyword like `static`, which can still be used as a name elsewhere
+    fn contextual(&mut self, kw: &str) -> bool {
+        match self.peek().as_deref() {
+            Some(Tok::Name(r)) if self.lexer.bindings.resolve_raw(*r) == kw => {
+                self.next();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Parses an optional modifier like `volatile` or `final`
+    fn modifier(&mut self, tok: Tok) -> bool {
+        if self.peek().as_deref() == Some(&tok) {
+            self.next();
+            true
+        } else {
+            false
+        }
     }
 
     fn class(&mut self, ext: bool) -> Result<Option<PreItem>, Error> {
         let path = self.path().ok_or(self.err("expected class name"))?;
+        let type_params = self.type_params()?;
+        let mut extends = None;
+        if self.peek().as_deref() == Some(&Tok::Extends) {
+            self.next();
+            extends = Some(Box::new(
+                self.ty()?.ok_or(self.err("expected superclass type"))?,
+            ));
+        }
+        let mut implements = Vec::new();
+        if self.peek().as_deref() == Some(&Tok::Colon) {
+            self.next();
+            loop {
+                implements.push(self.ty()?.ok_or(self.err("expected interface type"))?);
+                if self.peek().as_deref() == Some(&Tok::Comma) {
+                    self.next();
+                } else {
+                    break;
+                }
+            }
+        }
         match self.peek().as_deref() {
             Some(Tok::Semicolon) => {
                 self.next();
                 Ok(Some(PreItem::Class {
                     ext,
                     path,
+                    type_params,
                     variants: None,
                     methods: Vec::new(),
                     members: Vec::new(),
                     constructor: None,
+                    implements,
+                    extends,
+                    super_args: None,
                 }))
             }
             Some(Tok::OpenBrace) => {
                 self.next();
-                let (methods, members, constructor) = self.class_members(ext)?;
+                let (methods, members, constructor, super_args) = self.class_members(ext)?;
                 self.expect(Tok::CloseBrace, "'}'")?;
 
                 Ok(Some(PreItem::Class {
                     path,
+                    type_params,
                     methods,
                     members,
                     constructor,
                     ext,
                     variants: None,
+                    implements,
+                    extends,
+                    super_args,
                 }))
             }
             _ => return Err(self.err("expected ';' or '{'")),
         }
     }
 
+    /// Parses the part of an interface after the `interface`, like `Shape<T> { fn area(): f64; }`
+    fn interface(&mut self) -> Result<Option<PreItem>, Error> {
+        let path = self.path().ok_or(self.err("expected interface name"))?;
+        let type_params = self.type_params()?;
+        self.expect(Tok::OpenBrace, "'{'")?;
+        let mut methods = Vec::new();
+        while *self.peek().ok_or(self.err("expected closing '}'"))? != Tok::CloseBrace {
+            let ifdef = self.ifdef()?;
+            self.expect(Tok::Fn, "'fn' or closing '}'")?;
+            let (name, type_params, args, defaults, varargs, ret_ty) = self.prototype()?;
+            if !type_params.is_empty() {
+                return Err(self.err("methods can't have type parameters"));
+            }
+            if varargs {
+                return Err(self.err("methods can't have varargs"));
+            }
+            if !defaults.is_empty() {
+                return Err(self.err("interface methods can't have default values"));
+            }
+            self.expect(Tok::Semicolon, "';'")?;
+            if ifdef.resolve(self) {
+                methods.push(PreEFn {
+                    name,
+                    ret_ty,
+                    args,
+                    mapping: *name,
+                    is_static: false,
+                    varargs: false,
+                });
+            }
+        }
+        self.expect(Tok::CloseBrace, "'}'")?;
+        Ok(Some(PreItem::Interface {
+            path,
+            type_params,
+            methods,
+        }))
+    }
+
     fn item(&mut self) -> Result<Option<PreItem>, Error> {
         self.defines()?;
         let ifdef = self.ifdef()?;
+        let annotations = self.annotations()?;
         let i = match self.peek().as_deref() {
             None => Ok(None),
             Some(Tok::ExternBlock(s)) => {
@@ -1570,6 +2429,11 @@ impl<'a> Parser<'a> {
                 } else {
                     false
                 };
+                let volatile = self.volatile();
+                let final_ = self.modifier(Tok::Final);
+                if volatile && final_ {
+                    return Err(self.err("a 'let' can't be both 'volatile' and 'final'"));
+                }
 
                 let name = self.ident().ok_or(self.err("expected name"))?;
                 let ty = if self.peek().as_deref() == Some(&Tok::Colon) {
@@ -1583,16 +2447,24 @@ impl<'a> Parser<'a> {
                 if self.peek().as_deref() == Some(&Tok::Equals) {
                     self.next();
                     value = Some(self.term()?.ok_or(self.err("expected expression"))?);
+                } else if final_ {
+                    return Err(self.err("expected '=', 'final' bindings need an initial value"));
                 }
 
                 self.expect(Tok::Semicolon, "';'")?;
 
-                Ok(Some(PreItem::Let(name, ty, value, public)))
+                Ok(Some(PreItem::Let(
+                    name, ty, value, public, volatile, final_,
+                )))
             }
             Some(Tok::Class) => {
                 self.next();
                 self.class(false)
             }
+            Some(Tok::Interface) => {
+                self.next();
+                self.interface()
+            }
             Some(Tok::Extern | Tok::Fn | Tok::Enum) => {
                 // fn f(x: T, y: T): Z = x
                 let (public, ext) = match &*self.next().unwrap() {
@@ -1622,9 +2494,15 @@ impl<'a> Parser<'a> {
                 } else {
                     false
                 };
-                let (name, args, ret_type) = self.prototype()?;
+                let (name, type_params, args, defaults, varargs, ret_type) = self.prototype()?;
 
                 if ext {
+                    if !type_params.is_empty() {
+                        return Err(self.err("extern functions can't have type parameters"));
+                    }
+                    if !defaults.is_empty() {
+                        return Err(self.err("extern functions can't have default values"));
+                    }
                     self.expect(Tok::Equals, "'='")?;
                     let mapping = match self.peek().as_deref() {
                         Some(Tok::LitS(m)) => self.lexer.bindings.raw(m),
@@ -1638,6 +2516,8 @@ impl<'a> Parser<'a> {
                         ret_ty: ret_type,
                         args,
                         mapping,
+                        is_static: false,
+                        varargs,
                     })))
                 } else {
                     let mut throws = Vec::new();
@@ -1663,12 +2543,17 @@ impl<'a> Parser<'a> {
 
                     Ok(Some(PreItem::Fn(PreFn {
                         name,
+                        type_params,
                         ret_ty: ret_type,
                         args,
                         body,
                         public,
                         throws,
                         inline,
+                        annotations,
+                        is_static: true,
+                        defaults,
+                        varargs,
                     })))
                 }
             }
@@ -1703,21 +2588,70 @@ impl<'a> Parser<'a> {
         }
     }
 
+    /// Parses the `'outer` after a `break` or `continue`, if there is one
+    fn label(&mut self) -> Option<RawSym> {
+        match self.peek().as_deref() {
+            Some(Tok::Label(l)) => {
+                let l = *l;
+                self.next();
+                Some(l)
+            }
+            _ => None,
+        }
+    }
+
+    /// Parses a block of statements in braces, like the body of a `try`
+    fn stmt_block(&mut self) -> Result<Vec<PreStatement>, Error> {
+        self.expect(Tok::OpenBrace, "'{'")?;
+        let mut block = Vec::new();
+        loop {
+            if self.peek().as_deref() == Some(&Tok::CloseBrace) {
+                self.next();
+                break;
+            }
+
+            let stmt = self.stmt()?.ok_or(self.err("expected statement"))?;
+            if matches!(&stmt, PreStatement::Term(x) if x.needs_semicolon()) {
+                self.expect(Tok::Semicolon, "';' after expression statement")?;
+            }
+            block.push(stmt);
+        }
+        Ok(block)
+    }
+
     fn stmt(&mut self) -> Result<Option<PreStatement>, Error> {
         self.defines()?;
         let ifdef = self.ifdef()?;
         let i = match self.peek().as_deref() {
+            Some(Tok::Label(l)) => {
+                // 'outer: while ... { ... }
+                let l = *l;
+                self.next();
+                self.expect(Tok::Colon, "':' after loop label")?;
+                if !matches!(
+                    self.peek().as_deref(),
+                    Some(Tok::While | Tok::Loop | Tok::Do | Tok::For)
+                ) {
+                    return Err(self.err("expected loop after label"));
+                }
+                let stmt = self.stmt()?.ok_or(self.err("expected loop after label"))?;
+                Ok(Some(PreStatement::Labeled(l, Box::new(stmt))))
+            }
             Some(
-                Tok::Fn | Tok::Extern | Tok::ExternBlock(_) | Tok::Let | Tok::Enum | Tok::Class,
+                Tok::Fn
+                | Tok::Annotation(_)
+                | Tok::Extern
+                | Tok::ExternBlock(_)
+                | Tok::Let
+                | Tok::Enum
+                | Tok::Class
+                | Tok::Interface,
             ) => Ok(self.item()?.map(PreStatement::Item)),
-            Some(Tok::While | Tok::Loop) => {
-                let cond = match &*self.next().unwrap() {
-                    Tok::While => self.term()?.ok_or(self.err("expected while condition"))?,
-                    Tok::Loop => Box::new(Spanned::new(
-                        Pre::Lit(Literal::Bool(true), None),
-                        self.span(),
-                    )),
-                    _ => unreachable!(),
+            Some(Tok::While | Tok::Loop | Tok::Do) => {
+                let kw = self.next().unwrap().inner;
+                let cond = match kw {
+                    Tok::While => Some(self.term()?.ok_or(self.err("expected while condition"))?),
+                    _ => None,
                 };
 
                 self.expect(Tok::OpenBrace, "'{'")?;
@@ -1735,7 +2669,17 @@ impl<'a> Parser<'a> {
                     block.push(stmt);
                 }
 
-                Ok(Some(PreStatement::While(cond, block)))
+                match (kw, cond) {
+                    (Tok::Do, _) => {
+                        // do { ... } while cond;
+                        self.expect(Tok::While, "'while' after do-while body")?;
+                        let cond = self.term()?.ok_or(self.err("expected while condition"))?;
+                        self.expect(Tok::Semicolon, "';' after do-while condition")?;
+                        Ok(Some(PreStatement::DoWhile(block, cond)))
+                    }
+                    (_, Some(cond)) => Ok(Some(PreStatement::While(cond, block))),
+                    (_, None) => Ok(Some(PreStatement::Loop(block))),
+                }
             }
             Some(Tok::For) => {
                 self.next();
@@ -1757,11 +2701,36 @@ impl<'a> Parser<'a> {
                 };
 
                 let a = self.term()?.ok_or(self.err("expected expression"))?;
-                let b = if self.peek().as_deref() == Some(&Tok::DotDot) {
-                    self.next();
-                    Some(self.term()?.ok_or(self.err("expected expression"))?)
-                } else {
-                    None
+                let b = match self.peek().as_deref() {
+                    Some(Tok::DotDot) | Some(Tok::DotDotEq) => {
+                        let inclusive = self.peek().as_deref() == Some(&Tok::DotDotEq);
+                        self.next();
+                        let b = self.term()?.ok_or(self.err("expected expression"))?;
+                        // `step` is only a keyword here, so it can still be used as a name
+                        let step = if self.contextual("step") {
+                            Some(self.term()?.ok_or(self.err("expected step"))?)
+                        } else {
+                            None
+                        };
+                        Some((b, inclusive, step, false))
+                    }
+                    _ => None,
+                };
+                // for i in (a..b) or (a..b).rev()
+                let rev = self.lexer.bindings.raw("rev");
+                let range = match &**a {
+                    Pre::Range(x, y, inclusive) => Some((x, y, *inclusive, false)),
+                    Pre::Method(r, m, args) if **m == rev && args.is_empty() => match &***r {
+                        Pre::Range(x, y, inclusive) => Some((x, y, *inclusive, true)),
+                        _ => None,
+                    },
+                    _ => None,
+                };
+                let (a, b) = match (b, range) {
+                    (None, Some((x, y, inclusive, rev))) => {
+                        (x.clone(), Some((y.clone(), inclusive, None, rev)))
+                    }
+                    (b, _) => (a, b),
                 };
 
                 self.expect(Tok::OpenBrace, "'{'")?;
@@ -1781,6 +2750,57 @@ impl<'a> Parser<'a> {
 
                 Ok(Some(PreStatement::For(var, public, unroll, a, b, block)))
             }
+            Some(Tok::Try) => {
+                // try { ... } catch e: Exception { ... }
+                self.next();
+                let body = self.stmt_block()?;
+                let mut handlers = Vec::new();
+                while self.peek().as_deref() == Some(&Tok::Catch) {
+                    self.next();
+                    let name = self.ident().ok_or(self.err("expected exception name"))?;
+                    self.expect(Tok::Colon, "':' after exception name")?;
+                    let ty = self.ty()?.ok_or(self.err("expected exception type"))?;
+                    handlers.push((name, ty, self.stmt_block()?));
+                }
+                if handlers.is_empty() {
+                    return Err(self.err("expected 'catch' after try block"));
+                }
+                Ok(Some(PreStatement::Try(body, handlers)))
+            }
+            Some(Tok::Throw) => {
+                self.next();
+                let x = self
+                    .term()?
+                    .ok_or(self.err("expected exception to throw"))?;
+                self.expect(Tok::Semicolon, "';' after throw")?;
+                Ok(Some(PreStatement::Throw(x)))
+            }
+            Some(Tok::Assert) => {
+                // assert cond, "message";
+                self.next();
+                let cond = self
+                    .term()?
+                    .ok_or(self.err("expected assertion condition"))?;
+                let msg = if self.peek().as_deref() == Some(&Tok::Comma) {
+                    self.next();
+                    self.term()?.ok_or(self.err("expected assertion message"))?
+                } else {
+                    // Without a message, the condition's source is the message
+                    let src = self.lexer.input[cond.span.0..cond.span.1]
+                        .split_whitespace()
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                        .replace('\\', "\\\\")
+                        .replace('"', "\\\"");
+                    let msg = format!("assertion failed: {}", src);
+                    Box::new(Spanned::new(
+                        Pre::Lit(Literal::Str(self.lexer.bindings.raw(msg)), None),
+                        cond.span,
+                    ))
+                };
+                self.expect(Tok::Semicolon, "';' after assertion")?;
+                Ok(Some(PreStatement::Assert(cond, msg)))
+            }
             _ => Ok(self.term()?.map(PreStatement::Term)),
         };
         if ifdef.resolve(self) {
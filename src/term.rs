@@ -65,6 +65,7 @@ pub enum BinOp {
     BitXor,
     BitShr,
     BitShl,
+    BitUshr,
     And,
     Or,
 }
@@ -86,21 +87,30 @@ impl BinOp {
             | BinOp::BitOr
             | BinOp::BitXor
             | BinOp::BitShr
-            | BinOp::BitShl => BinOpType::Arith,
+            | BinOp::BitShl
+            | BinOp::BitUshr => BinOpType::Arith,
             BinOp::Gt | BinOp::Lt | BinOp::Eq | BinOp::Neq | BinOp::Geq | BinOp::Leq => {
                 BinOpType::Comp
             }
             BinOp::And | BinOp::Or => BinOpType::Logic,
         }
     }
+
+    /// Shifts allow any integer type on the right, like Java.
+    pub fn is_shift(self) -> bool {
+        matches!(self, BinOp::BitShr | BinOp::BitShl | BinOp::BitUshr)
+    }
 }
 
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Literal {
     /// Java doesn't have unsigned integers, which makes int literals convenient
     Int(i64),
+    Float(f64),
     Str(RawSym),
     Bool(bool),
+    /// A UTF-16 code unit, like Java's `char`
+    Char(u16),
 }
 
 lazy_static::lazy_static! {
@@ -214,7 +224,16 @@ pub struct ClassInfo {
     pub methods: Vec<(RawSym, FnId, FnType)>,
     pub variants: Option<Vec<(RawSym, Vec<Type>)>>,
     pub members: Vec<(RawSym, Sym, Type)>,
+    /// The members that have an initial value, which class literals can leave out
+    pub initialized: Vec<Sym>,
     pub constructor: Option<Vec<Type>>,
+    /// Whether this is an extern class, which can be thrown if it's an exception
+    pub ext: bool,
+    pub interface: bool,
+    /// The interfaces this class implements, which it can be used as
+    pub implements: Vec<Type>,
+    /// The class this class extends, which it can also be used as and inherits methods and members from
+    pub extends: Option<Type>,
 }
 
 pub enum ArrayMethod {
@@ -222,6 +241,47 @@ pub enum ArrayMethod {
     Pop,
     Clear,
     Push(Box<Term>),
+    // (idx, value)
+    Insert(Box<Term>, Box<Term>),
+    RemoveAt(Box<Term>),
+    SwapRemove(Box<Term>),
+    IndexOf(Box<Term>),
+    Contains(Box<Term>),
+    Reserve(Box<Term>),
+}
+
+pub enum StrMethod {
+    CodePointAt(Box<Term>),
+    /// `s[i]`, the UTF-16 code unit at `i` as a `char`
+    CharAt(Box<Term>),
+    Len,
+    // (start, end)
+    Substring(Box<Term>, Box<Term>),
+    StartsWith(Box<Term>),
+    EndsWith(Box<Term>),
+    Contains(Box<Term>),
+}
+
+pub enum BuilderMethod {
+    Append(Box<Term>),
+    Finish,
+}
+
+/// Built-in functions that take one argument, like `parseInt(s)`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Builtin {
+    ParseInt,
+    ParseLong,
+    /// `str(x)` for a number or `bool`, which is Java's `String.valueOf`
+    ToStr,
+}
+
+pub enum MapMethod {
+    Get(Box<Term>),
+    // (key, value)
+    Put(Box<Term>, Box<Term>),
+    ContainsKey(Box<Term>),
+    Remove(Box<Term>),
 }
 
 pub enum LValue {
@@ -234,8 +294,10 @@ pub enum LValue {
 }
 
 pub enum ForIter {
-    // for i in [unroll] 0..10 (note: only i32)
-    Range(Box<Term>, Box<Term>, bool),
+    // for i in [unroll] 0..10 [step 2] (note: only i32)
+    // The second bool is whether it's inclusive, i.e. 0..=10
+    // The third bool is whether it's reversed, i.e. (0..10).rev(), which counts down from 9 to 0
+    Range(Box<Term>, Box<Term>, Option<Box<Term>>, bool, bool, bool),
     // for i: t in arr
     Array(Box<Term>),
     SArray(Box<Term>, Type),
@@ -248,37 +310,74 @@ pub enum Term {
     BinOp(BinOp, Box<Term>, Box<Term>),
     Block(Vec<Statement>, Option<Box<Term>>),
     If(Box<Term>, Box<Term>, Option<Box<Term>>),
-    Break,
-    Continue,
+    /// `break 'label`, or the innermost loop if there's no label
+    Break(Option<RawSym>),
+    Continue(Option<RawSym>),
     Return(Option<Box<Term>>),
     Variant(TypeId, RawSym, Vec<Term>),
     Tuple(Vec<Term>),
-    TupleIdx(Box<Term>, usize),
+    // (tuple, idx, element types (needed to find the element's components in backend))
+    TupleIdx(Box<Term>, usize, Vec<Type>),
     // (array, inner type (needed for empty arrays in backend), is dynamic)
     Array(Vec<Term>, Type, bool),
     // (arr, idx, static, inner_ty, inline)
     ArrayIdx(Box<Term>, Box<Term>, bool, Type, bool),
+    // (arr, start, end), only for dynamic arrays
+    ArraySlice(Box<Term>, Box<Term>, Box<Term>),
     // (len, ty)
     ArrayNew(Box<Term>, Type),
+    // (value, len)
+    ArrayRepeat(Box<Term>, Box<Term>),
     ArrayMethod(Box<Term>, ArrayMethod),
+    StrMethod(Box<Term>, StrMethod),
+    BuilderMethod(Box<Term>, BuilderMethod),
+    MapMethod(Box<Term>, MapMethod),
+    Builtin(Builtin, Box<Term>),
     Member(Box<Term>, Sym),
     Constructor(TypeId, Vec<Term>),
     Set(LValue, Option<BinOp>, Box<Term>),
-    Match(
-        TypeId,
-        Box<Term>,
-        Vec<(Option<RawSym>, Vec<(Sym, Type)>, Term)>,
-    ),
+    Match(TypeId, Box<Term>, Vec<MatchBranch>),
+    OptionSome(Box<Term>),
+    OptionNone(Type),
+    // Like Match, but the scrutinee is an `Option` and the variants are `Some` and `None`
+    MatchOption(Box<Term>, Vec<MatchBranch>),
+    // Like Match, but the scrutinee is an `i32` and the patterns are integer literals, with None for the default branch
+    MatchInt(Box<Term>, Vec<(Option<i32>, Term)>),
     Not(Box<Term>),
+    BitNot(Box<Term>),
+    Neg(Box<Term>),
+    Cast(Box<Term>, Type),
+    InstanceOf(Box<Term>, TypeId),
     Null(Type),
     Selph(TypeId),
+    // The zero value of a type, the same as an uninitialized Java variable
+    Default(Type),
+    // (args, body, return type, functional interface type), lowered to a Java lambda
+    Lambda(Vec<(Sym, Type)>, Box<Term>, Type, Type),
+    // A call to the superclass constructor, which only appears in a class's `Item`
+    Super(Vec<Term>),
 }
+/// (variant, captures, body), where a variant of None is the default branch
+pub type MatchBranch = (Option<RawSym>, Vec<(Sym, Type)>, Term);
 pub enum Statement {
     Term(Term),
     Let(Sym, Type, Term),
     While(Term, Vec<Statement>),
+    Loop(Vec<Statement>),
+    /// do { block } while cond;
+    DoWhile(Vec<Statement>, Term),
+    /// 'label: loop
+    Labeled(RawSym, Box<Statement>),
     For(Sym, ForIter, Vec<Statement>),
+    /// throw e; where e is an instance of an extern class
+    Throw(Term),
+    /// try { body } catch e: Exception { handler }
+    Try(Vec<Statement>, Vec<(TypeId, Sym, Vec<Statement>)>),
+    /// assert cond, message; which does nothing unless assertions are enabled
+    Assert(Term, Term),
     InlineJava(RawSym),
+    /// A statement along with where it came from in the source, for debug comments in the generated Java
+    Src(FileId, Span, Box<Statement>),
 }
 
 pub enum Item {
@@ -294,17 +393,38 @@ pub enum Item {
         Vec<(Sym, Type)>,
         Vec<Fn>,
     ),
-    Class(TypeId, Vec<(Sym, Type, Option<Term>)>, Vec<Fn>),
+    /// The last fields are the interfaces it implements, and the class it extends along with the `super(...)` call if there is one
+    Class(
+        TypeId,
+        Vec<(Sym, Type, Option<Term>)>,
+        Vec<Fn>,
+        Vec<Type>,
+        Option<(Type, Option<Term>)>,
+    ),
+    Interface(TypeId, Vec<FnSig>),
     Let(Sym, Type, Option<Term>),
 }
+/// A method of an interface, which implementing classes define with the same name
+pub struct FnSig {
+    pub name: RawSym,
+    pub args: Vec<(RawSym, Type)>,
+    pub ret_ty: Type,
+}
 pub struct Fn {
     pub id: FnId,
     pub public: bool,
+    pub type_params: Vec<RawSym>,
     pub ret_ty: Type,
     pub args: Vec<(Sym, Type)>,
     pub body: Term,
     pub throws: Vec<RawSym>,
     pub inline: bool,
+    /// Java annotations like `Override`, without the `@`
+    pub annotations: Vec<RawSym>,
+    /// Whether it's generated as a `static` method, which is true for everything outside of classes
+    pub is_static: bool,
+    /// The default values of the last `defaults.len()` arguments, which calls that leave them out get when lowered
+    pub defaults: Vec<Term>,
 }
 pub struct ExternFn {
     pub id: FnId,
@@ -315,15 +435,27 @@ pub struct ExternFn {
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum Type {
+    I8,
+    I16,
     I32,
     I64,
+    F32,
+    F64,
     Bool,
+    Char,
     Str,
+    StrBuilder,
     Unit,
-    Class(TypeId),
+    /// The type arguments are empty unless the class is generic, like `Box<str>`
+    Class(TypeId, Vec<Type>),
     Tuple(Vec<Type>),
     Array(Box<Type>),
     SArray(Box<Type>, usize),
+    Option(Box<Type>),
+    /// A Java `HashMap`, with the key and value types
+    Map(Box<Type>, Box<Type>),
+    /// A type parameter of the enclosing generic function, which becomes a Java generic
+    Param(RawSym),
 }
 
 impl Term {
@@ -338,6 +470,158 @@ impl Term {
             _ => None,
         }
     }
+
+    /// Whether `to_lval()` would succeed, without consuming the term
+    pub fn is_lval(&self) -> bool {
+        match self {
+            Term::Var(_) | Term::Member(_, _) => true,
+            Term::ArrayIdx(b, _, _, _, _) => b.is_lval(),
+            _ => false,
+        }
+    }
+
+    /// Whether `f` returns true for this term or any term inside it
+    pub fn any(&self, f: &mut dyn FnMut(&Term) -> bool) -> bool {
+        if f(self) {
+            return true;
+        }
+        match self {
+            Term::Var(_)
+            | Term::Lit(_, _)
+            | Term::Break(_)
+            | Term::Continue(_)
+            | Term::Null(_)
+            | Term::Selph(_)
+            | Term::Default(_)
+            | Term::OptionNone(_) => false,
+            Term::Call(o, _, a) => o.iter().any(|x| x.any(f)) || a.iter().any(|x| x.any(f)),
+            Term::BinOp(_, a, b) | Term::ArrayIdx(a, b, _, _, _) | Term::ArrayRepeat(a, b) => {
+                a.any(f) || b.any(f)
+            }
+            Term::ArraySlice(x, a, b) => x.any(f) || a.any(f) || b.any(f),
+            Term::Block(v, e) => v.iter().any(|x| x.any(f)) || e.iter().any(|x| x.any(f)),
+            Term::If(a, b, c) => a.any(f) || b.any(f) || c.iter().any(|x| x.any(f)),
+            Term::Return(x) => x.iter().any(|x| x.any(f)),
+            Term::Variant(_, _, v)
+            | Term::Tuple(v)
+            | Term::Array(v, _, _)
+            | Term::Constructor(_, v)
+            | Term::Super(v) => v.iter().any(|x| x.any(f)),
+            Term::TupleIdx(x, _, _)
+            | Term::ArrayNew(x, _)
+            | Term::Member(x, _)
+            | Term::OptionSome(x)
+            | Term::Not(x)
+            | Term::BitNot(x)
+            | Term::Neg(x)
+            | Term::Cast(x, _)
+            | Term::InstanceOf(x, _)
+            | Term::Builtin(_, x)
+            | Term::Lambda(_, x, _, _) => x.any(f),
+            Term::ArrayMethod(x, m) => {
+                x.any(f)
+                    || match m {
+                        ArrayMethod::Push(y)
+                        | ArrayMethod::RemoveAt(y)
+                        | ArrayMethod::SwapRemove(y)
+                        | ArrayMethod::IndexOf(y)
+                        | ArrayMethod::Contains(y)
+                        | ArrayMethod::Reserve(y) => y.any(f),
+                        ArrayMethod::Insert(i, y) => i.any(f) || y.any(f),
+                        _ => false,
+                    }
+            }
+            Term::StrMethod(x, m) => {
+                x.any(f)
+                    || match m {
+                        StrMethod::CodePointAt(i)
+                        | StrMethod::CharAt(i)
+                        | StrMethod::StartsWith(i)
+                        | StrMethod::EndsWith(i)
+                        | StrMethod::Contains(i) => i.any(f),
+                        StrMethod::Substring(a, b) => a.any(f) || b.any(f),
+                        StrMethod::Len => false,
+                    }
+            }
+            Term::BuilderMethod(x, m) => {
+                x.any(f)
+                    || match m {
+                        BuilderMethod::Append(y) => y.any(f),
+                        BuilderMethod::Finish => false,
+                    }
+            }
+            Term::MapMethod(x, m) => {
+                x.any(f)
+                    || match m {
+                        MapMethod::Get(k) | MapMethod::ContainsKey(k) | MapMethod::Remove(k) => {
+                            k.any(f)
+                        }
+                        MapMethod::Put(k, v) => k.any(f) || v.any(f),
+                    }
+            }
+            Term::Set(l, _, x) => l.any(f) || x.any(f),
+            Term::Match(_, x, branches) | Term::MatchOption(x, branches) => {
+                x.any(f) || branches.iter().any(|(_, _, t)| t.any(f))
+            }
+            Term::MatchInt(x, branches) => x.any(f) || branches.iter().any(|(_, t)| t.any(f)),
+        }
+    }
+
+    /// Whether this term always jumps somewhere else with `return`, `break` or `continue` instead of having a value
+    pub fn diverges(&self) -> bool {
+        match self {
+            Term::Return(_) | Term::Break(_) | Term::Continue(_) => true,
+            Term::Block(_, e) => e.as_ref().is_some_and(|e| e.diverges()),
+            Term::If(c, a, b) => {
+                c.diverges() || (a.diverges() && b.as_ref().is_some_and(|b| b.diverges()))
+            }
+            Term::Match(_, x, branches) | Term::MatchOption(x, branches) => {
+                x.diverges() || branches.iter().all(|(_, _, t)| t.diverges())
+            }
+            Term::MatchInt(x, branches) => {
+                x.diverges() || branches.iter().all(|(_, t)| t.diverges())
+            }
+            Term::Tuple(v) => v.iter().any(Term::diverges),
+            Term::OptionSome(x) => x.diverges(),
+            _ => false,
+        }
+    }
+}
+impl LValue {
+    fn any(&self, f: &mut dyn FnMut(&Term) -> bool) -> bool {
+        match self {
+            LValue::Var(_) => false,
+            LValue::Idx(l, i, _) => l.any(f) || i.any(f),
+            LValue::Member(x, _) => x.any(f),
+        }
+    }
+}
+impl Statement {
+    /// Whether `f` returns true for any term inside this statement
+    pub fn any(&self, f: &mut dyn FnMut(&Term) -> bool) -> bool {
+        match self {
+            Statement::Term(x) | Statement::Let(_, _, x) | Statement::Throw(x) => x.any(f),
+            Statement::Assert(c, m) => c.any(f) || m.any(f),
+            Statement::While(c, v) => c.any(f) || v.iter().any(|x| x.any(f)),
+            Statement::Loop(v) => v.iter().any(|x| x.any(f)),
+            Statement::DoWhile(v, c) => v.iter().any(|x| x.any(f)) || c.any(f),
+            Statement::Labeled(_, s) | Statement::Src(_, _, s) => s.any(f),
+            Statement::For(_, iter, v) => {
+                let iter = match iter {
+                    ForIter::Range(a, b, step, _, _, _) => {
+                        a.any(f) || b.any(f) || matches!(step, Some(x) if x.any(f))
+                    }
+                    ForIter::Array(a) | ForIter::SArray(a, _) => a.any(f),
+                };
+                iter || v.iter().any(|x| x.any(f))
+            }
+            Statement::Try(body, handlers) => {
+                body.iter().any(|x| x.any(f))
+                    || handlers.iter().any(|(_, _, v)| v.iter().any(|x| x.any(f)))
+            }
+            Statement::InlineJava(_) => false,
+        }
+    }
 }
 
 // Presyntax
@@ -378,6 +662,8 @@ pub enum Pre {
     Lit(Literal, Option<PreType>),
     // f(a, b, c)
     Call(RawPath, Vec<SPre>),
+    // Point { x: a, y: b }
+    ClassLit(RawPath, Vec<(Spanned<RawSym>, SPre)>),
     // o.f(a, b, c)
     Method(SPre, Spanned<RawSym>, Vec<SPre>),
     // a + b
@@ -386,10 +672,10 @@ pub enum Pre {
     Block(Vec<PreStatement>, Option<SPre>),
     // if a { b } else { c }
     If(SPre, SPre, Option<SPre>),
-    // break
-    Break,
-    // continue
-    Continue,
+    // break 'a
+    Break(Option<RawSym>),
+    // continue 'a
+    Continue(Option<RawSym>),
     // return x
     Return(Option<SPre>),
     // (a, b)
@@ -400,34 +686,75 @@ pub enum Pre {
     Array(Vec<SPre>),
     // x[(inline) i]
     ArrayIdx(SPre, SPre, bool),
+    // x[a..b]
+    ArraySlice(SPre, SPre, SPre),
+    // (a..b) or (a..=b), which is only allowed as the range of a for loop
+    Range(SPre, SPre, bool),
     // [; 84]
     ArrayNew(SPre),
+    // [x; 84]
+    ArrayRepeat(SPre, SPre),
     // x.m
     Member(SPre, Spanned<RawSym>),
     // v op= x
     Set(SPre, Option<BinOp>, SPre),
     // match x { s => t, else => u }
-    Match(
-        SPre,
-        Vec<(Spanned<Option<RawSym>>, Vec<(Spanned<RawSym>, bool)>, SPre)>,
-    ),
+    Match(SPre, Vec<PreMatchBranch>),
     // !x
     Not(SPre),
+    // ~x
+    BitNot(SPre),
+    // -x
+    Neg(SPre),
+    // x as T
+    Cast(SPre, PreType),
+    // x instanceof T
+    InstanceOf(SPre, PreType),
     // null
     Null,
     // self
     Selph,
+    // default::<T>()
+    Default(PreType),
+    // x?
+    Try(SPre),
+    // |a, b: T| x
+    Lambda(Vec<(Spanned<RawSym>, Option<PreType>)>, SPre),
+}
+
+/// (name, public, volatile, type, initial value)
+pub type PreMember = (Spanned<RawSym>, bool, bool, PreType, Option<SPre>);
+
+/// (pattern, captures with whether they're public, body)
+pub type PreMatchBranch = (Spanned<PrePattern>, Vec<(Spanned<RawSym>, bool)>, SPre);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PrePattern {
+    Variant(RawSym),
+    Int(i64),
+    // else
+    Default,
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct PreFn {
     pub name: Spanned<RawSym>,
     pub public: bool,
+    /// `fn pick<T>(...)`, only allowed on functions outside of classes
+    pub type_params: Vec<Spanned<RawSym>>,
     pub ret_ty: PreType,
     pub args: Vec<(Spanned<RawSym>, PreType, bool)>,
     pub body: SPre,
     pub throws: Vec<RawSym>,
     pub inline: bool,
+    /// Java annotations like `Override`, without the `@`
+    pub annotations: Vec<RawSym>,
+    /// Whether it's static, which functions outside of classes always are; methods are with `fn static name()`
+    pub is_static: bool,
+    /// The default values of the last `defaults.len()` arguments
+    pub defaults: Vec<SPre>,
+    /// Whether the last parameter is `name: T...`, which collects any extra arguments into a `[T]`
+    pub varargs: bool,
 }
 #[derive(Clone, Debug, PartialEq)]
 pub struct PreEFn {
@@ -435,6 +762,10 @@ pub struct PreEFn {
     pub ret_ty: PreType,
     pub args: Vec<(Spanned<RawSym>, PreType, bool)>,
     pub mapping: RawSym,
+    /// Whether it's a static method of an extern class, called as `Class::method()`
+    pub is_static: bool,
+    /// Whether the last parameter is `name: T...`, a Java varargs parameter
+    pub varargs: bool,
 }
 #[derive(Clone, Debug, PartialEq)]
 pub enum PreFnEither {
@@ -450,12 +781,34 @@ pub enum PreItem {
     Class {
         ext: bool,
         path: RawPath,
-        variants: Option<Vec<(RawSym, Vec<PreType>)>>,
+        /// `class Box<T>`, always empty for enums
+        type_params: Vec<Spanned<RawSym>>,
+        variants: Option<Vec<(Spanned<RawSym>, Vec<PreType>)>>,
         methods: Vec<PreFnEither>,
-        members: Vec<(Spanned<RawSym>, bool, PreType, Option<SPre>)>,
+        members: Vec<PreMember>,
         constructor: Option<Vec<PreType>>,
+        /// `class Circle: Shape`, always empty for enums
+        implements: Vec<PreType>,
+        /// `class Bot extends Base`, always None for enums
+        extends: Option<Box<PreType>>,
+        /// A `super(a, b);` in the class body
+        super_args: Option<Spanned<Vec<SPre>>>,
+    },
+    /// The methods' mappings are always their own names, since implementing classes define them with those names
+    Interface {
+        path: RawPath,
+        type_params: Vec<Spanned<RawSym>>,
+        methods: Vec<PreEFn>,
     },
-    Let(Spanned<RawSym>, Option<PreType>, Option<SPre>, bool),
+    // let [pub] [volatile] [final] a: t = x; the bools are public, volatile and final
+    Let(
+        Spanned<RawSym>,
+        Option<PreType>,
+        Option<SPre>,
+        bool,
+        bool,
+        bool,
+    ),
     // use a::b; the bool is true if it's a wildcard a::b::*
     Use(RawPath, bool),
 }
@@ -465,29 +818,69 @@ pub enum PreStatement {
     Item(PreItem),
     Term(SPre),
     While(SPre, Vec<PreStatement>),
-    // for pub a in [unroll] b..c
+    Loop(Vec<PreStatement>),
+    DoWhile(Vec<PreStatement>, SPre),
+    // 'a: loop
+    Labeled(RawSym, Box<PreStatement>),
+    // for pub a in [unroll] b..c or b..=c [step d] (the bools are whether it's inclusive and whether it's reversed)
     For(
         Spanned<RawSym>,
         bool,
         bool,
         SPre,
-        Option<SPre>,
+        Option<(SPre, bool, Option<SPre>, bool)>,
+        Vec<PreStatement>,
+    ),
+    // throw e;
+    Throw(SPre),
+    // assert cond, message;
+    Assert(SPre, SPre),
+    // try { ... } catch e: Exception { ... }
+    Try(
         Vec<PreStatement>,
+        Vec<(Spanned<RawSym>, PreType, Vec<PreStatement>)>,
     ),
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum PreType {
+    I8,
+    I16,
     I32,
     I64,
+    F32,
+    F64,
     Bool,
+    Char,
     Str,
+    StrBuilder,
     Class(RawPath),
+    // Option<T>
+    Generic(RawPath, Vec<PreType>),
     Tuple(Vec<PreType>),
     Array(Box<PreType>),
     SArray(Box<PreType>, usize),
 }
 
+impl PreStatement {
+    /// The span that debug comments for this statement point to, if it has one
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            PreStatement::Item(PreItem::Let(n, _, _, _, _, _)) => Some(n.span),
+            PreStatement::Item(_) => None,
+            PreStatement::Term(x)
+            | PreStatement::While(x, _)
+            | PreStatement::DoWhile(_, x)
+            | PreStatement::Throw(x)
+            | PreStatement::Assert(x, _) => Some(x.span),
+            PreStatement::For(n, _, _, _, _, _) => Some(n.span),
+            // The label goes on the loop inside, which has the span
+            PreStatement::Labeled(_, _) => None,
+            PreStatement::Loop(_) | PreStatement::Try(_, _) => None,
+        }
+    }
+}
+
 impl Pre {
     pub fn needs_semicolon(&self) -> bool {
         match self {
@@ -558,7 +951,7 @@ impl Term {
                 Term::Variant(*tid, *s, xs.iter().map(|x| x.cloned_(cln)).collect())
             }
             Term::Tuple(v) => Term::Tuple(v.iter().map(|x| x.cloned_(cln)).collect()),
-            Term::TupleIdx(x, i) => Term::TupleIdx(Box::new(x.cloned_(cln)), *i),
+            Term::TupleIdx(x, i, t) => Term::TupleIdx(Box::new(x.cloned_(cln)), *i, t.clone()),
             Term::Array(v, t, d) => {
                 Term::Array(v.iter().map(|x| x.cloned_(cln)).collect(), t.clone(), *d)
             }
@@ -569,10 +962,24 @@ impl Term {
                 t.clone(),
                 *inl,
             ),
+            Term::ArraySlice(arr, a, b) => Term::ArraySlice(
+                Box::new(arr.cloned_(cln)),
+                Box::new(a.cloned_(cln)),
+                Box::new(b.cloned_(cln)),
+            ),
             Term::ArrayNew(x, t) => Term::ArrayNew(Box::new(x.cloned_(cln)), t.clone()),
+            Term::ArrayRepeat(x, l) => {
+                Term::ArrayRepeat(Box::new(x.cloned_(cln)), Box::new(l.cloned_(cln)))
+            }
             Term::ArrayMethod(arr, m) => {
                 Term::ArrayMethod(Box::new(arr.cloned_(cln)), m.cloned_(cln))
             }
+            Term::StrMethod(s, m) => Term::StrMethod(Box::new(s.cloned_(cln)), m.cloned_(cln)),
+            Term::BuilderMethod(s, m) => {
+                Term::BuilderMethod(Box::new(s.cloned_(cln)), m.cloned_(cln))
+            }
+            Term::MapMethod(s, m) => Term::MapMethod(Box::new(s.cloned_(cln)), m.cloned_(cln)),
+            Term::Builtin(b, x) => Term::Builtin(*b, Box::new(x.cloned_(cln))),
             Term::Call(o, f, a) => Term::Call(
                 o.as_ref().map(|o| Box::new(o.cloned_(cln))),
                 *f,
@@ -597,17 +1004,39 @@ impl Term {
                     .collect();
                 Term::Match(*tid, Box::new(x.cloned_(cln)), branches)
             }
+            Term::OptionSome(x) => Term::OptionSome(Box::new(x.cloned_(cln))),
+            Term::OptionNone(t) => Term::OptionNone(t.clone()),
+            Term::MatchOption(x, branches) => {
+                let branches = branches
+                    .iter()
+                    .map(|(s, v, t)| (*s, v.clone(), t.cloned_(cln)))
+                    .collect();
+                Term::MatchOption(Box::new(x.cloned_(cln)), branches)
+            }
+            Term::MatchInt(x, branches) => {
+                let branches = branches.iter().map(|(i, t)| (*i, t.cloned_(cln))).collect();
+                Term::MatchInt(Box::new(x.cloned_(cln)), branches)
+            }
             Term::Set(l, op, x) => Term::Set(l.cloned_(cln), *op, Box::new(x.cloned_(cln))),
-            Term::Break => Term::Break,
-            Term::Continue => Term::Continue,
+            Term::Break(l) => Term::Break(*l),
+            Term::Continue(l) => Term::Continue(*l),
             Term::Return(x) => Term::Return(x.as_ref().map(|x| Box::new(x.cloned_(cln)))),
             Term::Member(a, b) => Term::Member(Box::new(a.cloned_(cln)), *b),
             Term::Constructor(f, a) => {
                 Term::Constructor(*f, a.iter().map(|x| x.cloned_(cln)).collect())
             }
             Term::Not(x) => Term::Not(Box::new(x.cloned_(cln))),
+            Term::BitNot(x) => Term::BitNot(Box::new(x.cloned_(cln))),
+            Term::Neg(x) => Term::Neg(Box::new(x.cloned_(cln))),
+            Term::Cast(x, t) => Term::Cast(Box::new(x.cloned_(cln)), t.clone()),
+            Term::InstanceOf(x, t) => Term::InstanceOf(Box::new(x.cloned_(cln)), *t),
             Term::Null(t) => Term::Null(t.clone()),
             Term::Selph(t) => Term::Selph(*t),
+            Term::Default(t) => Term::Default(t.clone()),
+            Term::Lambda(a, x, r, t) => {
+                Term::Lambda(a.clone(), Box::new(x.cloned_(cln)), r.clone(), t.clone())
+            }
+            Term::Super(a) => Term::Super(a.iter().map(|x| x.cloned_(cln)).collect()),
         }
     }
 }
@@ -619,12 +1048,27 @@ impl Statement {
             Statement::While(a, b) => {
                 Statement::While(a.cloned_(cln), b.iter().map(|x| x.cloned_(cln)).collect())
             }
+            Statement::Loop(b) => Statement::Loop(b.iter().map(|x| x.cloned_(cln)).collect()),
+            Statement::DoWhile(a, b) => {
+                Statement::DoWhile(a.iter().map(|x| x.cloned_(cln)).collect(), b.cloned_(cln))
+            }
+            Statement::Labeled(l, s) => Statement::Labeled(*l, Box::new(s.cloned_(cln))),
             Statement::For(s, i, b) => Statement::For(
                 *s,
                 i.cloned_(cln),
                 b.iter().map(|x| x.cloned_(cln)).collect(),
             ),
+            Statement::Throw(x) => Statement::Throw(x.cloned_(cln)),
+            Statement::Assert(c, m) => Statement::Assert(c.cloned_(cln), m.cloned_(cln)),
+            Statement::Try(body, handlers) => Statement::Try(
+                body.iter().map(|x| x.cloned_(cln)).collect(),
+                handlers
+                    .iter()
+                    .map(|(t, s, v)| (*t, *s, v.iter().map(|x| x.cloned_(cln)).collect()))
+                    .collect(),
+            ),
             Statement::InlineJava(s) => Self::InlineJava(*s),
+            Statement::Src(file, span, s) => Statement::Src(*file, *span, Box::new(s.cloned_(cln))),
         }
     }
 }
@@ -635,6 +1079,49 @@ impl ArrayMethod {
             ArrayMethod::Pop => ArrayMethod::Pop,
             ArrayMethod::Clear => ArrayMethod::Clear,
             ArrayMethod::Push(x) => ArrayMethod::Push(Box::new(x.cloned_(cln))),
+            ArrayMethod::Insert(i, x) => {
+                ArrayMethod::Insert(Box::new(i.cloned_(cln)), Box::new(x.cloned_(cln)))
+            }
+            ArrayMethod::RemoveAt(i) => ArrayMethod::RemoveAt(Box::new(i.cloned_(cln))),
+            ArrayMethod::SwapRemove(i) => ArrayMethod::SwapRemove(Box::new(i.cloned_(cln))),
+            ArrayMethod::IndexOf(x) => ArrayMethod::IndexOf(Box::new(x.cloned_(cln))),
+            ArrayMethod::Contains(x) => ArrayMethod::Contains(Box::new(x.cloned_(cln))),
+            ArrayMethod::Reserve(n) => ArrayMethod::Reserve(Box::new(n.cloned_(cln))),
+        }
+    }
+}
+impl BuilderMethod {
+    fn cloned_(&self, cln: &mut Cloner) -> BuilderMethod {
+        match self {
+            BuilderMethod::Append(x) => BuilderMethod::Append(Box::new(x.cloned_(cln))),
+            BuilderMethod::Finish => BuilderMethod::Finish,
+        }
+    }
+}
+impl MapMethod {
+    fn cloned_(&self, cln: &mut Cloner) -> MapMethod {
+        match self {
+            MapMethod::Get(k) => MapMethod::Get(Box::new(k.cloned_(cln))),
+            MapMethod::Put(k, v) => {
+                MapMethod::Put(Box::new(k.cloned_(cln)), Box::new(v.cloned_(cln)))
+            }
+            MapMethod::ContainsKey(k) => MapMethod::ContainsKey(Box::new(k.cloned_(cln))),
+            MapMethod::Remove(k) => MapMethod::Remove(Box::new(k.cloned_(cln))),
+        }
+    }
+}
+impl StrMethod {
+    fn cloned_(&self, cln: &mut Cloner) -> StrMethod {
+        match self {
+            StrMethod::CodePointAt(i) => StrMethod::CodePointAt(Box::new(i.cloned_(cln))),
+            StrMethod::CharAt(i) => StrMethod::CharAt(Box::new(i.cloned_(cln))),
+            StrMethod::Len => StrMethod::Len,
+            StrMethod::Substring(a, b) => {
+                StrMethod::Substring(Box::new(a.cloned_(cln)), Box::new(b.cloned_(cln)))
+            }
+            StrMethod::StartsWith(x) => StrMethod::StartsWith(Box::new(x.cloned_(cln))),
+            StrMethod::EndsWith(x) => StrMethod::EndsWith(Box::new(x.cloned_(cln))),
+            StrMethod::Contains(x) => StrMethod::Contains(Box::new(x.cloned_(cln))),
         }
     }
 }
@@ -650,9 +1137,14 @@ impl LValue {
 impl ForIter {
     fn cloned_(&self, cln: &mut Cloner) -> ForIter {
         match self {
-            ForIter::Range(a, b, u) => {
-                ForIter::Range(Box::new(a.cloned_(cln)), Box::new(b.cloned_(cln)), *u)
-            }
+            ForIter::Range(a, b, step, u, i, r) => ForIter::Range(
+                Box::new(a.cloned_(cln)),
+                Box::new(b.cloned_(cln)),
+                step.as_ref().map(|x| Box::new(x.cloned_(cln))),
+                *u,
+                *i,
+                *r,
+            ),
             ForIter::Array(a) => ForIter::Array(Box::new(a.cloned_(cln))),
             ForIter::SArray(a, t) => ForIter::SArray(Box::new(a.cloned_(cln)), t.clone()),
         }
@@ -682,6 +1174,7 @@ impl BinOp {
             BinOp::BitXor => "^",
             BinOp::BitShr => ">>",
             BinOp::BitShl => "<<",
+            BinOp::BitUshr => ">>>",
 
             BinOp::And => "&&",
             BinOp::Or => "||",
@@ -694,12 +1187,22 @@ impl Term {
             Term::Var(x) => Doc::start(cxt.resolve_local(*x)),
             Term::Lit(l, t) => match l {
                 Literal::Int(i) => Doc::start(i).add(match t {
+                    Type::I8 => "i8",
+                    Type::I16 => "i16",
                     Type::I32 => "i32",
                     Type::I64 => "i64",
                     _ => unreachable!(),
                 }),
+                Literal::Float(f) => Doc::start(format!("{:?}", f)).add(match t {
+                    Type::F32 => "f",
+                    _ => "",
+                }),
                 Literal::Str(s) => Doc::start('"').add(cxt.resolve_raw(*s)).add('"'),
                 Literal::Bool(t) => Doc::start(t),
+                Literal::Char(c) => Doc::start(match char::from_u32(*c as u32) {
+                    Some(c) => format!("{:?}", c),
+                    None => format!("'\\u{{{:x}}}'", c),
+                }),
             }
             .style(Style::Literal),
             Term::Variant(tid, s, todo) => cxt
@@ -713,7 +1216,7 @@ impl Term {
                     Doc::start(",").space(),
                 ))
                 .add(')'),
-            Term::TupleIdx(x, i) => x.pretty(cxt).add('.').add(i),
+            Term::TupleIdx(x, i, _) => x.pretty(cxt).add('.').add(i),
             Term::Array(v, _, b) => Doc::start('[')
                 .chain(Doc::intersperse(
                     v.iter().map(|x| x.pretty(cxt)),
@@ -724,12 +1227,90 @@ impl Term {
             Term::ArrayIdx(arr, i, _, _, _) => {
                 arr.pretty(cxt).add('[').chain(i.pretty(cxt)).add(']')
             }
+            Term::ArraySlice(arr, a, b) => arr
+                .pretty(cxt)
+                .add('[')
+                .chain(a.pretty(cxt))
+                .add("..")
+                .chain(b.pretty(cxt))
+                .add(']'),
             Term::ArrayNew(x, _) => Doc::start("[; ").chain(x.pretty(cxt)).add("]"),
+            Term::ArrayRepeat(x, l) => Doc::start('[')
+                .chain(x.pretty(cxt))
+                .add("; ")
+                .chain(l.pretty(cxt))
+                .add(']'),
             Term::ArrayMethod(arr, m) => arr.pretty(cxt).add('.').chain(match m {
                 ArrayMethod::Len => Doc::start("len()"),
                 ArrayMethod::Pop => Doc::start("pop()"),
                 ArrayMethod::Clear => Doc::start("clear()"),
                 ArrayMethod::Push(x) => Doc::start("push(").chain(x.pretty(cxt)).add(')'),
+                ArrayMethod::Insert(i, x) => Doc::start("insert(")
+                    .chain(i.pretty(cxt))
+                    .add(", ")
+                    .chain(x.pretty(cxt))
+                    .add(')'),
+                ArrayMethod::RemoveAt(i) => Doc::start("remove_at(").chain(i.pretty(cxt)).add(')'),
+                ArrayMethod::SwapRemove(i) => {
+                    Doc::start("swap_remove(").chain(i.pretty(cxt)).add(')')
+                }
+                ArrayMethod::IndexOf(x) => Doc::start("index_of(").chain(x.pretty(cxt)).add(')'),
+                ArrayMethod::Contains(x) => Doc::start("contains(").chain(x.pretty(cxt)).add(')'),
+                ArrayMethod::Reserve(n) => Doc::start("reserve(").chain(n.pretty(cxt)).add(')'),
+            }),
+            Term::StrMethod(s, m) => match m {
+                StrMethod::CodePointAt(i) => s
+                    .pretty(cxt)
+                    .add(".code_point_at(")
+                    .chain(i.pretty(cxt))
+                    .add(')'),
+                StrMethod::CharAt(i) => s.pretty(cxt).add('[').chain(i.pretty(cxt)).add(']'),
+                StrMethod::Len => s.pretty(cxt).add(".len()"),
+                StrMethod::Substring(a, b) => s
+                    .pretty(cxt)
+                    .add(".substring(")
+                    .chain(a.pretty(cxt))
+                    .add(", ")
+                    .chain(b.pretty(cxt))
+                    .add(')'),
+                StrMethod::StartsWith(x) => s
+                    .pretty(cxt)
+                    .add(".starts_with(")
+                    .chain(x.pretty(cxt))
+                    .add(')'),
+                StrMethod::EndsWith(x) => s
+                    .pretty(cxt)
+                    .add(".ends_with(")
+                    .chain(x.pretty(cxt))
+                    .add(')'),
+                StrMethod::Contains(x) => s
+                    .pretty(cxt)
+                    .add(".contains(")
+                    .chain(x.pretty(cxt))
+                    .add(')'),
+            },
+            Term::BuilderMethod(s, m) => s.pretty(cxt).add('.').chain(match m {
+                BuilderMethod::Append(x) => Doc::start("append(").chain(x.pretty(cxt)).add(')'),
+                BuilderMethod::Finish => Doc::start("finish()"),
+            }),
+            Term::Builtin(b, x) => Doc::start(match b {
+                Builtin::ParseInt => "parseInt(",
+                Builtin::ParseLong => "parseLong(",
+                Builtin::ToStr => "str(",
+            })
+            .chain(x.pretty(cxt))
+            .add(')'),
+            Term::MapMethod(s, m) => s.pretty(cxt).add('.').chain(match m {
+                MapMethod::Get(k) => Doc::start("get(").chain(k.pretty(cxt)).add(')'),
+                MapMethod::Put(k, v) => Doc::start("put(")
+                    .chain(k.pretty(cxt))
+                    .add(", ")
+                    .chain(v.pretty(cxt))
+                    .add(')'),
+                MapMethod::ContainsKey(k) => {
+                    Doc::start("containsKey(").chain(k.pretty(cxt)).add(')')
+                }
+                MapMethod::Remove(k) => Doc::start("remove(").chain(k.pretty(cxt)).add(')'),
             }),
             Term::Call(None, f, a) => cxt
                 .fn_name(*f)
@@ -779,7 +1360,7 @@ impl Term {
                         .map(|no| Doc::keyword(" else").space().chain(no.pretty(cxt)))
                         .unwrap_or(Doc::none()),
                 ),
-            Term::Match(_, x, branches) => Doc::keyword("match")
+            Term::Match(_, x, branches) | Term::MatchOption(x, branches) => Doc::keyword("match")
                 .space()
                 .chain(x.pretty(cxt))
                 .space()
@@ -801,6 +1382,28 @@ impl Term {
                 .indent()
                 .line()
                 .add('}'),
+            Term::MatchInt(x, branches) => Doc::keyword("match")
+                .space()
+                .chain(x.pretty(cxt))
+                .space()
+                .add('{')
+                .line()
+                .chain(Doc::intersperse(
+                    branches.iter().map(|(i, t)| {
+                        match i {
+                            Some(i) => Doc::start(i),
+                            None => Doc::keyword("else"),
+                        }
+                        .space()
+                        .add("=>")
+                        .space()
+                        .chain(t.pretty(cxt))
+                    }),
+                    Doc::start(',').line(),
+                ))
+                .indent()
+                .line()
+                .add('}'),
             Term::Set(v, op, x) => v
                 .pretty(cxt)
                 .space()
@@ -808,8 +1411,16 @@ impl Term {
                 .add("=")
                 .space()
                 .chain(x.pretty(cxt)),
-            Term::Break => Doc::keyword("break"),
-            Term::Continue => Doc::keyword("continue"),
+            Term::Break(None) => Doc::keyword("break"),
+            Term::Break(Some(l)) => Doc::keyword("break")
+                .space()
+                .add('\'')
+                .add(cxt.resolve_raw(*l)),
+            Term::Continue(None) => Doc::keyword("continue"),
+            Term::Continue(Some(l)) => Doc::keyword("continue")
+                .space()
+                .add('\'')
+                .add(cxt.resolve_raw(*l)),
             Term::Return(None) => Doc::keyword("return"),
             Term::Return(Some(x)) => Doc::keyword("return").space().chain(x.pretty(cxt)),
             Term::Member(x, m) => x.pretty(cxt).add('.').chain(cxt.sym_path(*m).pretty(cxt)),
@@ -823,8 +1434,52 @@ impl Term {
                 ))
                 .add(")"),
             Term::Not(x) => Doc::start("!").chain(x.pretty(cxt).nest(Prec::Atom)),
+            Term::BitNot(x) => Doc::start("~").chain(x.pretty(cxt).nest(Prec::Atom)),
+            Term::Neg(x) => Doc::start("-").chain(x.pretty(cxt).nest(Prec::Atom)),
+            Term::Cast(x, t) => x
+                .pretty(cxt)
+                .nest(Prec::Atom)
+                .space()
+                .chain(Doc::keyword("as"))
+                .space()
+                .chain(t.pretty(cxt))
+                .prec(Prec::Term),
+            Term::InstanceOf(x, t) => x
+                .pretty(cxt)
+                .nest(Prec::Atom)
+                .space()
+                .chain(Doc::keyword("instanceof"))
+                .space()
+                .chain(cxt.type_name(*t).pretty(cxt))
+                .prec(Prec::Term),
             Term::Null(_) => Doc::keyword("null"),
+            Term::OptionSome(x) => Doc::start("Some(").chain(x.pretty(cxt)).add(')'),
+            Term::OptionNone(_) => Doc::start("None"),
             Term::Selph(_) => Doc::keyword("self"),
+            Term::Default(t) => Doc::keyword("default")
+                .add("::<")
+                .chain(t.pretty(cxt))
+                .add(">()"),
+            Term::Lambda(a, x, _, _) => Doc::start('|')
+                .chain(Doc::intersperse(
+                    a.iter().map(|(s, t)| {
+                        Doc::start(cxt.resolve_local(*s))
+                            .add(":")
+                            .space()
+                            .chain(t.pretty(cxt))
+                    }),
+                    Doc::start(",").space(),
+                ))
+                .add('|')
+                .space()
+                .chain(x.pretty(cxt)),
+            Term::Super(a) => Doc::keyword("super")
+                .add("(")
+                .chain(Doc::intersperse(
+                    a.iter().map(|x| x.pretty(cxt)),
+                    Doc::start(",").space(),
+                ))
+                .add(")"),
         }
     }
 }
@@ -914,7 +1569,12 @@ impl Item {
                     .line()
                     .add('}')
             }
-            Item::Class(tid, _, _) => Doc::keyword("class").chain(cxt.type_name(*tid).pretty(cxt)),
+            Item::Class(tid, _, _, _, _) => {
+                Doc::keyword("class").chain(cxt.type_name(*tid).pretty(cxt))
+            }
+            Item::Interface(tid, _) => {
+                Doc::keyword("interface").chain(cxt.type_name(*tid).pretty(cxt))
+            }
             Item::ExternClass(c, _) => cxt.type_name(*c).pretty(cxt),
             Item::InlineJava(s) => Doc::keyword("extern")
                 .space()
@@ -968,6 +1628,38 @@ impl Statement {
                 .indent()
                 .line()
                 .add("}"),
+            Statement::Loop(block) => Doc::keyword("loop")
+                .space()
+                .add("{")
+                .line()
+                .chain(Doc::intersperse(
+                    block.iter().map(|x| x.pretty(cxt)),
+                    Doc::none().line(),
+                ))
+                .indent()
+                .line()
+                .add("}"),
+            Statement::DoWhile(block, cond) => Doc::keyword("do")
+                .space()
+                .add("{")
+                .line()
+                .chain(Doc::intersperse(
+                    block.iter().map(|x| x.pretty(cxt)),
+                    Doc::none().line(),
+                ))
+                .indent()
+                .line()
+                .add("}")
+                .space()
+                .chain(Doc::keyword("while"))
+                .space()
+                .chain(cond.pretty(cxt))
+                .add(";"),
+            Statement::Labeled(l, s) => Doc::start('\'')
+                .add(cxt.resolve_raw(*l))
+                .add(':')
+                .space()
+                .chain(s.pretty(cxt)),
             Statement::For(s, iter, block) => Doc::keyword("for")
                 .space()
                 .add(cxt.resolve_local(*s))
@@ -985,6 +1677,41 @@ impl Statement {
                 .indent()
                 .line()
                 .add("}"),
+            Statement::Throw(x) => Doc::keyword("throw").space().chain(x.pretty(cxt)).add(";"),
+            Statement::Assert(c, m) => Doc::keyword("assert")
+                .space()
+                .chain(c.pretty(cxt))
+                .add(",")
+                .space()
+                .chain(m.pretty(cxt))
+                .add(";"),
+            Statement::Try(body, handlers) => {
+                let block = |v: &[Statement], cxt: &Bindings| {
+                    Doc::start("{")
+                        .line()
+                        .chain(Doc::intersperse(
+                            v.iter().map(|x| x.pretty(cxt)),
+                            Doc::none().line(),
+                        ))
+                        .indent()
+                        .line()
+                        .add("}")
+                };
+                let mut doc = Doc::keyword("try").space().chain(block(body, cxt));
+                for (t, s, v) in handlers {
+                    doc = doc
+                        .space()
+                        .chain(Doc::keyword("catch"))
+                        .space()
+                        .add(cxt.resolve_local(*s))
+                        .add(':')
+                        .space()
+                        .chain(cxt.type_name(*t).pretty(cxt))
+                        .space()
+                        .chain(block(v, cxt));
+                }
+                doc
+            }
             Statement::InlineJava(s) => Doc::keyword("extern")
                 .space()
                 .chain(
@@ -994,18 +1721,93 @@ impl Statement {
                         .style(Style::Literal),
                 )
                 .add(';'),
+            Statement::Src(_, _, s) => s.pretty(cxt),
         }
     }
 }
 impl Type {
+    pub fn is_numeric(&self) -> bool {
+        matches!(
+            self,
+            Type::I8 | Type::I16 | Type::I32 | Type::I64 | Type::F32 | Type::F64 | Type::Char
+        )
+    }
+
+    /// Whether values of this type are Java objects, so it can be used as a type argument
+    pub fn is_reference(&self) -> bool {
+        matches!(
+            self,
+            Type::Class(_, _) | Type::Str | Type::StrBuilder | Type::Map(_, _) | Type::Param(_)
+        )
+    }
+
+    /// Whether this type mentions a type parameter anywhere
+    pub fn has_param(&self) -> bool {
+        match self {
+            Type::Param(_) => true,
+            Type::Tuple(v) | Type::Class(_, v) => v.iter().any(Type::has_param),
+            Type::Array(t) | Type::SArray(t, _) | Type::Option(t) => t.has_param(),
+            Type::Map(k, v) => k.has_param() || v.has_param(),
+            _ => false,
+        }
+    }
+
+    /// The type parameters this type mentions
+    pub fn params(&self) -> Vec<RawSym> {
+        match self {
+            Type::Param(r) => vec![*r],
+            Type::Tuple(v) | Type::Class(_, v) => v.iter().flat_map(Type::params).collect(),
+            Type::Array(t) | Type::SArray(t, _) | Type::Option(t) => t.params(),
+            Type::Map(k, v) => k.params().into_iter().chain(v.params()).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Replaces type parameters with the types they're instantiated with
+    pub fn subst(&self, map: &[(RawSym, Type)]) -> Type {
+        match self {
+            Type::Param(r) => map
+                .iter()
+                .find(|(r2, _)| r2 == r)
+                .map_or_else(|| self.clone(), |(_, t)| t.clone()),
+            Type::Tuple(v) => Type::Tuple(v.iter().map(|x| x.subst(map)).collect()),
+            Type::Class(c, v) => Type::Class(*c, v.iter().map(|x| x.subst(map)).collect()),
+            Type::Array(t) => Type::Array(Box::new(t.subst(map))),
+            Type::SArray(t, i) => Type::SArray(Box::new(t.subst(map)), *i),
+            Type::Option(t) => Type::Option(Box::new(t.subst(map))),
+            Type::Map(k, v) => Type::Map(Box::new(k.subst(map)), Box::new(v.subst(map))),
+            _ => self.clone(),
+        }
+    }
+
     pub fn pretty(&self, cxt: &Bindings) -> Doc {
         match self {
+            Type::I8 => Doc::keyword("i8"),
+            Type::I16 => Doc::keyword("i16"),
             Type::I32 => Doc::keyword("i32"),
             Type::I64 => Doc::keyword("i64"),
+            Type::F32 => Doc::keyword("f32"),
+            Type::F64 => Doc::keyword("f64"),
             Type::Bool => Doc::keyword("bool"),
+            Type::Char => Doc::keyword("char"),
             Type::Str => Doc::keyword("str"),
+            Type::StrBuilder => Doc::keyword("StrBuilder"),
+            Type::Map(k, v) => Doc::start("Map<")
+                .chain(k.pretty(cxt))
+                .add(", ")
+                .chain(v.pretty(cxt))
+                .add('>'),
             Type::Unit => Doc::start("()"),
-            Type::Class(c) => cxt.type_name(*c).pretty(cxt),
+            Type::Class(c, args) if args.is_empty() => cxt.type_name(*c).pretty(cxt),
+            Type::Class(c, args) => cxt
+                .type_name(*c)
+                .pretty(cxt)
+                .add('<')
+                .chain(Doc::intersperse(
+                    args.iter().map(|x| x.pretty(cxt)),
+                    Doc::start(",").space(),
+                ))
+                .add('>'),
             Type::Tuple(v) => Doc::start('(')
                 .chain(Doc::intersperse(
                     v.iter().map(|x| x.pretty(cxt)),
@@ -1018,6 +1820,8 @@ impl Type {
                 .add("; ")
                 .add(u)
                 .add(']'),
+            Type::Option(t) => Doc::start("Option<").chain(t.pretty(cxt)).add('>'),
+            Type::Param(r) => Doc::start(cxt.resolve_raw(*r)),
         }
     }
 }
@@ -1036,11 +1840,26 @@ impl LValue {
 impl ForIter {
     pub fn pretty(&self, cxt: &Bindings) -> Doc {
         match self {
-            ForIter::Range(a, b, u) => a
-                .pretty(cxt)
-                .nest(Prec::Atom)
-                .add("..")
-                .chain(b.pretty(cxt).nest(Prec::Atom)),
+            ForIter::Range(a, b, step, _, i, r) => {
+                let doc = a
+                    .pretty(cxt)
+                    .nest(Prec::Atom)
+                    .add(if *i { "..=" } else { ".." })
+                    .chain(b.pretty(cxt).nest(Prec::Atom));
+                let doc = if *r {
+                    Doc::start('(').chain(doc).add(").rev()")
+                } else {
+                    doc
+                };
+                match step {
+                    Some(step) => doc
+                        .space()
+                        .chain(Doc::keyword("step"))
+                        .space()
+                        .chain(step.pretty(cxt).nest(Prec::Atom)),
+                    None => doc,
+                }
+            }
             ForIter::Array(a) | ForIter::SArray(a, _) => a.pretty(cxt),
         }
     }
@@ -0,0 +1,180 @@
+mod common;
+
+use common::{compile_err, run};
+
+#[test]
+fn constant_arrays_are_shared() {
+    let out = run(
+        "constant_arrays_are_shared",
+        "extern {
+            class Ext { static void setLast(int[] a, int len) { a[len - 1] = 9; } }
+        }
+        extern fn setLast(a: [i32]) = \"Ext.setLast\";
+        fn set(a: [i32]) {
+            a[1] = 42;
+        }
+        fn pub main() {
+            let a: [i32] = [];
+            a.push(0);
+            a.push(1);
+            a.push(2);
+            set(a);
+            println(str(a[1]));
+            let b = a;
+            b[0] = 7;
+            println(str(a[0]));
+            let c: [i32] = [1, 2, 3];
+            setLast(c);
+            println(str(c[2]));
+            let d: [i32] = [];
+            d.push(10);
+            d.push(20);
+            d.insert(0, 5);
+            println(str(d.remove_at(1)) + \" \" + str(d[1]));
+        }",
+        &[],
+    );
+    assert_eq!(out, "42\n7\n9\n10 20\n");
+}
+
+#[test]
+fn extern_array_args() {
+    let src = "extern {
+            class Ext {
+                static int sum(int[] a, int len) {
+                    int s = 0;
+                    for (int i = 0; i < len; i++) s += a[i];
+                    return s;
+                }
+                static int[] range(int n) {
+                    int[] a = new int[n];
+                    for (int i = 0; i < n; i++) a[i] = i;
+                    return a;
+                }
+            }
+        }
+        extern fn sum(a: [i32]): i32 = \"Ext.sum\";
+        extern fn range(n: i32): [i32] = \"Ext.range\";
+        fn pub main() {
+            let a = range(4);
+            a.push(10);
+            println(str(sum(a)));
+        }";
+    for flags in [
+        &[][..],
+        &["--boxed-arrays"],
+        &["--boxed-arrays", "--no-soa"],
+    ] {
+        assert_eq!(run("extern_array_args", src, flags), "16\n");
+    }
+}
+
+#[test]
+fn unknown_static_index() {
+    let e = compile_err(
+        "unknown_static_index",
+        "fn f(n: i32): i32 { let a = [1, 2, 3]; a[n] } fn pub main() { f(1); }",
+    );
+    assert!(
+        e.contains("Index into a static array must be known"),
+        "{}",
+        e
+    );
+}
+
+#[test]
+fn nested_arrays() {
+    let src = "fn pub main() {
+        let grid: [[i32]] = [];
+        for i in 0..3 {
+            let row: [i32] = [i];
+            grid.push(row);
+        }
+        grid[0].push(5);
+        grid[2].push(7);
+        grid[2].push(8);
+        println(str(grid.len()) + \" \" + grid[0].len() + \" \" + grid[1].len() + \" \" + grid[2].len());
+        println(str(grid[0][1]) + \" \" + grid[1][0] + \" \" + grid[2][2]);
+    }";
+    for flags in [&[][..], &["--no-soa"], &["--boxed-arrays"]] {
+        assert_eq!(run("nested_arrays", src, flags), "3 2 1 3\n5 1 8\n");
+    }
+}
+
+#[test]
+fn array_repeat() {
+    let src = "fn pub main() {
+        let n = 3;
+        let a: [bool] = [true; n];
+        let s = \"\";
+        for i in 0..a.len() {
+            s = s + a[i] + \" \";
+        }
+        println(s + a.len());
+        let t: [(i32, bool)] = [(1, true); 2];
+        t.push((2, false));
+        println(str(t[1].0) + t[1].1 + t[2].0 + t.len());
+    }";
+    for flags in [&[][..], &["--no-soa"], &["--boxed-arrays"]] {
+        assert_eq!(
+            run("array_repeat", src, flags),
+            "true true true 3\n1true23\n"
+        );
+    }
+}
+
+#[test]
+fn diverging_array_elements() {
+    for (i, body) in [
+        "let a: [i32] = [1, return 0];",
+        "let a: [i32] = [1]; a.push(return 0);",
+        "let a: [(i32, bool)] = [(1, true)]; a.push((2, return 0));",
+        "let a: [i32] = [1]; a.insert(0, if a.len() > 0 { return 0 } else { return 1 });",
+        "let a: [i32] = [1]; a.contains(return 0);",
+        "let a: [i32] = [1]; a.index_of({ return 0 });",
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        let e = compile_err(
+            &format!("diverging_array_element_{}", i),
+            &format!("fn pub f(): i32 {{ {} 1 }}", body),
+        );
+        assert!(
+            e.contains("An array element needs a value, it can't always return, break or continue"),
+            "{}",
+            e
+        );
+    }
+}
+
+#[test]
+fn resize_needs_lvalue() {
+    let e = compile_err(
+        "resize_needs_lvalue",
+        "fn make(): [i32] { let a: [i32] = []; a } fn pub f() { make().reserve(10); }",
+    );
+    assert!(
+        e.contains("Can only call 'reserve()' on variables, members and array indices, since it can change or reallocate the array"),
+        "{}",
+        e
+    );
+}
+
+#[test]
+fn push_uses_old_length() {
+    let out = run(
+        "push_uses_old_length",
+        "fn pub main() {
+            let a: [i32] = [];
+            a.push(a.len());
+            a.push(a.len() * 10);
+            for i in 0..3 {
+                a.push(if i > 1 { break } else { i });
+            }
+            println(str(a.len()) + \" \" + str(a[0]) + \" \" + str(a[1]) + \" \" + str(a[3]));
+        }",
+        &[],
+    );
+    assert_eq!(out, "4 0 10 1\n");
+}
@@ -0,0 +1,123 @@
+mod common;
+
+use common::{compile, compile_err, run};
+
+/// Java overloads `println`, so these print numbers and chars as themselves
+const PRINT: &str = "extern fn printi(i: i32) = \"System.out.println\";
+extern fn printc(c: char) = \"System.out.println\";
+extern fn printb(b: bool) = \"System.out.println\";
+";
+
+#[test]
+fn char_int_casts() {
+    let src = "fn code(c: char): i32 = c as i32;
+    fn pub main() {
+        printb('A' as i32 == 65);
+        let n = code('A') + 1;
+        let c = n as char;
+        printc(c);
+        printb(c == 'B');
+        printi(code('\\n'));
+        printi(code('\\''));
+        printi(code('\u{e9}'));
+        let big = 65601;
+        printb(big as char == 'A');
+    }";
+    let out = run("char_int_casts", &format!("{}{}", PRINT, src), &[]);
+    assert_eq!(out, "true\nB\ntrue\n10\n39\n233\ntrue\n");
+}
+
+#[test]
+fn char_arrays() {
+    let src = "fn pub main() {
+        let cs: [char] = ['a', 'b'];
+        cs.push('c');
+        let s = \"\";
+        for i in 0..cs.len() {
+            s = s + cs[i];
+        }
+        println(s);
+        printi(cs[2] as i32 - 'a' as i32);
+    }";
+    let out = run("char_arrays", &format!("{}{}", PRINT, src), &[]);
+    assert_eq!(out, "abc\n2\n");
+}
+
+#[test]
+fn char_literals_and_labels() {
+    let src = "fn pub main() {
+        'outer: loop {
+            printc('o');
+            break 'outer;
+        }
+    }";
+    let out = run(
+        "char_literals_and_labels",
+        &format!("{}{}", PRINT, src),
+        &[],
+    );
+    assert_eq!(out, "o\n");
+    let e = compile_err("char_outside_bmp", "fn pub main() { let c = '\u{1F600}'; }");
+    assert!(e.contains("Char literals must fit in a Java char"), "{}", e);
+}
+
+#[test]
+fn string_index_is_char() {
+    let src = "fn pub main() {
+        let s = \"hey\";
+        let c: char = s[0];
+        printb(c == 'h');
+        printc(s[1]);
+        printi(s[s.len() - 1] as i32 - 'a' as i32);
+    }";
+    let out = run("string_index_is_char", &format!("{}{}", PRINT, src), &[]);
+    assert_eq!(out, "true\ne\n24\n");
+}
+
+#[test]
+fn char_arithmetic() {
+    let out = run(
+        "char_arithmetic",
+        "fn rot(c: char, n: i32): char = ((c - 'a' + n) % 26 + 'a') as char;
+        fn pub main() {
+            let c = '7';
+            let x: i32 = c - '0';
+            println(str(x) + \" \" + (1 + 'a') + \" \" + ('a' << 1));
+            println(str(rot('y', 3)) + rot('b', 1));
+        }",
+        &[],
+    );
+    assert_eq!(out, "7 98 194\nbc\n");
+}
+
+#[test]
+fn char_range() {
+    let e = compile_err("char_range_big", "fn pub main() { let c = 65536 as char; }");
+    assert!(e.contains("Can't cast 65536 to char"), "{}", e);
+    let e = compile_err("char_range_neg", "fn pub main() { let c = -1 as char; }");
+    assert!(e.contains("Can't cast -1 to char"), "{}", e);
+
+    let src = "fn pub f(pub x: i32): char = (x * 2) as char;";
+    let java = compile("char_range_unchecked", src, &[]).unwrap();
+    assert!(!java.contains("IllegalArgumentException"), "{}", java);
+    let java = compile("char_range_checked", src, &["--bounds-checks"]).unwrap();
+    assert!(
+        java.contains("throw new IllegalArgumentException(\"Value \" + "),
+        "{}",
+        java
+    );
+}
+
+#[test]
+fn string_index_arithmetic() {
+    let out = run(
+        "string_index_arithmetic",
+        "fn pub main() {
+            let s = \"hey\";
+            let c: char = s[0];
+            println(str(c == 'h') + s[1] + (s[s.len() - 1] - 'a'));
+        }",
+        &[],
+    );
+    assert_eq!(out, "truee24\n");
+}
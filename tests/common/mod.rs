@@ -0,0 +1,101 @@
+//! Helpers for compiling ByteC snippets with the `bytec` binary and running the generated Java.
+//! Running needs `javac` and `java` on the `PATH`.
+#![allow(dead_code)]
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Declared at the top of every program passed to `run()`
+const PRELUDE: &str = "extern fn println(s: str) = \"System.out.println\";\n";
+
+/// A fresh directory for the test `name`, with an empty `src` directory for the ByteC files
+fn test_dir(name: &str) -> PathBuf {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join(name);
+    if dir.exists() {
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+    std::fs::create_dir_all(dir.join("src")).unwrap();
+    dir
+}
+
+/// Removes the color codes from the compiler's error messages
+fn strip_colors(s: &str) -> String {
+    let mut out = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            for c in chars.by_ref() {
+                if c == 'm' {
+                    break;
+                }
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+fn bytec(dir: &Path, src: &str, flags: &[&str]) -> Result<String, String> {
+    std::fs::write(dir.join("src/T.bt"), src).unwrap();
+    let out = Command::new(env!("CARGO_BIN_EXE_bytec"))
+        .args(flags)
+        .arg(dir.join("src"))
+        .arg(dir.join("pkg"))
+        .output()
+        .unwrap();
+    if out.status.success() {
+        Ok(std::fs::read_to_string(dir.join("pkg/T.java")).unwrap())
+    } else {
+        Err(strip_colors(&String::from_utf8_lossy(&out.stderr)))
+    }
+}
+
+/// Compiles `src` as `T.bt`, returning the generated Java, or the compiler's error messages if it fails
+pub fn compile(name: &str, src: &str, flags: &[&str]) -> Result<String, String> {
+    bytec(&test_dir(name), src, flags)
+}
+
+/// Compiles `src` and expects it to fail, returning the error messages
+pub fn compile_err(name: &str, src: &str) -> String {
+    match compile(name, src, &[]) {
+        Ok(_) => panic!("expected a compile error"),
+        Err(e) => e,
+    }
+}
+
+/// Compiles `src`, which defines `fn pub main()` and can use `println(s: str)`, runs it, and returns what it printed
+pub fn run(name: &str, src: &str, flags: &[&str]) -> String {
+    let dir = test_dir(name);
+    if let Err(e) = bytec(&dir, &format!("{}{}", PRELUDE, src), flags) {
+        panic!("bytec failed:\n{}", e);
+    }
+    std::fs::write(
+        dir.join("pkg/Main.java"),
+        "package pkg; public class Main { public static void main(String[] a) { T.main(); } }",
+    )
+    .unwrap();
+
+    let javac = Command::new("javac")
+        .arg("-nowarn")
+        .arg("-d")
+        .arg(dir.join("classes"))
+        .arg(dir.join("pkg/T.java"))
+        .arg(dir.join("pkg/Main.java"))
+        .output()
+        .expect("javac is needed to run the generated Java");
+    if !javac.status.success() {
+        panic!("javac failed:\n{}", String::from_utf8_lossy(&javac.stderr));
+    }
+
+    let java = Command::new("java")
+        .arg("-cp")
+        .arg(dir.join("classes"))
+        .arg("pkg.Main")
+        .output()
+        .expect("java is needed to run the generated Java");
+    if !java.status.success() {
+        panic!("java failed:\n{}", String::from_utf8_lossy(&java.stderr));
+    }
+    String::from_utf8(java.stdout).unwrap()
+}
@@ -0,0 +1,56 @@
+mod common;
+
+use common::{compile, run};
+
+#[test]
+fn simple_if_is_ternary() {
+    let java = compile(
+        "simple_if_is_ternary",
+        "fn pub f(pub c: bool, pub y: i32): i32 = if c { y } else { 0 };",
+        &[],
+    )
+    .unwrap();
+    assert!(java.contains("return c ? y : 0;"), "{}", java);
+}
+
+#[test]
+fn diverging_else_keeps_value() {
+    let out = run(
+        "diverging_else_keeps_value",
+        "fn f(c: bool, y: i32): i32 {
+            let x = if c { y } else { return 0 };
+            x + 1
+        }
+        fn g(c: bool, y: i32): i32 {
+            let x = if c { println(\"g\"); y } else { return 0 };
+            x + 1
+        }
+        fn pub main() {
+            println(str(f(true, 4)) + \" \" + str(f(false, 4)));
+            println(str(g(true, 4)) + \" \" + str(g(false, 4)));
+        }",
+        &[],
+    );
+    assert_eq!(out, "5 0\ng\n5 0\n");
+}
+
+#[test]
+fn diverging_then_keeps_value() {
+    let out = run(
+        "diverging_then_keeps_value",
+        "fn f(c: bool, y: i32): i32 {
+            let x: i32 = if c { return 0 } else { y };
+            x + 1
+        }
+        fn pub main() {
+            println(str(f(true, 4)) + \" \" + str(f(false, 4)));
+            let s = 0;
+            for i in 0..5 {
+                s += if i > 2 { break } else { i * 10 };
+            }
+            println(str(s));
+        }",
+        &[],
+    );
+    assert_eq!(out, "0 5\n30\n");
+}
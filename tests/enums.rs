@@ -0,0 +1,28 @@
+mod common;
+
+use common::{compile, compile_err};
+
+#[test]
+fn duplicate_variant() {
+    let e = compile_err(
+        "duplicate_variant",
+        "enum E { A(i32), B, A } fn pub main() {}",
+    );
+    assert!(e.contains("Duplicate variant 'A' in enum"), "{}", e);
+    // The error points at the second `A`
+    assert!(e.contains("1:21"), "{}", e);
+}
+
+#[test]
+fn enum_field_clash() {
+    let src = "enum E { A(i32), B; let pub _enum_A_0: i32; } fn pub main() {}";
+    assert!(compile("enum_field_clash", src, &[]).is_ok());
+    let e = compile("enum_field_clash", src, &["--name-separator=_"]).unwrap_err();
+    assert!(e.contains("stored in the field '_enum_A_0'"), "{}", e);
+
+    // With a digit as the separator, `A`'s 11th member and `A1`'s first member get the same name
+    let src = "enum E { A(i32, i32, i32, i32, i32, i32, i32, i32, i32, i32, i32), A1(i32) }
+        fn pub main() {}";
+    let e = compile("enum_field_clash", src, &["--name-separator=1"]).unwrap_err();
+    assert!(e.contains("stored in the field '_enum1A110'"), "{}", e);
+}
@@ -0,0 +1,48 @@
+mod common;
+
+use common::{compile, run};
+
+#[test]
+fn default_parameters() {
+    // The example from the guide's language overview
+    let out = run(
+        "default_parameters",
+        "fn advance(x: i32, by: i32 = 1): i32 = x + by;
+        let a = advance(2); // advance(2, 1)
+        fn pub main() {
+            println(str(a) + \" \" + str(advance(2, 5)));
+        }",
+        &[],
+    );
+    assert_eq!(out, "3 7\n");
+}
+
+#[test]
+fn predef_flag() {
+    let out = run(
+        "predef_flag",
+        "fn pub main() {
+            println(str(abs(-3) + max(2, 5)));
+        }",
+        &[
+            "--predef=abs(x: i32): i32 = \"Math.abs\"",
+            "--predef=max(a: i32, b: i32): i32 = \"Math.max\"",
+        ],
+    );
+    assert_eq!(out, "8\n");
+}
+
+#[test]
+fn predef_error_points_at_flag() {
+    let e = compile(
+        "predef_error_points_at_flag",
+        "fn pub f(): i32 = abs(-1);",
+        &[
+            "--predef=abs(x: i32): i32 = \"Math.abs\"",
+            "--predef=max(a: i32, b: Foo): i32 = \"Math.max\"",
+        ],
+    )
+    .unwrap_err();
+    assert!(e.contains("Name not found: Foo"), "{}", e);
+    assert!(e.contains("--> --predef:2:26"), "{}", e);
+}
@@ -0,0 +1,45 @@
+mod common;
+
+use common::{compile_err, run};
+
+#[test]
+fn int_literal_bases() {
+    let out = run(
+        "int_literal_bases",
+        "fn wide(): i64 = 0xFFFFFFFF;
+        fn pub main() {
+            let a: i32 = 0xFFFFFFFF;
+            println(str(a) + \" \" + wide());
+            println(str(0xFF) + \" \" + 0b1010 + \" \" + 0o17 + \" \" + 0x80000000);
+            let max = 0x7FFFFFFF;
+            println(str(max + 1) + \" \" + (0x7FFFFFFF as i64 + 1));
+        }",
+        &[],
+    );
+    assert_eq!(
+        out,
+        "-1 4294967295\n255 10 15 -2147483648\n-2147483648 2147483648\n"
+    );
+    let e = compile_err("bare_hex_prefix", "fn pub main() { let x = 0x; }");
+    assert!(e.contains("expected digits after base prefix"), "{}", e);
+}
+
+#[test]
+fn digit_separators() {
+    let out = run(
+        "digit_separators",
+        "fn pub main() {
+            println(str(1_000 + 0xFF_FF) + \" \" + 1_0.2_5);
+        }",
+        &[],
+    );
+    assert_eq!(out, "66535 10.25\n");
+    for (name, lit) in [
+        ("trailing_underscore", "1_"),
+        ("double_underscore", "1__0"),
+        ("underscore_after_prefix", "0x_FF"),
+    ] {
+        let e = compile_err(name, &format!("fn pub main() {{ let x = {}; }}", lit));
+        assert!(e.contains("'_' can only be used between digits"), "{}", e);
+    }
+}
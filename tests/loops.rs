@@ -0,0 +1,108 @@
+mod common;
+
+use common::{compile_err, run};
+
+#[test]
+fn inclusive_range_to_int_limits() {
+    let out = run(
+        "inclusive_range_to_int_limits",
+        "fn max(): i32 = 2147483647;
+        fn min(): i32 = -2147483647 - 1;
+        fn pub main() {
+            for i in 2147483645..=max() {
+                if i == 2147483646 {
+                    continue;
+                }
+                println(str(i));
+            }
+            for i in (min()..=min() + 1).rev() {
+                println(str(i));
+            }
+            let e = max() - 1;
+            for i in e - 6..=e step 3 {
+                println(str(i));
+            }
+        }",
+        &[],
+    );
+    assert_eq!(
+        out,
+        "2147483645\n2147483647\n-2147483647\n-2147483648\n2147483640\n2147483643\n2147483646\n"
+    );
+}
+
+#[test]
+fn stepped_range_to_int_limits() {
+    let out = run(
+        "stepped_range_to_int_limits",
+        "fn check(i: i32): bool {
+            // Stops the test if the loop wraps around instead of looping forever
+            if i > -2147483640 && i < 2147483640 {
+                println(\"wrapped\");
+                return false;
+            }
+            println(str(i));
+            true
+        }
+        fn pub main() {
+            for i in 2147483640..2147483647 step 3 {
+                if !check(i) { break; }
+            }
+            let n = 2147483647;
+            for i in 2147483643..n step 2 {
+                if !check(i) { break; }
+            }
+            for i in -2147483643..-2147483648 step -4 {
+                if !check(i) { break; }
+            }
+        }",
+        &[],
+    );
+    assert_eq!(
+        out,
+        "2147483640\n2147483643\n2147483646\n2147483643\n2147483645\n-2147483643\n-2147483647\n"
+    );
+}
+
+#[test]
+fn inclusive_range_to_literal_limit() {
+    let msg = "An inclusive range to the largest or smallest i32 would loop forever";
+    let e = compile_err(
+        "inclusive_range_to_max",
+        "fn pub main() { for i in 0..=2147483647 {} }",
+    );
+    assert!(e.contains(msg), "{}", e);
+    let e = compile_err(
+        "inclusive_range_to_min",
+        "fn pub main() { for i in (-2147483648..=0).rev() {} }",
+    );
+    assert!(e.contains(msg), "{}", e);
+}
+
+#[test]
+fn step_is_contextual() {
+    let out = run(
+        "step_is_contextual",
+        "fn step(step: i32): i32 = step * 2;
+        fn pub main() {
+            let step = step(2);
+            for i in 0..10 step step {
+                println(str(i));
+            }
+            for i in 6..0 step -3 {
+                println(str(i));
+            }
+        }",
+        &[],
+    );
+    assert_eq!(out, "0\n4\n8\n6\n3\n");
+}
+
+#[test]
+fn unroll_unknown_range() {
+    let e = compile_err(
+        "unroll_unknown_range",
+        "fn f(n: i32) { for i in unroll 0..n {} } fn pub main() { f(3); }",
+    );
+    assert!(e.contains("Couldn't unroll loop"), "{}", e);
+}
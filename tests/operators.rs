@@ -0,0 +1,71 @@
+mod common;
+
+use common::{compile, run};
+
+#[test]
+fn bitwise_precedence() {
+    let java = compile(
+        "bitwise_precedence",
+        "fn pub mix(pub a: i32, pub b: i32, pub c: i32): i32 = a & b | c;
+        fn pub group(pub a: i32, pub b: i32, pub c: i32): i32 = a & (b | c) ^ c;
+        fn pub test(pub a: i32, pub b: i32, pub c: i32): bool = a & b == c && b | c != 0;",
+        &[],
+    )
+    .unwrap();
+    assert!(java.contains("return a & b | c;"));
+    assert!(java.contains("return a & (b | c) ^ c;"));
+    // `==` binds tighter than `&` in Java too, so the operands need parentheses
+    assert!(java.contains("return (a & b) == c && (b | c) != 0;"));
+}
+
+#[test]
+fn bitwise_values() {
+    let out = run(
+        "bitwise_values",
+        "fn f(a: i32, b: i32, c: i32): i32 = a & b | c;
+        fn g(a: i32, b: i32, c: i32): i32 = a & (b | c) ^ c;
+        fn pub main() {
+            println(str(f(6, 3, 8)));
+            println(str(g(6, 3, 8)));
+            println(str(f(12, 10, 1) ^ 5));
+        }",
+        &[],
+    );
+    assert_eq!(out, "10\n10\n12\n");
+}
+
+#[test]
+fn literal_folding() {
+    let java = compile(
+        "literal_folding",
+        "fn pub add(): i32 = 2 + 3 * 4;
+        fn pub wrap(): i32 = 2147483647 + 1;
+        fn pub wide(): i64 = 2147483647 + 1;
+        fn pub cmp(): bool = 1 + 1 < 3;
+        fn pub shift(): i32 = 1 << 33;",
+        &[],
+    )
+    .unwrap();
+    assert!(java.contains("return 14;"), "{}", java);
+    assert!(java.contains("return -2147483648;"), "{}", java);
+    assert!(java.contains("return 2147483648L;"), "{}", java);
+    assert!(java.contains("return true;"), "{}", java);
+    assert!(java.contains("return 2;"), "{}", java);
+}
+
+#[test]
+fn negation_folding() {
+    let java = compile(
+        "negation_folding",
+        "fn pub ne(pub a: i32, pub b: i32): bool = !(a == b);
+        fn pub eq(pub a: i32, pub b: i32): bool = !(a != b);
+        fn pub same(pub x: bool): bool = !!x;
+        fn pub str_ne(pub s: str, pub t: str): bool = !(s == t);",
+        &[],
+    )
+    .unwrap();
+    assert!(java.contains("return a != b;"), "{}", java);
+    assert!(java.contains("return a == b;"), "{}", java);
+    assert!(java.contains("return x;"), "{}", java);
+    assert!(java.contains("return !s.equals(t);"), "{}", java);
+}
@@ -0,0 +1,81 @@
+mod common;
+
+use common::{compile, run};
+
+#[test]
+fn compound_assignment_propagation() {
+    let out = run(
+        "compound_assignment_propagation",
+        "extern fn printi(i: i32) = \"System.out.println\";
+        fn pub main() {
+            let x = 10;
+            let y = 3;
+            x -= y;
+            printi(x);
+            x /= 2;
+            printi(x);
+            let a: [i32] = [1, 2, 3];
+            a.pop();
+            printi(a.len());
+        }",
+        &[],
+    );
+    assert_eq!(out, "7\n3\n2\n");
+}
+
+/// Compiles a function `f` with a loop running `body`, and returns whether anything was moved into a local before the loop
+fn hoisted(name: &str, globals: &str, body: &str) -> bool {
+    let src = format!(
+        "{}
+        fn pub f(pub n: i32, pub s: str, pub w: i32, pub h: i32): i32 {{
+            let c = 0;
+            for i in 0..n {{
+                {}
+            }}
+            c
+        }}",
+        globals, body
+    );
+    compile(name, &src, &[]).unwrap().contains("$_inv")
+}
+
+#[test]
+fn loop_invariant_hoisting() {
+    assert!(hoisted("hoist_always", "", "c += w * h - 1;"));
+    assert!(!hoisted(
+        "hoist_conditional",
+        "",
+        "if i == 5 { c += w * h - 1; }"
+    ));
+    assert!(!hoisted(
+        "hoist_after_continue",
+        "",
+        "if i == 5 { continue; } c += w * h - 1;"
+    ));
+    assert!(hoisted("hoist_global", "let g = 2;", "c += g * h - 1;"));
+    assert!(!hoisted(
+        "hoist_volatile",
+        "let volatile g = 2;",
+        "c += g * h - 1;"
+    ));
+}
+
+#[test]
+fn object_equality_not_hoisted() {
+    // `s.equals("abc")` would throw if it ran before a loop that never runs
+    let out = run(
+        "object_equality_not_hoisted",
+        "fn count(s: str, n: i32): i32 {
+            let c = 0;
+            for i in 0..n {
+                c += if s == \"abc\" { 1 } else { 0 };
+            }
+            c
+        }
+        fn pub main() {
+            println(str(count(null, 0)) + \" \" + str(count(\"abc\", 3)));
+        }",
+        &[],
+    );
+    assert_eq!(out, "0 3\n");
+}
@@ -0,0 +1,31 @@
+mod common;
+
+use common::compile;
+
+#[test]
+fn deterministic_output() {
+    let src = "enum Dir { North, East(i32), South(i32, bool), West; let pub n: i32; }
+    class Point {
+        let pub x: i32;
+        let pub y: i32;
+        fn len(): i32 = self.x + self.y;
+    }
+    fn pub pair(pub a: i32): (i32, str) = (a * 2, \"pair\");
+    fn pub go(pub d: Dir, pub p: Point): str {
+        let m = default::<Map<str, i32>>();
+        m.put(\"a\", p.len());
+        let names: [str] = [\"north\", \"east\", \"south\", \"west\"];
+        let s = match d {
+            North => names[0],
+            East(i) => names[1] + i,
+            South(i, b) => names[2] + i + b,
+            West => names[3],
+        };
+        s + pair(m.get(\"a\")).1 + \"north\"
+    }";
+    let first = compile("deterministic_output", src, &["--intern-strings"]).unwrap();
+    for _ in 0..3 {
+        let again = compile("deterministic_output", src, &["--intern-strings"]).unwrap();
+        assert_eq!(first, again);
+    }
+}
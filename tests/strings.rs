@@ -0,0 +1,39 @@
+mod common;
+
+use common::{compile, run};
+
+#[test]
+fn concatenation() {
+    let out = run(
+        "concatenation",
+        "fn pub main() {
+            let n = 3;
+            let s = \"items\";
+            println(\"x=\" + n);
+            println(n + \" \" + s);
+            println(s + \"!\");
+            println(n + 1 + \" \" + (n * 2) + true);
+        }",
+        &[],
+    );
+    assert_eq!(out, "x=3\n3 items\nitems!\n4 6true\n");
+}
+
+#[test]
+fn escapes() {
+    let src = "fn pub main() {
+        println(\"say \\\"hi\\\" to C:\\\\dir\\tnow\");
+        println(\"a\\nb\\r\\0\\u{1}7\");
+        println(str(\"\\u{1F600}\".len()) + '\\'' + '\\\\' + '\\t' + '\"');
+    }";
+    assert_eq!(
+        run("escapes", src, &[]),
+        "say \"hi\" to C:\\dir\tnow\na\nb\r\0\u{1}7\n2'\\\t\"\n"
+    );
+    let println = "extern fn println(s: str) = \"System.out.println\";";
+    let java = compile("escapes_java", &format!("{}{}", println, src), &[]).unwrap();
+    assert!(java.contains(r#""say \"hi\" to C:\\dir\tnow""#), "{}", java);
+    // A three digit octal escape, so the 7 after it isn't part of it
+    assert!(java.contains(r#""a\nb\r\000\0017""#), "{}", java);
+    assert!(java.contains(r#"'\'' + '\\' + '\t' + '"'"#), "{}", java);
+}
@@ -0,0 +1,22 @@
+mod common;
+
+use common::run;
+
+#[test]
+fn nullable_try() {
+    let out = run(
+        "nullable_try",
+        "fn len(s: str): Option<i32> = Some(s?.len());
+        fn same(s: str): str {
+            let t = s?;
+            t
+        }
+        fn show(o: Option<i32>): str = match o { Some(x) => str(x), None => \"none\" };
+        fn pub main() {
+            println(show(len(\"abc\")) + \" \" + show(len(null)));
+            println(same(\"x\") + \" \" + (same(null) == null));
+        }",
+        &[],
+    );
+    assert_eq!(out, "3 none\nx true\n");
+}
@@ -0,0 +1,34 @@
+mod common;
+
+use common::run;
+
+#[test]
+fn nested_tuple_index() {
+    let src = "fn pub main() {
+            let t: (i32, (bool, str), i32) = (1, (true, \"a\"), 2);
+            let a: [(i32, (bool, str))] = [(3, (false, \"b\"))];
+            let p = a.pop();
+            let i = (t.1).1;
+            println(i + (p.1).1 + t.2 + p.0);
+            let u = t.1;
+            if u.0 && !(p.1).0 {
+                println(u.1);
+            }
+        }";
+    for flags in [&[][..], &["--no-soa"], &["--boxed-arrays"]] {
+        assert_eq!(run("nested_tuple_index", src, flags), "ab23\na\n");
+    }
+}
+
+#[test]
+fn chained_tuple_index() {
+    let out = run(
+        "chained_tuple_index",
+        "fn pub main() {
+            let t = ((1, (2, 3)), 4.5);
+            println(str(t.0.1.0 + t.0.1.1) + \" \" + str(t.1 * 2.0));
+        }",
+        &[],
+    );
+    assert_eq!(out, "5 9.0\n");
+}